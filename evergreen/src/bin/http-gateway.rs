@@ -602,6 +602,7 @@ fn main() {
         // the gateway() config instead.
         skip_logging: true,
         appname: Some(String::from("http-gateway")),
+        validate_config: false,
     };
 
     // Connect to OpenSRF, parse the IDL
@@ -0,0 +1,70 @@
+//! Compile the IDL XML into the compact form loaded by
+//! idl::Parser::load_file() in place of the XML.
+//!
+//! Re-run after every IDL update.  A compiled IDL which no longer
+//! matches its XML is ignored at load time.
+use eg::idl;
+use evergreen as eg;
+use getopts;
+use std::env;
+use std::process;
+
+const HELP_TEXT: &str = r#"
+Compile the Evergreen IDL.
+
+Options
+
+    --idl-file <path>
+        IDL XML file.  Defaults to the EG_IDL_FILE environment
+        variable or /openils/conf/fm_IDL.xml
+
+    --out-file <path>
+        Where to write the compiled IDL.  Defaults to the IDL file
+        path plus ".compiled", which is where the IDL loader looks.
+
+    -h, --help
+        Display this help.
+"#;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut opts = getopts::Options::new();
+
+    opts.optopt("", "idl-file", "", "");
+    opts.optopt("", "out-file", "", "");
+    opts.optflag("h", "help", "");
+
+    let params = match opts.parse(&args[1..]) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{e}\n{HELP_TEXT}");
+            process::exit(1);
+        }
+    };
+
+    if params.opt_present("help") {
+        println!("{HELP_TEXT}");
+        return;
+    }
+
+    let idl_file = match params.opt_str("idl-file") {
+        Some(f) => f,
+        None => match eg::init::idl_file_path() {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("Cannot determine IDL file: {e}");
+                process::exit(1);
+            }
+        },
+    };
+
+    let out_file = params.opt_str("out-file");
+
+    match idl::Parser::compile_file(&idl_file, out_file.as_deref()) {
+        Ok(path) => println!("Compiled {idl_file} to {path}"),
+        Err(e) => {
+            eprintln!("Cannot compile IDL: {e}");
+            process::exit(1);
+        }
+    }
+}
@@ -683,7 +683,7 @@ impl Session {
                             .insert(tm.thread().to_string(), tm.from().to_string());
                     }
                     // We don't need to analyze every non-error message.
-                    s if (s as usize) < 400 => {}
+                    s if !s.is_error() => {}
                     _ => {
                         log::error!("{self} Request returned unexpected status: {:?}", msg);
                         self.subtract_reqs();
@@ -920,6 +920,7 @@ fn main() {
         // the gateway() config instead.
         skip_logging: true,
         appname: Some(String::from("http-gateway")),
+        validate_config: false,
     };
 
     // Connect to OpenSRF, parse the IDL
@@ -1,5 +1,6 @@
 use crate as eg;
 use eg::common::settings::Settings;
+use eg::common::user;
 use eg::constants as C;
 use eg::date;
 use eg::osrf::cache::Cache;
@@ -18,6 +19,61 @@ fn cache_key(token: &str) -> String {
     format!("{}{}", C::OILS_AUTH_CACHE_PRFX, token)
 }
 
+/// Cache key for the failed login counter used to block repeated
+/// login attempts for a user.
+fn login_count_key(user_id: i64) -> String {
+    format!("{}{}_count", C::OILS_AUTH_CACHE_PRFX, user_id)
+}
+
+/// Failed logins allowed per user within the block time, matching
+/// open-ils.auth's auth_limits defaults.
+const DEFAULT_LOGIN_BLOCK_COUNT: i64 = 10;
+
+/// Seconds a user's failed login counter lasts.
+const DEFAULT_LOGIN_BLOCK_TIME: i64 = 900;
+
+/// Check a user's password as part of a login, upgrading legacy
+/// password hashes when enabled.  See user::PW_UPGRADE_SETTING.
+///
+/// As with open-ils.auth logins, each failure bumps the user's failed
+/// login counter.  Once the counter reaches the auth_limits block_count,
+/// passwords are not checked at all until the counter expires after
+/// block_time seconds.  The counter is shared with open-ils.auth and
+/// cleared on logout.
+///
+/// The caller is responsible for starting and committing the transaction.
+pub fn verify_login_password(editor: &mut Editor, user_id: i64, password: &str) -> EgResult<bool> {
+    let limits = "apps/open-ils.auth/app_settings/auth_limits";
+
+    let block_count = HostSettings::get(&format!("{limits}/block_count"))?
+        .as_int()
+        .unwrap_or(DEFAULT_LOGIN_BLOCK_COUNT);
+
+    let block_time = HostSettings::get(&format!("{limits}/block_time"))?
+        .as_int()
+        .unwrap_or(DEFAULT_LOGIN_BLOCK_TIME);
+
+    let key = login_count_key(user_id);
+
+    let count = match Cache::get_global(&key)? {
+        Some(c) => c.as_int().unwrap_or(0),
+        None => 0,
+    };
+
+    if count >= block_count {
+        log::warn!("Blocking password check for user {user_id} after {count} failed logins");
+        return Ok(false);
+    }
+
+    if user::verify_password_with_upgrade(editor, user_id, password)? {
+        return Ok(true);
+    }
+
+    Cache::set_global_for(&key, EgValue::from(count + 1), block_time as u32)?;
+
+    Ok(false)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum LoginType {
     Temp,
@@ -115,6 +171,31 @@ impl LoginArgs {
     }
 }
 
+impl TryFrom<&EgValue> for LoginArgs {
+    type Error = EgError;
+
+    /// Unpack the options hash passed to open-ils.auth.login.
+    fn try_from(v: &EgValue) -> EgResult<LoginArgs> {
+        let login_type = match v["type"].as_str() {
+            Some(t) => LoginType::try_from(t)?,
+            None => LoginType::Temp,
+        };
+
+        Ok(LoginArgs {
+            username: v["username"].str()?.to_string(),
+            password: v["password"].str()?.to_string(),
+            login_type,
+            workstation: v["workstation"].as_str().map(|w| w.to_string()),
+        })
+    }
+}
+
+impl From<&LoginArgs> for EgValue {
+    fn from(args: &LoginArgs) -> EgValue {
+        args.to_eg_value()
+    }
+}
+
 #[derive(Debug)]
 pub struct InternalLoginArgs {
     pub user_id: i64,
@@ -156,6 +237,102 @@ impl InternalLoginArgs {
     }
 }
 
+impl TryFrom<&EgValue> for InternalLoginArgs {
+    type Error = EgError;
+
+    /// Unpack the options hash passed to
+    /// open-ils.auth_internal.session.create.
+    ///
+    /// "org_id" is accepted as an alias for "org_unit".
+    fn try_from(v: &EgValue) -> EgResult<InternalLoginArgs> {
+        let org_unit = match v["org_unit"].as_int() {
+            Some(id) => Some(id),
+            None => v["org_id"].as_int(),
+        };
+
+        Ok(InternalLoginArgs {
+            user_id: v["user_id"].int()?,
+            login_type: LoginType::try_from(v["login_type"].str()?)?,
+            org_unit,
+            workstation: v["workstation"].as_str().map(|w| w.to_string()),
+        })
+    }
+}
+
+impl From<&InternalLoginArgs> for EgValue {
+    fn from(args: &InternalLoginArgs) -> EgValue {
+        args.to_eg_value()
+    }
+}
+
+/// Options for open-ils.auth_internal.user.validate
+#[derive(Debug)]
+pub struct ValidateUserArgs {
+    pub user_id: i64,
+    pub login_type: LoginType,
+    pub barcode: Option<String>,
+}
+
+impl TryFrom<&EgValue> for ValidateUserArgs {
+    type Error = EgError;
+
+    fn try_from(v: &EgValue) -> EgResult<ValidateUserArgs> {
+        Ok(ValidateUserArgs {
+            user_id: v["user_id"].int()?,
+            login_type: LoginType::try_from(v["login_type"].str()?)?,
+            barcode: v["barcode"].as_str().map(|b| b.to_string()),
+        })
+    }
+}
+
+impl From<&ValidateUserArgs> for EgValue {
+    fn from(args: &ValidateUserArgs) -> EgValue {
+        let lt: &str = (&args.login_type).into();
+
+        let mut jv = eg::hash! {
+            "user_id": args.user_id,
+            "login_type": lt,
+        };
+
+        if let Some(b) = &args.barcode {
+            jv["barcode"] = EgValue::from(b.as_str());
+        }
+
+        jv
+    }
+}
+
+/// Response to a successful auth session create call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthSession {
+    pub authtoken: String,
+    pub authtime: u32,
+}
+
+impl TryFrom<&EgValue> for AuthSession {
+    type Error = EgError;
+
+    fn try_from(v: &EgValue) -> EgResult<AuthSession> {
+        let authtoken = v["authtoken"]
+            .as_str()
+            .ok_or_else(|| format!("Auth response has invalid authtoken: {v}"))?;
+
+        Ok(AuthSession {
+            authtoken: authtoken.to_string(),
+            authtime: v["authtime"].int()? as u32,
+        })
+    }
+}
+
+impl From<&AuthSession> for EgValue {
+    fn from(ses: &AuthSession) -> EgValue {
+        eg::hash! {
+            "authtoken": ses.authtoken.as_str(),
+            "authtime": ses.authtime,
+        }
+    }
+}
+
 pub struct Session {
     user: EgValue,
 
@@ -208,6 +385,61 @@ impl Session {
         Cache::del_global(&cache_key(self.token()))
     }
 
+    /// Push the cache expiration of the session forward by its
+    /// authtime, i.e. the inactivity timeout.
+    ///
+    /// Sessions with an endtime never expire later than the endtime.
+    /// Calling this repeatedly simply restarts the same inactivity
+    /// window.
+    ///
+    /// Returns the number of seconds until the session expires, or
+    /// None if the session no longer exists or reached its endtime.
+    pub fn refresh(token: &str) -> EgResult<Option<u32>> {
+        let key = cache_key(token);
+
+        let cache_val = match Cache::get_global(&key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let mut timeout = cache_val["authtime"].int()?;
+
+        if let Some(endtime) = cache_val["endtime"].as_i64() {
+            let remaining = endtime - date::epoch_secs().floor() as i64;
+
+            if remaining <= 0 {
+                Cache::del_global(&key)?;
+                return Ok(None);
+            }
+
+            timeout = timeout.min(remaining);
+        }
+
+        Cache::set_global_for(&key, cache_val, timeout as u32)?;
+
+        Ok(Some(timeout as u32))
+    }
+
+    /// Remove the cached session for the token along with the
+    /// user's failed login counter.
+    ///
+    /// Returns false if no session exists for the token, e.g. the
+    /// user already logged out or the session expired.
+    pub fn delete(token: &str) -> EgResult<bool> {
+        let ses = match Session::from_cache(token)? {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        ses.remove()?;
+
+        if let Ok(user_id) = ses.user().id() {
+            Cache::del_global(&login_count_key(user_id))?;
+        }
+
+        Ok(true)
+    }
+
     /// Logout and remove the cached auth session.
     pub fn logout(client: &Client, token: &str) -> EgResult<()> {
         let mut ses = client.session("open-ils.auth");
@@ -273,17 +505,17 @@ impl Session {
             return Err(format!("Unexpected response: {}", evt).into());
         }
 
-        let token = evt.payload_mut()["authtoken"]
-            .take_string()
-            .ok_or_else(|| format!("Auth cache value has invalid authtoken"))?;
+        let AuthSession {
+            authtoken,
+            authtime,
+        } = AuthSession::try_from(evt.payload())?;
 
-        let authtime = evt.payload()["authtime"].int()? as u32;
         let user = evt.payload_mut()["userobj"].take();
 
         let mut auth_ses = Session {
             user,
-            token,
-            authtime: authtime,
+            token: authtoken,
+            authtime,
             workstation: None,
             endtime: None,
             reset_interval: None,
@@ -381,6 +613,14 @@ impl Session {
     pub fn user(&self) -> &EgValue {
         &self.user
     }
+
+    /// The token and duration of this session as returned to callers.
+    pub fn auth_session(&self) -> AuthSession {
+        AuthSession {
+            authtoken: self.token.to_string(),
+            authtime: self.authtime,
+        }
+    }
 }
 
 /// Returns the auth session duration in seconds for the provided
@@ -1,125 +1,558 @@
 use crate as eg;
-use chrono::Duration;
+use chrono::{Datelike, Duration};
+use eg::common::hours;
 use eg::common::org;
 use eg::common::penalty;
 use eg::common::settings::Settings;
 use eg::constants as C;
 use eg::date;
-use eg::editor::Editor;
-use eg::result::EgResult;
+use eg::editor::{Editor, OrderBy};
+use eg::result::{EgError, EgResult};
 use eg::util;
 use eg::EgValue;
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 
 const DAY_OF_SECONDS: i64 = 86400;
 
+/// Append a line of text to an existing bill/payment note.
+///
+/// Non-empty values are joined with a newline.  If either value is
+/// missing or empty, the other is returned as-is.
+///
+/// ```
+/// use evergreen::common::billing;
+///
+/// assert_eq!(billing::append_note(None, "VOIDED"), "VOIDED");
+/// assert_eq!(billing::append_note(Some(""), "VOIDED"), "VOIDED");
+/// assert_eq!(billing::append_note(Some("Damaged"), "VOIDED"), "Damaged\nVOIDED");
+/// assert_eq!(billing::append_note(Some("Damaged"), ""), "Damaged");
+/// assert_eq!(billing::append_note(None, ""), "");
+/// ```
+pub fn append_note(existing: Option<&str>, addition: &str) -> String {
+    match existing {
+        Some(e) if !e.is_empty() && !addition.is_empty() => format!("{e}\n{addition}"),
+        Some(e) if !e.is_empty() => e.to_string(),
+        _ => addition.to_string(),
+    }
+}
+
 /// Void a list of billings.
+///
+/// Voiding marks a bill as no longer owed, removing its amount from
+/// the transaction balance.  Adjusting a bill to zero (see
+/// adjust_bills_to_zero()) instead leaves the bill in place and
+/// applies an account adjustment payment against it.
+///
+/// Bills which are already voided, or whose balance has already been
+/// fully adjusted away, are skipped.  Voiding an adjusted bill would
+/// effectively credit the patron twice.
+///
+/// A reason code, e.g. "amnesty", is recorded at the start of the bill
+/// note (see void_note()) and must appear in the list of codes in the
+/// bill.void_reason_codes org setting for the transaction's billing
+/// location.
+///
+/// The requestor must have the VOID_BILLING permission at the billing
+/// location of each affected transaction.
 pub fn void_bills(
     editor: &mut Editor,
     billing_ids: &[i64], // money.billing.id
     maybe_note: Option<&str>,
+    reason_code: Option<&str>,
 ) -> EgResult<()> {
+    void_bills_with_recalc(editor, billing_ids, maybe_note, reason_code, true).map(|_| ())
+}
+
+/// Outcome of a void_bills_with_summary() call.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoidSummary {
+    /// Number of bills voided by this call.
+    pub voided: usize,
+    /// Bills skipped because they were already voided.
+    pub already_voided: usize,
+    /// Bills skipped because their balance was already adjusted away.
+    pub adjusted_to_zero: usize,
+    /// Requested bill IDs which match no bill.
+    pub not_found: usize,
+    /// Total amount of the bills voided by this call.
+    pub amount_voided: f64,
+    /// Sorted (user ID, org unit ID) pairs whose penalties are affected.
+    pub penalty_users: Vec<(i64, i64)>,
+}
+
+impl VoidSummary {
+    /// Describe the outcome for staff, with the voided amount
+    /// formatted for the currency and locale.
+    ///
+    /// ```
+    /// use evergreen::common::billing::VoidSummary;
+    ///
+    /// let summary = VoidSummary {
+    ///     voided: 8,
+    ///     already_voided: 1,
+    ///     adjusted_to_zero: 1,
+    ///     amount_voided: 1234.5,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(summary.describe("USD", "en-US"), "Voided 8 bills totaling $1,234.50; skipped 2");
+    /// assert_eq!(summary.describe("EUR", "de-DE"), "Voided 8 bills totaling 1.234,50 €; skipped 2");
+    /// ```
+    pub fn describe(&self, currency: &str, locale: &str) -> String {
+        let mut text = format!(
+            "Voided {} bills totaling {}",
+            self.voided,
+            util::format_money(self.amount_voided, currency, locale)
+        );
+
+        let skipped = self.already_voided + self.adjusted_to_zero;
+        if skipped > 0 {
+            text += &format!("; skipped {skipped}");
+        }
+
+        if self.not_found > 0 {
+            text += &format!("; {} not found", self.not_found);
+        }
+
+        text
+    }
+}
+
+/// Void bills per void_bills(), returning a summary of which bills
+/// were voided or skipped, e.g. "voided 8, skipped 2".
+///
+/// It's still an error if none of the bills exist.
+pub fn void_bills_with_summary(
+    editor: &mut Editor,
+    billing_ids: &[i64],
+    maybe_note: Option<&str>,
+    reason_code: Option<&str>,
+) -> EgResult<VoidSummary> {
+    check_bill_perm(editor, "VOID_BILLING", billing_ids)?;
+    void_bills_inner(editor, billing_ids, maybe_note, reason_code, true)
+}
+
+/// Void bills per void_bills(), returning the (user ID, org unit ID)
+/// pairs whose penalties are affected.
+///
+/// If recalc_penalties is false, penalties are not recalculated and
+/// the caller is responsible for doing so, e.g. via
+/// penalty::calculate_penalties_batch() once a bulk operation is done.
+pub fn void_bills_with_recalc(
+    editor: &mut Editor,
+    billing_ids: &[i64],
+    maybe_note: Option<&str>,
+    reason_code: Option<&str>,
+    recalc_penalties: bool,
+) -> EgResult<Vec<(i64, i64)>> {
+    check_bill_perm(editor, "VOID_BILLING", billing_ids)?;
+
+    let summary = void_bills_inner(
+        editor,
+        billing_ids,
+        maybe_note,
+        reason_code,
+        recalc_penalties,
+    )?;
+
+    Ok(summary.penalty_users)
+}
+
+/// Compile the text appended to a bill's note when it's voided.
+///
+/// ```
+/// use evergreen::common::billing;
+///
+/// assert_eq!(billing::void_note(Some("amnesty"), Some("Snow day")), "[VOID:amnesty] Snow day");
+/// assert_eq!(billing::void_note(Some("staff_error"), None), "[VOID:staff_error]");
+/// assert_eq!(billing::void_note(None, Some("Snow day")), "Snow day");
+/// assert_eq!(billing::void_note(None, None), "");
+/// ```
+pub fn void_note(reason_code: Option<&str>, maybe_note: Option<&str>) -> String {
+    match (reason_code, maybe_note) {
+        (Some(code), Some(note)) if !note.is_empty() => format!("[VOID:{code}] {note}"),
+        (Some(code), _) => format!("[VOID:{code}]"),
+        (None, note) => note.unwrap_or("").to_string(),
+    }
+}
+
+/// Returns the void reason code recorded in a bill note, if any.
+///
+/// ```
+/// use evergreen::common::billing;
+///
+/// assert_eq!(billing::void_reason_code("[VOID:amnesty] Snow day"), Some("amnesty"));
+/// assert_eq!(billing::void_reason_code("Damaged\n[VOID:staff_error]"), Some("staff_error"));
+/// assert_eq!(billing::void_reason_code("Snow day"), None);
+/// ```
+pub fn void_reason_code(note: &str) -> Option<&str> {
+    note.lines()
+        .rev()
+        .find_map(|line| line.strip_prefix("[VOID:")?.split_once(']'))
+        .map(|(code, _)| code)
+}
+
+/// Verify the reason code is allowed at the org unit.
+fn check_void_reason(settings: &mut Settings, code: &str, org_id: i64) -> EgResult<()> {
+    let allowed = settings.get_value_at_org("bill.void_reason_codes", org_id)?;
+
+    if allowed.members().any(|c| c.as_str() == Some(code)) {
+        Ok(())
+    } else {
+        Err(EgError::Validation(format!(
+            "Void reason code '{code}' is not allowed at org unit {org_id}"
+        )))
+    }
+}
+
+/// Verify the requestor has the permission at the billing location of
+/// each transaction the bills are linked to.
+fn check_bill_perm(editor: &mut Editor, perm: &str, bill_ids: &[i64]) -> EgResult<()> {
+    let bills = editor.search("mb", eg::hash! {"id": bill_ids})?;
+
+    let xact_ids: HashSet<i64> = bills
+        .iter()
+        .map(|b| b["xact"].int())
+        .collect::<EgResult<_>>()?;
+
+    let mut org_ids = Vec::new();
+    for xact_id in xact_ids {
+        org_ids.push(xact_org(editor, xact_id)?);
+    }
+
+    org_ids.sort();
+    org_ids.dedup();
+
+    for org_id in org_ids {
+        editor.check_perm(perm, org_id)?;
+    }
+
+    Ok(())
+}
+
+/// Void bills per void_bills(), returning a VoidSummary.
+///
+/// Skips the permission checks, e.g. for voids made by the system
+/// like voiding a returned item's deposit.
+///
+/// Penalties for the affected users are recalculated if calc_penalties
+/// is true.
+pub(crate) fn void_bills_inner(
+    editor: &mut Editor,
+    billing_ids: &[i64],
+    maybe_note: Option<&str>,
+    reason_code: Option<&str>,
+    calc_penalties: bool,
+) -> EgResult<VoidSummary> {
     let mut bills = editor.search("mb", eg::hash! {"id": billing_ids})?;
+    let mut settings = Settings::new(editor);
     let mut penalty_users: HashSet<(i64, i64)> = HashSet::new();
+    let mut bill_maps: HashMap<i64, Vec<BillPaymentMap>> = HashMap::new();
+    let mut summary = VoidSummary::default();
+    let mut voided_cents = 0;
 
     if bills.len() == 0 {
-        Err(format!("No such billings: {billing_ids:?}"))?;
+        Err(EgError::NotFound(format!(
+            "No such billings: {billing_ids:?}"
+        )))?;
+    }
+
+    let requested: HashSet<i64> = billing_ids.iter().copied().collect();
+    summary.not_found = requested.len() - bills.len();
+
+    // Check the reason code at every billing location before voiding
+    // anything, so a code which is not allowed at one of them cannot
+    // leave the other bills voided.
+    if let Some(code) = reason_code {
+        let mut xact_ids = HashSet::new();
+        for bill in bills.iter().filter(|b| !b["voided"].boolish()) {
+            xact_ids.insert(bill["xact"].int()?);
+        }
+
+        let mut org_ids = HashSet::new();
+        for xact_id in xact_ids {
+            org_ids.insert(xact_org(editor, xact_id)?);
+        }
+
+        for org_id in org_ids {
+            check_void_reason(&mut settings, code, org_id)?;
+        }
     }
 
     for mut bill in bills.drain(0..) {
         if bill["voided"].boolish() {
             log::debug!("Billing {} already voided.  Skipping", bill["id"]);
+            summary.already_voided += 1;
             continue;
         }
 
-        let xact = editor.retrieve("mbt", bill["xact"].clone())?;
-        let xact = match xact {
-            Some(x) => x,
-            None => Err(editor.die_event())?,
+        let xact_id = bill["xact"].int()?;
+
+        let maps = match bill_maps.entry(xact_id) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(bill_payment_map_for_xact(editor, xact_id)?),
         };
 
-        let xact_org = xact_org(editor, xact.id()?)?;
-        let xact_user = xact["usr"].int()?;
-        let xact_id = xact.id()?;
+        let adjusted_to_zero = maps
+            .iter()
+            .find(|m| m.bill["id"] == bill["id"])
+            .map(|m| util::fpdiff(m.bill_amount, m.adjustment_amount) <= 0.0)
+            .unwrap_or(false);
+
+        if adjusted_to_zero {
+            log::info!("Billing {} already adjusted to zero.  Skipping", bill["id"]);
+            summary.adjusted_to_zero += 1;
+            continue;
+        }
+
+        let xact = editor.fetch_xact_summary(xact_id)?;
 
-        penalty_users.insert((xact_user, xact_org));
+        penalty_users.insert((xact.usr, xact.billing_location));
 
         bill["voided"] = "t".into();
         bill["voider"] = editor.requestor_id()?.into();
         bill["void_time"] = "now".into();
 
-        if let Some(orig_note) = bill["note"].as_str() {
-            if let Some(new_note) = maybe_note {
-                bill["note"] = format!("{}\n{}", orig_note, new_note).into();
-            }
-        } else if let Some(new_note) = maybe_note {
-            bill["note"] = new_note.into();
+        if maybe_note.is_some() || reason_code.is_some() {
+            let new_note = void_note(reason_code, maybe_note);
+            bill["note"] = append_note(bill["note"].as_str(), &new_note).into();
         }
 
+        voided_cents += (bill["amount"].float()? * 100.0).round() as i64;
+
         editor.update(bill)?;
         check_open_xact(editor, xact_id)?;
+
+        summary.voided += 1;
     }
 
-    for (user_id, org_id) in penalty_users.iter() {
-        penalty::calculate_penalties(editor, *user_id, *org_id, None)?;
+    if summary.already_voided > 0 || summary.adjusted_to_zero > 0 {
+        log::info!(
+            "void_bills() skipped {} voided and {} adjusted-to-zero bills",
+            summary.already_voided,
+            summary.adjusted_to_zero
+        );
     }
 
-    Ok(())
+    summary.amount_voided = voided_cents as f64 / 100.0;
+    summary.penalty_users = penalty_users.into_iter().collect();
+    summary.penalty_users.sort();
+
+    if calc_penalties {
+        for (user_id, org_id) in summary.penalty_users.iter() {
+            penalty::calculate_penalties(editor, *user_id, *org_id, None)?;
+        }
+    }
+
+    Ok(summary)
 }
 
-/// Sets or clears xact_finish on a transaction as needed.
-pub fn check_open_xact(editor: &mut Editor, xact_id: i64) -> EgResult<()> {
-    let mut xact = match editor.retrieve("mbt", xact_id)? {
-        Some(x) => x,
-        None => Err(editor.die_event())?,
-    };
+/// Void unvoided overdue fines billed between start and end
+/// (inclusive) on transactions whose billing location is org_id or
+/// one of its descendants, e.g. for a fine amnesty covering a
+/// closure.
+///
+/// The requestor must have the VOID_BILLING permission at the billing
+/// location of each affected transaction.
+///
+/// Returns the number of bills voided.  See void_bills().
+pub fn void_overdue_bills_in_range(
+    editor: &mut Editor,
+    org_id: i64,
+    start: &date::EgDate,
+    end: &date::EgDate,
+    note: &str,
+) -> EgResult<usize> {
+    let org_ids = org::descendants(editor, org_id)?;
 
-    let mbts = match editor.retrieve("mbts", xact_id)? {
-        Some(m) => m,
-        None => Err(editor.die_event())?,
+    let query = eg::hash! {
+        "btype": C::BTYPE_OVERDUE_MATERIALS,
+        "voided": "f",
+        "billing_ts": {"between": [date::to_iso(start), date::to_iso(end)]},
+        "xact": {
+            "in": {
+                "select": {"mbtslv": ["id"]},
+                "from": "mbtslv",
+                "where": {"billing_location": org_ids},
+            }
+        },
     };
 
+    let bill_ids = editor
+        .search("mb", query)?
+        .iter()
+        .map(|b| b.id())
+        .collect::<EgResult<Vec<i64>>>()?;
+
+    if bill_ids.is_empty() {
+        return Ok(0);
+    }
+
+    check_bill_perm(editor, "VOID_BILLING", &bill_ids)?;
+
+    let count = void_bills_inner(editor, &bill_ids, Some(note), None, true)?.voided;
+
+    log::info!("Voided {count} overdue bills at org {org_id} between {start} and {end}");
+
+    Ok(count)
+}
+
+/// What check_open_xact() should do with a transaction's xact_finish.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XactFinishAction {
+    /// Leave xact_finish as-is.
+    Keep,
+    /// Set xact_finish, closing the transaction.
+    Close,
+    /// Clear xact_finish, re-opening the transaction.
+    Reopen,
+}
+
+/// Decide whether a transaction should be closed or re-opened.
+///
+/// Transactions close on a zero balance and re-open on a non-zero
+/// balance, except that a transaction linked to a circulation which
+/// is still open (no stop_fines) stays open regardless of balance:
+///
+/// | zero_owed | xact_open | no_circ_or_complete | action |
+/// |-----------|-----------|---------------------|--------|
+/// | true      | true      | true                | Close  |
+/// | true      | true      | false               | Keep   |
+/// | true      | false     | true                | Keep   |
+/// | true      | false     | false               | Reopen |
+/// | false     | true      | *                   | Keep   |
+/// | false     | false     | *                   | Reopen |
+pub fn xact_finish_action(
+    zero_owed: bool,
+    xact_open: bool,
+    no_circ_or_complete: bool,
+) -> XactFinishAction {
+    match (zero_owed, xact_open, no_circ_or_complete) {
+        (true, true, true) => XactFinishAction::Close,
+        (true, false, false) => XactFinishAction::Reopen,
+        (false, false, _) => XactFinishAction::Reopen,
+        _ => XactFinishAction::Keep,
+    }
+}
+
+/// Sets or clears xact_finish on a transaction as needed.
+///
+/// See xact_finish_action().
+pub fn check_open_xact(editor: &mut Editor, xact_id: i64) -> EgResult<()> {
+    let summary = editor.fetch_xact_summary(xact_id)?;
+
     // See if we have a completed circ.
     let no_circ_or_complete = match editor.retrieve("circ", xact_id)? {
         Some(c) => c["stop_fines"].is_string(), // otherwise is_null()
         None => true,
     };
 
-    let zero_owed = mbts["balance_owed"].float()? == 0.0;
-    let xact_open = xact["xact_finish"].is_null();
-
-    if zero_owed {
-        if xact_open && no_circ_or_complete {
-            // If nothing is owed on the transaction, but it is still open,
-            // and this transaction is not an open circulation, close it.
+    let zero_owed = summary.balance_owed == 0.0;
 
+    let finish = match xact_finish_action(zero_owed, summary.xact_open, no_circ_or_complete) {
+        XactFinishAction::Keep => return Ok(()),
+        XactFinishAction::Close => {
             log::info!("Closing completed transaction {xact_id} on zero balance");
-            xact["xact_finish"] = "now".into();
-            return editor.update(xact);
+            EgValue::from("now")
         }
-    } else if !xact_open {
-        // Transaction closed but money or refund still owed.
-
-        if !zero_owed && !xact_open {
-            log::info!("Re-opening transaction {xact_id} on non-zero balance");
-            xact["xact_finish"] = EgValue::Null;
-            return editor.update(xact);
+        XactFinishAction::Reopen => {
+            if zero_owed {
+                log::info!("Re-opening transaction {xact_id} for open circulation");
+            } else {
+                log::info!("Re-opening transaction {xact_id} on non-zero balance");
+            }
+            EgValue::Null
         }
-    }
+    };
 
-    Ok(())
+    let mut xact = retrieve_xact(editor, xact_id)?;
+    xact["xact_finish"] = finish;
+
+    editor.update(xact)
+}
+
+fn retrieve_xact(editor: &mut Editor, xact_id: i64) -> EgResult<EgValue> {
+    editor
+        .retrieve("mbt", xact_id)?
+        .ok_or_else(|| editor.die_event())
 }
 
 /// Returns the context org unit ID for a transaction (by ID).
 pub fn xact_org(editor: &mut Editor, xact_id: i64) -> EgResult<i64> {
-    // There's a view for that!
-    // money.billable_xact_summary_location_view
-    if let Some(sum) = editor.retrieve("mbtslv", xact_id)? {
-        sum["billing_location"].int()
-    } else {
-        Err(format!("No Such Transaction: {xact_id}").into())
+    Ok(editor.fetch_xact_summary(xact_id)?.billing_location)
+}
+
+/// Move the billings and payments for a closed transaction into the
+/// money.aged_billing and money.aged_payment tables once the
+/// transaction has been closed for longer than the
+/// history.money.retention_age org setting for its billing location.
+///
+/// Returns true if the transaction was aged.  Transactions which are
+/// too recent, or whose location has no retention age, are left alone.
+///
+/// Returns an Err on transactions which are still open or have a
+/// non-zero balance.
+///
+/// The rows are copied and deleted by the database function
+/// money.age_billings_and_payments_for_xact().
+///
+/// Aging is a system operation, run as the system user, which the
+/// Editor must have.  See Editor::with_system_perms().  Callers
+/// acting for staff must authorize the aging run as a whole.
+///
+/// Uses an externally managed Editor transaction.
+pub fn age_transaction(editor: &mut Editor, xact_id: i64) -> EgResult<bool> {
+    if !editor.in_transaction() {
+        Err(format!("Transaction required to age xact {xact_id}"))?;
+    }
+
+    editor.with_system_perms(|e| age_transaction_inner(e, xact_id))
+}
+
+fn age_transaction_inner(editor: &mut Editor, xact_id: i64) -> EgResult<bool> {
+    let summary = editor.fetch_xact_summary(xact_id)?;
+
+    if summary.xact_open {
+        Err(EgError::Validation(format!(
+            "Cannot age open transaction {xact_id}"
+        )))?;
+    }
+
+    if summary.balance_owed != 0.0 {
+        Err(EgError::Validation(format!(
+            "Cannot age transaction {xact_id} with balance {}",
+            summary.balance_owed
+        )))?;
+    }
+
+    let mut settings = Settings::new(editor);
+    let retention = settings
+        .get_value_at_org("history.money.retention_age", summary.billing_location)?
+        .clone();
+
+    let retention = match retention.as_str() {
+        Some(r) => r,
+        None => return Ok(false),
+    };
+
+    // Closed transactions always have an xact_finish value.
+    let finished = date::parse_datetime(summary.summary["xact_finish"].str()?)?;
+
+    if date::is_within_interval_of_now(&finished, retention)? {
+        return Ok(false);
     }
+
+    log::info!("Aging billings and payments for transaction {xact_id}");
+
+    let query = eg::hash! {
+        "from": ["money.age_billings_and_payments_for_xact", xact_id]
+    };
+
+    editor.json_query(query)?;
+
+    Ok(true)
 }
 
 /// Creates and returns a newly created money.billing.
@@ -137,22 +570,67 @@ pub fn create_bill(
 
     let note = maybe_note.unwrap_or("SYSTEM GENERATED");
 
-    let bill = eg::hash! {
-        "xact": xact_id,
-        "amount": amount,
-        "period_start": period_start,
-        "period_end": period_end,
-        "billing_type": btype_label,
-        "btype": btype_id,
-        "note": note,
-    };
+    let mut bill = editor.idl().new_object("mb")?;
 
-    let bill = EgValue::create("mb", bill)?;
-    editor.create(bill)
+    bill.set("xact", xact_id)?;
+    bill.set("amount", amount)?;
+    bill.set("period_start", period_start)?;
+    bill.set("period_end", period_end)?;
+    bill.set("billing_type", btype_label)?;
+    bill.set("btype", btype_id)?;
+    bill.set("note", note)?;
+
+    editor.create(bill.into())
+}
+
+/// Find the bills for a transaction, oldest first by billing_ts.
+///
+/// When btype is provided, only bills of that billing type are
+/// returned.  Voided bills are excluded unless include_voided is set.
+pub fn find_bills(
+    editor: &mut Editor,
+    xact_id: i64,
+    btype: Option<i64>,
+    include_voided: bool,
+) -> EgResult<Vec<EgValue>> {
+    find_bills_with_ops(
+        editor,
+        xact_id,
+        btype,
+        include_voided,
+        EgValue::new_object(),
+    )
+}
+
+/// find_bills() with additional search options, e.g. fleshing.
+///
+/// Any order_by in the options is replaced.
+fn find_bills_with_ops(
+    editor: &mut Editor,
+    xact_id: i64,
+    btype: Option<i64>,
+    include_voided: bool,
+    mut ops: EgValue,
+) -> EgResult<Vec<EgValue>> {
+    let mut query = eg::hash! {"xact": xact_id};
+
+    if let Some(btype) = btype {
+        query["btype"] = EgValue::from(btype);
+    }
+
+    if !include_voided {
+        query["voided"] = EgValue::from("f");
+    }
+
+    ops["order_by"] = OrderBy::new("mb", "billing_ts").then("id").to_value()?;
+
+    editor.search_with_ops("mb", query, ops)
 }
 
 /// Void a set of bills (by type) for a transaction or apply
 /// adjustments to zero the bills, depending on settings, etc.
+///
+/// Bills which are already voided are not considered.
 pub fn void_or_zero_bills_of_type(
     editor: &mut Editor,
     xact_id: i64,
@@ -160,19 +638,101 @@ pub fn void_or_zero_bills_of_type(
     btype_id: i64,
     for_note: &str,
 ) -> EgResult<()> {
+    void_or_zero_bills_of_type_inner(
+        editor,
+        xact_id,
+        context_org,
+        btype_id,
+        for_note,
+        true,
+        false,
+    )
+    .map(|_| ())
+}
+
+/// Apply void_or_zero_bills_of_type() to each of a user's open
+/// transactions, using the transaction's billing location as the
+/// context org.
+///
+/// Penalties are recalculated once per affected org unit after all
+/// transactions are processed, as the system user, which the Editor
+/// must have.  See penalty::calculate_penalties_batch().
+///
+/// The requestor must have the VOID_BILLING or ADJUST_BILLS
+/// permission, whichever applies, at each transaction's billing
+/// location.
+///
+/// Returns the IDs of the transactions which had bills of the type.
+pub fn void_or_zero_bills_of_type_for_user(
+    editor: &mut Editor,
+    user_id: i64,
+    btype_id: i64,
+    for_note: &str,
+) -> EgResult<Vec<i64>> {
+    let query = eg::hash! {"usr": user_id, "xact_finish": eg::NULL};
+    let ops = eg::hash! {"order_by": {"mbtslv": "id"}};
+
+    let xacts = editor.search_with_ops("mbtslv", query, ops)?;
+
+    let mut xact_ids = Vec::new();
+    let mut penalty_orgs = HashSet::new();
+
+    for xact in xacts.iter() {
+        let xact_id = xact.id()?;
+        let org_id = xact["billing_location"].int()?;
+
+        if void_or_zero_bills_of_type_inner(
+            editor, xact_id, org_id, btype_id, for_note, false, true,
+        )? {
+            xact_ids.push(xact_id);
+            penalty_orgs.insert(org_id);
+        }
+    }
+
+    log::info!(
+        "Void/Zero Bills of btype={btype_id} for user={user_id} affected xacts {xact_ids:?}"
+    );
+
+    // The bills were voided under the requestor's permissions.  The
+    // penalty changes which follow from that are the system's.
+    let pairs: Vec<(i64, i64)> = penalty_orgs.into_iter().map(|o| (user_id, o)).collect();
+    penalty::calculate_penalties_batch(editor, &pairs)?;
+
+    Ok(xact_ids)
+}
+
+/// Returns true if the transaction has unvoided bills of the
+/// requested type.
+///
+/// Permissions are only checked if check_perms is true.  System
+/// callers, e.g. lost item processing, skip them.
+fn void_or_zero_bills_of_type_inner(
+    editor: &mut Editor,
+    xact_id: i64,
+    context_org: i64,
+    btype_id: i64,
+    for_note: &str,
+    calc_penalties: bool,
+    check_perms: bool,
+) -> EgResult<bool> {
     log::info!("Void/Zero Bills for xact={xact_id} and btype={btype_id}");
 
     let mut settings = Settings::new(&editor);
-    let query = eg::hash! {"xact": xact_id, "btype": btype_id};
-    let bills = editor.search("mb", query)?;
+    let bills = find_bills(editor, xact_id, Some(btype_id), false)?;
 
     if bills.len() == 0 {
-        return Ok(());
+        return Ok(false);
     }
 
     let bill_ids: Vec<i64> = bills
         .iter()
-        .map(|b| b.id().expect("Billing has ID"))
+        .filter_map(|b| {
+            let id = util::try_int(&b["id"]);
+            if id.is_none() {
+                log::warn!("Skipping billing with invalid ID: {}", b.dump());
+            }
+            id
+        })
         .collect();
 
     // "lost" settings are checked first for backwards compat /
@@ -198,19 +758,63 @@ pub fn void_or_zero_bills_of_type(
     }
 
     if prohibit_neg_balance && !has_refundable {
+        if check_perms {
+            check_bill_perm(editor, "ADJUST_BILLS", &bill_ids)?;
+        }
         let note = format!("System: ADJUSTED {for_note}");
-        adjust_bills_to_zero(editor, bill_ids.as_slice(), &note)
+        adjust_bills_to_zero_inner(editor, bill_ids.as_slice(), &note, calc_penalties)?;
     } else {
+        if check_perms {
+            check_bill_perm(editor, "VOID_BILLING", &bill_ids)?;
+        }
         let note = format!("System: VOIDED {for_note}");
-        void_bills(editor, bill_ids.as_slice(), Some(&note))
+        void_bills_inner(
+            editor,
+            bill_ids.as_slice(),
+            Some(&note),
+            None,
+            calc_penalties,
+        )?;
     }
+
+    Ok(true)
 }
 
 /// Assumes all bills are linked to the same transaction.
+///
+/// The requestor must have the ADJUST_BILLS permission at the
+/// transaction's billing location.
 pub fn adjust_bills_to_zero(editor: &mut Editor, bill_ids: &[i64], note: &str) -> EgResult<()> {
+    adjust_bills_to_zero_with_recalc(editor, bill_ids, note, true).map(|_| ())
+}
+
+/// Adjust bills to zero per adjust_bills_to_zero(), returning the
+/// (user ID, org unit ID) pair whose penalties are affected.
+///
+/// If recalc_penalties is false, penalties are not recalculated and
+/// the caller is responsible for doing so, e.g. via
+/// penalty::calculate_penalties_batch().
+pub fn adjust_bills_to_zero_with_recalc(
+    editor: &mut Editor,
+    bill_ids: &[i64],
+    note: &str,
+    recalc_penalties: bool,
+) -> EgResult<Vec<(i64, i64)>> {
+    check_bill_perm(editor, "ADJUST_BILLS", bill_ids)?;
+    adjust_bills_to_zero_inner(editor, bill_ids, note, recalc_penalties)
+}
+
+/// Adjust bills to zero without checking permissions, e.g. for
+/// adjustments made by the system.
+fn adjust_bills_to_zero_inner(
+    editor: &mut Editor,
+    bill_ids: &[i64],
+    note: &str,
+    calc_penalties: bool,
+) -> EgResult<Vec<(i64, i64)>> {
     let mut bills = editor.search("mb", eg::hash! {"id": bill_ids})?;
     if bills.len() == 0 {
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     let xact_id = bills[0]["xact"].int()?;
@@ -236,7 +840,7 @@ pub fn adjust_bills_to_zero(editor: &mut Editor, bill_ids: &[i64], note: &str) -
         .reduce(|a, b| a + b)
     {
         Some(t) => t,
-        None => return Ok(()), // should never happen
+        None => return Ok(Vec::new()), // should never happen
     };
 
     for bill in bills.iter_mut() {
@@ -290,9 +894,12 @@ pub fn adjust_bills_to_zero(editor: &mut Editor, bill_ids: &[i64], note: &str) -
     check_open_xact(editor, xact_id)?;
 
     let org_id = xact_org(editor, xact_id)?;
-    penalty::calculate_penalties(editor, user_id, org_id, None)?;
 
-    Ok(())
+    if calc_penalties {
+        penalty::calculate_penalties(editor, user_id, org_id, None)?;
+    }
+
+    Ok(vec![(user_id, org_id)])
 }
 
 pub struct BillPaymentMap {
@@ -306,72 +913,138 @@ pub struct BillPaymentMap {
     pub bill_amount: f64,
     /// Total of account adjustments that apply to the bill.
     pub adjustment_amount: f64,
+    /// Total of the (non-adjustment) payments applied to the bill.
+    pub paid_amount: f64,
+}
+
+impl BillPaymentMap {
+    /// Amount still owed on the bill after adjustments and payments,
+    /// rounded to the cent.
+    pub fn net_balance(&self) -> f64 {
+        let cents = (self.bill_amount - self.adjustment_amount - self.paid_amount) * 100.0;
+        cents.round() / 100.0
+    }
 }
 
+/// How payments are allocated to bills when building a BillPaymentMap.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PaymentMapping {
+    /// Payments which exactly match a bill's remaining amount are
+    /// applied to that bill first, largest payments first.  Remaining
+    /// payments are then applied to the remaining bills.
+    ///
+    /// This most closely reflects what each payment was likely meant
+    /// to pay for, but the result depends on the order in which
+    /// payments of the same amount and bills with the same timestamp
+    /// are returned by the database, so it may vary between runs.
+    #[default]
+    AmountMatch,
+    /// Payments are applied oldest first to bills oldest first, with
+    /// ties broken by ID.
+    ///
+    /// Always produces the same allocation for the same data, which
+    /// makes it suitable for audits, at the cost of ignoring any
+    /// payment which was clearly made for a specific bill.
+    Deterministic,
+}
+
+/// Map a transaction's payments onto its bills using the
+/// PaymentMapping::AmountMatch strategy.
 pub fn bill_payment_map_for_xact(
     editor: &mut Editor,
     xact_id: i64,
 ) -> EgResult<Vec<BillPaymentMap>> {
-    let query = eg::hash! {
-        "xact": xact_id,
-        "voided": "f",
-    };
-    let ops = eg::hash! {
-        "order_by": {
-            "mb": {
-                "billing_ts": {
-                    "direction": "asc"
-                }
-            }
-        }
-    };
-
-    let mut bills = editor.search_with_ops("mb", query, ops)?;
+    bill_payment_map_for_xact_with_mapping(editor, xact_id, PaymentMapping::AmountMatch)
+}
 
-    let mut maps = Vec::new();
+/// Map a transaction's payments onto its bills.
+///
+/// Account adjustments are always applied to the bill they reference.
+pub fn bill_payment_map_for_xact_with_mapping(
+    editor: &mut Editor,
+    xact_id: i64,
+    mapping: PaymentMapping,
+) -> EgResult<Vec<BillPaymentMap>> {
+    let bills = find_bills(editor, xact_id, None, false)?;
 
-    if bills.len() == 0 {
-        return Ok(maps);
+    if bills.is_empty() {
+        return Ok(Vec::new());
     }
 
-    for bill in bills.drain(0..) {
-        let amount = bill["amount"].float()?;
+    let query = eg::hash! {"xact": xact_id, "voided": "f"};
 
-        let map = BillPaymentMap {
+    let ops = eg::hash! {
+        "flesh": 1,
+        "flesh_fields": {"mp": ["account_adjustment"]},
+        "order_by": OrderBy::new("mp", "payment_ts").to_value()?,
+    };
+
+    let payments = editor.search_with_ops("mp", query, ops)?;
+
+    map_payments_to_bills(bills, payments, mapping)
+}
+
+/// Sort key for ordering bills and payments by time, then ID.
+fn time_and_id(value: &EgValue, ts_field: &str) -> (Option<date::EgDate>, Option<i64>) {
+    let ts = value[ts_field]
+        .as_str()
+        .and_then(|ts| date::parse_datetime(ts).ok());
+
+    (ts, value["id"].as_int())
+}
+
+/// Allocate payments (with fleshed account_adjustment values) to
+/// bills per the mapping strategy.
+///
+/// Bills are expected in billing_ts order and payments in payment_ts
+/// order, as returned by bill_payment_map_for_xact_with_mapping().
+pub fn map_payments_to_bills(
+    mut bills: Vec<EgValue>,
+    mut payments: Vec<EgValue>,
+    mapping: PaymentMapping,
+) -> EgResult<Vec<BillPaymentMap>> {
+    if mapping == PaymentMapping::Deterministic {
+        bills.sort_by_key(|b| time_and_id(b, "billing_ts"));
+        payments.sort_by_key(|p| time_and_id(p, "payment_ts"));
+    }
+
+    let mut maps = Vec::new();
+
+    for bill in bills.drain(0..) {
+        let amount = bill["amount"].float()?;
+
+        let map = BillPaymentMap {
             bill: bill,
             adjustments: Vec::new(),
             payments: Vec::new(),
             bill_amount: amount,
             adjustment_amount: 0.00,
+            paid_amount: 0.00,
         };
 
         maps.push(map);
     }
 
-    let query = eg::hash! {"xact": xact_id, "voided": "f"};
-
-    let ops = eg::hash! {
-        "flesh": 1,
-        "flesh_fields": {"mp": ["account_adjustment"]},
-        "order_by": {"mp": {"payment_ts": {"direction": "asc"}}},
-    };
-
-    let mut payments = editor.search_with_ops("mp", query, ops)?;
-
     if payments.len() == 0 {
         // If we have no payments, return the unmodified maps.
         return Ok(maps);
     }
 
-    // Sort payments largest to lowest amount.
-    // This will come in handy later.
-    payments.sort_by(|a, b| {
-        if b["amount"].float().unwrap() < a["amount"].float().unwrap() {
-            Ordering::Less
-        } else {
-            Ordering::Greater
-        }
-    });
+    if mapping == PaymentMapping::AmountMatch {
+        // Sort payments largest to lowest amount.
+        // This will come in handy later.
+        // Ties are broken by payment ID so equal amounts always sort
+        // the same way.
+        payments.sort_by(|a, b| {
+            let a_amount = a["amount"].as_float().unwrap_or(0.0);
+            let b_amount = b["amount"].as_float().unwrap_or(0.0);
+
+            b_amount
+                .partial_cmp(&a_amount)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a["id"].as_int().cmp(&b["id"].as_int()))
+        });
+    }
 
     let mut used_adjustments: HashSet<i64> = HashSet::new();
 
@@ -380,13 +1053,19 @@ pub fn bill_payment_map_for_xact(
 
         // Find adjustments that apply to this individual billing and
         // has not already been accounted for.
-        let mut my_adjustments: Vec<&mut EgValue> = payments
-            .iter_mut()
-            .filter(|p| p["payment_type"].as_str().unwrap() == "account_adjustment")
-            .filter(|p| used_adjustments.contains(&p["account_adjustment"].id().unwrap()))
-            .filter(|p| p["account_adjustment"]["billing"] == bill["id"])
-            .map(|p| &mut p["account_adjustment"])
-            .collect();
+        let mut my_adjustments: Vec<&mut EgValue> = Vec::new();
+        for pay in payments.iter_mut() {
+            if util::json_string(&pay["payment_type"])? != "account_adjustment" {
+                continue;
+            }
+            if used_adjustments.contains(&pay["account_adjustment"].id()?) {
+                continue;
+            }
+            if pay["account_adjustment"]["billing"] != bill["id"] {
+                continue;
+            }
+            my_adjustments.push(&mut pay["account_adjustment"]);
+        }
 
         if my_adjustments.len() == 0 {
             continue;
@@ -424,64 +1103,77 @@ pub fn bill_payment_map_for_xact(
         }
     }
 
-    // Try to map payments to bills by amounts starting with the
-    // largest payments.
-    let mut used_payments: HashSet<i64> = HashSet::new();
-    for payment in payments.iter() {
-        let map = match maps
-            .iter_mut()
-            .filter(|m| {
-                m.bill["amount"] == payment["amount"]
-                    && !used_payments.contains(&payment.id().unwrap())
-            })
-            .next()
-        {
-            Some(m) => m,
-            None => continue,
-        };
-
-        map.bill["amount"] = EgValue::from(0.0);
-        map.payments.push(payment.clone());
-        used_payments.insert(payment.id()?);
-    }
+    // Adjustments were applied directly to their bills above.
+    payments.retain(|p| p["payment_type"].as_str() != Some("account_adjustment"));
+
+    if mapping == PaymentMapping::AmountMatch {
+        // Try to map payments to bills by amounts starting with the
+        // largest payments.
+        let mut used_payments: HashSet<i64> = HashSet::new();
+        for payment in payments.iter() {
+            let map = match maps
+                .iter_mut()
+                .filter(|m| {
+                    m.bill["amount"] == payment["amount"]
+                        && !used_payments.contains(&payment.id().unwrap())
+                })
+                .next()
+            {
+                Some(m) => m,
+                None => continue,
+            };
+
+            map.bill["amount"] = EgValue::from(0.0);
+            map.paid_amount = util::fpsum(map.paid_amount, payment["amount"].float()?);
+            map.payments.push(payment.clone());
+            used_payments.insert(payment.id()?);
+        }
 
-    // Remove the used payments from our working list.
-    let mut new_payments = Vec::new();
-    for pay in payments.drain(0..) {
-        if !used_payments.contains(&pay.id()?) {
-            new_payments.push(pay);
+        // Remove the used payments from our working list.
+        let mut new_payments = Vec::new();
+        for pay in payments.drain(0..) {
+            if !used_payments.contains(&pay.id()?) {
+                new_payments.push(pay);
+            }
         }
+        payments = new_payments;
     }
-    payments = new_payments;
+
     let mut used_payments = HashSet::new();
 
     // Map remaining bills to payments in whatever order.
-    for map in maps
-        .iter_mut()
-        .filter(|m| m.bill["amount"].float().unwrap() > 0.0)
-    {
-        let bill = &mut map.bill;
+    for map in maps.iter_mut() {
         // Loop over remaining unused / unmapped payments.
-        for pay in payments
-            .iter_mut()
-            .filter(|p| !used_payments.contains(&p.id().unwrap()))
-        {
-            loop {
-                let bill_amount = bill["amount"].float()?;
-                if bill_amount > 0.0 {
-                    let new_amount = util::fpdiff(bill_amount, pay["amount"].float()?);
-                    if new_amount < 0.0 {
-                        let mut new_payment = pay.clone();
-                        new_payment["amount"] = EgValue::from(bill_amount);
-                        bill["amount"] = EgValue::from(0.0);
-                        map.payments.push(new_payment);
-                        pay["amount"] = EgValue::from(-new_amount);
-                    } else {
-                        bill["amount"] = EgValue::from(new_amount);
-                        map.payments.push(pay.clone());
-                        used_payments.insert(pay.id()?);
-                    }
-                }
+        for pay in payments.iter_mut() {
+            let bill_amount = map.bill["amount"].float()?;
+
+            if bill_amount <= 0.0 {
+                break;
+            }
+
+            let pay_id = pay.id()?;
+
+            if used_payments.contains(&pay_id) {
+                continue;
+            }
+
+            let pay_amount = pay["amount"].float()?;
+            let new_amount = util::fpdiff(bill_amount, pay_amount);
+
+            if new_amount < 0.0 {
+                // The payment covers the rest of the bill.  Whatever's
+                // left of it goes toward the next bill.
+                let mut new_payment = pay.clone();
+                new_payment["amount"] = EgValue::from(bill_amount);
+                map.bill["amount"] = EgValue::from(0.0);
+                map.payments.push(new_payment);
+                map.paid_amount = util::fpsum(map.paid_amount, bill_amount);
+                pay["amount"] = EgValue::from(-new_amount);
+            } else {
+                map.bill["amount"] = EgValue::from(new_amount);
+                map.payments.push(pay.clone());
+                map.paid_amount = util::fpsum(map.paid_amount, pay_amount);
+                used_payments.insert(pay_id);
             }
         }
     }
@@ -489,6 +1181,504 @@ pub fn bill_payment_map_for_xact(
     Ok(maps)
 }
 
+/// Stored vs. computed balance for a transaction.  See audit_xact_balance().
+#[derive(Debug, Clone)]
+pub struct BalanceAudit {
+    pub xact_id: i64,
+    /// balance_owed from the money.billable_xact_summary view.
+    pub stored_balance: f64,
+    /// Total of the bill_payment_map_for_xact() net balances.
+    pub computed_balance: f64,
+    /// True if the stored and computed balances differ by a cent
+    /// or more.
+    pub discrepancy: bool,
+    /// True if the stored balance is negative and the transaction
+    /// has no payment within the bill.negative_balance_interval_default
+    /// org setting interval which could account for it.
+    pub unexpected_negative: bool,
+}
+
+/// Compare a transaction's stored balance to one computed by mapping
+/// its payments onto its bills.
+///
+/// Payments in excess of the billed amounts are not mapped to any
+/// bill, so a negative stored balance is always reported as a
+/// discrepancy.  Whether that's a problem is indicated by
+/// unexpected_negative.
+pub fn audit_xact_balance(editor: &mut Editor, xact_id: i64) -> EgResult<BalanceAudit> {
+    let summary = editor.fetch_xact_summary(xact_id)?;
+    let stored_balance = summary.balance_owed;
+
+    let computed_balance = bill_payment_map_for_xact(editor, xact_id)?
+        .iter()
+        .fold(0.0, |total, map| util::fpsum(total, map.net_balance()));
+
+    let discrepancy = (stored_balance * 100.0).round() != (computed_balance * 100.0).round();
+
+    let mut unexpected_negative = false;
+
+    if stored_balance < 0.0 {
+        let mut settings = Settings::new(editor);
+        let interval = settings
+            .get_value_at_org(
+                "bill.negative_balance_interval_default",
+                summary.billing_location,
+            )?
+            .clone();
+
+        unexpected_negative = match interval.as_str() {
+            Some(i) => !xact_has_payment_within(editor, xact_id, i)?,
+            None => true,
+        };
+    }
+
+    if discrepancy || unexpected_negative {
+        log::warn!(
+            "Balance audit for xact {xact_id}: stored={stored_balance} \
+            computed={computed_balance} unexpected_negative={unexpected_negative}"
+        );
+    }
+
+    Ok(BalanceAudit {
+        xact_id,
+        stored_balance,
+        computed_balance,
+        discrepancy,
+        unexpected_negative,
+    })
+}
+
+/// Compute a transaction's balance from its bill, payment, and
+/// account adjustment rows, ignoring money.billable_xact_summary,
+/// e.g. to check a balance_owed which has drifted.
+///
+/// The balance is the unvoided bills less their unvoided adjustments,
+/// counted as generate_fines_for_xact() counts them, less the
+/// unvoided payments which are not account adjustments.
+pub fn recompute_balance(editor: &mut Editor, xact_id: i64) -> EgResult<f64> {
+    recompute_balance_with_repair(editor, xact_id, false)
+}
+
+/// Same as recompute_balance(), but if repair is true and the
+/// summary is writable, the computed balance replaces a stored
+/// balance_owed which differs.
+///
+/// The summary is writable when the IDL marks the mbts class neither
+/// readonly nor virtual and it has no source_definition, e.g. when
+/// it's mapped to money.materialized_billable_xact_summary instead
+/// of the stock view.  Otherwise nothing is repaired.  Repairs
+/// require an active transaction.
+pub fn recompute_balance_with_repair(
+    editor: &mut Editor,
+    xact_id: i64,
+    repair: bool,
+) -> EgResult<f64> {
+    let ops = eg::hash! {
+        "flesh": 1,
+        "flesh_fields": {"mb": ["adjustments"]},
+    };
+
+    let bills = find_bills_with_ops(editor, xact_id, None, true, ops)?;
+    let billed = net_billed_cents(&bills)?;
+
+    let query = eg::hash! {
+        "xact": xact_id,
+        "voided": "f",
+        "payment_type": {"!=": "account_adjustment"},
+    };
+
+    let mut paid = 0.0;
+    for pay in editor.search("mp", query)? {
+        paid += pay["amount"].float()? * 100.0;
+    }
+
+    let balance = (billed - paid).round() / 100.0;
+
+    if !repair {
+        return Ok(balance);
+    }
+
+    if !xact_summary_is_writable()? {
+        log::warn!("Cannot repair balance_owed for xact {xact_id}: mbts is read-only");
+        return Ok(balance);
+    }
+
+    let mut summary = editor
+        .retrieve("mbts", xact_id)?
+        .ok_or_else(|| editor.die_event())?;
+
+    let stored = summary["balance_owed"].float()?;
+
+    if (stored * 100.0).round() != (balance * 100.0).round() {
+        log::warn!("Repairing balance_owed for xact {xact_id}: stored={stored} computed={balance}");
+
+        summary["balance_owed"] = EgValue::from(balance);
+        editor.update(summary)?;
+    }
+
+    Ok(balance)
+}
+
+/// True if the IDL allows updating the mbts class.
+fn xact_summary_is_writable() -> EgResult<bool> {
+    let class = eg::idl::get_class("mbts")?;
+
+    Ok(!class.is_read_only() && !class.is_virtual() && class.source_definition().is_none())
+}
+
+/// Order in which apply_payment() pays down open transactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    /// Earliest xact_start first.
+    OldestFirst,
+    /// Largest balance owed first.
+    LargestFirst,
+}
+
+/// Returns the IDL class for a payment type, e.g. "cash_payment".
+///
+/// Account adjustments are not payments in this sense and are not
+/// included.
+///
+/// ```
+/// use evergreen::common::billing;
+///
+/// assert_eq!(billing::payment_class("cash_payment"), Some("mcp"));
+/// assert_eq!(billing::payment_class("account_adjustment"), None);
+/// ```
+pub fn payment_class(payment_type: &str) -> Option<&'static str> {
+    let class = match payment_type {
+        "cash_payment" => "mcp",
+        "check_payment" => "mckp",
+        "credit_card_payment" => "mccp",
+        "debit_card_payment" => "mdcp",
+        "credit_payment" => "mcrp",
+        "forgive_payment" => "mfp",
+        "goods_payment" => "mgp",
+        "work_payment" => "mwp",
+        _ => return None,
+    };
+
+    Some(class)
+}
+
+/// Split a payment across transactions, given as (xact_id, balance_owed)
+/// pairs in the order they should be paid.
+///
+/// Returns the (xact_id, amount) to pay toward each transaction and
+/// the amount left over once every balance is paid.  Amounts are
+/// calculated in cents.
+///
+/// ```
+/// use evergreen::common::billing;
+///
+/// let (splits, leftover) = billing::distribute_payment(5.0, &[(1, 3.0), (2, 4.0)]);
+/// assert_eq!(splits, [(1, 3.0), (2, 2.0)]);
+/// assert_eq!(leftover, 0.0);
+/// ```
+pub fn distribute_payment(amount: f64, balances: &[(i64, f64)]) -> (Vec<(i64, f64)>, f64) {
+    let mut remaining = (amount * 100.0).round() as i64;
+    let mut splits = Vec::new();
+
+    for (xact_id, balance) in balances {
+        if remaining <= 0 {
+            break;
+        }
+
+        let owed = (balance * 100.0).round() as i64;
+
+        if owed <= 0 {
+            continue;
+        }
+
+        let paid = owed.min(remaining);
+        remaining -= paid;
+
+        splits.push((*xact_id, paid as f64 / 100.0));
+    }
+
+    (splits, remaining as f64 / 100.0)
+}
+
+/// Apply a single payment across a user's open transactions.
+///
+/// Any amount left over once all balances are paid goes toward the
+/// last transaction paid, leaving it with a negative balance, unless
+/// the bill.prohibit_negative_balance_default org setting is set for
+/// that transaction's billing location, in which case an Err is
+/// returned.
+///
+/// Returns the created payment objects.
+///
+/// Uses an externally managed Editor transaction.
+pub fn apply_payment(
+    editor: &mut Editor,
+    user_id: i64,
+    amount: f64,
+    payment_type: &str,
+    distribution: Distribution,
+) -> EgResult<Vec<EgValue>> {
+    let classname = payment_class(payment_type)
+        .ok_or_else(|| EgError::Validation(format!("Invalid payment type: {payment_type}")))?;
+
+    if amount <= 0.0 {
+        Err(EgError::Validation(format!(
+            "Payment amount must be positive: {amount}"
+        )))?;
+    }
+
+    let query = eg::hash! {
+        "usr": user_id,
+        "xact_finish": eg::NULL,
+        "balance_owed": {">": 0},
+    };
+
+    let mut xacts = editor.search("mbts", query)?;
+
+    match distribution {
+        Distribution::OldestFirst => xacts.sort_by(|a, b| {
+            a["xact_start"]
+                .as_str()
+                .cmp(&b["xact_start"].as_str())
+                .then_with(|| a["id"].as_int().cmp(&b["id"].as_int()))
+        }),
+        Distribution::LargestFirst => xacts.sort_by(|a, b| {
+            let a_owed = a["balance_owed"].as_float().unwrap_or(0.0);
+            let b_owed = b["balance_owed"].as_float().unwrap_or(0.0);
+            b_owed
+                .partial_cmp(&a_owed)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a["id"].as_int().cmp(&b["id"].as_int()))
+        }),
+    }
+
+    let balances = xacts
+        .iter()
+        .map(|x| Ok((x.id()?, x["balance_owed"].float()?)))
+        .collect::<EgResult<Vec<(i64, f64)>>>()?;
+
+    let (mut splits, leftover) = distribute_payment(amount, &balances);
+
+    if leftover > 0.0 {
+        let last = match splits.last_mut() {
+            Some(s) => s,
+            None => Err(EgError::NotFound(format!(
+                "User {user_id} has no open transactions to pay"
+            )))?,
+        };
+
+        let org_id = xact_org(editor, last.0)?;
+        let mut settings = Settings::new(editor);
+
+        if settings
+            .get_value_at_org("bill.prohibit_negative_balance_default", org_id)?
+            .boolish()
+        {
+            Err(EgError::Validation(format!(
+                "Payment of {amount} exceeds the amount owed by {leftover}"
+            )))?;
+        }
+
+        last.1 = util::fpsum(last.1, leftover);
+    }
+
+    let mut payments = Vec::new();
+    let mut penalty_orgs = HashSet::new();
+
+    for (xact_id, pay_amount) in splits {
+        let mut payment = eg::hash! {
+            "amount": pay_amount,
+            "amount_collected": pay_amount,
+            "xact": xact_id,
+            "accepting_usr": editor.requestor_id()?,
+            "payment_ts": "now",
+        };
+
+        if let Some(ws_id) = editor.requestor_ws_id() {
+            payment["cash_drawer"] = EgValue::from(ws_id);
+        }
+
+        let payment = editor.create(EgValue::create(classname, payment)?)?;
+
+        check_open_xact(editor, xact_id)?;
+        penalty_orgs.insert(xact_org(editor, xact_id)?);
+
+        payments.push(payment);
+    }
+
+    for org_id in penalty_orgs {
+        penalty::calculate_penalties(editor, user_id, org_id, None)?;
+    }
+
+    Ok(payments)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LedgerEntryType {
+    Bill,
+    Payment,
+    Adjustment,
+}
+
+/// One charge, payment, or adjustment in a transaction's history.
+#[derive(Debug, Clone)]
+pub struct LedgerEntry {
+    pub entry_type: LedgerEntryType,
+    /// ID of the money.billing, money.payment, or
+    /// money.account_adjustment row.
+    pub id: i64,
+    /// billing_ts for bills, payment_ts for payments and adjustments.
+    pub timestamp: date::EgDate,
+    /// Always positive.  Bills add to the balance, payments and
+    /// adjustments reduce it.
+    pub amount: f64,
+    /// Voided entries are included, but do not affect the balance.
+    pub voided: bool,
+    /// Billing type label or payment type.
+    pub label: String,
+    pub note: Option<String>,
+    /// Balance owed after this entry is applied.
+    pub running_balance: f64,
+}
+
+impl LedgerEntry {
+    /// Describe the entry for staff as one line of a ledger, with
+    /// amounts formatted for the currency and locale.
+    ///
+    /// ```
+    /// use evergreen::common::billing::{LedgerEntry, LedgerEntryType};
+    /// use evergreen::date;
+    ///
+    /// let entry = LedgerEntry {
+    ///     entry_type: LedgerEntryType::Bill,
+    ///     id: 1,
+    ///     timestamp: date::parse_datetime("2024-03-01T12:00:00-0500").unwrap(),
+    ///     amount: 0.25,
+    ///     voided: false,
+    ///     label: "Overdue materials".to_string(),
+    ///     note: None,
+    ///     running_balance: 1000.25,
+    /// };
+    ///
+    /// assert_eq!(
+    ///     entry.describe("USD", "en-US"),
+    ///     "2024-03-01 Overdue materials $0.25 (balance $1,000.25)"
+    /// );
+    /// assert_eq!(
+    ///     entry.describe("EUR", "de-DE"),
+    ///     "2024-03-01 Overdue materials 0,25 € (balance 1.000,25 €)"
+    /// );
+    /// ```
+    pub fn describe(&self, currency: &str, locale: &str) -> String {
+        let sign = match self.entry_type {
+            LedgerEntryType::Bill => 1.0,
+            _ => -1.0,
+        };
+
+        format!(
+            "{} {} {} (balance {}){}",
+            date::to_iso_date(&self.timestamp),
+            self.label,
+            util::format_money(sign * self.amount, currency, locale),
+            util::format_money(self.running_balance, currency, locale),
+            if self.voided { " [voided]" } else { "" }
+        )
+    }
+}
+
+fn ledger_entry(
+    entry_type: LedgerEntryType,
+    row: &EgValue,
+    ts_field: &str,
+    label: &str,
+) -> EgResult<LedgerEntry> {
+    Ok(LedgerEntry {
+        entry_type,
+        id: row.id()?,
+        timestamp: date::parse_datetime(row[ts_field].str()?)?,
+        amount: row["amount"].float()?,
+        voided: row["voided"].boolish(),
+        label: label.to_string(),
+        note: row["note"].as_str().map(|n| n.to_string()),
+        running_balance: 0.0,
+    })
+}
+
+/// Chronological history of all bills, payments, and adjustments on
+/// a transaction, including voided entries, with a running balance.
+///
+/// Entries with the same timestamp are ordered bills first.
+pub fn xact_ledger(editor: &mut Editor, xact_id: i64) -> EgResult<Vec<LedgerEntry>> {
+    let mut entries = Vec::new();
+
+    for bill in find_bills(editor, xact_id, None, true)? {
+        let label = bill["billing_type"].as_str().unwrap_or("");
+        entries.push(ledger_entry(
+            LedgerEntryType::Bill,
+            &bill,
+            "billing_ts",
+            label,
+        )?);
+    }
+
+    // Adjustments are also visible as payments.  Collect them from
+    // money.account_adjustment instead.
+    let query = eg::hash! {
+        "xact": xact_id,
+        "payment_type": {"!=": "account_adjustment"},
+    };
+
+    for pay in editor.search("mp", query)? {
+        let label = pay["payment_type"].as_str().unwrap_or("");
+        entries.push(ledger_entry(
+            LedgerEntryType::Payment,
+            &pay,
+            "payment_ts",
+            label,
+        )?);
+    }
+
+    for adj in editor.search("maa", eg::hash! {"xact": xact_id})? {
+        entries.push(ledger_entry(
+            LedgerEntryType::Adjustment,
+            &adj,
+            "payment_ts",
+            "account_adjustment",
+        )?);
+    }
+
+    entries.sort_by(|a, b| {
+        a.timestamp
+            .cmp(&b.timestamp)
+            .then_with(|| {
+                let a_bill = a.entry_type == LedgerEntryType::Bill;
+                let b_bill = b.entry_type == LedgerEntryType::Bill;
+                b_bill.cmp(&a_bill)
+            })
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    // Track the balance in cents to avoid accumulating floating
+    // point errors over long histories.
+    let mut balance: i64 = 0;
+
+    for entry in entries.iter_mut() {
+        if !entry.voided {
+            let cents = (entry.amount * 100.0).round() as i64;
+
+            if entry.entry_type == LedgerEntryType::Bill {
+                balance += cents;
+            } else {
+                balance -= cents;
+            }
+        }
+
+        entry.running_balance = balance as f64 / 100.0;
+    }
+
+    Ok(entries)
+}
+
 /// Returns true if the most recent payment toward a transaction
 /// occurred within now minus the specified interval.
 pub fn xact_has_payment_within(
@@ -503,7 +1693,7 @@ pub fn xact_has_payment_within(
 
     let ops = eg::hash! {
         "limit": 1,
-        "order_by": {"mp": "payment_ts DESC"}
+        "order_by": OrderBy::new("mp", "payment_ts").desc().to_value()?,
     };
 
     let last_payment = editor.search_with_ops("mp", query, ops)?;
@@ -518,17 +1708,33 @@ pub fn xact_has_payment_within(
     let payment_ts = &payment["payment_ts"].as_str().unwrap();
     let payment_dt = date::parse_datetime(payment_ts)?;
 
-    let window_start = date::subtract_interval(date::now(), interval)?;
-
-    Ok(payment_dt > window_start)
+    date::is_within_interval_of_now(&payment_dt, interval)
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BillableTransactionType {
     Circ,
     Reservation,
 }
 
+/// Fine rules and evaluation options for generate_fines_for_xact().
+#[derive(Debug, Clone)]
+pub struct FineParams {
+    pub due_date: String,
+    /// Org unit whose settings, timezone, and hours apply, i.e. the
+    /// pickup library for reservations.
+    pub circ_lib: i64,
+    pub recurring_fine: f64,
+    pub fine_interval: String,
+    pub max_fine: f64,
+    pub grace_period: Option<String>,
+    pub xact_type: BillableTransactionType,
+    /// Evaluate fines as of this time instead of now.
+    pub as_of: Option<date::EgDate>,
+    /// Overrides the circ_lib's lib.timezone setting.
+    pub timezone: Option<String>,
+}
+
 pub fn generate_fines_for_resv(editor: &mut Editor, resv_id: i64) -> EgResult<()> {
     let resv = editor
         .retrieve("bresv", resv_id)?
@@ -539,147 +1745,436 @@ pub fn generate_fines_for_resv(editor: &mut Editor, resv_id: i64) -> EgResult<()
         None => return Ok(()),
     };
 
-    generate_fines_for_xact(
-        editor,
-        resv_id,
-        resv["end_time"].as_str().unwrap(),
-        resv["pickup_lib"].int()?,
-        resv["fine_amount"].float()?,
-        fine_interval,
-        resv["max_fine"].float()?,
-        None, // grace period
-        BillableTransactionType::Reservation,
-    )
+    let params = FineParams {
+        due_date: resv["end_time"].str()?.to_string(),
+        circ_lib: resv["pickup_lib"].int()?,
+        recurring_fine: resv["fine_amount"].float()?,
+        fine_interval: fine_interval.to_string(),
+        max_fine: resv["max_fine"].float()?,
+        grace_period: None,
+        xact_type: BillableTransactionType::Reservation,
+        as_of: None,    // as of now
+        timezone: None, // pickup lib timezone
+    };
+
+    generate_fines_for_xact(editor, resv_id, &params)
 }
 
-pub fn generate_fines_for_circ(editor: &mut Editor, circ_id: i64) -> EgResult<()> {
+/// Generate overdue fines for a circulation.
+///
+/// If as_of is provided, fines are calculated as if the generator
+/// ran at that time instead of now, e.g. to catch up on fines missed
+/// during an outage.  See generate_fines_for_xact().
+///
+/// If timezone is provided, fine periods are calculated in that
+/// timezone instead of the circ_lib's lib.timezone setting.
+pub fn generate_fines_for_circ(
+    editor: &mut Editor,
+    circ_id: i64,
+    as_of: Option<date::EgDate>,
+    timezone: Option<&str>,
+) -> EgResult<()> {
+    let mut settings = Settings::new(editor);
+    generate_fines_for_circ_with_settings(editor, circ_id, as_of, timezone, &mut settings)
+        .map(|_| ())
+}
+
+/// Implements generate_fines_for_circ(), returning the number of
+/// bills created.
+fn generate_fines_for_circ_with_settings(
+    editor: &mut Editor,
+    circ_id: i64,
+    as_of: Option<date::EgDate>,
+    timezone: Option<&str>,
+    settings: &mut Settings,
+) -> EgResult<usize> {
     log::info!("Generating fines for circulation {circ_id}");
 
     let circ = editor
         .retrieve("circ", circ_id)?
         .ok_or_else(|| editor.die_event())?;
 
-    generate_fines_for_xact(
-        editor,
-        circ_id,
-        circ["due_date"].as_str().unwrap(),
-        circ["circ_lib"].int()?,
-        circ["recurring_fine"].float()?,
-        circ["fine_interval"].str()?,
-        circ["max_fine"].float()?,
-        circ["grace_period"].as_str(),
-        BillableTransactionType::Circ,
-    )
+    if let Some(stop_fines) = circ["stop_fines"].as_str() {
+        // E.g. LONGOVERDUE circs no longer accrue overdue fines.  Any
+        // long-overdue costs are billed when the circ is marked.
+        log::info!("Circulation {circ_id} has stop_fines={stop_fines}; skipping");
+        return Ok(0);
+    }
+
+    // A misconfigured fine interval would otherwise parse as zero and
+    // silently skip fine generation.
+    date::interval_to_seconds_strict(circ["fine_interval"].str()?)?;
+
+    let circ_lib = circ["circ_lib"].int()?;
+
+    settings.fetch_values_for_org(circ_lib, &FINE_SETTINGS)?;
+
+    let params = FineParams {
+        due_date: circ["due_date"].str()?.to_string(),
+        circ_lib,
+        recurring_fine: circ["recurring_fine"].float()?,
+        fine_interval: circ["fine_interval"].str()?.to_string(),
+        max_fine: circ["max_fine"].float()?,
+        grace_period: circ["grace_period"].as_str().map(|g| g.to_string()),
+        xact_type: BillableTransactionType::Circ,
+        as_of,
+        timezone: timezone.map(|tz| tz.to_string()),
+    };
+
+    generate_fines_for_xact_with_settings(editor, circ_id, &params, settings)
 }
 
-pub fn generate_fines_for_xact(
+/// Org unit settings read while generating fines for a transaction.
+const FINE_SETTINGS: [&str; 6] = [
+    "lib.timezone",
+    "circ.fines.charge_when_closed",
+    "circ.fines.truncate_to_max_fine",
+    "circ.grace.extend",
+    "circ.grace.extend.into_closed",
+    "circ.grace.extend.all",
+];
+
+/// Outcome of a generate_fines_for_circs() run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FineRunSummary {
+    /// Circulations whose fines were generated, including those which
+    /// needed no new bills.
+    pub processed: usize,
+    /// Number of bills created across all circulations.
+    pub bills_created: usize,
+    /// Circ ID and error message of each circulation which failed.
+    pub errors: Vec<(i64, String)>,
+}
+
+/// Generate overdue fines for a batch of circulations, e.g. from the
+/// nightly fine generator.
+///
+/// All circulations share one Settings instance, so each org unit's
+/// fine settings are fetched once per run.
+///
+/// Each circulation is processed within its own savepoint.  A circ
+/// which fails has its changes rolled back and is recorded in the
+/// summary errors, then the run moves on to the next circ.  A
+/// transaction is started if the editor has none.  Changes are not
+/// committed; that's up to the caller.
+///
+/// If the transaction is dropped mid-run, e.g. by a request timeout,
+/// the changes for every circ processed so far within it are lost.
+/// Those circs are recorded as errors and the run continues in a new
+/// transaction.
+///
+/// Each fine period is billed at most once, so a run which was
+/// interrupted may be repeated with the same list of circs.
+///
+/// When the editor has neither a requestor nor a system user, e.g.
+/// the nightly fine run, the system user configured via the
+/// SYSTEM_USER_SETTING org setting at the root org unit is applied
+/// so that voids made during the run have a voider.
+pub fn generate_fines_for_circs(editor: &mut Editor, circ_ids: &[i64]) -> EgResult<FineRunSummary> {
+    generate_fines_for_circs_as_of(editor, circ_ids, None)
+}
+
+/// Generate fines per generate_fines_for_circs(), calculated as if
+/// the generator ran at as_of instead of now.
+///
+/// See generate_fines_for_circ().
+pub fn generate_fines_for_circs_as_of(
     editor: &mut Editor,
-    xact_id: i64,
-    due_date: &str,
-    circ_lib: i64,
-    mut recurring_fine: f64,
-    fine_interval: &str,
-    mut max_fine: f64,
-    grace_period: Option<&str>,
-    xact_type: BillableTransactionType,
-) -> EgResult<()> {
-    let mut settings = Settings::new(&editor);
+    circ_ids: &[i64],
+    as_of: Option<date::EgDate>,
+) -> EgResult<FineRunSummary> {
+    const SAVEPOINT: &str = "generate_fines_for_circ";
 
-    let fine_interval_secs = date::interval_to_seconds(fine_interval)?;
-    let mut grace_period = date::interval_to_seconds(grace_period.unwrap_or("0s"))?;
-    let now = date::now();
+    if !editor.in_transaction() {
+        editor.xact_begin()?;
+    }
 
-    if fine_interval_secs == 0 || recurring_fine * 100.0 == 0.0 || max_fine * 100.0 == 0.0 {
-        log::info!(
-            "Fine generator skipping transaction {xact_id}
-            due to 0 fine interval, 0 fine rate, or 0 max fine."
-        );
-        return Ok(());
+    if editor.requestor().is_none() && editor.system_user().is_none() {
+        let root_id = org::root_id(editor)?;
+        editor.apply_system_user_setting(root_id)?;
     }
 
-    // TODO add the bit about reservation time zone offsets
+    let mut settings = Settings::new(editor);
+    let mut summary = FineRunSummary::default();
 
-    let query = eg::hash! {
-        "xact": xact_id,
-        "btype": C::BTYPE_OVERDUE_MATERIALS,
-    };
+    // Circs whose changes live in the current transaction, along
+    // with the number of bills created for each.
+    let mut pending: Vec<(i64, usize)> = Vec::new();
+
+    for circ_id in circ_ids.iter().copied() {
+        if !editor.in_transaction() {
+            editor.xact_begin()?;
+        }
+
+        editor.savepoint_set(SAVEPOINT)?;
+
+        match generate_fines_for_circ_with_settings(editor, circ_id, as_of, None, &mut settings) {
+            Ok(count) => {
+                editor.savepoint_release(SAVEPOINT)?;
+                pending.push((circ_id, count));
+            }
+            Err(e) => {
+                log::error!("Fine generation failed for circulation {circ_id}: {e}");
+
+                summary.errors.push((circ_id, e.to_string()));
+
+                if editor.in_transaction() {
+                    // Failing to roll back leaves the transaction
+                    // unusable for the remaining circs.
+                    editor.savepoint_rollback(SAVEPOINT)?;
+                    editor.savepoint_release(SAVEPOINT)?;
+                    continue;
+                }
+
+                // The transaction was dropped, e.g. on a request
+                // timeout, taking the changes for the earlier circs
+                // with it.  Record those and carry on in a new one.
+                log::warn!(
+                    "Transaction lost while generating fines for circulation {circ_id}; \
+                    {} earlier circulations will need another run",
+                    pending.len()
+                );
+
+                for (lost_id, _) in pending.drain(..) {
+                    summary.errors.push((
+                        lost_id,
+                        format!(
+                            "Changes lost with the dropped transaction \
+                            while processing circulation {circ_id}"
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    summary.processed = pending.len();
+    summary.bills_created = pending.iter().map(|(_, count)| count).sum();
+
+    log::info!(
+        "Fine generation processed {} circulations, created {} bills, with {} errors",
+        summary.processed,
+        summary.bills_created,
+        summary.errors.len()
+    );
+
+    Ok(summary)
+}
 
+/// Returns the overdue fines for a transaction which were billed
+/// after the provided due date, most recent first, along with the
+/// current overdue fine total in cents, which includes all fines
+/// regardless of billing time.
+///
+/// Only fines after the current due date are returned.  Otherwise,
+/// when a due date changes, the fine generator would back-fill
+/// billings for a period of time where the item was not technically
+/// overdue.
+fn overdue_fines_since(
+    editor: &mut Editor,
+    xact_id: i64,
+    due_date: &str,
+) -> EgResult<(Vec<EgValue>, f64)> {
     let ops = eg::hash! {
         "flesh": 1,
         "flesh_fields": {"mb": ["adjustments"]},
-        "order_by": {"mb": "billing_ts DESC"},
     };
 
-    let mut fines = editor.search_with_ops("mb", query, ops)?;
-    let mut current_fine_total = 0.0;
+    let mut fines =
+        find_bills_with_ops(editor, xact_id, Some(C::BTYPE_OVERDUE_MATERIALS), true, ops)?;
+
+    // Most recent first.
+    fines.reverse();
+
+    let current_fine_total = net_billed_cents(&fines)?;
+
+    log::info!(
+        "Fine total for transaction {xact_id} is {:.2}",
+        current_fine_total / 100.0
+    );
+
+    let mut recent_fines: Vec<EgValue> = Vec::new();
+    for fine in fines.drain(..) {
+        if util::json_string(&fine["billing_ts"])?.as_str() > due_date {
+            recent_fines.push(fine);
+        }
+    }
+
+    Ok((recent_fines, current_fine_total))
+}
+
+/// Latest period_end of a set of fines.
+///
+/// Fines with no period_end, e.g. those created by hand, count as
+/// ending at their billing time.
+fn last_period_end(fines: &[EgValue]) -> EgResult<Option<date::EgDate>> {
+    let mut last = None;
+
     for fine in fines.iter() {
-        if !fine["voided"].boolish() {
-            current_fine_total += fine["amount"].float()? * 100.0;
+        let end = match fine["period_end"].as_str() {
+            Some(e) => e,
+            None => fine["billing_ts"].str()?,
+        };
+
+        let end = date::parse_datetime(end)?;
+
+        if last.map(|l| end > l).unwrap_or(true) {
+            last = Some(end);
+        }
+    }
+
+    Ok(last)
+}
+
+/// Net amount in cents of bills fleshed with their adjustments:
+/// unvoided bill amounts less unvoided adjustments.
+///
+/// This is the fine total of generate_fines_for_xact() and the billed
+/// side of recompute_balance(), so the two agree.
+fn net_billed_cents(bills: &[EgValue]) -> EgResult<f64> {
+    let mut total = 0.0;
+
+    for bill in bills.iter() {
+        if !bill["voided"].boolish() {
+            total += bill["amount"].float()? * 100.0;
         }
-        for adj in fine["adjustments"].members() {
+        for adj in bill["adjustments"].members() {
             if !adj["voided"].boolish() {
-                current_fine_total -= adj["amount"].float()? * 100.0;
+                total -= adj["amount"].float()? * 100.0;
             }
         }
     }
 
-    log::info!(
-        "Fine total for transaction {xact_id} is {:.2}",
-        current_fine_total / 100.0
-    );
+    Ok(total)
+}
+
+/// Generate overdue fines for a billable transaction.
+///
+/// A fine is billed for each fine period which ends before now,
+/// starting after the last period already billed.  Repeated runs,
+/// including runs for an earlier as_of time, bill each period at most
+/// once.
+///
+/// as_of replaces the current time as the point at which fines are
+/// evaluated.  Bills generated for an as_of time carry it as their
+/// billing time.
+///
+/// No fines are generated until the grace period following the due
+/// date has passed.  This applies to grace periods of any length,
+/// e.g. "4 hours", though only grace periods of a day or more are
+/// extended to cover closed days.  See extend_grace_period().
+///
+/// The current time, due date, and fine periods are all evaluated in
+/// the timezone of the circ_lib (lib.timezone), or the provided
+/// timezone, so day boundaries used for grace extension and closed
+/// day checks fall on the branch's local days instead of the
+/// server's.
+pub fn generate_fines_for_xact(
+    editor: &mut Editor,
+    xact_id: i64,
+    params: &FineParams,
+) -> EgResult<()> {
+    let mut settings = Settings::new(editor);
+    generate_fines_for_xact_with_settings(editor, xact_id, params, &mut settings).map(|_| ())
+}
+
+/// Implements generate_fines_for_xact(), returning the number of
+/// bills created.
+fn generate_fines_for_xact_with_settings(
+    editor: &mut Editor,
+    xact_id: i64,
+    params: &FineParams,
+    settings: &mut Settings,
+) -> EgResult<usize> {
+    let due_date = params.due_date.as_str();
+    let circ_lib = params.circ_lib;
+    let fine_interval = params.fine_interval.as_str();
+    let as_of = params.as_of;
+
+    let mut recurring_fine = params.recurring_fine;
+    let mut max_fine = params.max_fine;
+
+    let fine_interval_secs = date::interval_to_seconds(fine_interval)?;
+    let mut grace_period =
+        date::interval_to_seconds(params.grace_period.as_deref().unwrap_or("0s"))?;
+
+    if fine_interval_secs == 0 || recurring_fine * 100.0 == 0.0 || max_fine * 100.0 == 0.0 {
+        log::info!(
+            "Fine generator skipping transaction {xact_id}
+            due to 0 fine interval, 0 fine rate, or 0 max fine."
+        );
+        return Ok(0);
+    }
+
+    let timezone = match params.timezone.as_ref() {
+        Some(tz) => tz.to_string(),
+        None => settings
+            .get_value_at_org("lib.timezone", circ_lib)?
+            .as_str()
+            .unwrap_or("local")
+            .to_string(),
+    };
+
+    // Translate all of our dates to the timezone of the affected org
+    // unit (the pickup library for reservations) so the day / day of
+    // week information used for grace period extension and closed
+    // date checks matches the branch's calendar, which can vary
+    // across timezones.
+    let now = date::set_timezone(as_of.unwrap_or_else(date::now), &timezone)?;
 
     // Determine the billing period of the next fine to generate
-    // based on the billing time of the most recent fine *which
-    // occurred after the current due date*.  Otherwise, when a
-    // due date changes, the fine generator will back-fill billings
-    // for a period of time where the item was not technically overdue.
-    let fines: Vec<EgValue> = fines
-        .drain(..)
-        .filter(|f| f["billing_ts"].as_str().unwrap() > due_date)
-        .collect();
+    // based on the most recent fine period billed *after the current
+    // due date*.  Keying on the billed period instead of the billing
+    // time means a repeated run bills nothing new.
+    let (fines, mut current_fine_total) = overdue_fines_since(editor, xact_id, due_date)?;
 
-    let due_date_dt = date::parse_datetime(due_date)?;
+    let due_date_dt = date::set_timezone(date::parse_datetime(due_date)?, &timezone)?;
 
-    // First fine in the list (if we have one) will be the most recent.
-    let last_fine_dt = match fines.get(0) {
-        Some(f) => date::parse_datetime(&f["billing_ts"].as_str().unwrap())?,
+    // End of the first fine period to bill.
+    let first_period_end = match last_period_end(&fines)? {
+        Some(end) => {
+            let end = date::set_timezone(end, &timezone)?;
+
+            if end > now {
+                log::warn!("Transaction {xact_id} has future last fine date?");
+                return Ok(0);
+            }
+
+            date::add_interval(end, fine_interval)?
+        }
         None => {
             grace_period = extend_grace_period(
                 editor,
                 circ_lib,
                 grace_period,
                 due_date_dt,
-                Some(&mut settings),
+                Some(&mut *settings),
             )?;
 
-            // If we have no fines, due date is the last fine time.
+            // If we have no fines, the first period ends on the
+            // due date.
             due_date_dt
         }
     };
 
-    if last_fine_dt > now {
-        log::warn!("Transaction {xact_id} has futuer last fine date?");
-        return Ok(());
-    }
-
-    if last_fine_dt == due_date_dt
+    if fines.is_empty()
         && grace_period > 0
-        && now.timestamp() < due_date_dt.timestamp() - grace_period
+        && now.timestamp() < due_date_dt.timestamp() + grace_period
     {
         // We have no fines yet and we have a grace period and we
         // are still within the grace period.  New fines not yet needed.
 
         log::info!("Stil within grace period for circ {xact_id}");
-        return Ok(());
+        return Ok(0);
     }
 
-    // Generate fines for each past interval, including the one we are inside.
-    let range = now.timestamp() - last_fine_dt.timestamp();
+    // Generate fines for each period ending before now.
+    let range = now.timestamp() - first_period_end.timestamp();
     let pending_fine_count = (range as f64 / fine_interval_secs as f64).ceil() as i64;
 
-    if pending_fine_count == 0 {
+    if pending_fine_count <= 0 {
         // No fines to generate.
-        return Ok(());
+        return Ok(0);
     }
 
     recurring_fine *= 100.0;
@@ -693,33 +2188,39 @@ pub fn generate_fines_for_xact(
         .get_value_at_org("circ.fines.truncate_to_max_fine", circ_lib)?
         .boolish();
 
-    let timezone = match settings
-        .get_value_at_org("lib.timezone", circ_lib)?
-        .as_str()
-    {
-        Some(tz) => tz,
-        None => "local",
+    let calendar = if skip_closed_check {
+        None
+    } else {
+        Some(load_fine_calendar(
+            editor,
+            circ_lib,
+            &first_period_end,
+            pending_fine_count,
+            fine_interval_secs,
+        )?)
     };
 
+    let mut bills_created = 0;
+
     for slot in 0..pending_fine_count {
         if current_fine_total >= max_fine {
-            if xact_type == BillableTransactionType::Circ {
-                log::info!("Max fines reached for circulation {xact_id}");
+            // No more fines once the max is reached, including when
+            // the max was already met before we got here.
+            log::info!("Max fines reached for transaction {xact_id}");
 
+            if params.xact_type == BillableTransactionType::Circ {
                 if let Some(mut circ) = editor.retrieve("circ", xact_id)? {
-                    circ["stop_fines"] = EgValue::from("MAXFINES");
-                    circ["stop_fines_time"] = EgValue::from("now");
+                    circ["stop_fines"] = EgValue::from(C::CIRC_STOP_FINES_MAXFINES);
+                    circ["stop_fines_time"] = EgValue::from(date::to_iso(&now));
                     editor.update(circ)?;
-                    break;
                 }
             }
+
+            break;
         }
 
-        // Translate the last fine time to the timezone of the affected
-        // org unit so the org::next_open_date() calculation below
-        // can use the correct day / day of week information, which can
-        // vary across timezones.
-        let mut period_end = date::set_timezone(last_fine_dt, timezone)?;
+        // first_period_end is already in the org unit's timezone.
+        let mut period_end = first_period_end;
 
         let mut current_bill_count = slot;
         while current_bill_count > 0 {
@@ -728,17 +2229,24 @@ pub fn generate_fines_for_xact(
         }
 
         let duration = Duration::try_seconds(fine_interval_secs - 1)
-            .ok_or_else(|| format!("Invalid interval {fine_interval}"))?;
+            .ok_or_else(|| EgError::Validation(format!("Invalid interval {fine_interval}")))?;
 
         let period_start = period_end - duration;
 
-        if !skip_closed_check {
-            let org_open_data = org::next_open_date(editor, circ_lib, &period_end)?;
-            if org_open_data != org::OrgOpenState::Open {
-                // Avoid adding a fine if the org unit is closed
-                // on the day of the period_end date.
-                continue;
-            }
+        // Avoid adding a fine if the org unit is closed at the
+        // period_end date, unless configured to charge anyway.
+        // Skipped periods simply produce no billing for their slot.
+        let is_closed = match calendar.as_ref() {
+            Some(c) => !c.is_open_at(&period_end)?,
+            None => false,
+        };
+
+        if is_closed {
+            log::debug!(
+                "Skipping fine for xact {xact_id}; org {circ_lib} closed on {}",
+                date::to_iso(&period_end)
+            );
+            continue;
         }
 
         // The billing amount for this billing normally ought to be
@@ -753,18 +2261,26 @@ pub fn generate_fines_for_xact(
 
         current_fine_total += this_billing_amount;
 
+        let btype = C::BillingType::OverdueMaterials;
+
         let bill = eg::hash! {
             xact: xact_id,
             note: "System Generated Overdue Fine",
-            billing_type: "Overdue materials",
-            btype: C::BTYPE_OVERDUE_MATERIALS,
+            billing_type: btype.label(),
+            btype: i64::from(btype),
             amount: this_billing_amount / 100.0,
             period_start: date::to_iso(&period_start),
             period_end: date::to_iso(&period_end),
         };
 
-        let bill = EgValue::create("mb", bill)?;
+        let mut bill = EgValue::create("mb", bill)?;
+
+        if as_of.is_some() {
+            bill["billing_ts"] = EgValue::from(date::to_iso(&now));
+        }
+
         editor.create(bill)?;
+        bills_created += 1;
     }
 
     let xact = editor.retrieve("mbt", xact_id)?.unwrap(); // required
@@ -772,18 +2288,291 @@ pub fn generate_fines_for_xact(
 
     penalty::calculate_penalties(editor, user_id, circ_lib, None)?;
 
-    Ok(())
+    Ok(bills_created)
+}
+
+/// Predict when the next overdue fine for a circulation will post,
+/// e.g. for "your next fine will post on ..." patron notices.
+///
+/// Returns None if the circ is not overdue, is checked in or has
+/// stopped accruing fines, or has reached its max fine.
+///
+/// The returned time may be slightly in the past when a fine is due
+/// but the fine generator has not yet run.  Grace period extension
+/// and closed-day handling match generate_fines_for_xact().
+pub fn next_fine_time(editor: &mut Editor, circ_id: i64) -> EgResult<Option<date::EgDate>> {
+    let circ = editor
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| editor.die_event())?;
+
+    if !circ["checkin_time"].is_null() || !circ["stop_fines"].is_null() {
+        return Ok(None);
+    }
+
+    let due_date = circ["due_date"].str()?;
+    let due_date_dt = date::parse_datetime(due_date)?;
+    let now = date::now();
+
+    if due_date_dt > now {
+        return Ok(None);
+    }
+
+    let circ_lib = circ["circ_lib"].int()?;
+    let fine_interval = circ["fine_interval"].str()?;
+    let fine_interval_secs = date::interval_to_seconds(fine_interval)?;
+    let recurring_fine = circ["recurring_fine"].float()? * 100.0;
+    let max_fine = circ["max_fine"].float()? * 100.0;
+
+    if fine_interval_secs == 0 || recurring_fine == 0.0 || max_fine == 0.0 {
+        return Ok(None);
+    }
+
+    let (fines, current_fine_total) = overdue_fines_since(editor, circ_id, due_date)?;
+
+    if current_fine_total >= max_fine {
+        return Ok(None);
+    }
+
+    let mut settings = Settings::new(editor);
+
+    let timezone = settings
+        .get_value_at_org("lib.timezone", circ_lib)?
+        .as_str()
+        .unwrap_or("local")
+        .to_string();
+
+    // Days are evaluated in the branch's timezone, as they are by the
+    // fine generator.
+    let due_date_dt = date::set_timezone(due_date_dt, &timezone)?;
+
+    // The generator bills the period after the last one billed.
+    let mut next_fine = match last_period_end(&fines)? {
+        Some(end) => date::add_interval(date::set_timezone(end, &timezone)?, fine_interval)?,
+        None => {
+            let grace_period =
+                date::interval_to_seconds(circ["grace_period"].as_str().unwrap_or("0s"))?;
+
+            let grace_period = extend_grace_period(
+                editor,
+                circ_lib,
+                grace_period,
+                due_date_dt,
+                Some(&mut settings),
+            )?;
+
+            let grace = Duration::try_seconds(grace_period).ok_or_else(|| {
+                EgError::Validation(format!("Invalid grace period: {grace_period}"))
+            })?;
+
+            due_date_dt + grace
+        }
+    };
+
+    let skip_closed_check = settings
+        .get_value_at_org("circ.fines.charge_when_closed", circ_lib)?
+        .boolish();
+
+    if skip_closed_check {
+        return Ok(Some(next_fine));
+    }
+
+    // The generator produces no fine for closed periods, so the next
+    // fine lands on the first fine period where the org is open.
+    let calendar = load_fine_calendar(
+        editor,
+        circ_lib,
+        &next_fine,
+        org::OPEN_DATE_SCAN_DAYS as i64,
+        fine_interval_secs,
+    )?;
+
+    for _ in 0..org::OPEN_DATE_SCAN_DAYS {
+        if calendar.is_open_at(&next_fine)? {
+            return Ok(Some(next_fine));
+        }
+        next_fine = date::add_interval(next_fine, fine_interval)?;
+    }
+
+    log::warn!("Org {circ_lib} has no open fine periods; cannot predict next fine for {circ_id}");
+
+    Ok(None)
+}
+
+/// Load the org unit's hours and closed dates covering a run of fine
+/// periods, the first ending at first_period_end.
+fn load_fine_calendar(
+    editor: &mut Editor,
+    org_id: i64,
+    first_period_end: &date::EgDate,
+    periods: i64,
+    fine_interval_secs: i64,
+) -> EgResult<hours::OrgCalendar> {
+    // The extra day absorbs DST shifts across the range.
+    let days = (periods.max(1) * fine_interval_secs) / DAY_OF_SECONDS + 2;
+
+    hours::OrgCalendar::load(editor, org_id, first_period_end, days as u32)
 }
 
+/// Extend a grace period (in seconds) by whole days to cover days
+/// where the org unit is closed, per the circ.grace.extend settings.
+///
+/// Grace periods shorter than one day are returned as-is.  They still
+/// delay the first fine in generate_fines_for_xact().
+///
+/// A day counts as closed only when the org unit is closed for all
+/// of its open hours that day, via hours of operation and/or closed
+/// dates.  Days with partial closures (closed afternoons, half-day
+/// holidays) end the extension.
 pub fn extend_grace_period(
+    editor: &mut Editor,
+    context_org: i64,
+    grace_period: i64,
+    due_date: date::EgDate,
+    settings: Option<&mut Settings>,
+) -> EgResult<i64> {
+    extend_grace_period_within(
+        editor,
+        context_org,
+        grace_period,
+        due_date,
+        settings,
+        org::OPEN_DATE_SCAN_DAYS,
+    )
+}
+
+/// Same as extend_grace_period(), but inspects at most scan_days days
+/// of closed date / hours data looking for the next open day.
+///
+/// If no open day is found within the limit, a warning is logged
+/// and the grace period is returned unextended.
+pub fn extend_grace_period_within(
+    editor: &mut Editor,
+    context_org: i64,
+    grace_period: i64,
+    due_date: date::EgDate,
+    settings: Option<&mut Settings>,
+    scan_days: u32,
+) -> EgResult<i64> {
+    extend_grace_period_inner(
+        editor,
+        context_org,
+        grace_period,
+        due_date,
+        settings,
+        scan_days,
+        None,
+    )
+}
+
+/// One step in the calculation of a grace period extension.
+///
+/// See explain_grace_extension()
+#[derive(Debug, Clone)]
+pub struct GraceStep {
+    /// The day examined, for steps concerning a specific day.
+    pub date: Option<date::EgDate>,
+    /// Seconds this step added to the grace period.
+    pub added_seconds: i64,
+    pub description: String,
+}
+
+/// How a grace period was, or was not, extended.
+#[derive(Debug, Clone)]
+pub struct GraceExplanation {
+    pub original_seconds: i64,
+    pub extended_seconds: i64,
+    pub steps: Vec<GraceStep>,
+}
+
+/// Explain how extend_grace_period() arrives at its result, listing
+/// the settings consulted and each closed day which contributed to
+/// the extension, e.g. to answer "why did this item's grace period
+/// extend to Tuesday?"
+///
+/// This is diagnostic only.  The extended_seconds value always
+/// matches extend_grace_period().
+pub fn explain_grace_extension(
+    editor: &mut Editor,
+    circ_lib: i64,
+    grace_period: i64,
+    due_date: date::EgDate,
+) -> EgResult<GraceExplanation> {
+    let mut steps = Vec::new();
+
+    let extended_seconds = extend_grace_period_inner(
+        editor,
+        circ_lib,
+        grace_period,
+        due_date,
+        None,
+        org::OPEN_DATE_SCAN_DAYS,
+        Some(&mut steps),
+    )?;
+
+    Ok(GraceExplanation {
+        original_seconds: grace_period,
+        extended_seconds,
+        steps,
+    })
+}
+
+/// Describe why the org unit is closed on the day of the date/time.
+fn describe_closed_day(editor: &mut Editor, org_id: i64, day: &date::EgDate) -> EgResult<String> {
+    let weekday = day.date_naive().weekday().num_days_from_sunday() as i64;
+
+    if hours::closed_weekdays(editor, org_id)?.contains(&weekday) {
+        return Ok(format!(
+            "Closed on {} per hours of operation",
+            day.format("%A %Y-%m-%d")
+        ));
+    }
+
+    let reasons = hours::closed_dates_covering(editor, org_id, day)?
+        .iter()
+        .map(|cd| cd["reason"].as_str().unwrap_or("").to_string())
+        .collect::<Vec<String>>();
+
+    Ok(format!(
+        "Closed on {} per closed dates: {}",
+        day.format("%A %Y-%m-%d"),
+        reasons.join(", ")
+    ))
+}
+
+fn grace_note(
+    trace: &mut Option<&mut Vec<GraceStep>>,
+    date: Option<date::EgDate>,
+    added_seconds: i64,
+    description: &str,
+) {
+    if let Some(steps) = trace.as_mut() {
+        steps.push(GraceStep {
+            date,
+            added_seconds,
+            description: description.to_string(),
+        });
+    }
+}
+
+/// Implements extend_grace_period_within(), recording each step to
+/// trace when provided.
+fn extend_grace_period_inner(
     editor: &mut Editor,
     context_org: i64,
     grace_period: i64,
     mut due_date: date::EgDate,
     settings: Option<&mut Settings>,
+    scan_days: u32,
+    mut trace: Option<&mut Vec<GraceStep>>,
 ) -> EgResult<i64> {
     if grace_period < DAY_OF_SECONDS {
         // Only extended for >1day intervals.
+        grace_note(
+            &mut trace,
+            None,
+            0,
+            "Grace periods shorter than one day are not extended",
+        );
         return Ok(grace_period);
     }
 
@@ -802,6 +2591,7 @@ pub fn extend_grace_period(
 
     if !extend {
         // No extension configured.
+        grace_note(&mut trace, None, 0, "circ.grace.extend is not enabled");
         return Ok(grace_period);
     }
 
@@ -813,6 +2603,12 @@ pub fn extend_grace_period(
         // Merge closed dates trailing the grace period into the grace period.
         // Note to self: why add exactly one day?
         due_date = date::add_interval(due_date, "1 day")?;
+        grace_note(
+            &mut trace,
+            None,
+            0,
+            "circ.grace.extend.into_closed: the scan starts one day later",
+        );
     }
 
     let extend_all = settings
@@ -822,33 +2618,91 @@ pub fn extend_grace_period(
     if extend_all {
         // Start checking the day after the item was due.
         due_date = date::add_interval(due_date, "1 day")?;
+        grace_note(
+            &mut trace,
+            None,
+            0,
+            "circ.grace.extend.all: the scan starts the day after the due date",
+        );
     } else {
         // Jump to the end of the grace period.
         due_date = due_date
-            + Duration::try_seconds(grace_period)
-                .ok_or_else(|| format!("Invalid duration seconds: {grace_period}"))?;
+            + Duration::try_seconds(grace_period).ok_or_else(|| {
+                EgError::Validation(format!("Invalid duration seconds: {grace_period}"))
+            })?;
+        grace_note(
+            &mut trace,
+            None,
+            0,
+            "The scan starts at the end of the grace period",
+        );
     }
 
-    let org_open_data = org::next_open_date(editor, context_org, &due_date.into())?;
-
-    let closed_until = match org_open_data {
-        org::OrgOpenState::Never | org::OrgOpenState::Open => {
-            // No need to extend the grace period if the org unit
-            // is never open or it's open on the calculated due date;
-            return Ok(grace_period);
-        }
-        org::OrgOpenState::OpensOnDate(d) => d,
-    };
+    // Fetch the hours and closed dates for the whole scan at once.
+    let calendar = hours::OrgCalendar::load(editor, context_org, &due_date, scan_days)?;
+
+    // Whole-day fast path: an org unit closed every day of the week
+    // never opens, so there is nothing to extend into.
+    if calendar.closed_weekdays()?.len() == 7 {
+        grace_note(
+            &mut trace,
+            None,
+            0,
+            "Closed every day of the week per hours of operation; not extended",
+        );
+        return Ok(grace_period);
+    }
 
     // Extend the due date out (using seconds instead of whole days),
-    // until the due date occurs on the next open day.
+    // until the due date occurs on a day where the org unit is open
+    // for at least part of the day.  Partial closures, e.g. a closed
+    // afternoon, do not extend the grace period.
+    let scan_start = due_date;
     let mut new_grace_period = grace_period;
-    while due_date.date_naive() < closed_until.date_naive() {
-        new_grace_period += DAY_OF_SECONDS;
-        due_date = date::add_interval(due_date, "1 day")?;
+    let mut scanned = 0;
+
+    while scanned < scan_days {
+        if calendar.is_open_on_day(&due_date)? {
+            grace_note(
+                &mut trace,
+                Some(due_date),
+                0,
+                "Open; the extension ends here",
+            );
+            return Ok(new_grace_period);
+        }
+
+        let mut skip_days = 1;
+
+        if trace.is_some() {
+            let description = describe_closed_day(editor, context_org, &due_date)?;
+            grace_note(&mut trace, Some(due_date), DAY_OF_SECONDS, &description);
+        } else if let Some(end) = calendar.closed_through(&due_date)? {
+            // Days before the one the closing ends on are closed
+            // throughout, so jump straight to its last day.
+            let days = (end.date_naive() - due_date.date_naive()).num_days();
+            skip_days = days.clamp(1, (scan_days - scanned) as i64) as u32;
+        }
+
+        new_grace_period += DAY_OF_SECONDS * skip_days as i64;
+        due_date = date::add_interval(due_date, &format!("{skip_days} days"))?;
+        scanned += skip_days;
     }
 
-    Ok(new_grace_period)
+    log::warn!(
+        "No open day found for org {context_org} within {scan_days} days; \
+        grace period for due date {} may be inaccurate",
+        date::to_iso(&scan_start)
+    );
+
+    grace_note(
+        &mut trace,
+        None,
+        grace_period - new_grace_period,
+        "No open day found within the scan limit; the days above are discarded",
+    );
+
+    Ok(grace_period)
 }
 
 pub fn void_or_zero_overdues(
@@ -911,9 +2765,10 @@ pub fn void_or_zero_overdues(
     }
 
     if force_zero || (!force_void && prohibit_neg_balance && !has_refundable) {
-        adjust_bills_to_zero(editor, bill_ids.as_slice(), note.unwrap_or(""))
+        adjust_bills_to_zero_inner(editor, bill_ids.as_slice(), note.unwrap_or(""), true)
+            .map(|_| ())
     } else {
-        void_bills(editor, bill_ids.as_slice(), note)
+        void_bills_inner(editor, bill_ids.as_slice(), note, None, true).map(|_| ())
     }
 }
 
@@ -945,7 +2800,7 @@ fn calc_min_void_date(
     )?;
 
     let grace_duration = Duration::try_seconds(grace_period)
-        .ok_or_else(|| format!("Invalid duration seconds: {grace_period}"))?;
+        .ok_or_else(|| EgError::Validation(format!("Invalid duration seconds: {grace_period}")))?;
 
     if backdate < due_date + grace_duration {
         log::info!("Backdate {backdate} is within grace period, voiding all");
@@ -608,7 +608,13 @@ impl Circulator<'_> {
             // Caller suggests we void.  Verify settings allow it.
             if self.settings.get_value("circ.void_item_deposit")?.boolish() {
                 let bill_id = deposit.id()?;
-                billing::void_bills(self.editor(), &[bill_id], Some("DEPOSIT ITEM RETURNED"))?;
+                billing::void_bills_inner(
+                    self.editor(),
+                    &[bill_id],
+                    Some("DEPOSIT ITEM RETURNED"),
+                    None,
+                    true,
+                )?;
             }
         } else {
             let mut evt = EgEvent::new("ITEM_DEPOSIT_PAID");
@@ -953,7 +959,7 @@ impl Circulator<'_> {
         };
         if is_circ {
             if self.circ.as_ref().unwrap()["stop_fines"].is_null() {
-                billing::generate_fines_for_circ(self.editor(), xact_id)?;
+                billing::generate_fines_for_circ(self.editor(), xact_id, None, None)?;
 
                 // Update our copy of the circ after billing changes,
                 // which may apply a stop_fines value.
@@ -818,9 +818,9 @@ impl Circulator<'_> {
 
         let start_date = date::set_timezone(start_date, timezone)?;
 
-        let dur_secs = date::interval_to_seconds(&policy.duration)?;
-
-        let mut due_date = start_date + Duration::from_secs(dur_secs as u64);
+        // Apply whole days in the circ lib's timezone so the due time
+        // does not drift an hour across daylight saving transitions.
+        let mut due_date = date::add_interval_dst_aware(start_date, &policy.duration, timezone)?;
 
         if let Some(hdd) = policy.hard_due_date.as_ref() {
             let cdate_str = hdd["ceiling_date"].as_str().unwrap();
@@ -1174,21 +1174,19 @@ impl Circulator<'_> {
             }
         }
 
-        let mut btype = C::BTYPE_DEPOSIT;
-        let mut btype_label = C::BTYPE_LABEL_DEPOSIT;
-
-        if is_rental {
-            btype = C::BTYPE_RENTAL;
-            btype_label = C::BTYPE_LABEL_RENTAL;
-        }
+        let btype = if is_rental {
+            C::BillingType::Rental
+        } else {
+            C::BillingType::Deposit
+        };
 
         let circ_id = self.circ.as_ref().expect("Circ is Set").id()?;
 
         let bill = billing::create_bill(
             self.editor(),
             deposit_amount,
-            btype,
-            btype_label,
+            btype.into(),
+            btype.label(),
             circ_id,
             Some(C::BTYPE_NOTE_SYSTEM),
             None,
@@ -0,0 +1,257 @@
+//! Org unit hours of operation and closed dates.
+use crate as eg;
+use chrono::prelude::Datelike;
+use chrono::{NaiveTime, Timelike};
+use eg::date;
+use eg::Editor;
+use eg::EgResult;
+use eg::EgValue;
+
+/// Days of the week (0 = Sunday) on which the org unit is closed
+/// according to its hours of operation.
+///
+/// A day is closed when its open and close times are both midnight.
+/// Org units with no hours of operation are treated as always open.
+pub fn closed_weekdays(editor: &mut Editor, org_id: i64) -> EgResult<Vec<i64>> {
+    match editor.retrieve("aouhoo", org_id)? {
+        Some(h) => weekdays_closed_by(&h),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn weekdays_closed_by(hours: &EgValue) -> EgResult<Vec<i64>> {
+    let mut closed_days: Vec<i64> = Vec::new();
+
+    for day in 0..7 {
+        let open = hours[&format!("dow_{day}_open")].str()?;
+        let close = hours[&format!("dow_{day}_close")].str()?;
+        if open == "00:00:00" && close == open {
+            closed_days.push(day);
+        }
+    }
+
+    Ok(closed_days)
+}
+
+/// Closed date entries for the org unit whose range includes the
+/// provided date/time.
+pub fn closed_dates_covering(
+    editor: &mut Editor,
+    org_id: i64,
+    dt: &date::EgDate,
+) -> EgResult<Vec<EgValue>> {
+    let timestamp = date::to_iso(dt);
+
+    let query = eg::hash! {
+        "org_unit": org_id,
+        "close_start": {"<=": EgValue::from(timestamp.clone())},
+        "close_end": {">=": EgValue::from(timestamp)},
+    };
+
+    editor.search("aoucd", query)
+}
+
+/// True if the org unit is open at the provided date/time: its day of
+/// the week is not closed per the hours of operation and no closed
+/// date covers that moment.
+///
+/// Closed date ends are normalized per closed_date_end().  To check
+/// many date/times, load an OrgCalendar once instead.
+pub fn is_org_open(editor: &mut Editor, org_id: i64, dt: &date::EgDate) -> EgResult<bool> {
+    OrgCalendar::load(editor, org_id, dt, 0)?.is_open_at(dt)
+}
+
+/// The end of a closed date range.
+///
+/// Whole-day closures are often stored as a date or with a close_end
+/// of midnight, meaning closed through that day, so a close_end with
+/// no time component is moved to the end of its day.
+///
+/// ```
+/// use evergreen::common::hours;
+/// use evergreen::date;
+///
+/// let end = hours::closed_date_end("2024-03-04T00:00:00-0500").unwrap();
+/// assert_eq!(date::to_iso(&end), "2024-03-04T23:59:59-0500");
+///
+/// let end = hours::closed_date_end("2024-03-04T12:30:00-0500").unwrap();
+/// assert_eq!(date::to_iso(&end), "2024-03-04T12:30:00-0500");
+/// ```
+pub fn closed_date_end(close_end: &str) -> EgResult<date::EgDate> {
+    let end = date::parse_datetime(close_end)?;
+
+    if end.time() == NaiveTime::MIN {
+        let eod = end_of_day();
+        date::set_hms(&end, eod.hour(), eod.minute(), eod.second())
+    } else {
+        Ok(end)
+    }
+}
+
+/// True if the org unit is open at any point during the calendar day
+/// of the provided date/time, in the timezone of the provided date.
+///
+/// Unlike is_org_open(), this considers the actual open and close
+/// times for the day and closed dates which only cover part of a day,
+/// so a branch closed for the afternoon is still open that day.
+///
+/// A closed date ending at midnight covers the whole of its last day.
+/// See closed_date_end().  To check many days, load an OrgCalendar
+/// once instead.
+pub fn is_org_open_on_day(editor: &mut Editor, org_id: i64, dt: &date::EgDate) -> EgResult<bool> {
+    OrgCalendar::load(editor, org_id, dt, 0)?.is_open_on_day(dt)
+}
+
+/// Hours of operation and closed dates of an org unit over a range
+/// of days, fetched up front so each day can be checked without
+/// further queries.
+pub struct OrgCalendar {
+    hours: Option<EgValue>,
+    /// Start and normalized end of each closed date, in start order.
+    closings: Vec<(date::EgDate, date::EgDate)>,
+}
+
+impl OrgCalendar {
+    /// Load the hours of operation and the closed dates which overlap
+    /// the day of from or any of the following days.
+    pub fn load(
+        editor: &mut Editor,
+        org_id: i64,
+        from: &date::EgDate,
+        days: u32,
+    ) -> EgResult<OrgCalendar> {
+        let hours = editor.retrieve("aouhoo", org_id)?;
+
+        // Include closings ending at midnight on the first day, which
+        // cover that day once normalized.
+        let midnight = date::set_hms(from, 0, 0, 0)?;
+        let until = date::add_interval(midnight, &format!("{} days", days + 1))?;
+
+        let query = eg::hash! {
+            "org_unit": org_id,
+            "close_start": {"<": date::to_iso(&until)},
+            "close_end": {">=": date::to_iso(&midnight)},
+        };
+
+        let ops = eg::hash! {"order_by": {"aoucd": "close_start"}};
+
+        let mut closings = Vec::new();
+
+        for closing in editor.search_with_ops("aoucd", query, ops)? {
+            closings.push((
+                date::parse_datetime(closing["close_start"].str()?)?,
+                closed_date_end(closing["close_end"].str()?)?,
+            ));
+        }
+
+        Ok(OrgCalendar { hours, closings })
+    }
+
+    /// Days of the week closed per the hours of operation.  See
+    /// closed_weekdays().
+    pub fn closed_weekdays(&self) -> EgResult<Vec<i64>> {
+        match self.hours.as_ref() {
+            Some(h) => weekdays_closed_by(h),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Open and close times on the day of dt, or None if the hours of
+    /// operation close the whole day.
+    fn open_period(&self, dt: &date::EgDate) -> EgResult<Option<(date::EgDate, date::EgDate)>> {
+        let mut open = NaiveTime::MIN;
+        let mut close = end_of_day();
+
+        if let Some(h) = self.hours.as_ref() {
+            let weekday = dt.date_naive().weekday().num_days_from_sunday();
+
+            open = parse_time(h[&format!("dow_{weekday}_open")].str()?)?;
+            close = parse_time(h[&format!("dow_{weekday}_close")].str()?)?;
+
+            if open == NaiveTime::MIN && close == open {
+                return Ok(None);
+            }
+
+            if close <= open {
+                close = end_of_day();
+            }
+        }
+
+        Ok(Some((
+            date::set_hms(dt, open.hour(), open.minute(), open.second())?,
+            date::set_hms(dt, close.hour(), close.minute(), close.second())?,
+        )))
+    }
+
+    /// True if the org unit is open at dt, which must fall within the
+    /// loaded days.  See is_org_open().
+    pub fn is_open_at(&self, dt: &date::EgDate) -> EgResult<bool> {
+        let weekday = dt.date_naive().weekday().num_days_from_sunday() as i64;
+
+        if self.closed_weekdays()?.contains(&weekday) {
+            return Ok(false);
+        }
+
+        Ok(!self
+            .closings
+            .iter()
+            .any(|(start, end)| start <= dt && dt <= end))
+    }
+
+    /// True if the org unit is open at any point during the day of dt.
+    /// See is_org_open_on_day().
+    pub fn is_open_on_day(&self, dt: &date::EgDate) -> EgResult<bool> {
+        match self.open_period(dt)? {
+            Some(_) => Ok(self.closed_through(dt)?.is_none()),
+            None => Ok(false),
+        }
+    }
+
+    /// If closed dates cover all of the open hours on the day of dt,
+    /// returns the end of the unbroken run of closed dates covering
+    /// it, which may be days later.
+    ///
+    /// Returns None if the day is open or is only closed per the hours
+    /// of operation.
+    pub fn closed_through(&self, dt: &date::EgDate) -> EgResult<Option<date::EgDate>> {
+        let (day_start, day_end) = match self.open_period(dt)? {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        // Walk the closings in start order looking for a gap in
+        // coverage during open hours.
+        let mut covered_until = day_start;
+
+        for (start, end) in self.closings.iter() {
+            if *end <= day_start {
+                // Ends before we open.
+                continue;
+            }
+
+            if *start > covered_until {
+                break;
+            }
+
+            if *end > covered_until {
+                covered_until = *end;
+            }
+        }
+
+        if covered_until >= day_end {
+            Ok(Some(covered_until))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Full-day closed dates conventionally end at 23:59:59.
+fn end_of_day() -> NaiveTime {
+    NaiveTime::from_hms_opt(23, 59, 59).expect("In Bounds")
+}
+
+fn parse_time(time: &str) -> EgResult<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .map_err(|e| format!("Invalid hours of operation time: {time} {e}").into())
+}
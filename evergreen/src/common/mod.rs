@@ -9,6 +9,7 @@ pub mod circ;
 pub mod circulator;
 pub mod holdings;
 pub mod holds;
+pub mod hours;
 pub mod jq;
 pub mod noncat;
 pub mod org;
@@ -1,19 +1,37 @@
 use crate as eg;
 use chrono::prelude::Datelike;
 use chrono::Duration;
+use eg::common::hours;
 use eg::date;
 use eg::Editor;
 use eg::EgResult;
 use eg::EgValue;
 
+/// Transform, org unit ID, and depth of a cached org relations query.
+pub(crate) type OrgRelationsKey = (&'static str, i64, Option<i64>);
+
+/// Clear the org unit ancestors, descendants, etc. cached by the
+/// Editor, e.g. after modifying the org unit tree.
+pub fn clear_relations_cache(editor: &mut Editor) {
+    editor.org_relations().clear();
+}
+
 /// Apply a variety of DB transforms to an org unit and return
 /// the calculated org unit IDs.
+///
+/// Results are cached by the Editor.  See clear_relations_cache().
 fn org_relations_query(
     editor: &mut Editor,
     org_id: i64,
-    transform: &str,
+    transform: &'static str,
     depth: Option<i64>,
 ) -> EgResult<Vec<i64>> {
+    let key = (transform, org_id, depth);
+
+    if let Some(ids) = editor.org_relations().get(&key) {
+        return Ok(ids.clone());
+    }
+
     let mut query = eg::hash! {
         "select": {
             "aou": [{
@@ -28,7 +46,7 @@ fn org_relations_query(
     };
 
     if let Some(d) = depth {
-        query["select"][0]["params"] = EgValue::from(vec![d]);
+        query["select"]["aou"][0]["params"] = EgValue::from(vec![d]);
     }
 
     let list = editor.json_query(query)?;
@@ -37,6 +55,9 @@ fn org_relations_query(
     for h in list {
         ids.push(h.id()?);
     }
+
+    editor.org_relations().insert(key, ids.clone());
+
     Ok(ids)
 }
 
@@ -48,10 +69,24 @@ pub fn by_shortname(editor: &mut Editor, sn: &str) -> EgResult<EgValue> {
     }
 }
 
+/// ID of the root org unit, i.e. the one with no parent.
+pub fn root_id(editor: &mut Editor) -> EgResult<i64> {
+    if let Some(o) = editor
+        .search("aou", eg::hash! {"parent_ou": eg::NULL})?
+        .pop()
+    {
+        o.id()
+    } else {
+        Err(editor.die_event())
+    }
+}
+
+/// IDs of the org unit and all of its ancestors.
 pub fn ancestors(editor: &mut Editor, org_id: i64) -> EgResult<Vec<i64>> {
     org_relations_query(editor, org_id, "actor.org_unit_ancestors", None)
 }
 
+/// IDs of the org unit and all of its descendants.
 pub fn descendants(editor: &mut Editor, org_id: i64) -> EgResult<Vec<i64>> {
     org_relations_query(editor, org_id, "actor.org_unit_descendants", None)
 }
@@ -72,6 +107,10 @@ pub enum OrgOpenState {
     OpensOnDate(date::EgDate),
 }
 
+/// Default number of days inspected by next_open_date() when looking
+/// for an open day.
+pub const OPEN_DATE_SCAN_DAYS: u32 = 366;
+
 /// Returns an OrgOpenState descibing the open state of the org unit
 /// on the provided day in the timezone of the provided date.
 ///
@@ -85,29 +124,33 @@ pub fn next_open_date(
     editor: &mut Editor,
     org_id: i64,
     date: &date::EgDate,
+) -> EgResult<OrgOpenState> {
+    next_open_date_within(editor, org_id, date, OPEN_DATE_SCAN_DAYS)
+}
+
+/// Same as next_open_date(), but inspects at most max_days days of
+/// hours and closed date data.
+///
+/// Returns OrgOpenState::Never, with a warning logged, if no open day
+/// is found within the scan limit.
+pub fn next_open_date_within(
+    editor: &mut Editor,
+    org_id: i64,
+    date: &date::EgDate,
+    max_days: u32,
 ) -> EgResult<OrgOpenState> {
     let start_date = date.clone();
     let mut date = date.clone();
 
-    let mut closed_days: Vec<i64> = Vec::new();
-    if let Some(h) = editor.retrieve("aouhoo", org_id)? {
-        for day in 0..7 {
-            let open = h[&format!("dow_{day}_open")].as_str().unwrap();
-            let close = h[&format!("dow_{day}_close")].as_str().unwrap();
-            if open == "00:00:00" && close == open {
-                closed_days.push(day);
-            }
-        }
+    let closed_days = hours::closed_weekdays(editor, org_id)?;
 
-        // Always closed.
-        if closed_days.len() == 7 {
-            return Ok(OrgOpenState::Never);
-        }
+    // Always closed.
+    if closed_days.len() == 7 {
+        return Ok(OrgOpenState::Never);
     }
 
     let mut counter = 0;
-    while counter < 366 {
-        // inspect at most 1 year of data
+    while counter < max_days {
         counter += 1;
 
         // Zero-based day of week
@@ -123,14 +166,7 @@ pub fn next_open_date(
         // Open this day based on hours of operation.
         // See if any overlapping closings are configured instead.
 
-        let timestamp = date::to_iso(&date);
-        let query = eg::hash! {
-            "org_unit": org_id,
-            "close_start": {"<=": EgValue::from(timestamp.clone())},
-            "close_end": {">=": EgValue::from(timestamp)},
-        };
-
-        let org_closed = editor.search("aoucd", query)?;
+        let org_closed = hours::closed_dates_covering(editor, org_id, &date)?;
 
         if org_closed.len() == 0 {
             // No overlapping closings.  We've found our open day.
@@ -145,9 +181,9 @@ pub fn next_open_date(
         }
 
         // Find the end of the closed date range and jump ahead to that.
-        let mut range_end = org_closed[0]["close_end"].as_str().unwrap();
+        let mut range_end = org_closed[0]["close_end"].str()?;
         for day in org_closed.iter() {
-            let end = day["close_end"].as_str().unwrap();
+            let end = day["close_end"].str()?;
             if end > range_end {
                 range_end = end;
             }
@@ -158,6 +194,12 @@ pub fn next_open_date(
     }
 
     // If we get here it means we never found an open day.
+    log::warn!(
+        "No open day found for org {org_id} within {max_days} days of {}; \
+        dependent calculations (e.g. grace periods) may be inaccurate",
+        date::to_iso(&start_date)
+    );
+
     Ok(OrgOpenState::Never)
 }
 
@@ -13,6 +13,23 @@ fn number(v: &EgValue) -> i64 {
     v.int().expect("Has Number")
 }
 
+/// Run calculate_penalties() for each (user ID, context org unit ID)
+/// pair, e.g. those collected from a bulk billing operation which
+/// deferred its penalty updates.
+///
+/// Penalties are recalculated as the system user, which the Editor
+/// must have.  See Editor::with_system_perms().  The recalculation
+/// only applies what the system penalty rules call for, so the
+/// operation which collected the pairs is what needs authorizing.
+pub fn calculate_penalties_batch(editor: &mut Editor, pairs: &[(i64, i64)]) -> EgResult<()> {
+    editor.with_system_perms(|e| {
+        for (user_id, org_id) in pairs {
+            calculate_penalties(e, *user_id, *org_id, None)?;
+        }
+        Ok(())
+    })
+}
+
 pub fn calculate_penalties(
     editor: &mut Editor,
     user_id: i64,
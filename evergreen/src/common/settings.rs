@@ -1,16 +1,62 @@
 //! General purpose org / workstation / user setting fetcher and cache.
 //! Primarily uses the 'actor.get_cascade_setting()' DB function.
 use crate as eg;
-use eg::{Editor, EgResult, EgValue};
+use eg::common::org;
+use eg::date;
+use eg::osrf::cache::Cache;
+use eg::osrf::events;
+use eg::osrf::sclient::HostSettings;
+use eg::{Editor, EgError, EgResult, EgValue};
 use regex::Regex;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 // Setting names consist only of letters, numbers, unders, and dots.
 // This is crucial since the names are encoded as an SQL TEXT[] parameter
 // during lookuping.
 const SETTING_NAME_REGEX: &str = "[^a-zA-Z0-9_\\.]";
 
+/// Prefix for org unit setting values stored in the shared cache.
+const SHARED_CACHE_PRFX: &str = "eg_org_setting_";
+
+/// Time to live in seconds of org unit setting values stored in the
+/// shared "global" cache.  Zero means the shared cache is not used.
+static SHARED_CACHE_TTL: AtomicU32 = AtomicU32::new(0);
+
+/// Back org unit setting lookups with the shared "global" cache, so
+/// all workers share resolved values, storing each value for ttl
+/// seconds.  Zero disables the shared cache, which is the default.
+///
+/// Only lookups made with an org unit alone are shared, since user
+/// and workstation settings are rarely read by more than one worker.
+/// The global cache must be initialized (Cache::init_cache()) for the
+/// shared cache to have any effect.
+pub fn set_shared_cache_ttl(ttl: u32) {
+    SHARED_CACHE_TTL.store(ttl, Ordering::Relaxed);
+}
+
+pub fn shared_cache_ttl() -> u32 {
+    SHARED_CACHE_TTL.load(Ordering::Relaxed)
+}
+
+/// Apply the shared cache TTL configured for the service via
+/// apps/<service>/app_settings/settings_cache_ttl in opensrf.xml.
+///
+/// Leaves the shared cache disabled if no positive value is set.
+pub fn configure_shared_cache(service: &str) -> EgResult<()> {
+    let path = format!("apps/{service}/app_settings/settings_cache_ttl");
+
+    if let Some(ttl) = HostSettings::get(&path)?.as_int() {
+        if ttl > 0 {
+            log::info!("Sharing org unit setting values for {ttl} seconds");
+            set_shared_cache_ttl(ttl as u32);
+        }
+    }
+
+    Ok(())
+}
+
 /// SettingType may come in handy later when we need to know
 /// more about the types.
 #[derive(Debug, Clone, PartialEq)]
@@ -98,6 +144,29 @@ impl SettingContext {
     pub fn is_viable(&self) -> bool {
         self.org_id.is_some() || self.user_id.is_some()
     }
+
+    /// Key for a setting value in the shared cache, if values for this
+    /// context are shared.  Only org unit contexts are shared.
+    ///
+    /// ```
+    /// use evergreen::common::settings::SettingContext;
+    ///
+    /// let mut ctx = SettingContext::new();
+    /// ctx.set_org_id(4);
+    /// assert_eq!(
+    ///     ctx.shared_cache_key("circ.holds.target_skip_me").as_deref(),
+    ///     Some("eg_org_setting_circ.holds.target_skip_me:4")
+    /// );
+    ///
+    /// ctx.set_user_id(1);
+    /// assert_eq!(ctx.shared_cache_key("circ.holds.target_skip_me"), None);
+    /// ```
+    pub fn shared_cache_key(&self, name: &str) -> Option<String> {
+        match (self.org_id, self.user_id, self.workstation_id) {
+            (Some(org_id), None, None) => Some(format!("{SHARED_CACHE_PRFX}{name}:{org_id}")),
+            _ => None,
+        }
+    }
 }
 
 ///
@@ -115,6 +184,17 @@ impl SettingEntry {
     }
 }
 
+/// Outcome of importing a single org unit setting.
+///
+/// See Settings::import_org()
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingImport {
+    Created,
+    Updated,
+    /// The setting was not written, for the provided reason.
+    Skipped(String),
+}
+
 pub struct Settings {
     editor: Editor,
     default_context: SettingContext,
@@ -183,6 +263,109 @@ impl Settings {
         self.cache.clear();
     }
 
+    /// Drop cached values for a setting which has changed at an org
+    /// unit, locally and in the shared cache.
+    ///
+    /// Since values are inherited, shared entries for the org unit's
+    /// descendants are removed as well.
+    ///
+    /// A events::SETTINGS_CHANGED event is published so other
+    /// processes may drop their cached values too.
+    pub fn invalidate(&mut self, name: &str, org_id: i64) -> EgResult<()> {
+        for hash in self.cache.values_mut() {
+            hash.remove(name);
+        }
+
+        let payload = eg::hash! {"name": name, "org_id": org_id};
+        let published = events::publish(
+            self.editor.client_mut().singleton().borrow_mut().bus_mut(),
+            events::SETTINGS_CHANGED,
+            payload,
+        );
+
+        if let Err(e) = published {
+            log::warn!("Cannot announce changed setting {name}: {e}");
+        }
+
+        if shared_cache_ttl() == 0 {
+            return Ok(());
+        }
+
+        for id in org::descendants(&mut self.editor, org_id)? {
+            let mut ctx = SettingContext::new();
+            ctx.set_org_id(id);
+
+            if let Some(key) = ctx.shared_cache_key(name) {
+                if let Err(e) = Cache::del_global(&key) {
+                    log::warn!("Cannot remove shared setting value {key}: {e}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy a value from the shared cache into the local cache.
+    ///
+    /// Returns true if a value was found.  Cache failures are logged
+    /// and treated as a miss.
+    fn load_shared_value(&mut self, context: &SettingContext, name: &str) -> bool {
+        if shared_cache_ttl() == 0 {
+            return false;
+        }
+
+        let key = match context.shared_cache_key(name) {
+            Some(k) => k,
+            None => return false,
+        };
+
+        let mut cached = match Cache::get_global(&key) {
+            Ok(Some(v)) => v,
+            Ok(None) => return false,
+            Err(e) => {
+                log::debug!("Shared setting cache unavailable: {e}");
+                return false;
+            }
+        };
+
+        // Values are wrapped so unset (null) settings can be cached.
+        let entry = SettingEntry {
+            value: cached["value"].take(),
+        };
+
+        self.cache
+            .entry(context.clone())
+            .or_default()
+            .insert(name.to_string(), entry);
+
+        true
+    }
+
+    /// Copy freshly fetched values into the shared cache.
+    fn store_shared_values(&mut self, context: &SettingContext, names: &[&str]) {
+        let ttl = shared_cache_ttl();
+        if ttl == 0 {
+            return;
+        }
+
+        for name in names {
+            let key = match context.shared_cache_key(name) {
+                Some(k) => k,
+                None => return,
+            };
+
+            let value = match self.get_cached_value(context, name) {
+                Some(v) => v.clone(),
+                None => continue,
+            };
+
+            if let Err(e) = Cache::set_global_for(&key, eg::hash! {"value": value}, ttl) {
+                log::debug!("Cannot share setting value {key}: {e}");
+                return;
+            }
+        }
+    }
+
     /// Returns a setting value using the default context.
     ///
     /// Returns JSON null if no setting exists.
@@ -199,6 +382,184 @@ impl Settings {
         self.get_context_value(&ctx, name)
     }
 
+    /// Returns the value of an org unit setting along with the ID of
+    /// the org unit (org_id or one of its ancestors) where the value
+    /// is configured.
+    ///
+    /// The value is JSON null and the org unit is None when no org
+    /// unit in the ancestry has a value for the setting.
+    ///
+    /// Unlike get_value_at_org(), values are read directly and not
+    /// cached, so the result reflects the current configuration.
+    pub fn get_value_and_source_org(
+        &mut self,
+        name: &str,
+        org_id: i64,
+    ) -> EgResult<(EgValue, Option<i64>)> {
+        let ancestors = org::ancestors(&mut self.editor, org_id)?;
+
+        let query = eg::hash! {"name": name, "org_unit": ancestors};
+        let mut found = self.editor.search("aous", query)?;
+
+        // The value from the org unit nearest org_id, i.e. the
+        // ancestor with the most ancestors of its own, wins.
+        let mut source: Option<(usize, usize)> = None;
+
+        for (idx, setting) in found.iter().enumerate() {
+            let setting_org = setting["org_unit"].int()?;
+            let depth = org::ancestors(&mut self.editor, setting_org)?.len();
+
+            if source.map(|(_, d)| depth > d).unwrap_or(true) {
+                source = Some((idx, depth));
+            }
+        }
+
+        let setting = match source {
+            Some((idx, _)) => found.swap_remove(idx),
+            None => return Ok((EgValue::Null, None)),
+        };
+
+        let value = match setting["value"].as_str() {
+            Some(v) => EgValue::parse(v).map_err(|e| format!("Cannot parse setting value: {e}"))?,
+            None => EgValue::Null,
+        };
+
+        Ok((value, Some(setting["org_unit"].int()?)))
+    }
+
+    /// Returns all org unit setting values configured directly on
+    /// the org unit (not inherited) as a hash of setting name to value,
+    /// e.g. for copying configuration to another environment via
+    /// import_org().
+    pub fn export_org(editor: &mut Editor, org_id: i64) -> EgResult<EgValue> {
+        let query = eg::hash! {"org_unit": org_id};
+        let ops = eg::hash! {"order_by": {"aous": "name"}};
+
+        let mut export = EgValue::new_object();
+
+        for setting in editor.search_with_ops("aous", query, ops)? {
+            let value = match setting["value"].as_str() {
+                Some(v) => {
+                    EgValue::parse(v).map_err(|e| format!("Cannot parse setting value: {e}"))?
+                }
+                None => EgValue::Null,
+            };
+
+            export.insert(setting["name"].str()?, value)?;
+        }
+
+        Ok(export)
+    }
+
+    /// Apply org unit setting values, as produced by export_org(), to
+    /// an org unit.
+    ///
+    /// Settings which already have a value at the org unit are skipped
+    /// unless overwrite is true.  Unknown settings and invalid
+    /// interval values are also skipped.
+    ///
+    /// Returns the outcome for each setting, sorted by name.
+    ///
+    /// Uses an externally managed Editor transaction.  Changes are not
+    /// announced here, since other processes would re-read and cache
+    /// the old values until the transaction is committed.  After
+    /// committing, pass the report to invalidate_import().
+    pub fn import_org(
+        editor: &mut Editor,
+        org_id: i64,
+        data: &EgValue,
+        overwrite: bool,
+    ) -> EgResult<Vec<(String, SettingImport)>> {
+        if !editor.in_transaction() {
+            Err(format!("Transaction required to import settings"))?;
+        }
+
+        if !data.is_object() {
+            Err(EgError::Validation(format!(
+                "Settings import data must be an object: {data}"
+            )))?;
+        }
+
+        let mut names: Vec<&str> = data.keys().collect();
+        names.sort();
+
+        let mut datatypes = HashMap::new();
+        for stype in editor.search("coust", eg::hash! {"name": names.clone()})? {
+            datatypes.insert(stype["name"].string()?, stype["datatype"].string()?);
+        }
+
+        let mut report = Vec::new();
+
+        for name in names {
+            let value = &data[name];
+
+            let datatype = match datatypes.get(name) {
+                Some(d) => d.as_str(),
+                None => {
+                    report.push((
+                        name.to_string(),
+                        SettingImport::Skipped("No such setting type".to_string()),
+                    ));
+                    continue;
+                }
+            };
+
+            if datatype == "interval" {
+                let valid = match value.as_str() {
+                    Some(v) => date::validate_interval(v).map_err(|e| e.to_string()),
+                    None => Err(format!("Interval must be a string: {value}")),
+                };
+
+                if let Err(e) = valid {
+                    report.push((name.to_string(), SettingImport::Skipped(e)));
+                    continue;
+                }
+            }
+
+            let query = eg::hash! {"org_unit": org_id, "name": name};
+
+            let outcome = match editor.search("aous", query)?.pop() {
+                Some(_) if !overwrite => {
+                    SettingImport::Skipped("Setting already has a value".to_string())
+                }
+                Some(mut existing) => {
+                    existing["value"] = EgValue::from(value.dump());
+                    editor.update(existing)?;
+                    SettingImport::Updated
+                }
+                None => {
+                    let setting = eg::hash! {
+                        "org_unit": org_id,
+                        "name": name,
+                        "value": value.dump(),
+                    };
+                    editor.create(EgValue::create("aous", setting)?)?;
+                    SettingImport::Created
+                }
+            };
+
+            report.push((name.to_string(), outcome));
+        }
+
+        Ok(report)
+    }
+
+    /// Invalidate each setting created or updated by import_org() at
+    /// the org unit, once the import has been committed.
+    pub fn invalidate_import(
+        &mut self,
+        org_id: i64,
+        report: &[(String, SettingImport)],
+    ) -> EgResult<()> {
+        for (name, outcome) in report {
+            if matches!(outcome, SettingImport::Created | SettingImport::Updated) {
+                self.invalidate(name, org_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns a setting value for the provided context.
     pub fn get_context_value(
         &mut self,
@@ -209,7 +570,8 @@ impl Settings {
             self.cache.insert(context.clone(), HashMap::new());
         }
 
-        if self.get_cached_value(context, name).is_none() {
+        if self.get_cached_value(context, name).is_none() && !self.load_shared_value(context, name)
+        {
             // No value in the cache.  Fetch it.
             self.fetch_context_values(context, &[name])?;
         }
@@ -247,11 +609,13 @@ impl Settings {
         let mut ctx = SettingContext::new();
         ctx.set_org_id(org_id);
 
-        let names: Vec<&str> = names
-            .iter()
-            .filter(|n| self.get_cached_value(&ctx, n).is_none())
-            .map(|n| *n)
-            .collect();
+        let mut needed = Vec::new();
+        for name in names {
+            if self.get_cached_value(&ctx, name).is_none() && !self.load_shared_value(&ctx, name) {
+                needed.push(*name);
+            }
+        }
+        let names = needed;
 
         if names.len() > 0 {
             self.fetch_context_values(&ctx, names.as_slice())
@@ -293,6 +657,8 @@ impl Settings {
             }
         }
 
+        let names_list = names;
+
         // First param is an SQL TEXT[].
         // e.g. '{foo.bar,foo.baz}'
         let names = format!("{{{}}}", names.join(","));
@@ -310,6 +676,8 @@ impl Settings {
             self.store_setting_value(context, &set)?;
         }
 
+        self.store_shared_values(context, &names_list);
+
         Ok(())
     }
 
@@ -1,5 +1,6 @@
 //! Shared, user-focused utility functions
 use crate as eg;
+use eg::common::settings::Settings;
 use eg::editor::Editor;
 use eg::result::EgResult;
 use eg::EgValue;
@@ -7,6 +8,63 @@ use md5;
 
 pub const PW_TYPE_MAIN: &str = "main";
 
+/// Password type of upgraded main passwords.  See PW_UPGRADE_SETTING.
+pub const PW_TYPE_UPGRADED: &str = "main_upgraded";
+
+/// Org unit setting which enables storing an upgraded hash of the
+/// user's main password on successful login.
+///
+/// Evergreen stores main passwords as a hash of md5(salt + md5(password)),
+/// so the md5 digest alone is enough to log in.  The upgraded
+/// credential is a hash of the plain text password, stored as its own
+/// actor.passwd row of type PW_TYPE_UPGRADED.  The algorithm and cost
+/// come from the config.passwd_type row (see actor.create_salt()).
+///
+/// The legacy main credential is left as is, so the Perl open-ils.auth
+/// stack, which sends the md5 digest, keeps working for upgraded users.
+///
+/// To migrate, create the password type, then enable this setting
+/// for the org units whose users to upgrade.  Users are upgraded as
+/// they log in.
+///
+/// ```sql
+/// INSERT INTO config.passwd_type (code, name, crypt_algo, iter_count)
+///     VALUES ('main_upgraded', 'Upgraded Main Password', 'bf', 12);
+/// ```
+///
+/// Passwords changed via the Perl stack only update the main
+/// credential.  The user's next login with the new password replaces
+/// the stale upgraded credential, but until then the old password is
+/// still accepted, so delete the user's PW_TYPE_UPGRADED row when
+/// changing a password outside of this code.
+///
+/// To roll back, disable the setting and remove the upgraded
+/// credentials.  Users then log in with their untouched legacy
+/// credentials.
+///
+/// ```sql
+/// DELETE FROM actor.passwd WHERE passwd_type = 'main_upgraded';
+/// DELETE FROM config.passwd_type WHERE code = 'main_upgraded';
+/// ```
+pub const PW_UPGRADE_SETTING: &str = "auth.password.upgrade_legacy_hash";
+
+/// How a password matched the stored credential.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordMatch {
+    NoMatch,
+    /// Matched the legacy md5(salt + md5(password)) value, which is
+    /// what Evergreen stores for migrated passwords.
+    Legacy,
+    /// Matched the user's PW_TYPE_UPGRADED hash of the password itself.
+    Upgraded,
+}
+
+impl PasswordMatch {
+    pub fn is_match(&self) -> bool {
+        *self != PasswordMatch::NoMatch
+    }
+}
+
 /// Returns result of True if the password provides matches the user's password.
 ///
 /// # Arguments
@@ -76,6 +134,88 @@ pub fn verify_password(
     }
 }
 
+/// Returns true if the user has a password of the provided type.
+pub fn has_password_type(e: &mut Editor, user_id: i64, pw_type: &str) -> EgResult<bool> {
+    let query = eg::hash! {from: ["actor.get_salt", user_id, pw_type]};
+
+    match e.json_query(query)?.first() {
+        Some(v) => Ok(v["actor.get_salt"].is_string()),
+        None => Ok(false),
+    }
+}
+
+/// Determine whether the plain text password matches the user's main
+/// password, and if so, via which credential.
+///
+/// The password is compared as-is only against an upgraded
+/// credential.  The legacy main credential is always checked via the
+/// md5 scheme, so its stored digest never passes for the password.
+pub fn check_password(e: &mut Editor, user_id: i64, password: &str) -> EgResult<PasswordMatch> {
+    if has_password_type(e, user_id, PW_TYPE_UPGRADED)?
+        && verify_password(e, user_id, password, PW_TYPE_UPGRADED)?
+    {
+        Ok(PasswordMatch::Upgraded)
+    } else if verify_migrated_password(e, user_id, password, false)? {
+        Ok(PasswordMatch::Legacy)
+    } else {
+        Ok(PasswordMatch::NoMatch)
+    }
+}
+
+/// Store a PW_TYPE_UPGRADED hash of the plain text password,
+/// replacing any existing one.  The main credential is not modified.
+pub fn upgrade_password(e: &mut Editor, user_id: i64, password: &str) -> EgResult<()> {
+    let query = eg::hash! {from: ["actor.create_salt", PW_TYPE_UPGRADED]};
+
+    let salt = match e.json_query(query)?.pop() {
+        Some(v) => v["actor.create_salt"].str()?.to_string(),
+        None => return Err("actor.create_salt() failed to return a response".into()),
+    };
+
+    let query = eg::hash! {
+        from: ["actor.set_passwd", user_id, PW_TYPE_UPGRADED, password, salt]
+    };
+
+    e.json_query(query)?;
+
+    log::info!("Upgraded password hash for user {user_id}");
+
+    Ok(())
+}
+
+/// True if PW_UPGRADE_SETTING is enabled for the user's home org unit.
+pub fn password_upgrade_enabled(e: &mut Editor, user_id: i64) -> EgResult<bool> {
+    let user = e.retrieve("au", user_id)?.ok_or_else(|| e.die_event())?;
+
+    let mut settings = Settings::new(e);
+    settings.set_org_id(user["home_ou"].int()?);
+
+    Ok(settings.get_value(PW_UPGRADE_SETTING)?.boolish())
+}
+
+/// Returns true if the password matches the user's main password.
+///
+/// When the legacy credential matches and PW_UPGRADE_SETTING is
+/// enabled, an upgraded credential is stored, or replaced if the
+/// password was changed elsewhere.  The caller is responsible for
+/// starting and committing the transaction.
+///
+/// This does no failed login counting; logins should go through
+/// auth::verify_login_password().
+pub fn verify_password_with_upgrade(
+    e: &mut Editor,
+    user_id: i64,
+    password: &str,
+) -> EgResult<bool> {
+    let matched = check_password(e, user_id, password)?;
+
+    if matched == PasswordMatch::Legacy && password_upgrade_enabled(e, user_id)? {
+        upgrade_password(e, user_id, password)?;
+    }
+
+    Ok(matched.is_match())
+}
+
 /// Returns a list of all org unit IDs where the provided user has
 /// the provided work permission.
 pub fn has_work_perm_at(e: &mut Editor, user_id: i64, perm: &str) -> EgResult<Vec<i64>> {
@@ -1,4 +1,5 @@
 //! Evergreen Constants
+use crate::result::{EgError, EgResult};
 
 // ---------------------------------------------------------------------
 // Copy Statuses
@@ -34,6 +35,16 @@ pub const CIRC_FINE_LEVEL_MEDIUM: i64 = 2;
 pub const CIRC_FINE_LEVEL_HIGH: i64 = 3;
 pub const CIRC_POLICY_UNLIMITED: &str = "unlimited";
 
+// ---------------------------------------------------------------------
+// Circulation Stop Fines Reasons
+// ---------------------------------------------------------------------
+pub const CIRC_STOP_FINES_CHECKIN: &str = "CHECKIN";
+pub const CIRC_STOP_FINES_RENEW: &str = "RENEW";
+pub const CIRC_STOP_FINES_LOST: &str = "LOST";
+pub const CIRC_STOP_FINES_CLAIMSRETURNED: &str = "CLAIMSRETURNED";
+pub const CIRC_STOP_FINES_LONGOVERDUE: &str = "LONGOVERDUE";
+pub const CIRC_STOP_FINES_MAXFINES: &str = "MAXFINES";
+
 // ---------------------------------------------------------------------
 // Billing Types
 // ---------------------------------------------------------------------
@@ -54,10 +65,141 @@ pub const BTYPE_LONG_OVERDUE_MATERIALS_PROCESSING_FEE: i64 = 11;
 // ---------------------------------------------------------------------
 pub const BTYPE_LABEL_OVERDUE_MATERIALS: &str = "Overdue materials";
 pub const BTYPE_LABEL_COLLECTION_FEE: &str = "Long Overdue Collection Fee";
+pub const BTYPE_LABEL_LONG_OVERDUE_MATERIALS: &str = "Long-Overdue Materials";
+pub const BTYPE_LABEL_LONG_OVERDUE_MATERIALS_PROCESSING_FEE: &str =
+    "Long-Overdue Materials Processing Fee";
 pub const BTYPE_LABEL_DEPOSIT: &str = "System: Deposit";
 pub const BTYPE_LABEL_RENTAL: &str = "System: Rental";
 pub const BTYPE_NOTE_SYSTEM: &str = "SYSTEM GENERATED";
 
+/// Stock billing types.
+///
+/// Prefer this over the raw BTYPE_* constants in new code so a match
+/// on the billing type is checked for unhandled variants.
+///
+/// ```
+/// use evergreen::constants::{self as C, BillingType};
+///
+/// let btype = BillingType::try_from(C::BTYPE_LOST_MATERIALS).unwrap();
+/// assert_eq!(btype, BillingType::LostMaterials);
+///
+/// let id: i64 = BillingType::Deposit.into();
+/// assert_eq!(id, C::BTYPE_DEPOSIT);
+/// assert_eq!(BillingType::Deposit.label(), C::BTYPE_LABEL_DEPOSIT);
+///
+/// assert!(BillingType::try_from(9999).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BillingType {
+    OverdueMaterials,
+    LongOverdueCollectionFee,
+    LostMaterials,
+    LostMaterialsProcessingFee,
+    Deposit,
+    Rental,
+    DamagedItem,
+    DamagedItemProcessingFee,
+    NotificationFee,
+    LongOverdueMaterials,
+    LongOverdueMaterialsProcessingFee,
+}
+
+impl BillingType {
+    /// Label applied to bills of this type which are created by the
+    /// system.
+    #[rustfmt::skip]
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::OverdueMaterials                  => BTYPE_LABEL_OVERDUE_MATERIALS,
+            Self::LongOverdueCollectionFee          => BTYPE_LABEL_COLLECTION_FEE,
+            Self::LostMaterials                     => "Lost Materials",
+            Self::LostMaterialsProcessingFee        => "Lost Materials Processing Fee",
+            Self::Deposit                           => BTYPE_LABEL_DEPOSIT,
+            Self::Rental                            => BTYPE_LABEL_RENTAL,
+            Self::DamagedItem                       => "Damaged Item",
+            Self::DamagedItemProcessingFee          => "Damaged Item Processing Fee",
+            Self::NotificationFee                   => "Notification Fee",
+            Self::LongOverdueMaterials              => BTYPE_LABEL_LONG_OVERDUE_MATERIALS,
+            Self::LongOverdueMaterialsProcessingFee => BTYPE_LABEL_LONG_OVERDUE_MATERIALS_PROCESSING_FEE,
+        }
+    }
+}
+
+/// let id: i64 = btype.into();
+#[rustfmt::skip]
+impl From<BillingType> for i64 {
+    fn from(t: BillingType) -> i64 {
+        match t {
+            BillingType::OverdueMaterials                  => BTYPE_OVERDUE_MATERIALS,
+            BillingType::LongOverdueCollectionFee          => BTYPE_LONG_OVERDUE_COLLECTION_FEE,
+            BillingType::LostMaterials                     => BTYPE_LOST_MATERIALS,
+            BillingType::LostMaterialsProcessingFee        => BTYPE_LOST_MATERIALS_PROCESSING_FEE,
+            BillingType::Deposit                           => BTYPE_DEPOSIT,
+            BillingType::Rental                            => BTYPE_RENTAL,
+            BillingType::DamagedItem                       => BTYPE_DAMAGED_ITEM,
+            BillingType::DamagedItemProcessingFee          => BTYPE_DAMAGED_ITEM_PROCESSING_FEE,
+            BillingType::NotificationFee                   => BTYPE_NOTIFICATION_FEE,
+            BillingType::LongOverdueMaterials              => BTYPE_LONG_OVERDUE_MATERIALS,
+            BillingType::LongOverdueMaterialsProcessingFee => BTYPE_LONG_OVERDUE_MATERIALS_PROCESSING_FEE,
+        }
+    }
+}
+
+/// let btype = BillingType::try_from(id)?;
+impl TryFrom<i64> for BillingType {
+    type Error = EgError;
+    fn try_from(id: i64) -> EgResult<BillingType> {
+        match id {
+            BTYPE_OVERDUE_MATERIALS => Ok(BillingType::OverdueMaterials),
+            BTYPE_LONG_OVERDUE_COLLECTION_FEE => Ok(BillingType::LongOverdueCollectionFee),
+            BTYPE_LOST_MATERIALS => Ok(BillingType::LostMaterials),
+            BTYPE_LOST_MATERIALS_PROCESSING_FEE => Ok(BillingType::LostMaterialsProcessingFee),
+            BTYPE_DEPOSIT => Ok(BillingType::Deposit),
+            BTYPE_RENTAL => Ok(BillingType::Rental),
+            BTYPE_DAMAGED_ITEM => Ok(BillingType::DamagedItem),
+            BTYPE_DAMAGED_ITEM_PROCESSING_FEE => Ok(BillingType::DamagedItemProcessingFee),
+            BTYPE_NOTIFICATION_FEE => Ok(BillingType::NotificationFee),
+            BTYPE_LONG_OVERDUE_MATERIALS => Ok(BillingType::LongOverdueMaterials),
+            BTYPE_LONG_OVERDUE_MATERIALS_PROCESSING_FEE => {
+                Ok(BillingType::LongOverdueMaterialsProcessingFee)
+            }
+            _ => Err(EgError::NotFound(format!("No such billing type: {id}"))),
+        }
+    }
+}
+
+/// Map a stored billing type label back onto its type.
+///
+/// Bills store their billing type label as text, so both the legacy
+/// labels and the stock type names are accepted.
+impl TryFrom<&str> for BillingType {
+    type Error = EgError;
+    fn try_from(label: &str) -> EgResult<BillingType> {
+        let btype = match label {
+            BTYPE_LABEL_OVERDUE_MATERIALS | "Overdue Materials" => BillingType::OverdueMaterials,
+            "Lost Materials" => BillingType::LostMaterials,
+            "Lost Materials Processing Fee" => BillingType::LostMaterialsProcessingFee,
+            "Damaged Item" => BillingType::DamagedItem,
+            "Damaged Item Processing Fee" => BillingType::DamagedItemProcessingFee,
+            "Notification Fee" => BillingType::NotificationFee,
+            BTYPE_LABEL_COLLECTION_FEE => BillingType::LongOverdueCollectionFee,
+            BTYPE_LABEL_DEPOSIT => BillingType::Deposit,
+            BTYPE_LABEL_RENTAL => BillingType::Rental,
+            BTYPE_LABEL_LONG_OVERDUE_MATERIALS => BillingType::LongOverdueMaterials,
+            BTYPE_LABEL_LONG_OVERDUE_MATERIALS_PROCESSING_FEE => {
+                BillingType::LongOverdueMaterialsProcessingFee
+            }
+            _ => {
+                return Err(EgError::NotFound(format!(
+                    "No such billing type label: {label}"
+                )))
+            }
+        };
+
+        Ok(btype)
+    }
+}
+
 // ---------------------------------------------------------------------
 // Hold Types
 // ---------------------------------------------------------------------
@@ -1,9 +1,11 @@
 //! Date handling utilities
 
-use crate::result::EgResult;
-use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, TimeZone};
+use crate::result::{EgError, EgResult};
+use chrono::{DateTime, Datelike, Days, Duration, FixedOffset, Local, NaiveDate, TimeZone, Utc};
 use chrono_tz::Tz;
 use regex::{Captures, Regex};
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::time::SystemTime;
 
 const INTERVAL_PART_REGEX: &str = r#"\s*([\+-]?)\s*(\d+)\s*(\w+)\s*"#;
@@ -12,8 +14,66 @@ const INTERVAL_HMS_REGEX: &str = r#"(\d{2,}):(\d{2}):(\d{2})"#;
 /// Shortcut -- one fewer import for most mods.
 pub type EgDate = DateTime<FixedOffset>;
 
+/// Source of the current time for now() and friends.
+pub trait Clock {
+    fn now(&self) -> EgDate;
+}
+
+/// Reads the system wall clock.  This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> EgDate {
+        Local::now().into()
+    }
+}
+
+/// Always reports the same time, e.g. to test time-dependent billing
+/// logic deterministically.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub EgDate);
+
+impl Clock for FixedClock {
+    fn now(&self) -> EgDate {
+        self.0
+    }
+}
+
+// The clock is tied to the current thread so tests running in
+// parallel may each freeze time without affecting one another.
+thread_local! {
+    static THREAD_CLOCK: RefCell<Box<dyn Clock>> = RefCell::new(Box::new(SystemClock));
+}
+
+/// Replace the clock consulted by now() for the current thread.
+///
+/// ```
+/// use evergreen::date;
+///
+/// let frozen = date::parse_datetime("2024-03-01T12:00:00-0500").unwrap();
+/// date::set_thread_clock(date::FixedClock(frozen));
+///
+/// assert_eq!(date::now(), frozen);
+/// assert!(date::is_within_interval_of_now(&frozen, "1 second").unwrap());
+///
+/// date::reset_thread_clock();
+/// assert!(date::now() > frozen);
+/// ```
+pub fn set_thread_clock(clock: impl Clock + 'static) {
+    THREAD_CLOCK.with(|c| *c.borrow_mut() = Box::new(clock));
+}
+
+/// Return the current thread to the system clock.
+pub fn reset_thread_clock() {
+    set_thread_clock(SystemClock);
+}
+
 /// Turn an interval string into a number of seconds.
 ///
+/// Returns an Err if the interval contains no number/unit pairs, e.g.
+/// an empty string.
+///
 /// Supports a subset of the language, which is typically enough
 /// for our use cases.  For better parsing, if needed, we could use
 /// (e.g.) <https://crates.io/crates/parse_duration>
@@ -26,22 +86,38 @@ pub type EgDate = DateTime<FixedOffset>;
 ///
 /// let seconds = date::interval_to_seconds("1 min 2 seconds").expect("Parse OK");
 /// assert_eq!(seconds, 62);
+///
+/// // Explicit zero intervals are fine.
+/// assert_eq!(date::interval_to_seconds("0s").unwrap(), 0);
+/// assert_eq!(date::interval_to_seconds("0 seconds").unwrap(), 0);
+///
+/// // Intervals with nothing to parse are not.
+/// assert!(date::interval_to_seconds("").is_err());
+/// assert!(date::interval_to_seconds("  ").is_err());
+/// ```
+///
+/// A sign, attached or not, applies only to the number/unit pair it
+/// precedes and the pairs are summed.  A trailing "ago" negates the
+/// entire sum.
+///
+/// ```
+/// use evergreen::date;
+///
+/// assert_eq!(date::interval_to_seconds("-1 day").unwrap(), -86400);
+/// assert_eq!(date::interval_to_seconds("- 1 day").unwrap(), -86400);
+/// assert_eq!(date::interval_to_seconds("1 day ago").unwrap(), -86400);
+///
+/// // 1 day minus 2 hours.
+/// assert_eq!(date::interval_to_seconds("1 day -2 hours").unwrap(), 79200);
+/// assert_eq!(date::interval_to_seconds("1 day -2 hours ago").unwrap(), -79200);
+/// assert_eq!(date::interval_to_seconds("-1 day ago").unwrap(), 86400);
 /// ```
 pub fn interval_to_seconds(interval: &str) -> EgResult<i64> {
-    let hms_reg = Regex::new(INTERVAL_HMS_REGEX).unwrap();
     let part_reg = Regex::new(INTERVAL_PART_REGEX).unwrap();
-
-    let mut interval = interval.to_lowercase();
-    interval = interval.replace("and", ",");
-    interval = interval.replace(",", " ");
-
-    // Format hh:mm:ss
-    let interval = hms_reg.replace(&interval, |caps: &Captures| {
-        // caps[0] is the full source string
-        format!("{} h {} min {} s", &caps[1], &caps[2], &caps[3])
-    });
+    let (interval, ago) = strip_ago(&normalize_interval(interval));
 
     let mut amount = 0;
+    let mut parsed = 0;
     for (_, [sign, count, itype]) in part_reg.captures_iter(&interval).map(|c| c.extract()) {
         let count = match count.parse::<i64>() {
             Ok(c) => c,
@@ -51,23 +127,9 @@ pub fn interval_to_seconds(interval: &str) -> EgResult<i64> {
             }
         };
 
-        let change = if itype.starts_with("s") {
-            count
-        } else if itype.starts_with("min") {
-            count * 60
-        } else if itype.starts_with("h") {
-            count * 60 * 60
-        } else if itype.starts_with("d") {
-            count * 60 * 60 * 24
-        } else if itype.starts_with("w") {
-            count * 60 * 60 * 24 * 7
-        } else if itype.starts_with("mon") {
-            (count * 60 * 60 * 24 * 365) / 12
-        } else if itype.starts_with("y") {
-            count * 60 * 60 * 24 * 365
-        } else {
-            0
-        };
+        parsed += 1;
+
+        let change = count * interval_unit_seconds(itype).unwrap_or(0);
 
         if sign == "-" {
             amount -= change;
@@ -76,17 +138,190 @@ pub fn interval_to_seconds(interval: &str) -> EgResult<i64> {
         }
     }
 
+    if parsed == 0 {
+        // Avoid treating e.g. a blank setting value as a zero interval.
+        return Err(EgError::Validation(format!(
+            "Interval contains no values: '{interval}'"
+        )));
+    }
+
+    if ago {
+        amount = -amount;
+    }
+
     Ok(amount)
 }
 
+/// Remove a trailing "ago" from a normalized interval string,
+/// returning the remaining text and whether "ago" was found.
+fn strip_ago(interval: &str) -> (String, bool) {
+    let trimmed = interval.trim_end();
+
+    if let Some(rest) = trimmed.strip_suffix("ago") {
+        if rest.is_empty() || rest.ends_with(char::is_whitespace) {
+            return (rest.to_string(), true);
+        }
+    }
+
+    (interval.to_string(), false)
+}
+
+/// Variant of interval_to_seconds() which returns an Err on any
+/// unrecognized unit or unparseable text instead of ignoring it.
+///
+/// ```
+/// use evergreen::date;
+///
+/// assert_eq!(date::interval_to_seconds_strict("1 day 00:30:00").unwrap(), 88200);
+/// assert!(date::interval_to_seconds_strict("1 fortnite").is_err());
+/// assert!(date::interval_to_seconds_strict("1 day extra").is_err());
+/// assert!(date::interval_to_seconds_strict("daily").is_err());
+/// assert_eq!(date::interval_to_seconds_strict("2 hours ago").unwrap(), -7200);
+/// assert!(date::interval_to_seconds_strict("ago 2 hours").is_err());
+/// ```
+pub fn interval_to_seconds_strict(interval: &str) -> EgResult<i64> {
+    validate_interval(interval)?;
+    interval_to_seconds(interval)
+}
+
+/// Lowercase an interval string, remove conjunctions, and expand
+/// hh:mm:ss components into their h/min/s equivalent.
+fn normalize_interval(interval: &str) -> String {
+    let hms_reg = Regex::new(INTERVAL_HMS_REGEX).unwrap();
+
+    let mut interval = interval.to_lowercase();
+    interval = interval.replace("and", ",");
+    interval = interval.replace(",", " ");
+
+    // Format hh:mm:ss
+    hms_reg
+        .replace(&interval, |caps: &Captures| {
+            // caps[0] is the full source string
+            format!("{} h {} min {} s", &caps[1], &caps[2], &caps[3])
+        })
+        .to_string()
+}
+
+/// Number of seconds in one of the provided interval unit, e.g. "min".
+///
+/// None if the unit is not recognized.
+fn interval_unit_seconds(itype: &str) -> Option<i64> {
+    let secs = if itype.starts_with("s") {
+        1
+    } else if itype.starts_with("min") {
+        60
+    } else if itype.starts_with("h") {
+        60 * 60
+    } else if itype.starts_with("d") {
+        60 * 60 * 24
+    } else if itype.starts_with("w") {
+        60 * 60 * 24 * 7
+    } else if itype.starts_with("mon") {
+        (60 * 60 * 24 * 365) / 12
+    } else if itype.starts_with("y") {
+        60 * 60 * 24 * 365
+    } else {
+        return None;
+    };
+
+    Some(secs)
+}
+
+/// Verify an interval string is made up entirely of number/unit
+/// pairs, optionally followed by "ago", using units understood by
+/// interval_to_seconds().
+///
+/// interval_to_seconds() treats unknown units as zero and skips text
+/// it does not understand, which can turn a typo into a silent no-op.
+/// Use this to reject bad values up front.  The Err names the first
+/// bad token.
+///
+/// ```
+/// use evergreen::date;
+///
+/// assert!(date::validate_interval("1 day 2 hours").is_ok());
+/// assert!(date::validate_interval("00:15:00").is_ok());
+/// assert!(date::validate_interval("2 hours ago").is_ok());
+///
+/// let err = date::validate_interval("1 fortnite").unwrap_err();
+/// assert!(err.to_string().contains("fortnite"));
+///
+/// assert!(date::validate_interval("").is_err());
+///
+/// let err = date::validate_interval("daily").unwrap_err();
+/// assert!(err.to_string().contains("'daily'"));
+///
+/// let err = date::validate_interval("1 day extra").unwrap_err();
+/// assert!(err.to_string().contains("'extra'"));
+/// ```
+pub fn validate_interval(interval: &str) -> EgResult<()> {
+    let part_reg = Regex::new(INTERVAL_PART_REGEX).unwrap();
+    let (normalized, _) = strip_ago(&normalize_interval(interval));
+
+    // First word of any text between number/unit pairs.
+    let check_text = |text: &str| match text.split_whitespace().next() {
+        Some(token) => Err(EgError::Validation(format!(
+            "Invalid interval text '{token}' in '{interval}'"
+        ))),
+        None => Ok(()),
+    };
+
+    let mut parsed_to = 0;
+
+    for caps in part_reg.captures_iter(&normalized) {
+        let part = caps.get(0).unwrap(); // the full match is always set
+
+        check_text(&normalized[parsed_to..part.start()])?;
+
+        let itype = &caps[3];
+        if interval_unit_seconds(itype).is_none() {
+            return Err(EgError::Validation(format!(
+                "Invalid interval unit '{itype}' in '{interval}'"
+            )));
+        }
+
+        parsed_to = part.end();
+    }
+
+    check_text(&normalized[parsed_to..])?;
+
+    if parsed_to == 0 {
+        return Err(EgError::Validation(format!(
+            "Interval contains no values: '{interval}'"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Compare two interval strings by their length in seconds.
+///
+/// Returns an Err if either interval cannot be parsed.
+///
+/// ```
+/// use evergreen::date;
+/// use std::cmp::Ordering;
+///
+/// assert_eq!(date::interval_cmp("1 day", "23 hours").unwrap(), Ordering::Greater);
+/// assert_eq!(date::interval_cmp("00:15:00", "15 minutes").unwrap(), Ordering::Equal);
+/// assert_eq!(date::interval_cmp("1 day ago", "0s").unwrap(), Ordering::Less);
+/// assert!(date::interval_cmp("", "1 day").is_err());
+/// ```
+pub fn interval_cmp(a: &str, b: &str) -> EgResult<Ordering> {
+    Ok(interval_to_seconds(a)?.cmp(&interval_to_seconds(b)?))
+}
+
 /// Current date/time with a fixed offset matching the local time zone.
 pub fn now_local() -> EgDate {
     now()
 }
 
 /// Current date/time with a fixed offset matching the local time zone.
+///
+/// The time comes from the current thread's clock.  See
+/// set_thread_clock().
 pub fn now() -> EgDate {
-    to_local_timezone_fixed(Local::now().into())
+    to_local_timezone_fixed(THREAD_CLOCK.with(|c| c.borrow().now()))
 }
 
 /// Parse an ISO date string and return a date which retains its original
@@ -95,6 +330,10 @@ pub fn now() -> EgDate {
 /// If the datetime string is in the Local timezone, for example, the
 /// DateTime value produced will also be in the local timezone.
 ///
+/// Date-only strings (YYYY-MM-DD) are anchored at midnight in the
+/// local timezone of the running process.  When the date belongs to
+/// a specific location, e.g. a branch, use parse_date_in_timezone().
+///
 /// ```
 /// use evergreen::date;
 /// use chrono::{DateTime, FixedOffset, Local};
@@ -127,18 +366,26 @@ pub fn parse_datetime(dt: &str) -> EgResult<EgDate> {
         // Assume its a full date + time
         return match dt.parse::<EgDate>() {
             Ok(d) => Ok(d),
-            Err(e) => return Err(format!("Could not parse datetime string: {e} {dt}").into()),
+            Err(e) => {
+                return Err(EgError::Validation(format!(
+                    "Could not parse datetime string: {e} {dt}"
+                )))
+            }
         };
     }
 
     if dt.len() < 10 {
-        return Err(format!("Invalid date string: {dt}").into());
+        return Err(EgError::Validation(format!("Invalid date string: {dt}")));
     }
 
     // Assumes it's just a YYYY-MM-DD
     let date = match dt.parse::<NaiveDate>() {
         Ok(d) => d,
-        Err(e) => return Err(format!("Could not parse date string: {e} {dt}").into()),
+        Err(e) => {
+            return Err(EgError::Validation(format!(
+                "Could not parse date string: {e} {dt}"
+            )))
+        }
     };
 
     // If we only have a date, use the local timezone.
@@ -147,12 +394,62 @@ pub fn parse_datetime(dt: &str) -> EgResult<EgDate> {
         .earliest()
     {
         Some(d) => d,
-        None => return Err(format!("Could not parse date string: {dt}").into()),
+        None => {
+            return Err(EgError::Validation(format!(
+                "Could not parse date string: {dt}"
+            )))
+        }
     };
 
     Ok(local_date.into())
 }
 
+/// Parse a date-only (YYYY-MM-DD) string as midnight in the provided
+/// timezone instead of the local timezone of the running process.
+///
+/// Use this in place of parse_datetime() for dates whose meaning
+/// depends on a location, e.g. closed dates or due dates for a branch
+/// whose timezone may differ from the server's.  "local" is accepted
+/// as with set_timezone().
+///
+/// ```
+/// use evergreen::date;
+///
+/// let dt = date::parse_date_in_timezone("2023-07-11", "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&dt), "2023-07-11T00:00:00-0400");
+///
+/// let dt = date::parse_date_in_timezone("2023-01-11", "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&dt), "2023-01-11T00:00:00-0500");
+///
+/// assert!(date::parse_date_in_timezone("2023-07-11", "Mars/Olympus_Mons").is_err());
+/// assert!(date::parse_date_in_timezone("2023-07-11T12:00:00-0400", "UTC").is_err());
+/// ```
+pub fn parse_date_in_timezone(dt: &str, timezone: &str) -> EgResult<EgDate> {
+    if timezone == "local" {
+        if dt.len() != 10 {
+            return Err(EgError::Validation(format!("Invalid date string: {dt}")));
+        }
+        return parse_datetime(dt);
+    }
+
+    let date = dt
+        .parse::<NaiveDate>()
+        .map_err(|e| EgError::Validation(format!("Could not parse date string: {e} {dt}")))?;
+
+    let tz: Tz = timezone
+        .parse()
+        .map_err(|e| EgError::Validation(format!("Cannot parse timezone: {timezone} {e}")))?;
+
+    // Midnight may not exist or may be ambiguous on DST transition
+    // days in some timezones.  Use the earliest valid option.
+    let midnight = tz
+        .with_ymd_and_hms(date.year(), date.month(), date.day(), 0, 0, 0)
+        .earliest()
+        .ok_or_else(|| EgError::Validation(format!("Could not parse date string: {dt}")))?;
+
+    Ok(midnight.fixed_offset())
+}
+
 /// Turn a DateTime into the kind of date string we like in these parts.
 /// ```
 /// use evergreen::date;
@@ -164,6 +461,24 @@ pub fn to_iso(dt: &EgDate) -> String {
     dt.format("%FT%T%z").to_string()
 }
 
+/// Date-only YYYY-MM-DD string, e.g. for expire_date values.
+///
+/// The date is the calendar date in the datetime's own offset, which
+/// may differ from the UTC date for times near midnight.  Apply
+/// set_timezone() first if the date should reflect another timezone.
+///
+/// ```
+/// use evergreen::date;
+/// let dt: date::EgDate = "2023-07-11T22:00:00-0400".parse().unwrap();
+/// assert_eq!(date::to_iso_date(&dt), "2023-07-11");
+///
+/// let dt = date::set_timezone(dt, "UTC").unwrap();
+/// assert_eq!(date::to_iso_date(&dt), "2023-07-12");
+/// ```
+pub fn to_iso_date(dt: &EgDate) -> String {
+    dt.format("%F").to_string()
+}
+
 /// Same as to_iso but includes milliseconds
 /// e.g. 2023-09-08T10:59:01.687-0400
 pub fn to_iso_millis(dt: &EgDate) -> String {
@@ -214,15 +529,19 @@ pub fn set_timezone(dt: EgDate, timezone: &str) -> EgResult<EgDate> {
     }
 
     // Parse the time zone string.
-    let tz: Tz = timezone
-        .parse()
-        .or_else(|e| Err(format!("Cannot parse timezone: {timezone} {e}")))?;
+    let tz: Tz = timezone.parse().or_else(|e| {
+        Err(EgError::Validation(format!(
+            "Cannot parse timezone: {timezone} {e}"
+        )))
+    })?;
 
     let modified = dt.with_timezone(&tz);
 
     let fixed: EgDate = match modified.format("%FT%T%z").to_string().parse() {
         Ok(f) => f,
-        Err(e) => Err(format!("Cannot reconstruct date: {modified:?} : {e}"))?,
+        Err(e) => Err(EgError::Validation(format!(
+            "Cannot reconstruct date: {modified:?} : {e}"
+        )))?,
     };
 
     Ok(fixed)
@@ -244,13 +563,17 @@ pub fn set_hms(date: &EgDate, hours: u32, minutes: u32, seconds: u32) -> EgResul
 
     let datetime = match date.date_naive().and_hms_opt(hours, minutes, seconds) {
         Some(dt) => dt,
-        None => Err(format!("Could not set time to {hours}:{minutes}:{seconds}"))?,
+        None => Err(EgError::Validation(format!(
+            "Could not set time to {hours}:{minutes}:{seconds}"
+        )))?,
     };
 
     // and_local_timezone() can return multiples in cases where it's ambiguous.
     let new_date: EgDate = match datetime.and_local_timezone(offset).single() {
         Some(d) => d,
-        None => Err(format!("Error setting timezone for datetime {datetime:?}"))?,
+        None => Err(EgError::Validation(format!(
+            "Error setting timezone for datetime {datetime:?}"
+        )))?,
     };
 
     Ok(new_date)
@@ -270,7 +593,103 @@ pub fn set_hms(date: &EgDate, hours: u32, minutes: u32, seconds: u32) -> EgResul
 pub fn add_interval(date: EgDate, interval: &str) -> EgResult<EgDate> {
     let seconds = interval_to_seconds(interval)?;
     let duration = Duration::try_seconds(seconds)
-        .ok_or_else(|| format!("Invalid duration seconds: {seconds}"))?;
+        .ok_or_else(|| EgError::Validation(format!("Invalid duration seconds: {seconds}")))?;
+
+    Ok(date + duration)
+}
+
+/// Add calendar days to a date in the provided timezone, preserving
+/// the wall-clock time across daylight saving transitions.
+///
+/// Adding "1 day" via add_interval() adds exactly 86400 seconds, which
+/// shifts the local time by an hour when a DST boundary is crossed.
+///
+/// If the resulting wall-clock time does not exist in the timezone
+/// (it falls in a spring-forward gap), the time is pushed forward
+/// past the gap.
+///
+/// "local" is accepted as with set_timezone().
+///
+/// ```
+/// use evergreen::date;
+///
+/// // 2024-03-10 is a US spring-forward date.
+/// let dt = date::parse_datetime("2024-03-08T00:00:00-0500").unwrap();
+///
+/// let due = date::add_days_dst_aware(dt, 7, "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&due), "2024-03-15T00:00:00-0400");
+///
+/// // Fixed-length arithmetic lands an hour later on the wall clock.
+/// let due = date::add_interval(dt, "7 days").unwrap();
+/// let due = date::set_timezone(due, "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&due), "2024-03-15T01:00:00-0400");
+///
+/// // Negative values move backwards.
+/// let dt = date::parse_datetime("2024-03-11T09:00:00-0400").unwrap();
+/// let prev = date::add_days_dst_aware(dt, -2, "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&prev), "2024-03-09T09:00:00-0500");
+/// ```
+pub fn add_days_dst_aware(date: EgDate, days: i64, timezone: &str) -> EgResult<EgDate> {
+    if timezone == "local" {
+        return add_days_in_timezone(&date, days, &Local);
+    }
+
+    let tz: Tz = timezone
+        .parse()
+        .map_err(|e| EgError::Validation(format!("Cannot parse timezone: {timezone} {e}")))?;
+
+    add_days_in_timezone(&date, days, &tz)
+}
+
+fn add_days_in_timezone<T: TimeZone>(date: &EgDate, days: i64, tz: &T) -> EgResult<EgDate> {
+    let local = date.with_timezone(tz).naive_local();
+
+    let shifted = if days >= 0 {
+        local.checked_add_days(Days::new(days as u64))
+    } else {
+        local.checked_sub_days(Days::new(days.unsigned_abs()))
+    }
+    .ok_or_else(|| EgError::Validation(format!("Cannot add {days} days to {date}")))?;
+
+    // A nonexistent local time means we landed in a DST gap.  Gaps
+    // are an hour or less in practice, so step forward out of it.
+    let result = tz
+        .from_local_datetime(&shifted)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(shifted + Duration::hours(1)))
+                .earliest()
+        })
+        .ok_or_else(|| {
+            EgError::Validation(format!(
+                "Cannot resolve local time {shifted} for date {date}"
+            ))
+        })?;
+
+    Ok(result.fixed_offset())
+}
+
+/// Add an interval (string) to a date, applying whole days as calendar
+/// days in the provided timezone (see add_days_dst_aware()) and any
+/// remainder as a fixed number of seconds.
+///
+/// ```
+/// use evergreen::date;
+///
+/// let dt = date::parse_datetime("2024-03-08T12:00:00-0500").unwrap();
+/// let due = date::add_interval_dst_aware(dt, "1 week 2 hours", "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&due), "2024-03-15T14:00:00-0400");
+/// ```
+pub fn add_interval_dst_aware(date: EgDate, interval: &str, timezone: &str) -> EgResult<EgDate> {
+    let seconds = interval_to_seconds(interval)?;
+
+    let days = seconds / 86400;
+    let remainder = seconds % 86400;
+
+    let date = add_days_dst_aware(date, days, timezone)?;
+
+    let duration = Duration::try_seconds(remainder)
+        .ok_or_else(|| EgError::Validation(format!("Invalid duration seconds: {remainder}")))?;
 
     Ok(date + duration)
 }
@@ -278,12 +697,134 @@ pub fn add_interval(date: EgDate, interval: &str) -> EgResult<EgDate> {
 pub fn subtract_interval(date: EgDate, interval: &str) -> EgResult<EgDate> {
     let seconds = interval_to_seconds(interval)?;
     let duration = Duration::try_seconds(seconds)
-        .ok_or_else(|| format!("Invalid duration seconds: {seconds}"))?;
+        .ok_or_else(|| EgError::Validation(format!("Invalid duration seconds: {seconds}")))?;
 
     Ok(date - duration)
 }
 
+/// Returns true if the date/time falls after now minus the interval.
+///
+/// Both values are compared as UTC instants, so the offset of the
+/// provided date/time does not matter.
+///
+/// ```
+/// use chrono::{Duration, FixedOffset, Utc};
+/// use evergreen::date;
+///
+/// // One hour ago, expressed in a far-from-local offset.
+/// let offset = FixedOffset::east_opt(13 * 3600).unwrap();
+/// let dt = (Utc::now() - Duration::hours(1)).with_timezone(&offset);
+///
+/// assert!(date::is_within_interval_of_now(&dt, "2 hours").unwrap());
+/// assert!(!date::is_within_interval_of_now(&dt, "30 minutes").unwrap());
+/// ```
+pub fn is_within_interval_of_now(dt: &EgDate, interval: &str) -> EgResult<bool> {
+    let seconds = interval_to_seconds(interval)?;
+    let duration = Duration::try_seconds(seconds)
+        .ok_or_else(|| EgError::Validation(format!("Invalid duration seconds: {seconds}")))?;
+
+    let window_start = now().with_timezone(&Utc) - duration;
+
+    Ok(dt.with_timezone(&Utc) > window_start)
+}
+
+/// Largest unit displayed by format_duration_with().
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DurationUnit {
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+}
+
+/// Render a Duration as a friendly string, e.g. "2 days, 3 hours".
+///
+/// Shows days, hours, and minutes; see format_duration_with().
+///
+/// ```
+/// use evergreen::date;
+/// use chrono::Duration;
+///
+/// let d = Duration::days(2) + Duration::hours(3) + Duration::seconds(20);
+/// assert_eq!(date::format_duration(d), "2 days, 3 hours");
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    format_duration_with(duration, DurationUnit::Days, false)
+}
+
+/// Render a Duration as a friendly string, with control over the
+/// largest unit shown and whether seconds are included.
+///
+/// Values larger than the largest unit roll into it, e.g. 36 hours
+/// with a largest unit of Hours is "36 hours".  Zero-valued parts
+/// are omitted.  A duration with no displayable parts is rendered
+/// as "0" of the smallest unit shown.
+///
+/// Negative durations (e.g. time remaining on an overdue item) are
+/// prefixed with "-".  Callers wanting "overdue by ..." phrasing can
+/// format the absolute value, e.g. format_duration(d.abs()).
+///
+/// ```
+/// use evergreen::date;
+/// use date::DurationUnit;
+/// use chrono::Duration;
+///
+/// let d = Duration::hours(36) + Duration::minutes(1) + Duration::seconds(5);
+/// assert_eq!(date::format_duration_with(d, DurationUnit::Hours, true), "36 hours, 1 minute, 5 seconds");
+/// assert_eq!(date::format_duration_with(d, DurationUnit::Days, false), "1 day, 12 hours, 1 minute");
+///
+/// assert_eq!(date::format_duration(-Duration::days(3)), "-3 days");
+/// assert_eq!(date::format_duration(Duration::seconds(30)), "0 minutes");
+/// ```
+pub fn format_duration_with(
+    duration: Duration,
+    largest_unit: DurationUnit,
+    include_seconds: bool,
+) -> String {
+    let mut remaining = duration.num_seconds().unsigned_abs();
+
+    let units = [
+        (DurationUnit::Days, 86400, "day"),
+        (DurationUnit::Hours, 3600, "hour"),
+        (DurationUnit::Minutes, 60, "minute"),
+        (DurationUnit::Seconds, 1, "second"),
+    ];
+
+    let mut parts = Vec::new();
+    let mut smallest = "minute";
+
+    for (unit, secs, label) in units {
+        if unit > largest_unit {
+            continue;
+        }
+        if unit == DurationUnit::Seconds && !include_seconds {
+            break;
+        }
+
+        smallest = label;
+
+        let count = remaining / secs;
+        remaining %= secs;
+
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            parts.push(format!("{count} {label}{plural}"));
+        }
+    }
+
+    if parts.is_empty() {
+        return format!("0 {smallest}s");
+    }
+
+    let sign = if duration < Duration::zero() { "-" } else { "" };
+
+    format!("{sign}{}", parts.join(", "))
+}
+
 /// Epoch seconds with fractional milliseconds.
+///
+/// Always reads the system clock, since it's used for timing and
+/// logging, not business logic.
 pub fn epoch_secs() -> f64 {
     if let Ok(dur) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
         let ms = dur.as_millis();
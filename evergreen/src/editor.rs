@@ -1,14 +1,24 @@
 //! Create, Retrieve, Update, Delete IDL-classed objects via (by default) open-ils.cstore.
 use crate as eg;
+use eg::common::org::OrgRelationsKey;
+use eg::common::settings::Settings;
 use eg::event::EgEvent;
 use eg::idl;
+use eg::osrf::breaker;
 use eg::osrf::params::ApiParams;
+use eg::osrf::session;
 use eg::result::{EgError, EgResult};
 use eg::Client;
 use eg::ClientSession;
 use eg::EgValue;
+use std::collections::HashMap;
 
-const DEFAULT_TIMEOUT: i32 = 60;
+/// Default maximum "flesh" depth for retrieve and search calls.
+pub const DEFAULT_MAX_FLESH_DEPTH: i64 = 5;
+
+/// Org setting whose value is the ID of the user which automated
+/// processes act as.  See Editor::set_system_user().
+pub const SYSTEM_USER_SETTING: &str = "lib.system_user";
 
 /// Specifies Which service are we communicating with.
 #[derive(Debug, Clone, PartialEq)]
@@ -46,6 +56,161 @@ pub struct QueryOps {
 }
 */
 
+/// Builds the "order_by" value for search and json_query calls.
+///
+/// ```
+/// use evergreen::editor::OrderBy;
+///
+/// let order = OrderBy::new("mb", "billing_ts").then("id").desc();
+/// let value = order.to_value_unchecked();
+///
+/// assert_eq!(value[0]["class"].as_str(), Some("mb"));
+/// assert_eq!(value[0]["field"].as_str(), Some("billing_ts"));
+/// assert_eq!(value[0]["direction"].as_str(), Some("asc"));
+/// assert_eq!(value[1]["field"].as_str(), Some("id"));
+/// assert_eq!(value[1]["direction"].as_str(), Some("desc"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct OrderBy {
+    classname: String,
+    /// (field name, descending)
+    fields: Vec<(String, bool)>,
+}
+
+impl OrderBy {
+    /// Order by a field, ascending.
+    pub fn new(classname: &str, field: &str) -> Self {
+        OrderBy {
+            classname: classname.to_string(),
+            fields: vec![(field.to_string(), false)],
+        }
+    }
+
+    /// Sort the most recently added field in ascending order.
+    pub fn asc(mut self) -> Self {
+        if let Some(f) = self.fields.last_mut() {
+            f.1 = false;
+        }
+        self
+    }
+
+    /// Sort the most recently added field in descending order.
+    pub fn desc(mut self) -> Self {
+        if let Some(f) = self.fields.last_mut() {
+            f.1 = true;
+        }
+        self
+    }
+
+    /// Add a secondary sort field, ascending.
+    pub fn then(mut self, field: &str) -> Self {
+        self.fields.push((field.to_string(), false));
+        self
+    }
+
+    /// Returns an Err if the class or any field is not in the IDL.
+    pub fn validate(&self, idl: &idl::Parser) -> EgResult<()> {
+        for (field, _) in self.fields.iter() {
+            if !idl.field_exists(&self.classname, field) {
+                return Err(EgError::Validation(format!(
+                    "Cannot order by unknown field {}.{field}",
+                    self.classname
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify the fields against the loaded IDL and compile the
+    /// order_by value.
+    pub fn to_value(&self) -> EgResult<EgValue> {
+        self.validate(idl::parser())?;
+        Ok(self.to_value_unchecked())
+    }
+
+    /// Compile the order_by value without consulting the IDL.
+    pub fn to_value_unchecked(&self) -> EgValue {
+        let mut list = EgValue::new_array();
+
+        for (field, desc) in self.fields.iter() {
+            let spec = eg::hash! {
+                "class": self.classname.as_str(),
+                "field": field.as_str(),
+                "direction": if *desc { "desc" } else { "asc" },
+            };
+            // Pushing onto a new array cannot fail.
+            list.push(spec).ok();
+        }
+
+        list
+    }
+}
+
+/// Billable transaction summary details, including the transaction's
+/// context (billing) org unit.
+///
+/// See Editor::fetch_xact_summary()
+#[derive(Debug, Clone)]
+pub struct XactSummary {
+    pub xact_id: i64,
+    pub usr: i64,
+    pub billing_location: i64,
+    pub balance_owed: f64,
+    /// True if the transaction has no xact_finish value.
+    pub xact_open: bool,
+    /// The money.billable_xact_summary_location_view object.
+    pub summary: EgValue,
+}
+
+/// Who is making the request and from where.
+///
+/// See Editor::requestor_context()
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Requestor {
+    /// actor.usr.id
+    pub id: i64,
+    /// Workstation ID, when the requestor logged in with a workstation.
+    pub wsid: Option<i64>,
+    /// Workstation org unit, when the requestor logged in with a
+    /// workstation.
+    pub ws_ou: Option<i64>,
+    pub home_ou: i64,
+}
+
+impl Requestor {
+    /// Build from an "au" object as returned by an auth session
+    /// retrieve, which includes the "wsid" and "ws_ou" virtual fields.
+    ///
+    /// ```
+    /// use evergreen as eg;
+    /// use eg::editor::Requestor;
+    ///
+    /// let user = eg::hash! {"id": 1, "home_ou": 4, "wsid": 7, "ws_ou": 5};
+    /// let reqr = Requestor::from_value(&user).unwrap();
+    /// assert_eq!(reqr.context_org(), 5);
+    ///
+    /// let user = eg::hash! {"id": 1, "home_ou": 4};
+    /// let reqr = Requestor::from_value(&user).unwrap();
+    /// assert_eq!(reqr.wsid, None);
+    /// assert_eq!(reqr.context_org(), 4);
+    /// ```
+    pub fn from_value(user: &EgValue) -> EgResult<Self> {
+        Ok(Requestor {
+            id: user.id()?,
+            wsid: user["wsid"].as_int(),
+            ws_ou: user["ws_ou"].as_int(),
+            home_ou: user["home_ou"].int()?,
+        })
+    }
+
+    /// Org unit whose settings and permissions apply to the requestor's
+    /// actions: the workstation org unit, falling back to the
+    /// requestor's home org unit when there is no workstation.
+    pub fn context_org(&self) -> i64 {
+        self.ws_ou.unwrap_or(self.home_ou)
+    }
+}
+
 pub struct Editor {
     client: Client,
     session: Option<ClientSession>,
@@ -56,6 +221,13 @@ pub struct Editor {
     requestor: Option<EgValue>,
     timeout: i32,
 
+    /// User ID which automated processes act as when there is no
+    /// requestor.
+    system_user: Option<i64>,
+
+    /// Maximum "flesh" depth allowed in retrieve/search options.
+    max_flesh_depth: i64,
+
     /// True if the caller wants us to perform actions within
     /// a transaction.  Write actions require this.
     xact_wanted: bool,
@@ -67,6 +239,11 @@ pub struct Editor {
     last_event: Option<EgEvent>,
 
     has_pending_changes: bool,
+
+    /// Org unit relations looked up via common::org.  The org unit
+    /// tree rarely changes, so they're kept for the life of the
+    /// Editor.
+    org_relations: HashMap<OrgRelationsKey, Vec<i64>>,
 }
 
 impl Clone for Editor {
@@ -75,6 +252,7 @@ impl Clone for Editor {
         e.personality = self.personality().clone();
         e.authtoken = self.authtoken().map(str::to_string);
         e.requestor = self.requestor().map(|r| r.clone());
+        e.system_user = self.system_user;
         e
     }
 }
@@ -85,32 +263,118 @@ impl Editor {
         Editor {
             client: client.clone(),
             personality: "".into(),
-            timeout: DEFAULT_TIMEOUT,
+            timeout: session::default_request_timeout(),
+            max_flesh_depth: DEFAULT_MAX_FLESH_DEPTH,
             xact_wanted: false,
             xact_id: None,
             session: None,
             authtoken: None,
             authtime: None,
             requestor: None,
+            system_user: None,
             last_event: None,
             has_pending_changes: false,
+            org_relations: HashMap::new(),
         }
     }
 
     /// Apply a new request timeout value in seconds.
+    ///
+    /// Requests which receive no response within the timeout return
+    /// an Err.  See Request::first_with_timeout().
     pub fn set_timeout(&mut self, timeout: i32) {
         self.timeout = timeout;
     }
 
-    /// Reset to the default timeout
+    /// Reset to the default timeout, configured via <request_timeout>
+    /// in opensrf_core.xml.
     pub fn reset_timeout(&mut self) {
-        self.timeout = DEFAULT_TIMEOUT;
+        self.timeout = session::default_request_timeout();
+    }
+
+    /// Run a set of Editor calls with a different request timeout,
+    /// restoring the previous timeout afterward.
+    pub fn with_timeout<T>(
+        &mut self,
+        timeout: i32,
+        f: impl FnOnce(&mut Editor) -> EgResult<T>,
+    ) -> EgResult<T> {
+        let orig_timeout = self.timeout;
+        self.timeout = timeout;
+
+        let result = f(self);
+
+        self.timeout = orig_timeout;
+
+        result
+    }
+
+    /// Apply a new maximum flesh depth for retrieve and search calls.
+    ///
+    /// Calls whose options request a deeper (or unlimited, i.e.
+    /// negative) flesh return an Err before anything is sent.
+    pub fn set_max_flesh_depth(&mut self, depth: i64) {
+        self.max_flesh_depth = depth;
+    }
+
+    /// Reset to DEFAULT_MAX_FLESH_DEPTH.
+    pub fn reset_max_flesh_depth(&mut self) {
+        self.max_flesh_depth = DEFAULT_MAX_FLESH_DEPTH;
+    }
+
+    /// Run a set of Editor calls with a different maximum flesh depth,
+    /// restoring the previous maximum afterward.
+    pub fn with_max_flesh_depth<T>(
+        &mut self,
+        depth: i64,
+        f: impl FnOnce(&mut Editor) -> EgResult<T>,
+    ) -> EgResult<T> {
+        let orig_depth = self.max_flesh_depth;
+        self.max_flesh_depth = depth;
+
+        let result = f(self);
+
+        self.max_flesh_depth = orig_depth;
+
+        result
+    }
+
+    /// Returns Err if the options request a flesh depth beyond our
+    /// maximum.
+    fn check_flesh_depth(&self, ops: &EgValue) -> EgResult<()> {
+        let depth = match ops["flesh"].as_int() {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        if depth < 0 || depth > self.max_flesh_depth {
+            return Err(format!(
+                "Flesh depth {depth} exceeds the maximum of {}.  \
+                See Editor::with_max_flesh_depth()",
+                self.max_flesh_depth
+            )
+            .into());
+        }
+
+        Ok(())
     }
 
     pub fn client_mut(&mut self) -> &mut Client {
         &mut self.client
     }
 
+    /// Ref to the global IDL, e.g. for verifying field names.
+    ///
+    /// This is the same process-wide instance as eg::idl().  It is not
+    /// copied per Editor.
+    ///
+    /// ```text
+    /// if editor.idl().field_exists("mb", "voided") { ... }
+    /// ```
+    pub fn idl(&self) -> &'static idl::Parser {
+        eg::idl()
+    }
+
     /// True if create/update/delete have been called within a
     /// transaction that has yet to be committed or rolled back.
     ///
@@ -200,10 +464,12 @@ impl Editor {
         self.xact_id.is_some()
     }
 
-    /// ID of the requestor.
+    /// ID of the requestor, or the system user if no requestor is set.
     pub fn requestor_id(&self) -> EgResult<i64> {
         if let Some(req) = self.requestor() {
             req.id()
+        } else if let Some(id) = self.system_user {
+            Ok(id)
         } else {
             Err(format!("Editor has no requestor").into())
         }
@@ -251,9 +517,125 @@ impl Editor {
         self.requestor.as_ref()
     }
 
-    /// True if a requestor is set
+    /// ID, workstation, and org unit details of the requestor.
+    ///
+    /// Returns None if no requestor is set or the requestor value
+    /// lacks an ID or home org unit.  See Requestor::context_org() for
+    /// the org unit to use when there's no workstation.
+    pub fn requestor_context(&self) -> Option<Requestor> {
+        Requestor::from_value(self.requestor()?).ok()
+    }
+
+    /// True if a requestor or system user is set
     pub fn has_requestor(&self) -> bool {
-        self.requestor.is_some()
+        self.requestor.is_some() || self.system_user.is_some()
+    }
+
+    /// Act as the specified user when there is no requestor, e.g. for
+    /// a nightly fine generator, so the user is stamped on the
+    /// financial rows we create (voider, accepting_usr, etc.).
+    ///
+    /// An authenticated requestor always takes precedence.
+    ///
+    /// Permission checks (allowed(), check_perm()) are run against
+    /// the system user's permissions, so the account needs the
+    /// permissions for whatever the automation does.  Without a
+    /// requestor there is no workstation or home org unit to default
+    /// to (see perm_org()), so use allowed_at() or check_perm() with
+    /// an explicit org unit.  Code paths which skip permission checks
+    /// for system actions are unaffected.
+    pub fn set_system_user(&mut self, user_id: Option<i64>) {
+        self.system_user = user_id;
+    }
+
+    pub fn system_user(&self) -> Option<i64> {
+        self.system_user
+    }
+
+    /// Apply the system user configured via the SYSTEM_USER_SETTING
+    /// org setting at the org unit, returning the user ID, if any.
+    pub fn apply_system_user_setting(&mut self, org_id: i64) -> EgResult<Option<i64>> {
+        let mut settings = Settings::new(self);
+        let value = settings.get_value_at_org(SYSTEM_USER_SETTING, org_id)?;
+
+        let user_id = match value.as_int() {
+            Some(id) => Some(id),
+            None if value.is_null() => None,
+            None => {
+                return Err(EgError::Validation(format!(
+                    "Invalid {SYSTEM_USER_SETTING} value at org unit {org_id}: {value}"
+                )))
+            }
+        };
+
+        self.system_user = user_id;
+
+        Ok(user_id)
+    }
+
+    /// Cached org unit relations.  See common::org.
+    pub(crate) fn org_relations(&mut self) -> &mut HashMap<OrgRelationsKey, Vec<i64>> {
+        &mut self.org_relations
+    }
+
+    /// Run a set of Editor calls as the system user, restoring the
+    /// requestor afterward, whether or not the calls succeed.
+    ///
+    /// This is for operations which are authorized as a whole, e.g.
+    /// transaction aging or penalty recalculation, but which make
+    /// changes the invoking staff member may lack the individual
+    /// permissions for.  Within the closure, permission checks run
+    /// against the system user and the system user is stamped on any
+    /// rows created (voider, accepting_usr, etc.).  The requestor has
+    /// no workstation or home org unit while elevated, so use
+    /// allowed_at() or check_perm() with an explicit org unit.
+    ///
+    /// Returns Err if no system user is set (see
+    /// apply_system_user_setting()) or the Editor uses the pcrud
+    /// personality, whose permission checks use our authtoken and
+    /// cannot be elevated.
+    ///
+    /// Security: anything the closure does is done with the system
+    /// user's permissions, regardless of who invoked it.  Callers must
+    /// verify the requestor is allowed to run the operation as a whole
+    /// before elevating, and must not let API parameters decide which
+    /// changes the closure makes beyond what the operation allows.
+    /// This is crate-private so every caller lives in this crate and
+    /// is reviewed with it.  Each elevation is logged with the
+    /// original requestor and the calling code location.
+    #[track_caller]
+    pub(crate) fn with_system_perms<T>(
+        &mut self,
+        f: impl FnOnce(&mut Editor) -> EgResult<T>,
+    ) -> EgResult<T> {
+        let caller = std::panic::Location::caller();
+
+        let system_user = match self.system_user {
+            Some(id) => id,
+            None => return Err(format!("Cannot elevate at {caller}: no system user set").into()),
+        };
+
+        if self.personality == Personality::Pcrud {
+            return Err(format!("Cannot elevate a pcrud Editor at {caller}").into());
+        }
+
+        let orig_requestor = self.requestor.take();
+
+        let orig_id = orig_requestor
+            .as_ref()
+            .and_then(|r| r.id().ok())
+            .map(|id| id.to_string())
+            .unwrap_or("none".to_string());
+
+        log::info!("Elevating requestor {orig_id} to system user {system_user} at {caller}");
+
+        let result = f(self);
+
+        self.requestor = orig_requestor;
+
+        log::debug!("Restored requestor {orig_id} after system user elevation at {caller}");
+
+        result
     }
 
     pub fn set_requestor(&mut self, r: &EgValue) {
@@ -359,11 +741,17 @@ impl Editor {
             self.request_np(&self.app_method("transaction.rollback"))?;
         }
 
+        self.clear_xact_state();
+
+        Ok(())
+    }
+
+    /// Forget our transaction without telling the worker, e.g. once
+    /// the worker is disconnected and the transaction is gone anyway.
+    fn clear_xact_state(&mut self) {
         self.xact_id = None;
         self.xact_wanted = false;
         self.has_pending_changes = false;
-
-        Ok(())
     }
 
     /// Start a new transaction, connecting to a worker if necessary.
@@ -391,13 +779,52 @@ impl Editor {
             self.request(&method, xact_id)?;
         }
 
-        self.xact_id = None;
-        self.xact_wanted = false;
-        self.has_pending_changes = false;
+        self.clear_xact_state();
 
         Ok(())
     }
 
+    /// Create a named savepoint within the active transaction.
+    ///
+    /// Savepoints nest: setting a savepoint while others are active
+    /// pushes a new savepoint on top of them.  Rolling back to or
+    /// releasing a savepoint also discards any savepoints set after
+    /// it.  Reusing an active name creates a new savepoint which hides
+    /// the earlier one until the new one is released.
+    ///
+    /// Savepoints do not outlive their transaction and are discarded
+    /// by xact_commit() and xact_rollback().
+    pub fn savepoint_set(&mut self, name: &str) -> EgResult<()> {
+        self.savepoint_request("savepoint.set", name)
+    }
+
+    /// Release a savepoint, keeping all changes made since it was set.
+    pub fn savepoint_release(&mut self, name: &str) -> EgResult<()> {
+        self.savepoint_request("savepoint.release", name)
+    }
+
+    /// Discard all changes made since the savepoint was set.
+    ///
+    /// The savepoint remains active and may be rolled back to again.
+    /// This is the only way to continue using a transaction after a
+    /// failed write call.
+    pub fn savepoint_rollback(&mut self, name: &str) -> EgResult<()> {
+        self.savepoint_request("savepoint.rollback", name)
+    }
+
+    fn savepoint_request(&mut self, verb: &str, name: &str) -> EgResult<()> {
+        if !self.in_transaction() {
+            Err(format!("Transaction required for {verb} {name}"))?;
+        }
+
+        let method = self.app_method(verb);
+
+        match self.request(&method, name)? {
+            Some(_) => Ok(()),
+            None => Err(format!("{method} returned no response").into()),
+        }
+    }
+
     /// End the stateful conversation with the remote worker.
     pub fn disconnect(&mut self) -> EgResult<()> {
         self.xact_rollback()?;
@@ -501,12 +928,45 @@ impl Editor {
             );
         }
 
+        // Fail fast while the service is failing.
+        let service: &str = self.personality().into();
+        let breakers = breaker::breakers();
+        breakers.check(service)?;
+
         let mut req = self.session().request(method, params).or_else(|e| {
+            breakers.record_failure(service);
             self.rollback()?;
             Err(e)
         })?;
 
-        req.first_with_timeout(self.timeout)
+        let result = req.first_with_timeout(self.timeout);
+
+        if req.timed_out() {
+            breakers.record_failure(service);
+        } else {
+            breakers.record_success(service);
+        }
+
+        match result {
+            Ok(resp) => Ok(resp),
+            Err(e) => {
+                if !req.complete() {
+                    // Drop any replies which straggle in after a
+                    // timeout so they are not mistaken for replies to
+                    // later requests.  Cancelling a connected request
+                    // disconnects the worker, taking any open
+                    // transaction with it.
+                    if let Err(cancel_err) = req.cancel() {
+                        log::warn!("Error cancelling timed out request {method}: {cancel_err}");
+                    }
+
+                    // The transaction went with the worker.  Sending
+                    // a rollback now would reach some other worker.
+                    self.clear_xact_state();
+                }
+                Err(e)
+            }
+        }
     }
 
     /// Returns our mutable session, creating a new one if needed.
@@ -559,6 +1019,24 @@ impl Editor {
         Err(format!("Unexpected response to method {method}").into())
     }
 
+    /// Run a json_query which returns one row with one integer
+    /// column, e.g. a count, and return the value.
+    ///
+    /// Returns Err if the query returns any other shape of data or
+    /// the value is not an integer, including NULL.
+    pub fn query_scalar_i64(&mut self, query: EgValue) -> EgResult<i64> {
+        scalar_from_rows(self.json_query(query)?)?.int()
+    }
+
+    /// Run a json_query which returns one row with one numeric
+    /// column, e.g. a sum of amounts, and return the value.
+    ///
+    /// Returns Err if the query returns any other shape of data or
+    /// the value is not a number, including NULL.
+    pub fn query_scalar_f64(&mut self, query: EgValue) -> EgResult<f64> {
+        scalar_from_rows(self.json_query(query)?)?.float()
+    }
+
     pub fn retrieve(
         &mut self,
         idlclass: &str,
@@ -573,6 +1051,13 @@ impl Editor {
         id: impl Into<ApiParams>,
         ops: EgValue, // flesh, etc.
     ) -> EgResult<Option<EgValue>> {
+        self.check_flesh_depth(&ops)?;
+
+        // Catch typos in hard-coded flesh specs during development.
+        if cfg!(debug_assertions) {
+            self.idl().validate_flesh_fields(&ops)?;
+        }
+
         let fmapper = self.get_fieldmapper_from_classname(idlclass)?;
 
         let method = self.app_method(&format!("direct.{fmapper}.retrieve"));
@@ -603,6 +1088,13 @@ impl Editor {
         query: EgValue,
         ops: EgValue, // flesh, etc.
     ) -> EgResult<Vec<EgValue>> {
+        self.check_flesh_depth(&ops)?;
+
+        // Catch typos in hard-coded flesh specs during development.
+        if cfg!(debug_assertions) {
+            self.idl().validate_flesh_fields(&ops)?;
+        }
+
         let fmapper = self.get_fieldmapper_from_classname(idlclass)?;
 
         let method = self.app_method(&format!("direct.{fmapper}.search.atomic"));
@@ -621,6 +1113,66 @@ impl Editor {
         Err(format!("Unexpected response to method {method}").into())
     }
 
+    /// Search for one page of objects, also returning the total number
+    /// of objects that match the query.
+    ///
+    /// The count is collected via a separate json_query which uses the
+    /// same query as its WHERE clause, so both reflect the same filters.
+    ///
+    /// Results are sorted by primary key for stable paging.
+    pub fn search_page(
+        &mut self,
+        idlclass: &str,
+        query: EgValue,
+        limit: usize,
+        offset: usize,
+    ) -> EgResult<(Vec<EgValue>, u64)> {
+        let pkey = idl::get_class(idlclass)?
+            .pkey()
+            .ok_or_else(|| format!("IDL class {idlclass} has no primary key"))?
+            .to_string();
+
+        let mut count_query = eg::hash! {"select": {}, "from": idlclass};
+        count_query["select"][idlclass] = eg::array! [{
+            "column": pkey.as_str(),
+            "transform": "count",
+            "aggregate": true,
+            "alias": "count",
+        }];
+        count_query["where"] = query.clone();
+
+        let total = self.query_scalar_i64(count_query)? as u64;
+
+        let mut ops = eg::hash! {"limit": limit, "offset": offset};
+        ops["order_by"][idlclass] = EgValue::from(pkey.as_str());
+
+        let page = self.search_with_ops(idlclass, query, ops)?;
+
+        Ok((page, total))
+    }
+
+    /// Fetch the summary of a billable transaction, its owning user, and
+    /// its billing location in a single call.
+    ///
+    /// The location view (mbtslv) carries all of the summary (mbts)
+    /// fields, so there's no need to retrieve the transaction, summary,
+    /// and location separately.  Retrieve the "mbt" directly when
+    /// the transaction itself needs modifying.
+    pub fn fetch_xact_summary(&mut self, xact_id: i64) -> EgResult<XactSummary> {
+        let summary = self
+            .retrieve("mbtslv", xact_id)?
+            .ok_or_else(|| format!("No Such Transaction: {xact_id}"))?;
+
+        Ok(XactSummary {
+            xact_id,
+            usr: summary["usr"].int()?,
+            billing_location: summary["billing_location"].int()?,
+            balance_owed: summary["balance_owed"].float()?,
+            xact_open: summary["xact_finish"].is_null(),
+            summary,
+        })
+    }
+
     /// Update an object.
     pub fn update(&mut self, object: EgValue) -> EgResult<()> {
         if !self.has_xact_id() {
@@ -668,9 +1220,92 @@ impl Editor {
         }
     }
 
+    /// Create an object then re-fetch it by its new primary key.
+    ///
+    /// The extra retrieve ensures the returned object reflects all
+    /// database-populated values (column defaults, trigger-applied
+    /// values, etc.) at the cost of an additional round trip.  Use
+    /// create() instead in hot paths where that's not needed.
+    pub fn create_and_retrieve(&mut self, object: EgValue) -> EgResult<EgValue> {
+        let classname = object
+            .classname()
+            .ok_or_else(|| format!("Cannot create non-IDL object: {}", object.dump()))?
+            .to_string();
+
+        let created = self.create(object)?;
+
+        let pkey = created
+            .pkey_value()
+            .ok_or_else(|| format!("Created {classname} object has no pkey value"))?
+            .clone();
+
+        self.retrieve(&classname, pkey.clone())?.ok_or_else(|| {
+            format!("Cannot retrieve newly created {classname} object {pkey}").into()
+        })
+    }
+
+    /// Create a new object or update the existing object whose values
+    /// for `match_fields` match those of the provided object.
+    ///
+    /// When a matching row is found, its primary key is applied to our
+    /// object before updating, so the existing row is replaced.
+    ///
+    /// Returns the created or updated object.
+    ///
+    /// NOTE There is a window between the search and the create/update
+    /// where another process could create a matching row.  Run this
+    /// within a transaction and rely on a unique constraint covering
+    /// the match fields to prevent duplicates.
+    pub fn upsert(
+        &mut self,
+        classname: &str,
+        mut object: EgValue,
+        match_fields: &[&str],
+    ) -> EgResult<EgValue> {
+        if !object.is_blessed() {
+            object.bless(classname)?;
+        }
+
+        if match_fields.is_empty() {
+            Err(format!("upsert() on {classname} requires match fields"))?;
+        }
+
+        let mut query = eg::hash! {};
+        for field in match_fields {
+            query[*field] = object.get_field(field)?.clone();
+        }
+
+        let pkey_field = idl::get_class(classname)?
+            .pkey()
+            .ok_or_else(|| format!("IDL class {classname} has no primary key"))?
+            .to_string();
+
+        let mut existing = self.search(classname, query)?;
+
+        if existing.len() > 1 {
+            Err(format!(
+                "upsert() on {classname} matched {} rows using fields {match_fields:?}",
+                existing.len()
+            ))?;
+        }
+
+        if let Some(row) = existing.pop() {
+            object[&pkey_field] = row[&pkey_field].clone();
+            self.update(object.clone())?;
+            Ok(object)
+        } else {
+            self.create(object)
+        }
+    }
+
     /// Delete an IDL Object.
     ///
-    /// Response is the PKEY value as a JsonValue.
+    /// Response is the PKEY value as an EgValue.
+    ///
+    /// Like create() and update(), this requires an active transaction.
+    /// As with the other write methods, no requestor is required here;
+    /// permission checks are the responsibility of the caller (or of
+    /// pcrud when using that personality).
     pub fn delete(&mut self, object: EgValue) -> EgResult<EgValue> {
         if !self.has_xact_id() {
             Err(format!("Transaction required for DELETE"))?;
@@ -684,7 +1319,7 @@ impl Editor {
             self.has_pending_changes = true;
             Ok(resp)
         } else {
-            Err(format!("Create returned no response").into())
+            Err(format!("Delete returned no response").into())
         }
     }
 
@@ -701,6 +1336,28 @@ impl Editor {
         self.allowed_maybe_at(perm, Some(org_id))
     }
 
+    /// Verify our authenticated requestor has the specified permission
+    /// at the specified org unit, returning a PERM_FAILURE event as an
+    /// error if not.
+    ///
+    /// Use this to fail early and clearly before making changes the
+    /// database would otherwise reject less helpfully.
+    pub fn check_perm(&mut self, perm: &str, org_id: i64) -> EgResult<()> {
+        if self.allowed_at(perm, org_id)? {
+            return Ok(());
+        }
+
+        // allowed_at() sets no event when there is no requestor.
+        let mut evt = EgEvent::new("PERM_FAILURE");
+        evt.set_ils_perm(perm);
+        if org_id > 0 {
+            evt.set_ils_perm_loc(org_id);
+        }
+        self.set_last_event(evt.clone());
+
+        Err(EgError::Event(evt))
+    }
+
     fn allowed_maybe_at(&mut self, perm: &str, org_id_op: Option<i64>) -> EgResult<bool> {
         let user_id = match self.requestor_id() {
             Ok(v) => v,
@@ -740,3 +1397,30 @@ impl Editor {
         Ok(has_perm)
     }
 }
+
+/// Returns the value of the only column of the only row in a set of
+/// json_query results.
+pub(crate) fn scalar_from_rows(mut rows: Vec<EgValue>) -> EgResult<EgValue> {
+    if rows.len() != 1 {
+        return Err(format!(
+            "Scalar query returned {} rows; expected exactly 1",
+            rows.len()
+        )
+        .into());
+    }
+
+    let mut row = rows.pop().unwrap(); // len checked above
+
+    if !row.is_object() || row.len() != 1 {
+        return Err(format!(
+            "Scalar query returned a row with {} columns; expected exactly 1: {}",
+            row.len(),
+            row.dump()
+        )
+        .into());
+    }
+
+    let key = row.keys().next().unwrap().to_string(); // len checked above
+
+    Ok(row[key.as_str()].take())
+}
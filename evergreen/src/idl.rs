@@ -2,12 +2,15 @@
 use crate as eg;
 use crate::EgResult;
 use crate::EgValue;
+use json::JsonValue;
 use roxmltree;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::OnceLock;
+use std::time::Instant;
 
 /// Parse the IDL once and store it here, making it accessible to all
 /// threads as a read-only value.
@@ -19,6 +22,14 @@ const OILS_NS_PERSIST: &str = "http://open-ils.org/spec/opensrf/IDL/persistence/
 const OILS_NS_REPORTER: &str = "http://open-ils.org/spec/opensrf/IDL/reporter/v1";
 const AUTO_FIELDS: [&str; 3] = ["isnew", "ischanged", "isdeleted"];
 
+/// Appended to the IDL file name to locate its compiled form, e.g.
+/// fm_IDL.xml.compiled.  See Parser::compile_file().
+pub const COMPILED_IDL_SUFFIX: &str = ".compiled";
+
+/// Bumped when the compiled IDL layout changes, so files written by
+/// older tools are rejected instead of misread.
+const COMPILED_IDL_VERSION: u32 = 1;
+
 /// Returns a ref to the global IDL parser instance
 pub fn parser() -> &'static Parser {
     if let Some(idl) = GLOBAL_IDL.get() {
@@ -39,6 +50,46 @@ pub fn get_class(classname: &str) -> EgResult<&Arc<Class>> {
         .ok_or_else(|| format!("No such IDL class: {classname}").into())
 }
 
+/// Path to the compiled form of an IDL file.
+///
+/// ```
+/// use evergreen::idl;
+///
+/// assert_eq!(
+///     idl::compiled_path("/openils/conf/fm_IDL.xml"),
+///     "/openils/conf/fm_IDL.xml.compiled"
+/// );
+/// ```
+pub fn compiled_path(filename: &str) -> String {
+    format!("{filename}{COMPILED_IDL_SUFFIX}")
+}
+
+/// Checksum of the IDL XML, stored in the compiled IDL so a compiled
+/// file built from a different version of the XML can be detected.
+pub fn checksum(xml: &str) -> String {
+    format!("{:x}", md5::compute(xml))
+}
+
+/// Translate IDL objects encoded as flat hashes, with the class name
+/// in the "_classname" key, into the fieldmapper array format used on
+/// the bus, e.g. {"__c":"mb","__p":[...]}, recursively.
+///
+/// Array positions follow the field order of the loaded IDL.
+pub fn hash_to_fieldmapper(value: JsonValue) -> EgResult<JsonValue> {
+    Ok(EgValue::from_classed_json_hash(value)?.into_json_value())
+}
+
+/// Translate IDL objects encoded in the fieldmapper array format
+/// into flat hashes with the class name in the "_classname" key,
+/// recursively.
+///
+/// All real fields are included in the hash, NULL or not.
+pub fn fieldmapper_to_hash(value: JsonValue) -> EgResult<JsonValue> {
+    let mut value = EgValue::from_json_value(value)?;
+    value.to_classed_hash();
+    Ok(value.into_json_value())
+}
+
 /// Various forms an IDL-classed object can take internally and on
 /// the wire.
 #[derive(Debug, Clone, PartialEq)]
@@ -327,6 +378,12 @@ impl Class {
         self.is_virtual
     }
 
+    /// True if the class is marked oils_persist:readonly, e.g. a
+    /// view, and cannot be created, updated, or deleted.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     /// Vec of non-virutal fields.
     pub fn real_fields(&self) -> Vec<&Field> {
         let mut fields: Vec<&Field> = Vec::new();
@@ -377,6 +434,135 @@ impl Class {
     }
 }
 
+// Compiled IDL encoding.  Each item is packed into an array, in the
+// order of its struct fields, to keep the compiled file small.
+
+fn compiled_string(v: &JsonValue) -> EgResult<String> {
+    v.as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("Invalid compiled IDL string: {v}").into())
+}
+
+fn compiled_bool(v: &JsonValue) -> EgResult<bool> {
+    v.as_bool()
+        .ok_or_else(|| format!("Invalid compiled IDL bool: {v}").into())
+}
+
+fn compiled_opt_string(v: &JsonValue) -> Option<String> {
+    v.as_str().map(|s| s.to_string())
+}
+
+impl Field {
+    fn to_compiled(&self) -> JsonValue {
+        let datatype: &str = (&self.datatype).into();
+        json::array![
+            self.name.as_str(),
+            self.label.as_str(),
+            datatype,
+            self.i18n,
+            self.array_pos,
+            self.is_virtual,
+            self.suppress_controller.as_deref(),
+        ]
+    }
+
+    fn from_compiled(v: &JsonValue) -> EgResult<Field> {
+        Ok(Field {
+            name: compiled_string(&v[0])?,
+            label: compiled_string(&v[1])?,
+            datatype: v[2].as_str().unwrap_or("").into(),
+            i18n: compiled_bool(&v[3])?,
+            array_pos: v[4]
+                .as_usize()
+                .ok_or_else(|| format!("Invalid compiled IDL field: {v}"))?,
+            is_virtual: compiled_bool(&v[5])?,
+            suppress_controller: compiled_opt_string(&v[6]),
+        })
+    }
+}
+
+impl Link {
+    fn to_compiled(&self) -> JsonValue {
+        let reltype: &str = (&self.reltype).into();
+        json::array![
+            self.field.as_str(),
+            reltype,
+            self.key.as_str(),
+            self.map.as_deref(),
+            self.class.as_str(),
+        ]
+    }
+
+    fn from_compiled(v: &JsonValue) -> EgResult<Link> {
+        Ok(Link {
+            field: compiled_string(&v[0])?,
+            reltype: v[1].as_str().unwrap_or("").into(),
+            key: compiled_string(&v[2])?,
+            map: compiled_opt_string(&v[3]),
+            class: compiled_string(&v[4])?,
+        })
+    }
+}
+
+impl Class {
+    fn to_compiled(&self) -> JsonValue {
+        let mut fields: Vec<&Field> = self.fields.values().collect();
+        fields.sort_by_key(|f| f.array_pos);
+
+        let mut links: Vec<&Link> = self.links.values().collect();
+        links.sort_by(|a, b| a.field.cmp(&b.field));
+
+        let fields: Vec<JsonValue> = fields.into_iter().map(|f| f.to_compiled()).collect();
+        let links: Vec<JsonValue> = links.into_iter().map(|l| l.to_compiled()).collect();
+
+        json::array![
+            self.classname.as_str(),
+            self.label.as_str(),
+            self.field_safe,
+            self.read_only,
+            self.pkey.as_deref(),
+            self.selector.as_deref(),
+            self.fieldmapper.as_deref(),
+            fields,
+            links,
+            self.tablename.as_deref(),
+            self.source_definition.as_deref(),
+            self.controller.as_deref(),
+            self.is_virtual,
+        ]
+    }
+
+    fn from_compiled(v: &JsonValue) -> EgResult<Class> {
+        let mut fields = HashMap::new();
+        for f in v[7].members() {
+            let field = Field::from_compiled(f)?;
+            fields.insert(field.name.to_string(), field);
+        }
+
+        let mut links = HashMap::new();
+        for l in v[8].members() {
+            let link = Link::from_compiled(l)?;
+            links.insert(link.field.to_string(), link);
+        }
+
+        Ok(Class {
+            classname: compiled_string(&v[0])?,
+            label: compiled_string(&v[1])?,
+            field_safe: compiled_bool(&v[2])?,
+            read_only: compiled_bool(&v[3])?,
+            pkey: compiled_opt_string(&v[4]),
+            selector: compiled_opt_string(&v[5]),
+            fieldmapper: compiled_opt_string(&v[6]),
+            fields,
+            links,
+            tablename: compiled_opt_string(&v[9]),
+            source_definition: compiled_opt_string(&v[10]),
+            controller: compiled_opt_string(&v[11]),
+            is_virtual: compiled_bool(&v[12])?,
+        })
+    }
+}
+
 impl fmt::Display for Class {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -408,17 +594,128 @@ impl Parser {
         &self.classes
     }
 
+    /// True if the named class exists.
+    pub fn class_exists(&self, classname: &str) -> bool {
+        self.classes.contains_key(classname)
+    }
+
+    /// Names of all fields on the class, in IDL order, or None if the
+    /// class does not exist.
+    pub fn field_names(&self, classname: &str) -> Option<Vec<&str>> {
+        let class = self.classes.get(classname)?;
+
+        let mut fields: Vec<&Field> = class.fields().values().collect();
+        fields.sort_by_key(|f| f.array_pos());
+
+        Some(fields.iter().map(|f| f.name()).collect())
+    }
+
+    /// Sorted names of the fields on the class which link to other
+    /// classes, i.e. the fields which may be fleshed, or None if the
+    /// class does not exist.
+    pub fn link_fields(&self, classname: &str) -> Option<Vec<&str>> {
+        let class = self.classes.get(classname)?;
+
+        let mut fields: Vec<&str> = class.links().keys().map(|k| k.as_str()).collect();
+        fields.sort();
+
+        Some(fields)
+    }
+
+    /// Verify every class and field named in the "flesh_fields" of a
+    /// set of query options is a known class and link field.
+    ///
+    /// NO-OP if the options contain no flesh_fields.
+    pub fn validate_flesh_fields(&self, ops: &EgValue) -> EgResult<()> {
+        for (classname, fields) in ops["flesh_fields"].entries() {
+            let links = self
+                .link_fields(classname)
+                .ok_or_else(|| format!("Cannot flesh unknown IDL class: {classname}"))?;
+
+            for field in fields.members() {
+                let field = field
+                    .as_str()
+                    .ok_or_else(|| format!("Invalid flesh field for {classname}: {field}"))?;
+
+                if !links.contains(&field) {
+                    return Err(
+                        format!("IDL class {classname} has no link field named {field}").into(),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// True if the named class exists and contains the named field.
+    ///
+    /// Useful for verifying field names before setting values on
+    /// values that are not (yet) blessed.
+    pub fn field_exists(&self, classname: &str, field: &str) -> bool {
+        self.classes
+            .get(classname)
+            .map(|c| c.has_field(field))
+            .unwrap_or(false)
+    }
+
     /// Load the IDL from a file.
     ///
+    /// If a compiled IDL (see compile_file()) exists alongside the
+    /// file and was compiled from its current contents, the compiled
+    /// form is loaded instead.  A stale or unreadable compiled IDL is
+    /// logged and ignored.
+    ///
     /// Returns an Err if the IDL has already been parsed and loaded, in
     /// part to discourage unnecessary reparsing, which is a heavy job.
     pub fn load_file(filename: &str) -> EgResult<()> {
+        Parser::load_file_inner(filename, None)
+    }
+
+    /// Load the IDL from a file, keeping only the listed classes and
+    /// the classes they link to directly.
+    ///
+    /// The file is still read and parsed as XML, but class definitions
+    /// are only built for the kept classes.  IDL-classed values of any
+    /// other class cannot be decoded, so callers must list every class
+    /// they expect to encounter.
+    ///
+    /// Returns an Err if a listed class does not exist or the IDL has
+    /// already been loaded.
+    pub fn load_file_classes(filename: &str, classes: &[&str]) -> EgResult<()> {
+        Parser::load_file_inner(filename, Some(classes))
+    }
+
+    fn load_file_inner(filename: &str, classes: Option<&[&str]>) -> EgResult<()> {
+        let started = Instant::now();
+
         let xml = match fs::read_to_string(filename) {
             Ok(x) => x,
             Err(e) => Err(format!("Cannot parse IDL file '{filename}': {e}"))?,
         };
 
-        let p = Parser::parse_string(&xml)?;
+        let mut source = filename.to_string();
+
+        let compiled = match classes {
+            // Partial loads always parse the XML.
+            Some(_) => None,
+            None => Parser::load_compiled(filename, &xml),
+        };
+
+        let p = match compiled {
+            Some(p) => {
+                source = compiled_path(filename);
+                p
+            }
+            None => Parser::parse_string(&xml, classes)?,
+        };
+
+        // Logged so the cost of full vs. partial loading can be compared.
+        log::info!(
+            "Loaded {} IDL classes from {source} in {:.3}s",
+            p.classes.len(),
+            started.elapsed().as_secs_f64()
+        );
 
         if GLOBAL_IDL.set(p).is_err() {
             return Err(format!("Cannot initialize IDL more than once").into());
@@ -427,8 +724,115 @@ impl Parser {
         Ok(())
     }
 
-    /// Parse the IDL as a string
-    fn parse_string(xml: &str) -> EgResult<Parser> {
+    /// Load the compiled form of the IDL file, if one exists and it
+    /// was compiled from the provided XML.
+    ///
+    /// Returns None, logging the reason, when the XML should be
+    /// parsed instead.
+    fn load_compiled(filename: &str, xml: &str) -> Option<Parser> {
+        let path = compiled_path(filename);
+
+        if !Path::new(&path).exists() {
+            return None;
+        }
+
+        let result = fs::read_to_string(&path)
+            .map_err(|e| format!("Cannot read {path}: {e}").into())
+            .and_then(|text| Parser::parse_compiled(&text, xml));
+
+        match result {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log::warn!("Ignoring compiled IDL {path}; parsing {filename} instead: {e}");
+                None
+            }
+        }
+    }
+
+    /// Compile IDL XML into the form read by parse_compiled().
+    ///
+    /// The compiled IDL is a compact JSON document which carries the
+    /// checksum of the source XML.  It is much faster to load, since
+    /// none of the XML needs to be interpreted.
+    pub fn compile(xml: &str) -> EgResult<String> {
+        let parser = Parser::parse_string(xml, None)?;
+
+        let mut names: Vec<&String> = parser.classes.keys().collect();
+        names.sort();
+
+        let classes: Vec<JsonValue> = names
+            .into_iter()
+            .map(|n| parser.classes[n].to_compiled())
+            .collect();
+
+        let compiled = json::object! {
+            "version": COMPILED_IDL_VERSION,
+            "checksum": checksum(xml),
+            "classes": classes,
+        };
+
+        Ok(compiled.dump())
+    }
+
+    /// Compile an IDL file, writing the result to outfile, which
+    /// defaults to the path load_file() checks for a compiled IDL.
+    ///
+    /// Returns the path of the compiled file.
+    pub fn compile_file(filename: &str, outfile: Option<&str>) -> EgResult<String> {
+        let xml = match fs::read_to_string(filename) {
+            Ok(x) => x,
+            Err(e) => Err(format!("Cannot read IDL file '{filename}': {e}"))?,
+        };
+
+        let path = match outfile {
+            Some(f) => f.to_string(),
+            None => compiled_path(filename),
+        };
+
+        if let Err(e) = fs::write(&path, Parser::compile(&xml)?) {
+            return Err(format!("Cannot write compiled IDL '{path}': {e}").into());
+        }
+
+        Ok(path)
+    }
+
+    /// Load a compiled IDL.
+    ///
+    /// Returns an Err if the compiled IDL was not built from the
+    /// provided XML or was built by an incompatible version.
+    pub(crate) fn parse_compiled(text: &str, xml: &str) -> EgResult<Parser> {
+        let compiled = match json::parse(text) {
+            Ok(c) => c,
+            Err(e) => Err(format!("Invalid compiled IDL: {e}"))?,
+        };
+
+        if compiled["version"].as_u32() != Some(COMPILED_IDL_VERSION) {
+            return Err(
+                format!("Unsupported compiled IDL version: {}", compiled["version"]).into(),
+            );
+        }
+
+        if compiled["checksum"].as_str() != Some(checksum(xml).as_str()) {
+            return Err("Compiled IDL is stale: checksum does not match the IDL XML".into());
+        }
+
+        let mut parser = Parser {
+            classes: HashMap::new(),
+        };
+
+        for class in compiled["classes"].members() {
+            let class = Class::from_compiled(class)?;
+            parser
+                .classes
+                .insert(class.classname.to_string(), Arc::new(class));
+        }
+
+        Ok(parser)
+    }
+
+    /// Parse the IDL as a string, optionally keeping only the listed
+    /// classes and the classes they link to.
+    pub(crate) fn parse_string(xml: &str, classes: Option<&[&str]>) -> EgResult<Parser> {
         let doc = match roxmltree::Document::parse(xml) {
             Ok(d) => d,
             Err(e) => Err(format!("Error parsing XML string for IDL: {e}"))?,
@@ -438,21 +842,65 @@ impl Parser {
             classes: HashMap::new(),
         };
 
+        let mut class_nodes = Vec::new();
+
         for root_node in doc.root().children() {
             if root_node.tag_name().name() == "IDL" {
                 for class_node in root_node.children() {
                     if class_node.node_type() == roxmltree::NodeType::Element
                         && class_node.tag_name().name() == "class"
                     {
-                        parser.add_class(&class_node);
+                        class_nodes.push(class_node);
                     }
                 }
             }
         }
 
+        let wanted = match classes {
+            Some(list) => Some(Parser::linked_classes(&class_nodes, list)?),
+            None => None,
+        };
+
+        for class_node in class_nodes {
+            if let Some(wanted) = wanted.as_ref() {
+                // id is required
+                if !wanted.contains(class_node.attribute("id").unwrap()) {
+                    continue;
+                }
+            }
+
+            parser.add_class(&class_node);
+        }
+
         Ok(parser)
     }
 
+    /// Returns the listed class names plus the names of all classes
+    /// they link to.
+    fn linked_classes<'a>(
+        class_nodes: &[roxmltree::Node<'a, '_>],
+        classes: &[&str],
+    ) -> EgResult<HashSet<&'a str>> {
+        let mut wanted = HashSet::new();
+
+        for classname in classes {
+            let node = class_nodes
+                .iter()
+                .find(|n| n.attribute("id") == Some(*classname))
+                .ok_or_else(|| format!("No such IDL class: {classname}"))?;
+
+            wanted.insert(node.attribute("id").unwrap());
+
+            for link_node in node.descendants().filter(|n| n.tag_name().name() == "link") {
+                if let Some(linked) = link_node.attribute("class") {
+                    wanted.insert(linked);
+                }
+            }
+        }
+
+        Ok(wanted)
+    }
+
     fn add_class(&mut self, node: &roxmltree::Node) {
         let name = node.attribute("id").unwrap(); // required
 
@@ -706,6 +1154,11 @@ impl Parser {
         Ok(flesh)
     }
 
+    /// Create a new empty IdlObject of the provided class.
+    pub fn new_object(&self, classname: &str) -> EgResult<IdlObject> {
+        IdlObject::new(classname)
+    }
+
     #[deprecated(note = "See EgValue::create()")]
     pub fn create_from(&self, classname: &str, v: EgValue) -> EgResult<EgValue> {
         EgValue::create(classname, v)
@@ -725,3 +1178,117 @@ impl Parser {
         }
     }
 }
+
+/// An IDL-classed object whose fields are verified against the IDL.
+///
+/// This wraps a Blessed EgValue, so code can move between the two
+/// freely, e.g. build an object here and hand it to the Editor.
+///
+/// ```text
+/// let mut bill = editor.idl().new_object("mb")?;
+/// bill.set("amount", 1.25)?;
+/// assert_eq!(bill.get_float("amount")?, 1.25);
+/// assert!(bill.set("amunt", 1.25).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdlObject {
+    value: EgValue,
+}
+
+impl IdlObject {
+    /// Create a new empty object of the provided class.
+    pub fn new(classname: &str) -> EgResult<IdlObject> {
+        Ok(IdlObject {
+            value: EgValue::stub(classname)?,
+        })
+    }
+
+    /// IDL class name, e.g. "mb".
+    pub fn class(&self) -> &str {
+        self.value
+            .classname()
+            .expect("IdlObject values are blessed")
+    }
+
+    /// Ref to the value of a field.
+    ///
+    /// Returns Err if the class has no such field.
+    pub fn get(&self, field: &str) -> EgResult<&EgValue> {
+        self.value.get_field(field)
+    }
+
+    /// Returns Err if the class has no such field or its value is
+    /// not an integer.
+    pub fn get_int(&self, field: &str) -> EgResult<i64> {
+        self.get(field)?
+            .as_int()
+            .ok_or_else(|| format!("{}.{field} is not an integer", self.class()).into())
+    }
+
+    /// Returns Err if the class has no such field or its value is
+    /// not a number.
+    pub fn get_float(&self, field: &str) -> EgResult<f64> {
+        self.get(field)?
+            .as_float()
+            .ok_or_else(|| format!("{}.{field} is not a number", self.class()).into())
+    }
+
+    /// Returns Err if the class has no such field or its value is
+    /// not a string.
+    pub fn get_str(&self, field: &str) -> EgResult<&str> {
+        self.get(field)?
+            .as_str()
+            .ok_or_else(|| format!("{}.{field} is not a string", self.class()).into())
+    }
+
+    /// Returns Err if the class has no such field.  See EgValue::boolish().
+    pub fn get_bool(&self, field: &str) -> EgResult<bool> {
+        Ok(self.get(field)?.boolish())
+    }
+
+    /// Set the value of a field.
+    ///
+    /// Returns Err if the class has no such field.
+    pub fn set(&mut self, field: &str, value: impl Into<EgValue>) -> EgResult<()> {
+        self.value.set_field(field, value)
+    }
+
+    /// Ref to the wrapped value.
+    pub fn value(&self) -> &EgValue {
+        &self.value
+    }
+
+    pub fn into_value(self) -> EgValue {
+        self.value
+    }
+
+    /// Create an object from an IDL-classed JSON value, e.g. one that
+    /// came from the wire.
+    pub fn from_json_value(v: JsonValue) -> EgResult<IdlObject> {
+        IdlObject::try_from(EgValue::from_json_value(v)?)
+    }
+
+    /// Translate into an IDL-classed JSON value, suitable for
+    /// IdlObject::from_json_value().
+    pub fn into_json_value(self) -> JsonValue {
+        self.value.into_json_value()
+    }
+}
+
+impl TryFrom<EgValue> for IdlObject {
+    type Error = eg::EgError;
+
+    fn try_from(value: EgValue) -> EgResult<IdlObject> {
+        if value.is_blessed() {
+            Ok(IdlObject { value })
+        } else {
+            Err(format!("IdlObject requires an IDL-classed value: {}", value.dump()).into())
+        }
+    }
+}
+
+impl From<IdlObject> for EgValue {
+    fn from(obj: IdlObject) -> EgValue {
+        obj.value
+    }
+}
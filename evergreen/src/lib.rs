@@ -12,6 +12,15 @@ pub use value::EgValue;
 
 pub const NULL: EgValue = EgValue::Null;
 
+/// Ref to the process-wide IDL.
+///
+/// The IDL is parsed once per process by init::load_idl() and shared
+/// read-only by all threads, so workers and Editors never hold their
+/// own copies.  Panics if the IDL has not been loaded.
+pub fn idl() -> &'static idl::Parser {
+    idl::parser()
+}
+
 pub mod common;
 pub mod constants;
 pub mod date;
@@ -0,0 +1,253 @@
+//! Circuit breakers for API calls to downstream services.
+//!
+//! When a service stops responding, every call to it waits out the
+//! full request timeout before failing, which stalls the caller and
+//! piles more work onto the struggling service.  Each service called
+//! via the Editor has a breaker which counts consecutive failed calls,
+//! i.e. calls which could not be sent or which timed out.  Calls which
+//! receive an error response still count as the service responding.
+//!
+//! Once a service reaches the failure threshold the breaker opens and
+//! calls to the service fail immediately for the cooldown period.
+//! After the cooldown, a single call is let through as a probe.  If it
+//! succeeds the breaker closes, otherwise it opens for another
+//! cooldown period.
+//!
+//! Breakers are shared by all threads in the process and are
+//! configured with the optional <circuit_breaker> element of the bus
+//! client config:
+//!
+//! ```text
+//! <circuit_breaker failures="5" cooldown="30"/>
+//! ```
+//!
+//! A failures value of 0 disables the breakers.
+use crate::osrf::conf;
+use crate::EgResult;
+use crate::EgValue;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Consecutive failures which open a breaker when not configured.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// Seconds a breaker stays open when not configured.
+pub const DEFAULT_COOLDOWN: u64 = 30;
+
+static BREAKERS: OnceLock<CircuitBreakers> = OnceLock::new();
+
+/// Process-wide breakers, configured from the bus client config.
+pub fn breakers() -> &'static CircuitBreakers {
+    BREAKERS.get_or_init(|| {
+        let options = conf::config()
+            .client()
+            .circuit_breaker()
+            .cloned()
+            .unwrap_or_default();
+
+        CircuitBreakers::new(options)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerOptions {
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl Default for BreakerOptions {
+    fn default() -> Self {
+        BreakerOptions::new(
+            DEFAULT_FAILURE_THRESHOLD,
+            Duration::from_secs(DEFAULT_COOLDOWN),
+        )
+    }
+}
+
+impl BreakerOptions {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        BreakerOptions {
+            failure_threshold,
+            cooldown,
+        }
+    }
+
+    /// Consecutive failures which open a breaker.  0 means never open.
+    pub fn failure_threshold(&self) -> u32 {
+        self.failure_threshold
+    }
+
+    /// How long an open breaker rejects calls before probing.
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    /// Calls pass through.
+    Closed,
+    /// Calls fail immediately.
+    Open,
+    /// The cooldown has passed and a probe call is allowed or running.
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half-open",
+        }
+    }
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+impl Breaker {
+    fn state(&self, cooldown: Duration) -> BreakerState {
+        match self.opened_at {
+            None => BreakerState::Closed,
+            Some(_) if self.probe_started.is_some() => BreakerState::HalfOpen,
+            Some(t) if t.elapsed() >= cooldown => BreakerState::HalfOpen,
+            Some(_) => BreakerState::Open,
+        }
+    }
+}
+
+pub struct CircuitBreakers {
+    options: BreakerOptions,
+    breakers: Mutex<BTreeMap<String, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(options: BreakerOptions) -> Self {
+        CircuitBreakers {
+            options,
+            breakers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn options(&self) -> &BreakerOptions {
+        &self.options
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Breaker>> {
+        // A panic while holding the lock leaves the breakers usable.
+        match self.breakers.lock() {
+            Ok(g) => g,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Returns Err if calls to the service should not be attempted.
+    ///
+    /// Once the cooldown of an open breaker passes, the first caller
+    /// is allowed through as the probe.  Others are rejected until the
+    /// probe reports back, or for another cooldown period if it never
+    /// does.
+    pub fn check(&self, service: &str) -> EgResult<()> {
+        let cooldown = self.options.cooldown;
+        let mut breakers = self.lock();
+
+        let breaker = match breakers.get_mut(service) {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+
+        let opened_at = match breaker.opened_at {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        let waited = match breaker.probe_started {
+            Some(t) => t.elapsed(),
+            None => opened_at.elapsed(),
+        };
+
+        if waited >= cooldown {
+            log::info!("Probing service {service} after circuit breaker cooldown");
+            breaker.probe_started = Some(Instant::now());
+            return Ok(());
+        }
+
+        Err(format!(
+            "Circuit breaker open for service {service} after {} consecutive failures",
+            breaker.failures
+        )
+        .into())
+    }
+
+    /// Record a call which reached the service, closing its breaker.
+    pub fn record_success(&self, service: &str) {
+        if let Some(breaker) = self.lock().remove(service) {
+            if breaker.opened_at.is_some() {
+                log::info!("Circuit breaker closed for service {service}");
+            }
+        }
+    }
+
+    /// Record a call which could not be sent or went unanswered.
+    pub fn record_failure(&self, service: &str) {
+        if self.options.failure_threshold == 0 {
+            return;
+        }
+
+        let mut breakers = self.lock();
+        let breaker = breakers.entry(service.to_string()).or_default();
+
+        breaker.failures += 1;
+
+        if breaker.probe_started.take().is_some() {
+            log::warn!("Probe of service {service} failed; circuit breaker remains open");
+            breaker.opened_at = Some(Instant::now());
+        } else if breaker.opened_at.is_none() && breaker.failures >= self.options.failure_threshold
+        {
+            log::warn!(
+                "Opening circuit breaker for service {service} after {} consecutive failures",
+                breaker.failures
+            );
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+
+    pub fn state(&self, service: &str) -> BreakerState {
+        match self.lock().get(service) {
+            Some(b) => b.state(self.options.cooldown),
+            None => BreakerState::Closed,
+        }
+    }
+
+    /// Describe the breakers of all services with recent failures.
+    ///
+    /// Services whose calls are all succeeding are omitted.
+    pub fn to_eg_value(&self) -> EgValue {
+        let mut list = EgValue::new_array();
+
+        for (service, breaker) in self.lock().iter() {
+            let value = EgValue::from_json_value_plain(json::object! {
+                "service": service.as_str(),
+                "state": breaker.state(self.options.cooldown).as_str(),
+                "failures": breaker.failures,
+            });
+
+            list.push(value).expect("Is Array");
+        }
+
+        list
+    }
+}
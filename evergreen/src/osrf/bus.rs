@@ -1,12 +1,136 @@
 use crate::osrf::addr::BusAddress;
+use crate::osrf::compression::{self, CompressionOptions};
 use crate::osrf::conf;
 use crate::osrf::logging::Logger;
 use crate::osrf::message::TransportMessage;
 use crate::util;
-use crate::EgResult;
+use crate::{EgError, EgResult};
+use json::JsonValue;
 use redis::{Commands, ConnectionAddr, ConnectionInfo, RedisConnectionInfo};
 use std::fmt;
 
+/// Describes a message pulled from the bus which could not be parsed.
+///
+/// The sender and thread are pulled from the raw message on a best
+/// effort basis so the source of the message can be traced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MalformedMessage {
+    recipient: String,
+    sender: Option<String>,
+    thread: Option<String>,
+    size: usize,
+    reason: String,
+}
+
+impl MalformedMessage {
+    fn new(recipient: &str, text: &str, size: usize, reason: String) -> Self {
+        MalformedMessage {
+            size,
+            reason,
+            recipient: recipient.to_string(),
+            sender: string_field(text, "from"),
+            thread: string_field(text, "thread"),
+        }
+    }
+
+    /// Bus address the message was sent to.
+    pub fn recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    pub fn thread(&self) -> Option<&str> {
+        self.thread.as_deref()
+    }
+
+    /// Size of the raw message in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for MalformedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Malformed message to={} from={} thread={} bytes={}: {}",
+            self.recipient,
+            self.sender.as_deref().unwrap_or("<unknown>"),
+            self.thread.as_deref().unwrap_or("<unknown>"),
+            self.size,
+            self.reason
+        )
+    }
+}
+
+impl From<MalformedMessage> for EgError {
+    fn from(m: MalformedMessage) -> Self {
+        EgError::Transport(m.to_string())
+    }
+}
+
+/// Returns the value of the first "key":"value" string pair in text,
+/// which need not be valid JSON.
+fn string_field(text: &str, key: &str) -> Option<String> {
+    let needle = format!("\"{key}\"");
+
+    let mut rest = &text[text.find(&needle)? + needle.len()..];
+    rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    rest = rest.strip_prefix('"')?;
+
+    let value = &rest[..rest.find('"')?];
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Parse the raw bytes of a message pulled from the bus, unpacking
+/// the message if it's compressed.
+///
+/// ```
+/// use evergreen::osrf::bus;
+///
+/// let jv = bus::parse_message(br#"{"to":"a","from":"b","thread":"c","body":[]}"#, "a").unwrap();
+/// assert_eq!(jv["from"], "b");
+///
+/// let err = bus::parse_message(br#"{"to":"a","from":"b","thread":"c","bo"#, "a").unwrap_err();
+/// assert_eq!(err.sender(), Some("b"));
+/// ```
+pub fn parse_message(bytes: &[u8], recipient: &str) -> Result<JsonValue, MalformedMessage> {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(t) => t,
+        Err(e) => {
+            let lossy = String::from_utf8_lossy(bytes);
+            return Err(MalformedMessage::new(
+                recipient,
+                &lossy,
+                bytes.len(),
+                format!("Invalid UTF-8: {e}"),
+            ));
+        }
+    };
+
+    let malformed = |reason| MalformedMessage::new(recipient, text, bytes.len(), reason);
+
+    let value = json::parse(text).map_err(|e| malformed(format!("Invalid JSON: {e}")))?;
+
+    if !value.is_object() {
+        return Err(malformed("Message is not a JSON object".to_string()));
+    }
+
+    compression::unpack(value).map_err(|e| malformed(e.to_string()))
+}
+
 /// Manages a Redis connection.
 pub struct Bus {
     connection: redis::Connection,
@@ -22,6 +146,10 @@ pub struct Bus {
     /// messages to be parsed and serialized without concern for
     /// IDL-classed information stored in the message.
     raw_data_mode: bool,
+
+    /// Compress large outbound messages.  Inbound messages are
+    /// decompressed regardless.
+    compression: Option<CompressionOptions>,
 }
 
 impl Bus {
@@ -44,6 +172,7 @@ impl Bus {
         let bus = Bus {
             connection,
             raw_data_mode: false,
+            compression: config.compression().cloned(),
             address: addr,
             router_name: config.router_name().to_string(),
         };
@@ -108,35 +237,26 @@ impl Bus {
         &mut self.connection
     }
 
-    /// Returns at most one String pulled from the queue or None if the
+    /// Returns at most one message pulled from the queue or None if the
     /// pop times out or is interrupted.
     ///
-    /// The string will be whole, unparsed JSON string.
-    fn recv_one_chunk(
-        &mut self,
-        mut timeout: i32,
-        recipient: Option<&str>,
-    ) -> EgResult<Option<String>> {
-        let recipient = match recipient {
-            Some(s) => s.to_string(),
-            None => self.address().as_str().to_string(),
-        };
-
-        let value: String;
+    /// The bytes are the whole, unparsed JSON string, which is not
+    /// verified to be UTF-8.
+    fn recv_one_chunk(&mut self, mut timeout: i32, recipient: &str) -> EgResult<Option<Vec<u8>>> {
+        let value: Vec<u8>;
 
         if timeout == 0 {
             // non-blocking
 
-            // LPOP returns a scalar response.
-            value = match self.connection().lpop(&recipient, None) {
-                Ok(c) => c,
-                Err(e) => match e.kind() {
-                    redis::ErrorKind::TypeError => {
-                        // Will read a Nil value on timeout.  That's OK.
-                        return Ok(None);
-                    }
-                    _ => return Err(format!("recv_one_chunk failed: {e}").into()),
-                },
+            // LPOP returns a scalar response, or Nil if the list is empty.
+            let resp: Option<Vec<u8>> = self
+                .connection()
+                .lpop(recipient, None)
+                .map_err(|e| format!("recv_one_chunk failed: {e}"))?;
+
+            value = match resp {
+                Some(v) => v,
+                None => return Ok(None),
             };
         } else {
             // Blocking
@@ -146,9 +266,9 @@ impl Bus {
                 timeout = 0;
             }
 
-            let mut resp: Vec<String> = self
+            let mut resp: Vec<Vec<u8>> = self
                 .connection()
-                .blpop(&recipient, timeout as usize)
+                .blpop(recipient, timeout as usize)
                 .or_else(|e| Err(format!("Redis blpop error recipient={recipient} : {e}")))?;
 
             if resp.len() > 1 {
@@ -161,30 +281,47 @@ impl Bus {
             }
         }
 
-        log::trace!("recv_one_value() pulled from bus: {}", value);
-
         Ok(Some(value))
     }
 
     /// Returns at most one JSON value pulled from the queue or None if
     /// the list pop times out or the pop is interrupted by a signal.
+    ///
+    /// A message which cannot be parsed is logged and skipped, also
+    /// returning None, so one bad message does not take down the
+    /// caller.
     fn recv_one_value(
         &mut self,
         timeout: i32,
         recipient: Option<&str>,
     ) -> EgResult<Option<json::JsonValue>> {
-        let json_string = match self.recv_one_chunk(timeout, recipient)? {
-            Some(s) => s,
+        let recipient = match recipient {
+            Some(s) => s.to_string(),
+            None => self.address().as_str().to_string(),
+        };
+
+        let bytes = match self.recv_one_chunk(timeout, &recipient)? {
+            Some(b) => b,
             None => {
                 return Ok(None);
             }
         };
 
-        log::trace!("{self} read json from the bus: {json_string}");
+        log::trace!(
+            "{self} read json from the bus: {}",
+            String::from_utf8_lossy(&bytes)
+        );
 
-        match json::parse(&json_string) {
+        match parse_message(&bytes, &recipient) {
             Ok(json_val) => Ok(Some(json_val)),
-            Err(err) => Err(format!("Error parsing JSON: {err:?}").into()),
+            Err(malformed) => {
+                log::error!("{self} skipping {malformed}");
+                log::debug!(
+                    "{self} malformed message: {}",
+                    String::from_utf8_lossy(&bytes)
+                );
+                Ok(None)
+            }
         }
     }
 
@@ -268,10 +405,15 @@ impl Bus {
         let json_op = self.recv_json_value(timeout, recipient)?;
 
         if let Some(jv) = json_op {
+            let sender = jv["from"].as_str().unwrap_or("<unknown>").to_string();
+            let thread = jv["thread"].as_str().unwrap_or("<unknown>").to_string();
+
             match TransportMessage::from_json_value(jv, self.raw_data_mode) {
                 Ok(v) => return Ok(Some(v)),
                 Err(e) => {
-                    log::error!("Error translating JSON value into EgValue: {e}");
+                    log::error!(
+                        "{self} skipping invalid message from={sender} thread={thread}: {e}"
+                    );
                     return Ok(None);
                 }
             };
@@ -307,10 +449,14 @@ impl Bus {
         // requirement for TransportMessage.
         let recipient = recipient.unwrap_or(json_val["to"].as_str().unwrap());
 
-        let json_str = json_val.dump();
+        let mut json_str = json_val.dump();
 
         log::trace!("send() writing chunk to={}: {}", recipient, json_str);
 
+        if let Some(options) = self.compression.as_ref() {
+            json_str = compression::pack(&json_val, json_str, options)?;
+        }
+
         let res: Result<i32, _> = self.connection().rpush(recipient, json_str);
 
         if let Err(e) = res {
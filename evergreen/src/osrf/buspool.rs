@@ -0,0 +1,188 @@
+//! Pool of bus connections shared by the worker threads of a service.
+//!
+//! By default, every worker thread opens its own bus connection when
+//! it starts and closes it when it exits.  With many services running
+//! many workers, and workers regularly cycling out after max_requests,
+//! that adds up to a lot of connections and reconnects on the broker.
+//!
+//! When apps/<service>/unix_config/bus_pool_size is set, workers
+//! instead borrow a connection from a pool of at most that many
+//! connections when they start and return it when they exit.  A
+//! returned connection is given a new bus address before it's reused,
+//! so messages sent to a previous worker never reach its successor.
+//!
+//! Tradeoffs versus per-thread connections:
+//!
+//! * The pool caps the connections held by the service, but a worker
+//!   still holds its connection for its whole life, since it listens
+//!   on it between requests.  A pool smaller than max_children caps
+//!   the number of workers, not the connections per worker.
+//! * When the pool is exhausted, a starting worker either waits up to
+//!   apps/<service>/unix_config/bus_pool_wait seconds for a connection
+//!   to be returned, or, when the wait is 0, fails to start.  Failed
+//!   workers are retried by the server like any other failed worker.
+//! * Connections to remote domains are opened as needed by each
+//!   worker and are not pooled.
+//! * An idle pooled connection is not monitored, so a connection the
+//!   broker has dropped is only discovered when a worker uses it.
+use crate::osrf::bus::Bus;
+use crate::osrf::conf;
+use crate::EgResult;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// What to do when every pooled connection is in use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExhaustedPolicy {
+    /// Wait up to this long for a connection to be returned.
+    Wait(Duration),
+    /// Return an Err immediately.
+    Fail,
+}
+
+impl ExhaustedPolicy {
+    /// Policy for a configured wait time in seconds, where 0 means
+    /// do not wait.
+    pub fn from_wait_secs(secs: u64) -> Self {
+        if secs == 0 {
+            ExhaustedPolicy::Fail
+        } else {
+            ExhaustedPolicy::Wait(Duration::from_secs(secs))
+        }
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    idle: Vec<Bus>,
+    in_use: usize,
+}
+
+pub struct BusPool {
+    size: usize,
+    policy: ExhaustedPolicy,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+impl BusPool {
+    /// Create a pool of up to size connections.  Connections are opened
+    /// as they are needed.
+    pub fn new(size: usize, policy: ExhaustedPolicy) -> Self {
+        BusPool {
+            size,
+            policy,
+            state: Mutex::new(PoolState::default()),
+            returned: Condvar::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn policy(&self) -> ExhaustedPolicy {
+        self.policy
+    }
+
+    /// Number of connections currently borrowed.
+    pub fn in_use(&self) -> usize {
+        self.lock().in_use
+    }
+
+    /// Number of open connections waiting to be borrowed.
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // A panic while holding the lock leaves the pool usable.
+        match self.state.lock() {
+            Ok(g) => g,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Borrow a connection, opening a new one if the pool has room.
+    ///
+    /// When the pool is exhausted, waits or fails per our policy.
+    pub fn acquire(&self) -> EgResult<Bus> {
+        let started = Instant::now();
+        let mut state = self.lock();
+
+        loop {
+            if let Some(bus) = state.idle.pop() {
+                state.in_use += 1;
+                return Ok(bus);
+            }
+
+            if state.in_use < self.size {
+                // Reserve our slot, then connect without holding
+                // the lock.
+                state.in_use += 1;
+                drop(state);
+
+                return match Bus::new(conf::config().client()) {
+                    Ok(bus) => Ok(bus),
+                    Err(e) => {
+                        self.forget();
+                        Err(e)
+                    }
+                };
+            }
+
+            let wait = match self.policy {
+                ExhaustedPolicy::Fail => None,
+                ExhaustedPolicy::Wait(max) => max.checked_sub(started.elapsed()),
+            };
+
+            let wait = match wait {
+                Some(w) if !w.is_zero() => w,
+                _ => {
+                    return Err(format!(
+                        "Bus connection pool exhausted: all {} connections in use",
+                        self.size
+                    )
+                    .into())
+                }
+            };
+
+            state = match self.returned.wait_timeout(state, wait) {
+                Ok((s, _)) => s,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    /// Return a borrowed connection to the pool.
+    ///
+    /// The connection's pending messages are cleared and it receives
+    /// a new address.  A connection which cannot be cleared is
+    /// assumed broken and is closed instead.
+    pub fn release(&self, mut bus: Bus) {
+        if let Err(e) = bus.clear_bus() {
+            log::warn!("Closing pooled bus connection which failed to clear: {e}");
+            self.forget();
+            return;
+        }
+
+        bus.generate_address();
+
+        let mut state = self.lock();
+        state.in_use -= 1;
+        state.idle.push(bus);
+        drop(state);
+
+        self.returned.notify_one();
+    }
+
+    /// Free the slot of a borrowed connection which will not be
+    /// returned.
+    pub fn forget(&self) {
+        let mut state = self.lock();
+        state.in_use = state.in_use.saturating_sub(1);
+        drop(state);
+
+        self.returned.notify_one();
+    }
+}
@@ -116,6 +116,12 @@ impl Cache {
         }
     }
 
+    /// True if the specified cache type has been initialized for the
+    /// current thread.
+    pub fn is_initialized(cache_name: &str) -> bool {
+        Cache::verify_cache(cache_name).is_ok()
+    }
+
     pub fn init_cache(cache_name: &str) -> EgResult<()> {
         if Cache::verify_cache(cache_name).is_ok() {
             log::warn!("Cache {cache_name} is already connected; ignoring");
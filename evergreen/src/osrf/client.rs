@@ -31,23 +31,126 @@ pub struct ClientSingleton {
     /// Queue of receieved transport messages that have yet to be
     /// processed by any sessions.
     backlog: Vec<message::TransportMessage>,
+
+    /// Routers we may send API calls to.  See router_addr().
+    routers: Vec<BusAddress>,
+
+    /// Index into routers of the router currently in use.
+    router_index: usize,
 }
 
 impl ClientSingleton {
-    fn new() -> EgResult<ClientSingleton> {
-        let bus = bus::Bus::new(conf::config().client())?;
+    fn new(kind: conf::DomainKind) -> EgResult<ClientSingleton> {
+        let config = conf::config()
+            .domain_client(kind)
+            .ok_or_else(|| format!("No bus config for the {kind:?} domain"))?;
+
+        let bus = bus::Bus::new(config)?;
         Ok(ClientSingleton::from_bus(bus))
     }
 
     /// Create a new singleton instance from a previously setup Bus.
+    ///
+    /// The primary domain is the domain of the Bus.
     fn from_bus(bus: bus::Bus) -> ClientSingleton {
-        let domain = conf::config().client().domain().name();
+        let domain = bus.domain().to_string();
+
+        let config = conf::config()
+            .client_for_domain(&domain)
+            .unwrap_or(conf::config().client());
 
         ClientSingleton {
-            domain: domain.to_string(),
+            routers: ClientSingleton::configured_routers(config),
+            domain,
             bus: Some(bus),
             backlog: Vec::new(),
             remote_bus_map: HashMap::new(),
+            router_index: 0,
+        }
+    }
+
+    /// The router on our primary domain, followed by any additional
+    /// routers in its client config.
+    fn configured_routers(client: &conf::BusClient) -> Vec<BusAddress> {
+        let mut routers = vec![BusAddress::for_router(
+            client.router_name(),
+            client.domain().name(),
+        )];
+
+        for router in client.routers() {
+            let addr = BusAddress::for_router(router.username(), router.domain());
+            if !routers.iter().any(|r| r.as_str() == addr.as_str()) {
+                routers.push(addr);
+            }
+        }
+
+        routers
+    }
+
+    /// Address of the router we send API calls to.
+    ///
+    /// This is the router on our primary domain until a failure
+    /// causes us to fail over to the next configured router.  The
+    /// router that last worked remains in use for subsequent calls
+    /// until it, too, fails.
+    pub fn router_addr(&self) -> &BusAddress {
+        &self.routers[self.router_index]
+    }
+
+    /// Number of routers available for failover, including the
+    /// primary router.
+    pub fn router_count(&self) -> usize {
+        self.routers.len()
+    }
+
+    /// Stop using the current router and move on to the next one
+    /// in our list, wrapping around to the first.
+    ///
+    /// Returns false if we have no other routers to try.
+    pub fn router_failed(&mut self) -> bool {
+        if self.routers.len() < 2 {
+            return false;
+        }
+
+        let failed = self.router_index;
+        self.router_index = (self.router_index + 1) % self.routers.len();
+
+        log::warn!(
+            "Router {} failed; failing over to router {}",
+            self.routers[failed],
+            self.router_addr()
+        );
+
+        true
+    }
+
+    /// Send a message to our current router, failing over to each
+    /// of the other configured routers in turn if the send fails.
+    pub fn send_to_router(&mut self, tmsg: message::TransportMessage) -> EgResult<()> {
+        let mut attempts = self.routers.len();
+
+        loop {
+            let addr = self.router_addr().clone();
+
+            log::trace!("Sending message via router {addr}");
+
+            let result = match self.get_domain_bus(addr.domain()) {
+                Ok(bus) => bus.send_to(tmsg.clone(), addr.as_str()),
+                Err(e) => Err(e),
+            };
+
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            log::error!("Error sending to router {addr}: {err}");
+
+            attempts -= 1;
+
+            if attempts == 0 || !self.router_failed() {
+                return Err(err);
+            }
         }
     }
 
@@ -101,6 +204,11 @@ impl ClientSingleton {
         }
     }
 
+    /// Clear and return our Bus connection, if we have one.
+    pub fn try_take_bus(&mut self) -> Option<bus::Bus> {
+        self.bus.take()
+    }
+
     /// Give this client a bus to use.
     pub fn set_bus(&mut self, bus: bus::Bus) {
         self.bus = Some(bus);
@@ -122,13 +230,19 @@ impl ClientSingleton {
 
     /// Add a connection to a new remote domain.
     ///
-    /// Panics if our configuration has no primary domain.
+    /// Uses the connection settings for the domain if it's our
+    /// private or public domain.
     fn add_connection(&mut self, domain: &str) -> EgResult<&mut bus::Bus> {
-        // When adding a connection to a remote domain, assume the same
-        // connection type, etc. is used and just change the domain.
-        let mut conf = conf::config().client().clone();
-
-        conf.set_domain(domain);
+        let conf = match conf::config().client_for_domain(domain) {
+            Some(c) => c.clone(),
+            None => {
+                // Otherwise assume the same connection type, etc. is
+                // used and just change the domain.
+                let mut c = conf::config().client().clone();
+                c.set_domain(domain);
+                c
+            }
+        };
 
         let bus = bus::Bus::new(&conf)?;
 
@@ -257,8 +371,19 @@ impl Client {
     /// using an existing Bus connection, instead of creating
     /// a new one, which is generally unnecessary.
     pub fn connect() -> EgResult<Client> {
+        Client::connect_to(conf::DomainKind::Private)
+    }
+
+    /// Create a new Client whose primary domain is the private or
+    /// public domain.
+    ///
+    /// Services use the private domain.  Code acting on behalf of a
+    /// gateway may use the public domain, so its API calls are routed
+    /// only to the services published there.  Calls to other domains
+    /// open connections as needed.  See ClientSingleton::get_domain_bus().
+    pub fn connect_to(kind: conf::DomainKind) -> EgResult<Client> {
         // This performs the actual bus-level connection.
-        let singleton = ClientSingleton::new()?;
+        let singleton = ClientSingleton::new(kind)?;
 
         let address = singleton.bus().address().clone();
         let domain = singleton.domain().to_string();
@@ -353,7 +478,7 @@ impl Client {
     /// Send a request and receive a ResponseIterator for iterating
     /// the responses to the method.
     ///
-    /// Uses the default request timeout DEFAULT_REQUEST_TIMEOUT.
+    /// Uses the default request timeout; see session::default_request_timeout().
     pub fn send_recv_iter(
         &self,
         service: &str,
@@ -370,8 +495,9 @@ impl Client {
         self.singleton().borrow_mut().wait(timeout)
     }
 
-    /// Sends an API request and returns the first response, or None if
-    /// the API call times out.
+    /// Sends an API request and returns the first response.
+    ///
+    /// Returns Err if the API call times out.
     ///
     /// This still waits for all responses to arrive before returning the
     /// first, so the request can be marked as complete and cleaned up.
@@ -0,0 +1,185 @@
+//! Optional compression of large transport messages on the bus.
+//!
+//! When a bus client is configured with a <compression> element,
+//! serialized messages at least as large as its threshold are sent as
+//! an envelope which repeats the routing fields of the message and
+//! carries the compressed message as a base64 string:
+//!
+//! ```text
+//! {"to":"...","from":"...","thread":"...","osrf_xid":"...",
+//!  "osrf_compression":"gzip","osrf_payload":"H4sIAAAA..."}
+//! ```
+//!
+//! Receivers check for the osrf_compression header and unpack the
+//! envelope before parsing the message, so compressed and uncompressed
+//! messages may arrive on the same connection.  Compression is off by
+//! default, since receivers which predate it -- including non-Rust
+//! OpenSRF peers -- cannot read compressed messages.  Enable it only
+//! on domains where every peer understands the header.
+use crate::EgResult;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use json::JsonValue;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// Header naming the compression algorithm of an envelope.
+pub const COMPRESSION_KEY: &str = "osrf_compression";
+
+/// Envelope key containing the base64-encoded compressed message.
+pub const PAYLOAD_KEY: &str = "osrf_payload";
+
+/// Messages of at least this many bytes are compressed when no
+/// threshold is configured.
+pub const DEFAULT_THRESHOLD: usize = 65536;
+
+/// Routing fields copied from the message into the envelope.
+const ENVELOPE_FIELDS: [&str; 4] = ["to", "from", "thread", "osrf_xid"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Gzip,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Gzip => "gzip",
+        }
+    }
+}
+
+impl TryFrom<&str> for Algorithm {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "gzip" => Ok(Algorithm::Gzip),
+            _ => Err(format!("Unsupported compression algorithm: '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Compression settings for a bus client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionOptions {
+    algorithm: Algorithm,
+    threshold: usize,
+}
+
+impl CompressionOptions {
+    pub fn new(algorithm: Algorithm, threshold: usize) -> Self {
+        CompressionOptions {
+            algorithm,
+            threshold,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Minimum size in bytes of a serialized message to compress.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+}
+
+fn compress(algorithm: Algorithm, text: &str) -> EgResult<Vec<u8>> {
+    match algorithm {
+        Algorithm::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+            encoder
+                .write_all(text.as_bytes())
+                .and_then(|_| encoder.finish())
+                .map_err(|e| format!("Cannot compress message: {e}").into())
+        }
+    }
+}
+
+fn decompress(algorithm: Algorithm, bytes: &[u8]) -> EgResult<String> {
+    match algorithm {
+        Algorithm::Gzip => {
+            let mut text = String::new();
+
+            GzDecoder::new(bytes)
+                .read_to_string(&mut text)
+                .map_err(|e| format!("Cannot decompress message: {e}"))?;
+
+            Ok(text)
+        }
+    }
+}
+
+/// Returns the text to send for a serialized message, which is an
+/// envelope containing the compressed message when the message is
+/// at least as large as the threshold, or the message as-is.
+///
+/// ```
+/// use evergreen::osrf::compression::*;
+///
+/// let options = CompressionOptions::new(Algorithm::Gzip, 100);
+/// let msg = json::object! {"to": "a", "from": "b", "thread": "t", "body": "x".repeat(500)};
+///
+/// let sent = pack(&msg, msg.dump(), &options).unwrap();
+/// assert!(sent.len() < msg.dump().len());
+///
+/// let unpacked = unpack(json::parse(&sent).unwrap()).unwrap();
+/// assert_eq!(unpacked, msg);
+/// ```
+pub fn pack(msg: &JsonValue, text: String, options: &CompressionOptions) -> EgResult<String> {
+    if text.len() < options.threshold {
+        return Ok(text);
+    }
+
+    let compressed = compress(options.algorithm, &text)?;
+
+    let mut envelope = JsonValue::new_object();
+
+    for field in ENVELOPE_FIELDS {
+        if !msg[field].is_null() {
+            envelope[field] = msg[field].clone();
+        }
+    }
+
+    envelope[COMPRESSION_KEY] = options.algorithm.as_str().into();
+    envelope[PAYLOAD_KEY] = BASE64.encode(compressed).into();
+
+    log::trace!(
+        "Compressed message of {} bytes to {} bytes",
+        text.len(),
+        envelope[PAYLOAD_KEY].as_str().map(|p| p.len()).unwrap_or(0)
+    );
+
+    Ok(envelope.dump())
+}
+
+/// Returns the message contained in a compressed envelope, or the
+/// value as-is if it's not compressed.
+pub fn unpack(value: JsonValue) -> EgResult<JsonValue> {
+    let algorithm = match value[COMPRESSION_KEY].as_str() {
+        Some(a) => Algorithm::try_from(a)?,
+        None => return Ok(value),
+    };
+
+    let payload = value[PAYLOAD_KEY]
+        .as_str()
+        .ok_or_else(|| format!("Compressed message has no {PAYLOAD_KEY}"))?;
+
+    let bytes = BASE64
+        .decode(payload)
+        .map_err(|e| format!("Invalid compressed message payload: {e}"))?;
+
+    let text = decompress(algorithm, &bytes)?;
+
+    json::parse(&text).map_err(|e| format!("Error parsing decompressed JSON: {e}").into())
+}
@@ -1,9 +1,14 @@
+use crate::osrf::breaker::{self, BreakerOptions};
+use crate::osrf::compression::{self, CompressionOptions};
 use gethostname::gethostname;
 use roxmltree;
+use std::env;
 use std::fmt;
 use std::fs;
+use std::io::Read;
 use std::str::FromStr;
 use std::sync::OnceLock;
+use std::time::Duration;
 use syslog;
 
 static GLOBAL_OSRF_CONFIG: OnceLock<Config> = OnceLock::new();
@@ -67,13 +72,18 @@ impl LogOptions {
     ///
     /// Defaults to Info
     pub fn log_level_from_str(level: &str) -> log::LevelFilter {
+        LogOptions::try_log_level_from_str(level).unwrap_or(log::LevelFilter::Info)
+    }
+
+    /// Returns None if the log level is not recognized.
+    pub fn try_log_level_from_str(level: &str) -> Option<log::LevelFilter> {
         match level {
-            "1" | "error" => log::LevelFilter::Error,
-            "2" | "warn" => log::LevelFilter::Warn,
-            "3" | "info" => log::LevelFilter::Info,
-            "4" | "debug" => log::LevelFilter::Debug,
-            "5" | "trace" => log::LevelFilter::Trace,
-            _ => log::LevelFilter::Info,
+            "1" | "error" => Some(log::LevelFilter::Error),
+            "2" | "warn" => Some(log::LevelFilter::Warn),
+            "3" | "info" => Some(log::LevelFilter::Info),
+            "4" | "debug" => Some(log::LevelFilter::Debug),
+            "5" | "trace" => Some(log::LevelFilter::Trace),
+            _ => None,
         }
     }
 }
@@ -100,6 +110,20 @@ impl fmt::Display for BusDomain {
     }
 }
 
+/// Which of the configured bus domains to connect to.
+///
+/// Evergreen runs services on a private domain, configured by the
+/// <opensrf> element, and exposes a subset of them to the gateways on
+/// a public domain, configured by the <gateway> element.  Routers on
+/// each domain are configured in <routers>.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DomainKind {
+    /// Internal calls between services.
+    Private,
+    /// Calls relayed from the gateways.
+    Public,
+}
+
 /// A set of bus login credentials
 #[derive(Debug, Clone)]
 pub struct BusClient {
@@ -110,6 +134,9 @@ pub struct BusClient {
     logging: LogOptions,
     settings_config: Option<String>,
     routers: Vec<ClientRouter>,
+    request_timeout: Option<i32>,
+    compression: Option<CompressionOptions>,
+    circuit_breaker: Option<BreakerOptions>,
 }
 
 impl BusClient {
@@ -132,9 +159,27 @@ impl BusClient {
     pub fn logging_mut(&mut self) -> &mut LogOptions {
         &mut self.logging
     }
+    /// Default number of seconds to wait for a response to an API
+    /// request, from the optional <request_timeout> element.
+    pub fn request_timeout(&self) -> Option<i32> {
+        self.request_timeout
+    }
     pub fn settings_config(&self) -> Option<&str> {
         self.settings_config.as_deref()
     }
+    /// Compression applied to large outbound messages, from the
+    /// optional <compression> element.  See osrf::compression.
+    pub fn compression(&self) -> Option<&CompressionOptions> {
+        self.compression.as_ref()
+    }
+    pub fn set_compression(&mut self, compression: Option<CompressionOptions>) {
+        self.compression = compression;
+    }
+    /// Circuit breaker settings from the optional <circuit_breaker>
+    /// element.  See osrf::breaker.
+    pub fn circuit_breaker(&self) -> Option<&BreakerOptions> {
+        self.circuit_breaker.as_ref()
+    }
     pub fn routers(&self) -> &Vec<ClientRouter> {
         &self.routers
     }
@@ -148,6 +193,19 @@ impl BusClient {
     pub fn set_password(&mut self, password: &str) {
         self.password = password.to_string();
     }
+
+    /// Add any problems with our bus connection settings to issues.
+    fn validate(&self, context: &str, issues: &mut Vec<String>) {
+        if self.username.is_empty() {
+            issues.push(format!("{context}: bus username is empty"));
+        }
+        if self.password.is_empty() {
+            issues.push(format!("{context}: bus password is empty"));
+        }
+        if self.domain.name.is_empty() {
+            issues.push(format!("{context}: bus domain is empty"));
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -191,36 +249,234 @@ impl Router {
     }
 }
 
+/// Worker thread pool sizing for a service.
+///
+/// Values not set here fall back to the service's unix_config
+/// host settings.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceConfig {
+    name: String,
+    min_workers: Option<usize>,
+    max_workers: Option<usize>,
+    min_idle_workers: Option<usize>,
+}
+
+impl ServiceConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// "min_children" in opensrf_core.xml
+    pub fn min_workers(&self) -> Option<usize> {
+        self.min_workers
+    }
+    /// "max_children" in opensrf_core.xml
+    pub fn max_workers(&self) -> Option<usize> {
+        self.max_workers
+    }
+    /// "min_spare_children" in opensrf_core.xml
+    pub fn min_idle_workers(&self) -> Option<usize> {
+        self.min_idle_workers
+    }
+
+    /// Returns Err if the configured values cannot be satisfied.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_worker_counts(
+            &self.name,
+            self.min_workers.unwrap_or(0),
+            self.max_workers.unwrap_or(usize::MAX),
+            self.min_idle_workers.unwrap_or(0),
+        )
+    }
+}
+
+/// Returns Err if a service's worker pool sizes make no sense together.
+pub fn validate_worker_counts(
+    service: &str,
+    min_workers: usize,
+    max_workers: usize,
+    min_idle_workers: usize,
+) -> Result<(), String> {
+    if max_workers == 0 {
+        return Err(format!("{service}: max_children must be greater than 0"));
+    }
+
+    if max_workers < min_workers {
+        return Err(format!(
+            "{service}: max_children ({max_workers}) is less than min_children ({min_workers})"
+        ));
+    }
+
+    if max_workers < min_idle_workers {
+        return Err(format!(
+            "{service}: max_children ({max_workers}) is less than min_spare_children ({min_idle_workers})"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Replace ${VAR} and ${VAR:-default} references in the config text
+/// with values from the environment.
+///
+/// Returns Err naming the variable and its containing element when a
+/// variable is undefined and has no default.
+///
+/// ```
+/// use evergreen::osrf::conf::interpolate_env;
+///
+/// std::env::set_var("EG_DOC_BUS_HOST", "redis.example.org");
+///
+/// let xml = "<domain>${EG_DOC_BUS_HOST}</domain><port>${EG_DOC_BUS_PORT:-6379}</port>";
+/// let expanded = interpolate_env(xml).unwrap();
+/// assert_eq!(expanded, "<domain>redis.example.org</domain><port>6379</port>");
+///
+/// let err = interpolate_env("<passwd>${EG_DOC_UNSET}</passwd>").unwrap_err();
+/// assert!(err.contains("EG_DOC_UNSET") && err.contains("passwd"));
+/// ```
+pub fn interpolate_env(text: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut remainder = text;
+
+    while let Some(start) = remainder.find("${") {
+        let end = match remainder[start..].find('}') {
+            Some(e) => start + e,
+            None => {
+                let offset = text.len() - remainder.len() + start;
+                return Err(format!(
+                    "Unterminated variable reference in element <{}>",
+                    enclosing_element(text, offset)
+                ));
+            }
+        };
+
+        expanded.push_str(&remainder[..start]);
+
+        let reference = &remainder[start + 2..end];
+
+        let (name, default) = match reference.split_once(":-") {
+            Some((n, d)) => (n, Some(d)),
+            None => (reference, None),
+        };
+
+        match env::var(name) {
+            Ok(v) => expanded.push_str(&v),
+            Err(_) => match default {
+                Some(d) => expanded.push_str(d),
+                None => {
+                    let offset = text.len() - remainder.len() + start;
+                    return Err(format!(
+                        "Undefined environment variable '{name}' in element <{}>",
+                        enclosing_element(text, offset)
+                    ));
+                }
+            },
+        }
+
+        remainder = &remainder[end + 1..];
+    }
+
+    expanded.push_str(remainder);
+
+    Ok(expanded)
+}
+
+/// Name of the element whose tag most recently opened before offset.
+fn enclosing_element(text: &str, offset: usize) -> &str {
+    let mut before = &text[..offset];
+
+    while let Some(pos) = before.rfind('<') {
+        let tag = &before[pos + 1..];
+        if !tag.starts_with('/') && !tag.starts_with('?') && !tag.starts_with('!') {
+            let len = tag
+                .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .unwrap_or(tag.len());
+            return &tag[..len];
+        }
+        before = &before[..pos];
+    }
+
+    "config"
+}
+
 #[derive(Debug, Clone)]
 pub struct ConfigBuilder {
     client: Option<BusClient>,
     routers: Vec<Router>,
     gateway: Option<BusClient>,
     log_protect: Vec<String>,
+    services: Vec<ServiceConfig>,
+
+    /// Problems found while parsing which do not prevent building
+    /// a usable config, e.g. an unrecognized log level.
+    issues: Vec<String>,
 }
 
 impl ConfigBuilder {
+    /// Check the config for problems, collecting all of them instead
+    /// of stopping at the first.
+    ///
+    /// This includes problems build() would tolerate, like an
+    /// unrecognized log level which reverts to the default.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut issues = self.issues.clone();
+
+        if let Err(e) = Config::get_os_hostname() {
+            issues.push(e);
+        }
+
+        match self.client.as_ref() {
+            Some(client) => client.validate("opensrf", &mut issues),
+            None => issues.push("Config has no client settings".to_string()),
+        }
+
+        if let Some(gateway) = self.gateway.as_ref() {
+            gateway.validate("gateway", &mut issues);
+        }
+
+        for router in self.routers.iter() {
+            let context = format!("router {}", router.client().domain().name());
+            router.client().validate(&context, &mut issues);
+        }
+
+        for service in self.services.iter() {
+            if let Err(e) = service.validate() {
+                issues.push(e);
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
     pub fn build(self) -> Result<Config, String> {
         if self.client.is_none() {
             return Err(format!("Config has no client settings"));
         }
 
+        for service in self.services.iter() {
+            service.validate()?;
+        }
+
         Ok(Config {
             hostname: Config::get_os_hostname()?,
             client: self.client.unwrap(),
             routers: self.routers,
             gateway: self.gateway,
             log_protect: self.log_protect,
+            services: self.services,
         })
     }
 
-    /// Load configuration from a YAML file.
+    /// Load configuration from an XML file.
     ///
-    /// May panic on invalid values (e.g. invalid log level) or unexpected
-    /// Yaml config structures.
+    /// ${VAR} and ${VAR:-default} references are replaced with values
+    /// from the environment before parsing.  See interpolate_env().
     pub fn from_file(filename: &str) -> Result<Self, String> {
         match fs::read_to_string(filename) {
-            Ok(text) => ConfigBuilder::from_xml_string(&text),
+            Ok(text) => ConfigBuilder::from_string(&text),
             Err(e) => Err(format!(
                 "Error reading configuration file: file='{}' {:?}",
                 filename, e
@@ -228,6 +484,29 @@ impl ConfigBuilder {
         }
     }
 
+    /// Load configuration from an in-memory XML document, e.g. one
+    /// embedded in a host application.
+    ///
+    /// Environment variables are interpolated as with from_file().
+    pub fn from_string(xml: &str) -> Result<Self, String> {
+        ConfigBuilder::from_xml_string(&interpolate_env(xml)?)
+    }
+
+    /// Load configuration from any reader of XML text.
+    ///
+    /// Environment variables are interpolated as with from_file().
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, String> {
+        let mut text = String::new();
+
+        reader
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Error reading configuration: {e}"))?;
+
+        ConfigBuilder::from_string(&text)
+    }
+
+    /// Parse configuration from XML text as-is, without interpolating
+    /// environment variables.
     pub fn from_xml_string(xml: &str) -> Result<Self, String> {
         let doc =
             roxmltree::Document::parse(xml).or_else(|e| Err(format!("Error parsing XML: {e}")))?;
@@ -247,6 +526,8 @@ impl ConfigBuilder {
             gateway: None,
             routers: Vec::new(),
             log_protect: Vec::new(),
+            services: Vec::new(),
+            issues: Vec::new(),
         };
 
         // Start with the Client portion, which will contain values
@@ -257,6 +538,7 @@ impl ConfigBuilder {
                 "routers" => builder.unpack_routers(&node)?,
                 "gateway" => builder.unpack_gateway(&node)?,
                 "shared" => builder.unpack_shared(&node)?,
+                "services" => builder.unpack_services(&node)?,
                 _ => {} // ignore
             }
         }
@@ -285,6 +567,46 @@ impl ConfigBuilder {
         Ok(())
     }
 
+    /// Unpack per-service worker pool sizes.
+    ///
+    /// <services>
+    ///   <service name="open-ils.rs-actor">
+    ///     <min_children>3</min_children>
+    ///     <max_children>30</max_children>
+    ///     <min_spare_children>1</min_spare_children>
+    ///   </service>
+    /// </services>
+    fn unpack_services(&mut self, node: &roxmltree::Node) -> Result<(), String> {
+        for snode in node.children().filter(|n| n.has_tag_name("service")) {
+            let name = match snode.attribute("name") {
+                Some(n) => n,
+                None => Err("Service config requires a name".to_string())?,
+            };
+
+            let count = |tag: &str| -> Result<Option<usize>, String> {
+                match self.child_node_text(&snode, tag) {
+                    Some(t) => t
+                        .trim()
+                        .parse::<usize>()
+                        .map(Some)
+                        .map_err(|e| format!("{name}: invalid {tag} '{t}': {e}")),
+                    None => Ok(None),
+                }
+            };
+
+            let service = ServiceConfig {
+                name: name.to_string(),
+                min_workers: count("min_children")?,
+                max_workers: count("max_children")?,
+                min_idle_workers: count("min_spare_children")?,
+            };
+
+            self.services.push(service);
+        }
+
+        Ok(())
+    }
+
     fn unpack_routers(&mut self, node: &roxmltree::Node) -> Result<(), String> {
         for rnode in node.children().filter(|n| n.has_tag_name("router")) {
             // Router client configs are (mostly) nested in a <transport> element.
@@ -404,6 +726,9 @@ impl ConfigBuilder {
         let mut password = "";
         let mut router_name = "router";
         let mut settings_config: Option<String> = None;
+        let mut request_timeout: Option<i32> = None;
+        let mut compression: Option<CompressionOptions> = None;
+        let mut circuit_breaker: Option<BreakerOptions> = None;
 
         for child in node.children() {
             match child.tag_name().name() {
@@ -427,6 +752,50 @@ impl ConfigBuilder {
                         settings_config = Some(t.to_string());
                     }
                 }
+                "request_timeout" => {
+                    if let Some(t) = child.text() {
+                        match t.trim().parse::<i32>() {
+                            Ok(n) => request_timeout = Some(n),
+                            Err(e) => Err(format!("Invalid request_timeout '{t}': {e}"))?,
+                        }
+                    }
+                }
+                "compression" => {
+                    // <compression threshold="65536">gzip</compression>
+                    let algorithm = child.text().unwrap_or("gzip").trim();
+                    let algorithm = compression::Algorithm::try_from(algorithm)?;
+
+                    let threshold = match child.attribute("threshold") {
+                        Some(t) => t
+                            .trim()
+                            .parse::<usize>()
+                            .map_err(|e| format!("Invalid compression threshold '{t}': {e}"))?,
+                        None => compression::DEFAULT_THRESHOLD,
+                    };
+
+                    compression = Some(CompressionOptions::new(algorithm, threshold));
+                }
+                "circuit_breaker" => {
+                    // <circuit_breaker failures="5" cooldown="30"/>
+                    let failures = match child.attribute("failures") {
+                        Some(f) => f
+                            .trim()
+                            .parse::<u32>()
+                            .map_err(|e| format!("Invalid circuit_breaker failures '{f}': {e}"))?,
+                        None => breaker::DEFAULT_FAILURE_THRESHOLD,
+                    };
+
+                    let cooldown = match child.attribute("cooldown") {
+                        Some(c) => c
+                            .trim()
+                            .parse::<u64>()
+                            .map_err(|e| format!("Invalid circuit_breaker cooldown '{c}': {e}"))?,
+                        None => breaker::DEFAULT_COOLDOWN,
+                    };
+
+                    circuit_breaker =
+                        Some(BreakerOptions::new(failures, Duration::from_secs(cooldown)));
+                }
                 _ => {}
             }
         }
@@ -435,6 +804,9 @@ impl ConfigBuilder {
             domain,
             logging,
             settings_config,
+            request_timeout,
+            compression,
+            circuit_breaker,
             routers: Vec::new(),
             username: username.to_string(),
             password: password.to_string(),
@@ -460,8 +832,11 @@ impl ConfigBuilder {
         let mut port = DEFAULT_BUS_PORT;
         if let Some(pnode) = node.children().filter(|c| c.has_tag_name("port")).next() {
             if let Some(ptext) = pnode.text() {
-                if let Ok(p) = ptext.parse::<u16>() {
-                    port = p;
+                match ptext.parse::<u16>() {
+                    Ok(p) => port = p,
+                    Err(_) => self
+                        .issues
+                        .push(format!("{domain_name}: invalid bus port '{ptext}'")),
                 }
             }
         }
@@ -493,20 +868,25 @@ impl ConfigBuilder {
                 }
                 "syslog" => {
                     if let Some(f) = child.text() {
-                        if let Ok(ff) = syslog::Facility::from_str(f) {
-                            ops.syslog_facility = Some(ff);
+                        match syslog::Facility::from_str(f) {
+                            Ok(ff) => ops.syslog_facility = Some(ff),
+                            Err(_) => self.issues.push(format!("Invalid syslog facility '{f}'")),
                         }
                     }
                 }
                 "actlog" => {
                     if let Some(f) = child.text() {
-                        if let Ok(ff) = syslog::Facility::from_str(f) {
-                            ops.activity_log_facility = Some(ff);
+                        match syslog::Facility::from_str(f) {
+                            Ok(ff) => ops.activity_log_facility = Some(ff),
+                            Err(_) => self.issues.push(format!("Invalid actlog facility '{f}'")),
                         }
                     }
                 }
                 "loglevel" => {
                     if let Some(level_num) = child.text() {
+                        if LogOptions::try_log_level_from_str(level_num).is_none() {
+                            self.issues.push(format!("Invalid loglevel '{level_num}'"));
+                        }
                         ops.log_level = Some(LogOptions::log_level_from_str(level_num));
                     }
                 }
@@ -525,6 +905,7 @@ pub struct Config {
     routers: Vec<Router>,
     gateway: Option<BusClient>,
     log_protect: Vec<String>,
+    services: Vec<ServiceConfig>,
 }
 
 impl Config {
@@ -567,6 +948,35 @@ impl Config {
         &self.hostname
     }
 
+    pub fn services(&self) -> &Vec<ServiceConfig> {
+        &self.services
+    }
+
+    /// Worker pool sizing for the named service, if configured.
+    pub fn service(&self, name: &str) -> Option<&ServiceConfig> {
+        self.services.iter().find(|s| s.name() == name)
+    }
+
+    /// Connection settings for the private or public domain.
+    ///
+    /// Returns None for the public domain if there is no gateway
+    /// config.
+    pub fn domain_client(&self, kind: DomainKind) -> Option<&BusClient> {
+        match kind {
+            DomainKind::Private => Some(&self.client),
+            DomainKind::Public => self.gateway.as_ref(),
+        }
+    }
+
+    /// Connection settings for the named domain, if it's our private
+    /// or public domain.
+    pub fn client_for_domain(&self, domain: &str) -> Option<&BusClient> {
+        [DomainKind::Private, DomainKind::Public]
+            .into_iter()
+            .filter_map(|kind| self.domain_client(kind))
+            .find(|c| c.domain().name() == domain)
+    }
+
     pub fn get_router_conf(&self, domain: &str) -> Option<&Router> {
         self.routers
             .iter()
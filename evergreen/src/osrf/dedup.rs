@@ -0,0 +1,150 @@
+//! Recognize API requests redelivered by the bus.
+//!
+//! After a transient failure, the bus may deliver a request to a
+//! service more than once.  That's harmless for lookups, but a method
+//! which changes data, e.g. one that creates a payment or checks out an
+//! item, would make the change twice.
+//!
+//! Every API request carries a unique osrf_msg_id.  For methods which
+//! opt in via MethodDef::set_dedup(), the worker records the ID of each
+//! request it handles in the global cache before calling the method,
+//! then replaces the record with the responses the method sent once it
+//! succeeds.  When a request arrives with an ID already in the cache:
+//!
+//! * If the prior call completed, its responses are sent again in
+//!   place of calling the method.
+//! * If the prior call is still running, the request is dropped, since
+//!   the running call replies to the same caller.
+//!
+//! A failed call removes its record so the request may be retried.  A
+//! call which succeeds after exceeding its timeout is still recorded as
+//! complete, since its changes were made.
+//!
+//! A worker which dies mid-call leaves its claim behind.  Claims older
+//! than the method's timeout are treated as stale and the request runs
+//! again.  For methods with no timeout, the claim lasts the window.
+//!
+//! Opt in methods which are not safe to run twice and whose responses
+//! are small enough to cache, e.g. checkout, checkin, and renewal.
+//! Lookups and other read-only methods do not need it.
+//!
+//! Records are kept for apps/<service>/unix_config/dedup_window
+//! seconds, DEFAULT_WINDOW if unset.  A window of 0 disables
+//! deduplication for the service.  The cache lookup and claim are not
+//! atomic, so two copies of a request arriving at the same instant may
+//! still both run.
+use crate::date;
+use crate::osrf::cache::Cache;
+use crate::EgResult;
+use crate::EgValue;
+use std::time::Duration;
+
+/// Seconds to remember a request when no window is configured.
+pub const DEFAULT_WINDOW: u32 = 300;
+
+const GLOBAL_CACHE: &str = "global";
+
+/// What we know of a previous delivery of a request.
+#[derive(Debug, PartialEq)]
+pub enum Prior {
+    /// The request has not been seen.  It's now claimed by the caller.
+    New,
+    /// Another delivery of the request is still being handled.  It
+    /// was claimed at this epoch time.
+    Running(f64),
+    /// The request was handled and sent these responses.
+    Complete(Vec<EgValue>),
+}
+
+/// Cache key for a request.
+///
+/// A transport message may carry more than one request, so the
+/// message ID is paired with the request's thread trace.
+///
+/// ```
+/// use evergreen::osrf::dedup;
+/// assert_eq!(dedup::cache_key("1234.5", 5), "osrf.dedup.1234.5.5");
+/// ```
+pub fn cache_key(msg_id: &str, thread_trace: usize) -> String {
+    format!("osrf.dedup.{msg_id}.{thread_trace}")
+}
+
+fn connect() -> EgResult<()> {
+    if !Cache::is_initialized(GLOBAL_CACHE) {
+        Cache::init_cache(GLOBAL_CACHE)?;
+    }
+    Ok(())
+}
+
+/// Translate a cached record into a Prior.
+pub fn prior_from_cache(value: Option<EgValue>) -> Prior {
+    let value = match value {
+        Some(v) => v,
+        None => return Prior::New,
+    };
+
+    if value["responses"].is_array() {
+        Prior::Complete(value["responses"].members().cloned().collect())
+    } else {
+        // Records without a claim time count as claimed long ago.
+        Prior::Running(value["claimed_at"].as_f64().unwrap_or(0.0))
+    }
+}
+
+/// True if a claim made at claimed_at (epoch seconds) is older than
+/// stale_after as of now.  Claims never go stale without a limit.
+///
+/// ```
+/// use evergreen::osrf::dedup;
+/// use std::time::Duration;
+///
+/// let limit = Some(Duration::from_secs(30));
+/// assert!(!dedup::is_stale(100.0, 120.0, limit));
+/// assert!(dedup::is_stale(100.0, 131.0, limit));
+/// assert!(!dedup::is_stale(100.0, 1000.0, None));
+/// ```
+pub fn is_stale(claimed_at: f64, now: f64, stale_after: Option<Duration>) -> bool {
+    match stale_after {
+        Some(limit) => now - claimed_at > limit.as_secs_f64(),
+        None => false,
+    }
+}
+
+/// Look up a request, claiming it as running if it's new or its
+/// running claim is older than stale_after, e.g. the method timeout.
+pub fn claim(key: &str, window: u32, stale_after: Option<Duration>) -> EgResult<Prior> {
+    connect()?;
+
+    let now = date::epoch_secs();
+    let mut prior = prior_from_cache(Cache::get_global(key)?);
+
+    if let Prior::Running(claimed_at) = prior {
+        if is_stale(claimed_at, now, stale_after) {
+            log::warn!("Replacing stale claim on request {key} made at {claimed_at}");
+            prior = Prior::New;
+        }
+    }
+
+    if prior == Prior::New {
+        let mut record = EgValue::new_object();
+        record["running"] = EgValue::from(true);
+        record["claimed_at"] = EgValue::from(now);
+        Cache::set_global_for(key, record, window)?;
+    }
+
+    Ok(prior)
+}
+
+/// Replace the running record of a request with its responses.
+pub fn complete(key: &str, responses: Vec<EgValue>, window: u32) -> EgResult<()> {
+    connect()?;
+    let mut record = EgValue::new_object();
+    record["responses"] = EgValue::from(responses);
+    Cache::set_global_for(key, record, window)
+}
+
+/// Forget a request so a later delivery runs it again.
+pub fn release(key: &str) -> EgResult<()> {
+    connect()?;
+    Cache::del_global(key)
+}
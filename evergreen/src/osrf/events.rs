@@ -0,0 +1,272 @@
+//! Broadcast events, e.g. a changed setting, to every process which
+//! subscribes to them.
+//!
+//! Events are published on bus pub/sub channels, one per event name.
+//! A Subscriber listens on its own bus connection in a background
+//! thread and calls the handlers registered for each event as it
+//! arrives.  When the connection drops, the Subscriber reconnects and
+//! subscribes again, waiting a little longer after each failed
+//! attempt.
+//!
+//! Delivery is best-effort:
+//!
+//! * Events published while a Subscriber is disconnected are lost.
+//!   After reconnecting, every handler is called once with an event
+//!   whose missed() is true, so it can assume anything may have
+//!   changed, e.g. by dropping all cached values.
+//! * Events are not acknowledged.  A publisher cannot tell which
+//!   subscribers, if any, received an event.
+//! * Missed-event notices may overlap real events, so a handler may be
+//!   told about the same change more than once.
+//!
+//! Use events to shorten the life of cached data, not as the only
+//! means of keeping it correct.  Cached values should still expire.
+use crate::osrf::bus::Bus;
+use crate::osrf::conf;
+use crate::EgResult;
+use crate::EgValue;
+use redis::Commands;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// An org unit setting or global flag changed.  The payload contains
+/// the setting "name" and the "org_id" it changed at.
+pub const SETTINGS_CHANGED: &str = "settings.changed";
+
+/// Drop all cached values.  The payload is unspecified.
+pub const CACHE_BUST: &str = "cache.bust";
+
+const CHANNEL_PREFIX: &str = "opensrf:event:";
+
+/// How often the listener thread checks whether it's been stopped.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnect attempts.
+const MAX_RECONNECT_WAIT: Duration = Duration::from_secs(30);
+
+/// Bus channel carrying an event.
+///
+/// ```
+/// use evergreen::osrf::events;
+/// assert_eq!(events::channel(events::SETTINGS_CHANGED), "opensrf:event:settings.changed");
+/// ```
+pub fn channel(event: &str) -> String {
+    format!("{CHANNEL_PREFIX}{event}")
+}
+
+/// Send an event to all current subscribers.
+///
+/// Returns the number of subscribed connections the event was
+/// delivered to.
+pub fn publish(bus: &mut Bus, event: &str, payload: EgValue) -> EgResult<usize> {
+    let mut message = EgValue::new_object();
+    message["event"] = EgValue::from(event);
+    message["payload"] = payload;
+
+    bus.connection()
+        .publish(channel(event), message.dump())
+        .map_err(|e| format!("Cannot publish event {event}: {e}").into())
+}
+
+/// A received event.
+#[derive(Debug, Clone)]
+pub struct Event {
+    name: String,
+    payload: EgValue,
+    missed: bool,
+}
+
+impl Event {
+    /// Parse an event as published by publish().
+    ///
+    /// ```
+    /// use evergreen::osrf::events::Event;
+    ///
+    /// let text = r#"{"event":"settings.changed","payload":{"name":"a.b","org_id":4}}"#;
+    /// let event = Event::from_message(text).unwrap();
+    /// assert_eq!(event.name(), "settings.changed");
+    /// assert_eq!(event.payload()["org_id"].int().unwrap(), 4);
+    /// assert!(!event.missed());
+    ///
+    /// assert!(Event::from_message(r#"{"payload":1}"#).is_err());
+    /// ```
+    pub fn from_message(text: &str) -> EgResult<Event> {
+        let mut message = EgValue::parse(text)?;
+
+        let name = message["event"]
+            .as_str()
+            .ok_or_else(|| format!("Event message has no name: {text}"))?
+            .to_string();
+
+        Ok(Event {
+            name,
+            payload: message["payload"].take(),
+            missed: false,
+        })
+    }
+
+    /// Notice that events of this name may have been published while
+    /// we were disconnected.
+    pub fn missed_notice(name: &str) -> Event {
+        Event {
+            name: name.to_string(),
+            payload: EgValue::Null,
+            missed: true,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn payload(&self) -> &EgValue {
+        &self.payload
+    }
+
+    /// True if this is not a published event, but a notice that
+    /// events may have been missed.
+    pub fn missed(&self) -> bool {
+        self.missed
+    }
+}
+
+type Handler = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// Calls registered handlers for events received in a background
+/// thread.
+///
+/// Handlers run in the listener thread, one event at a time, so they
+/// should be quick and must not depend on state owned by a worker
+/// thread.
+#[derive(Default)]
+pub struct Subscriber {
+    handlers: Vec<(String, Handler)>,
+}
+
+impl Subscriber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call the handler for every event of this name.
+    pub fn on(&mut self, event: &str, handler: impl Fn(&Event) + Send + Sync + 'static) {
+        self.handlers.push((event.to_string(), Box::new(handler)));
+    }
+
+    fn dispatch(&self, event: &Event) {
+        for (name, handler) in self.handlers.iter() {
+            if name == event.name() {
+                handler(event);
+            }
+        }
+    }
+
+    /// Start listening on the private domain in a background thread.
+    ///
+    /// The thread runs until SubscriberHandle::stop() is called.
+    pub fn start(self) -> SubscriberHandle {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop_flag = stopped.clone();
+
+        let thread = thread::spawn(move || self.listen(&stop_flag));
+
+        SubscriberHandle { stopped, thread }
+    }
+
+    /// Connect and listen until stopped, reconnecting as needed.
+    fn listen(&self, stopped: &AtomicBool) {
+        let mut events: Vec<&str> = self.handlers.iter().map(|(n, _)| n.as_str()).collect();
+        events.sort();
+        events.dedup();
+
+        let mut connected_once = false;
+        let mut wait = POLL_INTERVAL;
+
+        while !stopped.load(Ordering::Relaxed) {
+            let mut bus = match Bus::new(conf::config().client()) {
+                Ok(b) => b,
+                Err(e) => {
+                    log::error!("Event subscriber cannot connect: {e}; retrying in {wait:?}");
+                    thread::sleep(wait);
+                    wait = (wait * 2).min(MAX_RECONNECT_WAIT);
+                    continue;
+                }
+            };
+
+            let mut pubsub = bus.connection().as_pubsub();
+
+            let subscribed = events
+                .iter()
+                .try_for_each(|e| pubsub.subscribe(channel(e)))
+                .and_then(|_| pubsub.set_read_timeout(Some(POLL_INTERVAL)));
+
+            if let Err(e) = subscribed {
+                log::error!("Event subscriber cannot subscribe: {e}; retrying in {wait:?}");
+                drop(pubsub);
+                thread::sleep(wait);
+                wait = (wait * 2).min(MAX_RECONNECT_WAIT);
+                continue;
+            }
+
+            log::debug!("Event subscriber listening for {events:?}");
+
+            wait = POLL_INTERVAL;
+
+            if connected_once {
+                log::info!("Event subscriber reconnected; events may have been missed");
+                for name in events.iter() {
+                    self.dispatch(&Event::missed_notice(name));
+                }
+            }
+
+            connected_once = true;
+
+            while !stopped.load(Ordering::Relaxed) {
+                let msg = match pubsub.get_message() {
+                    Ok(m) => m,
+                    Err(e) if e.is_timeout() => continue,
+                    Err(e) => {
+                        log::warn!("Event subscriber lost its connection: {e}");
+                        break;
+                    }
+                };
+
+                let text: String = match msg.get_payload() {
+                    Ok(t) => t,
+                    Err(e) => {
+                        log::error!("Discarding unreadable event message: {e}");
+                        continue;
+                    }
+                };
+
+                match Event::from_message(&text) {
+                    Ok(event) => {
+                        log::debug!("Received event {}", event.name());
+                        self.dispatch(&event);
+                    }
+                    Err(e) => log::error!("Discarding invalid event message: {e}"),
+                }
+            }
+        }
+    }
+}
+
+/// Controls a started Subscriber.
+///
+/// Dropping the handle leaves the Subscriber running.
+pub struct SubscriberHandle {
+    stopped: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl SubscriberHandle {
+    /// Stop listening and wait for the listener thread to exit.
+    pub fn stop(self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if self.thread.join().is_err() {
+            log::error!("Event subscriber thread panicked");
+        }
+    }
+}
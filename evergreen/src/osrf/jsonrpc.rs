@@ -0,0 +1,292 @@
+//! Translate JSON-RPC 2.0 requests into OpenSRF API calls.
+//!
+//! Each JSON-RPC request is relayed as a single OpenSRF request to the
+//! service named by the first two components of the method name, e.g.
+//! "opensrf.settings.host_config.get" is sent to "opensrf.settings".
+//!
+//! Since an OpenSRF method may respond any number of times, the
+//! JSON-RPC "result" is always an array of all responses in order.
+use crate::osrf::client::Client;
+use crate::osrf::method::{MethodDef, ParamCount};
+use crate::EgValue;
+use json::JsonValue;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Implementation-defined server error for bus failures, timeouts,
+/// and API calls which fail on the server.
+pub const TRANSPORT_ERROR: i64 = -32000;
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl JsonRpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        JsonRpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn to_json_value(&self) -> JsonValue {
+        json::object! {
+            "code": self.code,
+            "message": self.message.as_str(),
+        }
+    }
+
+    /// Map an OpenSRF request failure onto a JSON-RPC error.
+    ///
+    /// Failed requests report the OpenSRF status in the error text,
+    /// e.g. "stat=(404) MethodNotFound".
+    fn from_osrf_error(err: &str) -> Self {
+        let code = if err.contains("(404) MethodNotFound") {
+            METHOD_NOT_FOUND
+        } else if err.contains("(400) BadRequest") {
+            INVALID_PARAMS
+        } else {
+            TRANSPORT_ERROR
+        };
+
+        JsonRpcError::new(code, err)
+    }
+}
+
+/// A parsed JSON-RPC request.
+#[derive(Debug, Clone)]
+pub struct JsonRpcRequest {
+    /// String, number, or null, echoed back in the response.
+    pub id: JsonValue,
+    pub method: String,
+    pub params: Vec<EgValue>,
+}
+
+impl JsonRpcRequest {
+    /// Unpack a JSON-RPC request object.
+    ///
+    /// On failure, returns the request ID, when one can be found,
+    /// along with the error.
+    pub fn from_json_value(mut value: JsonValue) -> Result<Self, (JsonValue, JsonRpcError)> {
+        if !value.is_object() {
+            return Err((
+                JsonValue::Null,
+                JsonRpcError::new(INVALID_REQUEST, "Request must be a JSON object"),
+            ));
+        }
+
+        let id = value["id"].take();
+
+        if !(id.is_null() || id.is_string() || id.is_number()) {
+            return Err((
+                JsonValue::Null,
+                JsonRpcError::new(INVALID_REQUEST, "Request id must be a string or number"),
+            ));
+        }
+
+        let invalid = |id: JsonValue, code: i64, msg: &str| Err((id, JsonRpcError::new(code, msg)));
+
+        if value["jsonrpc"].as_str() != Some(JSONRPC_VERSION) {
+            return invalid(id, INVALID_REQUEST, "Unsupported jsonrpc version");
+        }
+
+        let method = match value["method"].as_str() {
+            Some(m) => m.to_string(),
+            None => return invalid(id, INVALID_REQUEST, "Request method must be a string"),
+        };
+
+        let params = match value["params"].take() {
+            JsonValue::Null => Vec::new(),
+            JsonValue::Array(list) => {
+                let mut params = Vec::new();
+                for p in list {
+                    match EgValue::from_json_value(p) {
+                        Ok(v) => params.push(v),
+                        Err(e) => return invalid(id, INVALID_PARAMS, &e.to_string()),
+                    }
+                }
+                params
+            }
+            JsonValue::Object(_) => {
+                return invalid(id, INVALID_PARAMS, "Named params are not supported")
+            }
+            _ => return invalid(id, INVALID_PARAMS, "Request params must be an array"),
+        };
+
+        Ok(JsonRpcRequest { id, method, params })
+    }
+}
+
+/// Returns the OpenSRF service which handles the method.
+///
+/// ```
+/// use evergreen::osrf::jsonrpc;
+///
+/// assert_eq!(
+///     jsonrpc::service_for_method("open-ils.actor.user.retrieve"),
+///     Some("open-ils.actor")
+/// );
+/// assert_eq!(jsonrpc::service_for_method("open-ils"), None);
+/// ```
+pub fn service_for_method(method: &str) -> Option<&str> {
+    let mut dots = method.match_indices('.').map(|(idx, _)| idx);
+
+    dots.next()?;
+
+    match dots.next() {
+        Some(idx) => Some(&method[..idx]),
+        None => None,
+    }
+}
+
+/// Relays JSON-RPC requests to OpenSRF via an OpenSRF client.
+pub struct JsonRpcGateway {
+    client: Client,
+    /// When non-empty, only these methods are published and their
+    /// param counts are verified before relaying each request.
+    methods: HashMap<String, MethodDef>,
+}
+
+impl JsonRpcGateway {
+    pub fn new(client: Client) -> Self {
+        JsonRpcGateway {
+            client,
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Publish a method.
+    ///
+    /// Once any method is added, requests for methods which have not
+    /// been added are rejected as unknown.
+    pub fn add_method(&mut self, method: MethodDef) {
+        self.methods.insert(method.name().to_string(), method);
+    }
+
+    /// Verify the method is published and the request has a usable
+    /// number of parameters.
+    pub fn validate(&self, request: &JsonRpcRequest) -> Result<(), JsonRpcError> {
+        if self.methods.is_empty() {
+            return Ok(());
+        }
+
+        let name = request.method.as_str();
+
+        // Atomic variants share the signature of their root method.
+        let def = self
+            .methods
+            .get(name)
+            .or_else(|| self.methods.get(name.strip_suffix(".atomic")?));
+
+        let def = match def {
+            Some(d) => d,
+            None => {
+                return Err(JsonRpcError::new(
+                    METHOD_NOT_FOUND,
+                    &format!("Method not found: {name}"),
+                ))
+            }
+        };
+
+        let count = request.params.len();
+
+        if !u8::try_from(count).is_ok_and(|c| ParamCount::matches(def.param_count(), c)) {
+            return Err(JsonRpcError::new(
+                INVALID_PARAMS,
+                &format!(
+                    "Invalid param count sent: method={name} sent={count} needed={}",
+                    def.param_count()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Relay the request and collect all of the responses.
+    pub fn call(&self, request: JsonRpcRequest) -> Result<Vec<EgValue>, JsonRpcError> {
+        self.validate(&request)?;
+
+        let service = match service_for_method(&request.method) {
+            Some(s) => s,
+            None => {
+                return Err(JsonRpcError::new(
+                    METHOD_NOT_FOUND,
+                    &format!("Cannot determine service for method: {}", request.method),
+                ))
+            }
+        };
+
+        let responses = self
+            .client
+            .send_recv_iter(service, &request.method, request.params)
+            .map_err(|e| JsonRpcError::from_osrf_error(&e.to_string()))?;
+
+        responses
+            .map(|r| r.map_err(|e| JsonRpcError::from_osrf_error(&e.to_string())))
+            .collect()
+    }
+
+    /// Handle a single parsed JSON-RPC request object, returning the
+    /// JSON-RPC response object.
+    pub fn handle(&self, value: JsonValue) -> JsonValue {
+        let (id, result) = match JsonRpcRequest::from_json_value(value) {
+            Ok(mut request) => {
+                let id = request.id.take();
+                (id, self.call(request))
+            }
+            Err((id, err)) => (id, Err(err)),
+        };
+
+        match result {
+            Ok(values) => {
+                let values: Vec<JsonValue> =
+                    values.into_iter().map(|v| v.into_json_value()).collect();
+
+                json::object! {
+                    "jsonrpc": JSONRPC_VERSION,
+                    "id": id,
+                    "result": values,
+                }
+            }
+            Err(err) => error_response(id, &err),
+        }
+    }
+
+    /// Handle a JSON-RPC request document, returning the serialized
+    /// response.
+    pub fn handle_text(&self, text: &str) -> String {
+        match json::parse(text) {
+            Ok(value) => self.handle(value).dump(),
+            Err(e) => {
+                let err = JsonRpcError::new(PARSE_ERROR, &format!("Invalid JSON: {e}"));
+                error_response(JsonValue::Null, &err).dump()
+            }
+        }
+    }
+}
+
+/// Build a JSON-RPC error response object.
+pub fn error_response(id: JsonValue, err: &JsonRpcError) -> JsonValue {
+    json::object! {
+        "jsonrpc": JSONRPC_VERSION,
+        "id": id,
+        "error": err.to_json_value(),
+    }
+}
@@ -211,6 +211,47 @@ impl fmt::Display for MessageStatus {
 }
 
 impl MessageStatus {
+    /// True if this is the Request Complete status, which follows the
+    /// last response to a request.
+    ///
+    /// ```
+    /// use evergreen::osrf::message::MessageStatus;
+    /// assert!(MessageStatus::from(205).is_complete());
+    /// assert!(!MessageStatus::PartialComplete.is_complete());
+    /// ```
+    pub fn is_complete(&self) -> bool {
+        *self == MessageStatus::Complete
+    }
+
+    /// True if this is the status of a response split into chunks,
+    /// including the final chunk.
+    ///
+    /// ```
+    /// use evergreen::osrf::message::MessageStatus;
+    /// assert!(MessageStatus::Partial.is_partial());
+    /// assert!(MessageStatus::PartialComplete.is_partial());
+    /// assert!(!MessageStatus::Ok.is_partial());
+    /// ```
+    pub fn is_partial(&self) -> bool {
+        matches!(
+            self,
+            MessageStatus::Partial | MessageStatus::PartialComplete
+        )
+    }
+
+    /// True if the request failed, including statuses with codes we
+    /// don't recognize.
+    ///
+    /// ```
+    /// use evergreen::osrf::message::MessageStatus;
+    /// assert!(MessageStatus::from(404).is_error());
+    /// assert!(MessageStatus::from(599).is_error());
+    /// assert!(!MessageStatus::Continue.is_error());
+    /// ```
+    pub fn is_error(&self) -> bool {
+        self.is_4xx() || self.is_5xx()
+    }
+
     pub fn is_4xx(&self) -> bool {
         let num = *self as isize;
         num >= 400 && num < 500
@@ -249,6 +290,7 @@ pub struct TransportMessage {
     from: String,
     thread: String,
     osrf_xid: String,
+    osrf_msg_id: Option<String>,
     router_command: Option<String>,
     router_class: Option<String>,
     router_reply: Option<String>,
@@ -262,6 +304,7 @@ impl TransportMessage {
             from: from.to_string(),
             thread: thread.to_string(),
             osrf_xid: String::from(""),
+            osrf_msg_id: None,
             router_command: None,
             router_class: None,
             router_reply: None,
@@ -321,6 +364,16 @@ impl TransportMessage {
         self.osrf_xid = xid.to_string()
     }
 
+    /// Unique ID of an API request message, used by workers to
+    /// recognize a request redelivered by the bus.  See osrf::dedup.
+    pub fn msg_id(&self) -> Option<&str> {
+        self.osrf_msg_id.as_deref()
+    }
+
+    pub fn set_msg_id(&mut self, id: &str) {
+        self.osrf_msg_id = Some(id.to_string());
+    }
+
     pub fn router_command(&self) -> Option<&str> {
         self.router_command.as_deref()
     }
@@ -361,6 +414,10 @@ impl TransportMessage {
             tmsg.set_osrf_xid(xid);
         };
 
+        if let Some(id) = json_obj["osrf_msg_id"].as_str() {
+            tmsg.set_msg_id(id);
+        }
+
         if let Some(rc) = json_obj["router_command"].as_str() {
             tmsg.set_router_command(rc);
         }
@@ -404,6 +461,10 @@ impl TransportMessage {
             body: body,
         };
 
+        if let Some(id) = self.msg_id() {
+            obj["osrf_msg_id"] = id.into();
+        }
+
         if let Some(rc) = self.router_command() {
             obj["router_command"] = rc.into();
         }
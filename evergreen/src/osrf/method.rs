@@ -4,7 +4,154 @@ use crate::osrf::session;
 use crate::EgResult;
 use crate::EgValue;
 use json::JsonValue;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
 use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+// Deadline of the method call running on the current thread, if its
+// method has a timeout.
+thread_local! {
+    static CALL_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// True while catch_panic() is running a call on the current thread,
+// along with the backtrace of the most recent panic within the call.
+thread_local! {
+    static CATCHING_PANIC: Cell<bool> = const { Cell::new(false) };
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Start the timeout clock for a method call on the current thread.
+///
+/// A None timeout clears any existing deadline.
+pub fn set_call_deadline(timeout: Option<Duration>) {
+    CALL_DEADLINE.with(|d| d.set(timeout.map(|t| Instant::now() + t)));
+}
+
+/// Clear the method call deadline for the current thread.
+pub fn clear_call_deadline() {
+    CALL_DEADLINE.with(|d| d.set(None));
+}
+
+/// Time left before the current method call times out, or None if
+/// the call has no deadline.
+pub fn call_time_remaining() -> Option<Duration> {
+    CALL_DEADLINE
+        .with(|d| d.get())
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// True if the method call running on the current thread has
+/// exceeded its timeout.
+pub fn call_deadline_exceeded() -> bool {
+    call_time_remaining().is_some_and(|r| r.is_zero())
+}
+
+/// Returns Err if the method call running on the current thread has
+/// exceeded its timeout.
+///
+/// Handlers run to completion on the worker thread, so timeouts are
+/// enforced at the points where a handler talks to the network:
+/// sending a response and waiting on a reply.  A handler which loops
+/// for a long time without doing either may call this to stop early.
+///
+/// ```
+/// use evergreen::osrf::method;
+/// use std::time::Duration;
+///
+/// method::set_call_deadline(Some(Duration::from_secs(60)));
+/// assert!(method::check_call_deadline().is_ok());
+///
+/// method::set_call_deadline(Some(Duration::ZERO));
+/// assert!(method::check_call_deadline().is_err());
+///
+/// method::clear_call_deadline();
+/// assert!(method::check_call_deadline().is_ok());
+/// ```
+pub fn check_call_deadline() -> EgResult<()> {
+    if call_deadline_exceeded() {
+        Err("Method call exceeded its timeout".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Install a panic hook which captures the backtrace of panics within
+/// catch_panic() so they can be logged.  Other panics are passed to
+/// the previously installed hook.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING_PANIC.with(|c| c.get()) {
+                let bt = Backtrace::force_capture();
+                PANIC_BACKTRACE.with(|b| *b.borrow_mut() = Some(bt));
+            } else {
+                default_hook(info);
+            }
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "(non-string panic payload)"
+    }
+}
+
+/// Run a method handler call, converting a panic into an Err so the
+/// worker thread survives to handle its next request.
+///
+/// The panic is logged along with the method name and a backtrace.
+/// Handlers should still not panic on purpose: any state the handler
+/// was in the middle of changing, e.g. an open database transaction,
+/// is left to be cleaned up by the worker's error handling.
+///
+/// ```
+/// use evergreen::osrf::method;
+///
+/// let result = method::catch_panic("eg.test.panic", || panic!("Oops"));
+/// assert!(result.unwrap_err().to_string().contains("Oops"));
+///
+/// assert!(method::catch_panic("eg.test.ok", || Ok(())).is_ok());
+/// ```
+pub fn catch_panic<F>(method: &str, f: F) -> EgResult<()>
+where
+    F: FnOnce() -> EgResult<()>,
+{
+    install_panic_hook();
+
+    let was_catching = CATCHING_PANIC.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING_PANIC.with(|c| c.set(was_catching));
+
+    let payload = match result {
+        Ok(r) => return r,
+        Err(p) => p,
+    };
+
+    let msg = panic_message(payload.as_ref());
+
+    let backtrace = PANIC_BACKTRACE
+        .with(|b| b.borrow_mut().take())
+        .map(|bt| bt.to_string())
+        .unwrap_or_default();
+
+    log::error!("Method {method} panicked: {msg}\n{backtrace}");
+
+    Err(format!("Method {method} panicked: {msg}").into())
+}
 
 pub type MethodHandler = fn(
     &mut Box<dyn app::ApplicationWorker>,
@@ -54,6 +201,24 @@ impl ParamCount {
             ParamCount::Range(s, _) => s,
         }
     }
+
+    /// Maximum number of parameters allowed by this ParamCount
+    /// definition or None if there is no limit.
+    ///
+    /// ```
+    /// use evergreen::osrf::method::ParamCount;
+    /// assert_eq!(ParamCount::Range(1, 3).maximum(), Some(3));
+    /// assert_eq!(ParamCount::AtLeast(1).maximum(), None);
+    /// ```
+    pub fn maximum(&self) -> Option<u8> {
+        match *self {
+            ParamCount::Any => None,
+            ParamCount::Zero => Some(0),
+            ParamCount::Exactly(c) => Some(c),
+            ParamCount::AtLeast(_) => None,
+            ParamCount::Range(_, e) => Some(e),
+        }
+    }
 }
 
 impl fmt::Display for ParamCount {
@@ -208,6 +373,12 @@ pub struct MethodDef {
     pub param_count: ParamCount,
     pub handler: MethodHandler,
     pub params: Option<Vec<Param>>,
+    /// Max time a call to this method may run before the worker aborts
+    /// it and replies with a Timeout status.  None means no limit.
+    pub timeout: Option<Duration>,
+    /// True if a redelivered request for this method should replay
+    /// the prior result instead of running again.  See osrf::dedup.
+    pub dedup: bool,
 }
 
 impl MethodDef {
@@ -217,6 +388,8 @@ impl MethodDef {
             param_count,
             params: None,
             desc: None,
+            timeout: None,
+            dedup: false,
             name: name.to_string(),
         }
     }
@@ -247,6 +420,86 @@ impl MethodDef {
     pub fn set_desc(&mut self, desc: &str) {
         self.desc = Some(desc.to_string());
     }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.dedup
+    }
+
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+    /// True if the parameter at the provided position must be sent
+    /// as a non-null value.
+    ///
+    /// Params within the minimum param count are required.  Any
+    /// others are optional and may be omitted or sent as null.
+    pub fn param_required(&self, idx: usize) -> bool {
+        idx < self.param_count.minimum() as usize
+    }
+
+    /// Verify the provided params match our param count and the
+    /// types of any declared params.
+    ///
+    /// Returns Err with a description of the first mismatch found.
+    pub fn validate_params(&self, params: &[EgValue]) -> Result<(), String> {
+        let count = params.len();
+
+        if !u8::try_from(count).is_ok_and(|c| ParamCount::matches(&self.param_count, c)) {
+            return Err(format!(
+                "Invalid param count sent: method={} sent={} needed={}",
+                self.name(),
+                count,
+                self.param_count,
+            ));
+        }
+
+        let param_defs = match self.params() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        // There may be more param defs than parameters if some
+        // params are optional.
+        for (idx, (param_def, value)) in param_defs.iter().zip(params).enumerate() {
+            if value.is_null() && !self.param_required(idx) {
+                // NULL placeholders for optional params are allowed.
+                continue;
+            }
+
+            if param_def.datatype.matches(value) {
+                continue;
+            }
+
+            if value.is_null() {
+                return Err(format!(
+                    "Missing required param {} '{}' for method={}",
+                    idx + 1,
+                    param_def.name,
+                    self.name(),
+                ));
+            }
+
+            return Err(format!(
+                "Invalid param {} '{}' for method={}: wanted={} got={}",
+                idx + 1,
+                param_def.name,
+                self.name(),
+                param_def.datatype,
+                value_type(value),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn add_param(&mut self, param: Param) {
         let params = match self.params.as_mut() {
             Some(p) => p,
@@ -259,17 +512,31 @@ impl MethodDef {
         params.push(param);
     }
 
+    /// Describe the method for introspection, e.g. via
+    /// opensrf.system.method.all.
+    ///
+    /// The keys are stable so clients can build a call catalog:
+    /// "api_name", "desc", "argc" (display string), "argc_min",
+    /// "argc_max" (null if unbounded), "stream", and "params", a list
+    /// of objects with "name", "datatype", "desc", and "required".
     pub fn to_eg_value(&self) -> EgValue {
         let mut pa = EgValue::new_array();
         if let Some(params) = self.params() {
-            for param in params {
-                pa.push(param.to_eg_value()).expect("Is Array");
+            for (idx, param) in params.iter().enumerate() {
+                let mut value = param.to_eg_value();
+                value["required"] = self.param_required(idx).into();
+                pa.push(value).expect("Is Array");
             }
         }
 
         EgValue::from_json_value_plain(json::object! {
             "api_name": self.name(),
             "argc": self.param_count().to_string(),
+            "argc_min": self.param_count().minimum(),
+            "argc_max": match self.param_count().maximum() {
+                Some(m) => m.into(),
+                None => JsonValue::Null,
+            },
             "params": pa.into_json_value(),
             // All Rust methods are streaming.
             "stream": JsonValue::Boolean(true),
@@ -290,9 +557,8 @@ impl MethodDef {
         }
 
         if let Some(params) = self.params() {
-            let minimum = self.param_count.minimum();
             for (idx, param) in params.iter().enumerate() {
-                let required = if idx <= minimum as usize {
+                let required = if self.param_required(idx) {
                     "*" // required
                 } else {
                     ""
@@ -318,3 +584,22 @@ impl MethodDef {
         s
     }
 }
+
+/// Describe the base type of a param value for error messages.
+///
+/// Only the type is reported since param values may be sensitive.
+fn value_type(value: &EgValue) -> &'static str {
+    if value.is_string() {
+        "String"
+    } else if value.is_number() {
+        "Number"
+    } else if value.is_boolean() {
+        "Boolean"
+    } else if value.is_array() {
+        "Array"
+    } else if value.is_null() {
+        "Null"
+    } else {
+        "Object"
+    }
+}
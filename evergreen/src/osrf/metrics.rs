@@ -0,0 +1,240 @@
+//! Process-wide API call metrics, exported in the Prometheus text
+//! exposition format.
+//!
+//! Worker threads record each API call they handle and the Server
+//! records worker pool sizes.  The metrics are available via the
+//! opensrf.system.metrics API and, when a service's
+//! unix_config/metrics_port host setting is set, via a minimal HTTP
+//! endpoint suitable for scraping.
+use crate::EgResult;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Upper bounds in seconds of the API call latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Max time to wait on a scraper to send its HTTP request.
+const HTTP_READ_TIMEOUT: u64 = 5;
+
+static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+/// Counters for a single API method.
+#[derive(Debug, Clone, Default)]
+pub struct MethodStats {
+    pub requests: u64,
+    pub errors: u64,
+    /// Total time spent handling requests in seconds.
+    pub latency_sum: f64,
+    /// Per-bucket (non-cumulative) request counts, with one extra
+    /// entry for requests slower than the largest bucket.
+    bucket_counts: Vec<u64>,
+}
+
+/// Worker pool sizes for the running service.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoolStats {
+    pub workers: usize,
+    pub active_workers: usize,
+    pub max_workers: usize,
+}
+
+#[derive(Default)]
+struct Registry {
+    service: String,
+    methods: BTreeMap<String, MethodStats>,
+    pool: PoolStats,
+}
+
+fn with_registry<T>(f: impl FnOnce(&mut Registry) -> T) -> T {
+    let registry = REGISTRY.get_or_init(|| Mutex::new(Registry::default()));
+
+    // A panic while holding the lock leaves the counters usable.
+    let mut guard = match registry.lock() {
+        Ok(g) => g,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    f(&mut guard)
+}
+
+/// Set the service name applied as a label to all metrics.
+pub fn set_service(service: &str) {
+    with_registry(|r| r.service = service.to_string());
+}
+
+/// Record a handled API call.
+pub fn record_call(method: &str, duration: Duration, failed: bool) {
+    let secs = duration.as_secs_f64();
+
+    let bucket = LATENCY_BUCKETS
+        .iter()
+        .position(|b| secs <= *b)
+        .unwrap_or(LATENCY_BUCKETS.len());
+
+    with_registry(|r| {
+        let stats = r.methods.entry(method.to_string()).or_default();
+
+        if stats.bucket_counts.is_empty() {
+            stats.bucket_counts = vec![0; LATENCY_BUCKETS.len() + 1];
+        }
+
+        stats.requests += 1;
+        stats.latency_sum += secs;
+        stats.bucket_counts[bucket] += 1;
+
+        if failed {
+            stats.errors += 1;
+        }
+    });
+}
+
+/// Replace the current worker pool sizes.
+pub fn set_pool_stats(pool: PoolStats) {
+    with_registry(|r| r.pool = pool);
+}
+
+/// Current worker pool sizes.
+pub fn pool_stats() -> PoolStats {
+    with_registry(|r| r.pool)
+}
+
+/// Snapshot of the counters for one method.
+pub fn method_stats(method: &str) -> Option<MethodStats> {
+    with_registry(|r| r.methods.get(method).cloned())
+}
+
+/// Escape a Prometheus label value.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Compile all metrics in the Prometheus text format.
+pub fn render() -> String {
+    with_registry(|r| {
+        let service = label(&r.service);
+        let mut text = String::new();
+
+        text += "# HELP osrf_requests_total API calls handled.\n";
+        text += "# TYPE osrf_requests_total counter\n";
+        for (name, stats) in r.methods.iter() {
+            text += &format!(
+                "osrf_requests_total{{service=\"{service}\",method=\"{}\"}} {}\n",
+                label(name),
+                stats.requests
+            );
+        }
+
+        text += "# HELP osrf_request_errors_total API calls whose handler returned an error.\n";
+        text += "# TYPE osrf_request_errors_total counter\n";
+        for (name, stats) in r.methods.iter() {
+            text += &format!(
+                "osrf_request_errors_total{{service=\"{service}\",method=\"{}\"}} {}\n",
+                label(name),
+                stats.errors
+            );
+        }
+
+        text += "# HELP osrf_request_duration_seconds API call handling time.\n";
+        text += "# TYPE osrf_request_duration_seconds histogram\n";
+        for (name, stats) in r.methods.iter() {
+            let labels = format!("service=\"{service}\",method=\"{}\"", label(name));
+            let mut cumulative = 0;
+
+            for (idx, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                cumulative += stats.bucket_counts[idx];
+                text += &format!(
+                    "osrf_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}\n"
+                );
+            }
+
+            text += &format!(
+                "osrf_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}\n",
+                stats.requests
+            );
+            text += &format!(
+                "osrf_request_duration_seconds_sum{{{labels}}} {}\n",
+                stats.latency_sum
+            );
+            text += &format!(
+                "osrf_request_duration_seconds_count{{{labels}}} {}\n",
+                stats.requests
+            );
+        }
+
+        let gauges = [
+            ("osrf_workers", "Running worker threads.", r.pool.workers),
+            (
+                "osrf_workers_active",
+                "Worker threads handling a request.",
+                r.pool.active_workers,
+            ),
+            (
+                "osrf_workers_max",
+                "Maximum worker threads.",
+                r.pool.max_workers,
+            ),
+        ];
+
+        for (name, help, value) in gauges {
+            text += &format!("# HELP {name} {help}\n");
+            text += &format!("# TYPE {name} gauge\n");
+            text += &format!("{name}{{service=\"{service}\"}} {value}\n");
+        }
+
+        text
+    })
+}
+
+/// Start a thread which responds to every HTTP request on the
+/// address with the current metrics.
+pub fn serve(address: &str, port: u16) -> EgResult<()> {
+    let listener = TcpListener::bind((address, port))
+        .map_err(|e| format!("Cannot bind metrics endpoint to {address}:{port}: {e}"))?;
+
+    log::info!("Serving metrics at http://{address}:{port}/metrics");
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    if let Err(e) = respond(s) {
+                        log::warn!("Error responding to metrics request: {e}");
+                    }
+                }
+                Err(e) => log::warn!("Error accepting metrics connection: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(HTTP_READ_TIMEOUT)))?;
+
+    // We respond the same way to any request, so read enough to
+    // consume the request line and headers and ignore the rest.
+    let mut buf = [0u8; 1024];
+    let _ = stream.read(&mut buf)?;
+
+    let body = render();
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes())
+}
@@ -1,13 +1,20 @@
 //! OpenSRF Components
 pub mod addr;
 pub mod app;
+pub mod breaker;
 pub mod bus;
+pub mod buspool;
 pub mod cache;
 pub mod client;
+pub mod compression;
 pub mod conf;
+pub mod dedup;
+pub mod events;
+pub mod jsonrpc;
 pub mod logging;
 pub mod message;
 pub mod method;
+pub mod metrics;
 pub mod params;
 pub mod sclient;
 pub mod server;
@@ -1,14 +1,18 @@
 use crate::init;
 use crate::osrf::app;
+use crate::osrf::breaker;
+use crate::osrf::buspool::{BusPool, ExhaustedPolicy};
 use crate::osrf::client::Client;
 use crate::osrf::conf;
 use crate::osrf::message;
 use crate::osrf::method;
+use crate::osrf::metrics;
 use crate::osrf::sclient::HostSettings;
 use crate::osrf::session;
-use crate::osrf::worker::{Worker, WorkerState, WorkerStateEvent};
+use crate::osrf::worker::{self, Worker, WorkerState, WorkerStateEvent};
 use crate::util;
 use crate::EgResult;
+use crate::EgValue;
 use mptc::signals::SignalTracker;
 use std::collections::HashMap;
 use std::sync::mpsc;
@@ -22,11 +26,14 @@ const IDLE_THREAD_WARN_THRESHOLD: usize = 1;
 /// How often do we wake to check for shutdown, etc. signals when
 /// no other activity is occurring.
 const IDLE_WAKE_TIME: u64 = 3;
-/// Max time in seconds to allow active workers to finish their tasks.
-const SHUTDOWN_MAX_WAIT: i32 = 30;
 const DEFAULT_MIN_WORKERS: usize = 3;
 const DEFAULT_MAX_WORKERS: usize = 30;
 const DEFAULT_MIN_IDLE_WORKERS: usize = 1;
+/// Metrics endpoint listen address when metrics_port is set.
+const DEFAULT_METRICS_ADDRESS: &str = "127.0.0.1";
+/// Seconds a starting worker waits for a pooled bus connection when
+/// bus_pool_size is set and bus_pool_wait is not.
+const DEFAULT_BUS_POOL_WAIT: u64 = 10;
 
 #[derive(Debug)]
 pub struct WorkerThread {
@@ -56,6 +63,14 @@ pub struct Server {
     /// For comparision, the OSRF C code has no min/max idle support
     /// either.
     min_idle_workers: usize,
+
+    /// Max time in seconds active workers have to finish their
+    /// current conversation once we receive a stop signal.
+    drain_timeout: i32,
+
+    /// Bus connections shared by our workers.  When None, each
+    /// worker opens its own connection.
+    bus_pool: Option<Arc<BusPool>>,
 }
 
 impl Server {
@@ -67,18 +82,43 @@ impl Server {
 
         let client = init::osrf_init(&options)?;
 
-        let min_workers = HostSettings::get(&format!("apps/{service}/unix_config/min_children"))?
-            .as_usize()
-            .unwrap_or(DEFAULT_MIN_WORKERS);
+        // Pool sizes from opensrf_core.xml take precedence over
+        // the host settings.
+        let svc_conf = conf::config().service(service);
+
+        let min_workers = match svc_conf.and_then(|c| c.min_workers()) {
+            Some(n) => n,
+            None => HostSettings::get(&format!("apps/{service}/unix_config/min_children"))?
+                .as_usize()
+                .unwrap_or(DEFAULT_MIN_WORKERS),
+        };
 
-        let min_idle_workers =
-            HostSettings::get(&format!("apps/{service}/unix_config/min_spare_children"))?
+        let min_idle_workers = match svc_conf.and_then(|c| c.min_idle_workers()) {
+            Some(n) => n,
+            None => HostSettings::get(&format!("apps/{service}/unix_config/min_spare_children"))?
                 .as_usize()
-                .unwrap_or(DEFAULT_MIN_IDLE_WORKERS);
+                .unwrap_or(DEFAULT_MIN_IDLE_WORKERS),
+        };
 
-        let max_workers = HostSettings::get(&format!("apps/{service}/unix_config/max_children"))?
-            .as_usize()
-            .unwrap_or(DEFAULT_MAX_WORKERS);
+        let max_workers = match svc_conf.and_then(|c| c.max_workers()) {
+            Some(n) => n,
+            None => HostSettings::get(&format!("apps/{service}/unix_config/max_children"))?
+                .as_usize()
+                .unwrap_or(DEFAULT_MAX_WORKERS),
+        };
+
+        conf::validate_worker_counts(service, min_workers, max_workers, min_idle_workers)?;
+
+        log::info!(
+            "{service} starting with min_children={min_workers} \
+            max_children={max_workers} min_spare_children={min_idle_workers}"
+        );
+
+        let drain_timeout =
+            HostSettings::get(&format!("apps/{service}/unix_config/drain_timeout"))?
+                .as_i64()
+                .map(|t| t as i32)
+                .unwrap_or(worker::DEFAULT_DRAIN_TIMEOUT);
 
         // We have a single to-parent channel whose trasmitter is cloned
         // per thread.  Communication from worker threads to the parent
@@ -91,12 +131,52 @@ impl Server {
             mpsc::Receiver<WorkerStateEvent>,
         ) = mpsc::sync_channel(0);
 
+        metrics::set_service(service);
+
+        // Bus connection pooling is disabled unless a pool size is set.
+        // See osrf::buspool.
+        let pool_size = HostSettings::get(&format!("apps/{service}/unix_config/bus_pool_size"))?
+            .as_usize()
+            .unwrap_or(0);
+
+        let mut bus_pool = None;
+
+        if pool_size > 0 {
+            let wait = HostSettings::get(&format!("apps/{service}/unix_config/bus_pool_wait"))?
+                .as_usize()
+                .map(|w| w as u64)
+                .unwrap_or(DEFAULT_BUS_POOL_WAIT);
+
+            log::info!("{service} sharing a pool of {pool_size} bus connections");
+
+            let policy = ExhaustedPolicy::from_wait_secs(wait);
+            bus_pool = Some(Arc::new(BusPool::new(pool_size, policy)));
+        }
+
+        // The metrics HTTP endpoint is disabled unless a port is set.
+        if let Some(port) =
+            HostSettings::get(&format!("apps/{service}/unix_config/metrics_port"))?.as_int()
+        {
+            let address =
+                HostSettings::get(&format!("apps/{service}/unix_config/metrics_address"))?
+                    .as_str()
+                    .unwrap_or(DEFAULT_METRICS_ADDRESS)
+                    .to_string();
+
+            let port = u16::try_from(port)
+                .map_err(|_| format!("{service} has an invalid metrics_port: {port}"))?;
+
+            metrics::serve(&address, port)?;
+        }
+
         let mut server = Server {
             client,
             application,
             min_workers,
             max_workers,
             min_idle_workers,
+            drain_timeout,
+            bus_pool,
             methods: None,
             worker_id_gen: 0,
             to_parent_tx: tx,
@@ -141,6 +221,7 @@ impl Server {
         let service = self.service().to_string();
         let factory = self.app().worker_factory();
         let sig_tracker = self.sig_tracker.clone();
+        let bus_pool = self.bus_pool.clone();
 
         log::trace!("server: spawning a new worker {worker_id}");
 
@@ -152,6 +233,7 @@ impl Server {
                 worker_id,
                 methods,
                 to_parent_tx,
+                bus_pool,
             );
         });
 
@@ -171,10 +253,20 @@ impl Server {
         worker_id: u64,
         methods: Arc<HashMap<String, method::MethodDef>>,
         to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
+        bus_pool: Option<Arc<BusPool>>,
     ) {
         log::trace!("Creating new worker {worker_id}");
 
-        let mut worker = match Worker::new(service, worker_id, sig_tracker, methods, to_parent_tx) {
+        let worker = Worker::new(
+            service,
+            worker_id,
+            sig_tracker,
+            methods,
+            to_parent_tx,
+            bus_pool,
+        );
+
+        let mut worker = match worker {
             Ok(w) => w,
             Err(e) => {
                 log::error!("Cannot create worker: {e}. Exiting.");
@@ -317,6 +409,24 @@ impl Server {
         });
 
         hash.insert(name.to_string(), method);
+
+        let name = "opensrf.system.stream.example";
+        let mut method =
+            method::MethodDef::new(name, method::ParamCount::Zero, system_method_stream_example);
+        method.set_desc("Example streaming method; responds with 1, 2, and 3 separately");
+        hash.insert(name.to_string(), method);
+
+        let name = "opensrf.system.metrics";
+        let mut method =
+            method::MethodDef::new(name, method::ParamCount::Zero, system_method_metrics);
+        method.set_desc("Request and worker pool metrics in Prometheus text format");
+        hash.insert(name.to_string(), method);
+
+        let name = "opensrf.system.health";
+        let mut method =
+            method::MethodDef::new(name, method::ParamCount::Zero, system_method_health);
+        method.set_desc("Worker pool sizes and the state of downstream circuit breakers");
+        hash.insert(name.to_string(), method);
     }
 
     pub fn listen(&mut self) -> EgResult<()> {
@@ -383,7 +493,9 @@ impl Server {
     }
 
     fn shutdown(&mut self) {
-        let timer = util::Timer::new(SHUTDOWN_MAX_WAIT);
+        // Give draining workers time to notice the drain timeout has
+        // passed and report back before we force the matter.
+        let timer = util::Timer::new(self.drain_timeout + worker::IDLE_WAKE_TIME);
         let duration = Duration::from_secs(1);
 
         while !timer.done() && self.workers.len() > 0 {
@@ -468,6 +580,12 @@ impl Server {
 
         log::trace!("server: workers idle={idle} active={active}");
 
+        metrics::set_pool_stats(metrics::PoolStats {
+            workers: self.workers.len(),
+            active_workers: active,
+            max_workers: self.max_workers,
+        });
+
         if self.sig_tracker.any_shutdown_requested() {
             return;
         }
@@ -535,6 +653,59 @@ fn system_method_time(
     }
 }
 
+/// Sends each value to the caller as soon as it's available.
+///
+/// The worker follows up with the Request Complete message once we
+/// return.
+fn system_method_stream_example(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    _method: &message::MethodCall,
+) -> EgResult<()> {
+    stream_example(session)
+}
+
+fn stream_example(responder: &mut impl session::Responder) -> EgResult<()> {
+    for value in 1..=3 {
+        responder.respond(EgValue::from(value))?;
+    }
+    Ok(())
+}
+
+fn system_method_metrics(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    _method: &message::MethodCall,
+) -> EgResult<()> {
+    session.respond_complete(metrics::render())
+}
+
+/// Responds with the worker pool sizes and the circuit breakers of
+/// any downstream services whose calls have recently failed.
+fn system_method_health(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    _method: &message::MethodCall,
+) -> EgResult<()> {
+    let pool = metrics::pool_stats();
+    let breakers = breaker::breakers();
+
+    let mut health = EgValue::from_json_value_plain(json::object! {
+        "service": session.service(),
+        "workers": pool.workers,
+        "active_workers": pool.active_workers,
+        "max_workers": pool.max_workers,
+        "circuit_breaker_failures": breakers.options().failure_threshold(),
+        "circuit_breaker_cooldown": breakers.options().cooldown().as_secs(),
+    });
+
+    health["circuit_breakers"] = breakers.to_eg_value();
+
+    session.respond_complete(health)
+}
+
+/// Responds with one description per registered method, sorted by
+/// name.  See MethodDef::to_eg_value() for the format.
 fn system_method_introspect(
     worker: &mut Box<dyn app::ApplicationWorker>,
     session: &mut session::ServerSession,
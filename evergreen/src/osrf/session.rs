@@ -9,11 +9,13 @@ use crate::osrf::message::MethodCall;
 use crate::osrf::message::Payload;
 use crate::osrf::message::Status;
 use crate::osrf::message::TransportMessage;
+use crate::osrf::method;
 use crate::osrf::params::ApiParams;
 use crate::util;
 use crate::{EgResult, EgValue};
 use std::cell::RefCell;
 use std::cell::RefMut;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::fmt;
 use std::rc::Rc;
@@ -21,6 +23,15 @@ use std::rc::Rc;
 const CONNECT_TIMEOUT: i32 = 10;
 pub const DEFAULT_REQUEST_TIMEOUT: i32 = 60;
 
+/// Request timeout in seconds from the <request_timeout> client config
+/// setting, falling back to DEFAULT_REQUEST_TIMEOUT.
+pub fn default_request_timeout() -> i32 {
+    conf::config()
+        .client()
+        .request_timeout()
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+}
+
 /// Response data propagated from a session to the calling Request.
 #[derive(Debug)]
 struct Response {
@@ -47,6 +58,9 @@ pub struct Request {
     /// Having a local copy of the thread can be handy since our
     /// session is only accessible via temporary borrow().
     thread: String,
+
+    /// True if we gave up waiting on a response.
+    timed_out: bool,
 }
 
 impl Request {
@@ -59,6 +73,7 @@ impl Request {
             session,
             thread,
             complete: false,
+            timed_out: false,
             thread_trace,
         }
     }
@@ -78,6 +93,11 @@ impl Request {
         self.complete
     }
 
+    /// True if first_with_timeout() gave up waiting on a response.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
     /// True if we have received a COMPLETE message from the server
     /// and all responses from our network backlog have been read.
     ///
@@ -93,7 +113,7 @@ impl Request {
     /// about the first, but want to pull all data off the bus until the
     /// message is officially marked as complete.
     pub fn first(&mut self) -> EgResult<Option<EgValue>> {
-        self.first_with_timeout(DEFAULT_REQUEST_TIMEOUT)
+        self.first_with_timeout(default_request_timeout())
     }
 
     /// Returns the first response.
@@ -101,13 +121,34 @@ impl Request {
     /// This still waits for all responses to arrive so the request can
     /// be marked as complete and no responses are left lingering on the
     /// message bus.
+    ///
+    /// Returns Err if more than timeout seconds pass without receiving
+    /// a response.  The timer restarts with each response received,
+    /// so a streaming call may run longer than timeout overall.  A
+    /// negative timeout waits indefinitely.
     pub fn first_with_timeout(&mut self, timeout: i32) -> EgResult<Option<EgValue>> {
         let mut resp: Option<EgValue> = None;
+        let mut timer = util::Timer::new(timeout);
+
         while !self.complete {
-            if let Some(r) = self.recv_with_timeout(timeout)? {
+            let wait = if timeout < 0 {
+                -1
+            } else {
+                timer.remaining().max(0)
+            };
+
+            if let Some(r) = self.recv_with_timeout(wait)? {
+                timer.reset();
                 if resp.is_none() {
                     resp = Some(r);
                 } // else discard the non-first response.
+            } else if !self.complete && timeout >= 0 && timer.done() {
+                self.timed_out = true;
+                return Err(format!(
+                    "Request {} on thread {} timed out after {timeout} seconds",
+                    self.thread_trace, self.thread
+                )
+                .into());
             }
         }
 
@@ -152,7 +193,30 @@ impl Request {
     }
 
     pub fn recv(&mut self) -> EgResult<Option<EgValue>> {
-        self.recv_with_timeout(DEFAULT_REQUEST_TIMEOUT)
+        self.recv_with_timeout(default_request_timeout())
+    }
+
+    /// Abandon this request.
+    ///
+    /// Any responses already received for the request are discarded
+    /// and any which arrive later are dropped as they are read from
+    /// the bus.  The request is marked complete.
+    ///
+    /// If the session is connected, a DISCONNECT is sent to the worker
+    /// so it stops processing queued requests for this session.  The
+    /// session must be reconnected before it can be used for further
+    /// stateful requests.  Stateless requests cannot be interrupted on
+    /// the server, so the worker runs the method to completion and
+    /// its responses are simply ignored.
+    ///
+    /// If the server has already completed the call, this only discards
+    /// any unread responses.
+    pub fn cancel(&mut self) -> EgResult<()> {
+        let complete = self.complete;
+        self.complete = true;
+        self.session
+            .borrow_mut()
+            .cancel(self.thread_trace, complete)
     }
 }
 
@@ -174,9 +238,6 @@ struct ClientSessionInternal {
     /// Top-level bus address for the service we're making requests of.
     service_addr: BusAddress,
 
-    /// Routed messages go here.
-    router_addr: BusAddress,
-
     /// Worker-specific bus address for our session.
     ///
     /// Set any time a response arrives so we know who sent it.
@@ -195,6 +256,10 @@ struct ClientSessionInternal {
 
     /// Staging ground for "partial" messages arriving in chunks.
     partial_buffer: Option<String>,
+
+    /// Thread traces of cancelled requests whose late replies we
+    /// discard instead of adding to the backlog.
+    cancelled: HashSet<usize>,
 }
 
 impl fmt::Display for ClientSessionInternal {
@@ -205,14 +270,10 @@ impl fmt::Display for ClientSessionInternal {
 
 impl ClientSessionInternal {
     fn new(client: Client, service: &str) -> ClientSessionInternal {
-        let router_addr =
-            BusAddress::for_router(conf::config().client().router_name(), client.domain());
-
         let service_addr = BusAddress::for_bare_service(service);
 
         ClientSessionInternal {
             client,
-            router_addr,
             service_addr,
             worker_addr: None,
             service: String::from(service),
@@ -220,6 +281,7 @@ impl ClientSessionInternal {
             last_thread_trace: 0,
             partial_buffer: None,
             backlog: VecDeque::new(),
+            cancelled: HashSet::new(),
             thread: util::random_number(16),
         }
     }
@@ -243,10 +305,6 @@ impl ClientSessionInternal {
         self.backlog.clear();
     }
 
-    fn router_addr(&self) -> &BusAddress {
-        &self.router_addr
-    }
-
     fn worker_addr(&self) -> Option<&BusAddress> {
         self.worker_addr.as_ref()
     }
@@ -284,7 +342,16 @@ impl ClientSessionInternal {
         }
     }
 
-    fn recv(&mut self, thread_trace: usize, timeout: i32) -> EgResult<Option<Response>> {
+    fn recv(&mut self, thread_trace: usize, mut timeout: i32) -> EgResult<Option<Response>> {
+        // When called from within a method handler which has a
+        // timeout, wait no longer than the handler has left to run.
+        if let Some(remaining) = method::call_time_remaining() {
+            let secs = remaining.as_secs_f64().ceil() as i32;
+            if timeout < 0 || secs < timeout {
+                timeout = secs;
+            }
+        }
+
         let mut timer = util::Timer::new(timeout);
 
         let mut first_loop = true;
@@ -303,6 +370,8 @@ impl ClientSessionInternal {
             if first_loop {
                 first_loop = false;
             } else if timer.done() {
+                method::check_call_deadline()?;
+
                 // Avoid exiting on first loop so we have at least
                 // one chance to pull data from the network before exiting.
                 return Ok(None);
@@ -321,7 +390,9 @@ impl ClientSessionInternal {
 
             // Toss the messages onto our backlog as we receive them.
             for msg in tmsg.body_mut().drain(..) {
-                self.backlog.push_back(msg);
+                if !self.discard_cancelled(&msg) {
+                    self.backlog.push_back(msg);
+                }
             }
 
             // Loop back around and see if we can pull the message
@@ -342,7 +413,9 @@ impl ClientSessionInternal {
             // take_content() because this message is about to get dropped.
             let mut value = resp.take_content();
 
-            if resp.status() == &MessageStatus::Partial {
+            let status = *resp.status();
+
+            if status == MessageStatus::Partial {
                 let buf = match self.partial_buffer.as_mut() {
                     Some(b) => b,
                     None => {
@@ -362,7 +435,7 @@ impl ClientSessionInternal {
                     complete: false,
                     partial: true,
                 }));
-            } else if resp.status() == &MessageStatus::PartialComplete {
+            } else if status == MessageStatus::PartialComplete {
                 // Take + clear the partial buffer.
                 let mut buf = match self.partial_buffer.take() {
                     Some(b) => b,
@@ -428,8 +501,15 @@ impl ClientSessionInternal {
                 Ok(None)
             }
             MessageStatus::Continue => {
+                // The server is still working on our request.  Treat
+                // this like a partial response so the caller keeps
+                // waiting instead of counting it toward a timeout.
                 timer.reset();
-                Ok(None)
+                Ok(Some(Response {
+                    value: None,
+                    complete: false,
+                    partial: true,
+                }))
             }
             MessageStatus::Complete => {
                 log::trace!("{self} request {trace} complete");
@@ -439,13 +519,79 @@ impl ClientSessionInternal {
                     partial: false,
                 }))
             }
-            _ => {
+            // Every status is listed so a new one cannot be left
+            // unhandled by accident.
+            MessageStatus::Accepted
+            | MessageStatus::Partial
+            | MessageStatus::PartialComplete
+            | MessageStatus::Redirected => {
+                // Only sent with Result messages, if at all.
+                self.reset();
+                Err(format!("{self} request {trace} returned unexpected status: {statmsg}").into())
+            }
+            MessageStatus::BadRequest
+            | MessageStatus::Unauthorized
+            | MessageStatus::Forbidden
+            | MessageStatus::MethodNotFound
+            | MessageStatus::NotAllowed
+            | MessageStatus::ServiceNotFound
+            | MessageStatus::Timeout
+            | MessageStatus::Expfailed
+            | MessageStatus::InternalServerError
+            | MessageStatus::NotImplemented
+            | MessageStatus::ServiceUnavailable
+            | MessageStatus::VersionNotSupported
+            | MessageStatus::Unknown => {
                 self.reset();
-                return Err(format!("{self} request {trace} failed: {}", statmsg).into());
+                Err(format!("{self} request {trace} failed: {statmsg}").into())
             }
         }
     }
 
+    /// Returns true if the message is a reply to a cancelled request.
+    ///
+    /// Once the COMPLETE message for a cancelled request arrives, no
+    /// more replies are expected, so we stop tracking it.
+    fn discard_cancelled(&mut self, msg: &Message) -> bool {
+        let trace = msg.thread_trace();
+
+        if !self.cancelled.contains(&trace) {
+            return false;
+        }
+
+        if let Payload::Status(stat) = msg.payload() {
+            if stat.status().is_complete() {
+                self.cancelled.remove(&trace);
+            }
+        }
+
+        log::debug!("{self} discarding reply to cancelled request {trace}");
+
+        true
+    }
+
+    /// Drop all replies for a request and ignore any that arrive later.
+    ///
+    /// See Request::cancel().
+    fn cancel(&mut self, trace: usize, complete: bool) -> EgResult<()> {
+        log::debug!("{self} cancelling request {trace}");
+
+        self.backlog.retain(|m| m.thread_trace() != trace);
+        self.partial_buffer = None;
+
+        if !complete {
+            self.cancelled.insert(trace);
+        }
+
+        // Only a connected worker is ours alone to interrupt.
+        // A stateless worker may already be serving another caller.
+        if self.connected() {
+            self.disconnect()?;
+        }
+
+        Ok(())
+    }
+
     fn incr_thread_trace(&mut self) -> usize {
         self.last_thread_trace += 1;
         self.last_thread_trace
@@ -466,7 +612,7 @@ impl ClientSessionInternal {
             self.worker_addr = None;
         }
 
-        let tmsg = TransportMessage::with_body(
+        let mut tmsg = TransportMessage::with_body(
             self.destination_addr().as_str(),
             self.client.address().as_str(),
             self.thread(),
@@ -477,14 +623,20 @@ impl ClientSessionInternal {
             ),
         );
 
-        if !self.connected() {
-            // Top-level API calls always go through the router on
-            // our primary domain
+        // Thread strings are random and traces are unique within a
+        // thread, so together they identify the request.
+        tmsg.set_msg_id(&format!("{}.{trace}", self.thread()));
 
-            let router_addr = self.router_addr().as_str();
-            self.client_internal_mut()
-                .bus_mut()
-                .send_to(tmsg, router_addr)?;
+        if !self.connected() {
+            // Top-level API calls always go through a router, the one
+            // on our primary domain unless we have failed over.
+            // See ClientSingleton::router_addr().
+            let mut client = self.client_internal_mut();
+            log::debug!(
+                "{self} sending {method} via router {}",
+                client.router_addr()
+            );
+            client.send_to_router(tmsg)?;
         } else {
             if let Some(a) = self.worker_addr() {
                 // Requests directly to client addresses must be routed
@@ -523,22 +675,34 @@ impl ClientSessionInternal {
             Message::new(MessageType::Connect, trace, Payload::NoPayload),
         );
 
-        // Connect calls always go to our router.
-        self.client
-            .singleton()
-            .borrow_mut()
-            .bus_mut()
-            .send_to(tm, self.router_addr().as_str())?;
+        // Connect calls always go to a router.  Unlike API calls,
+        // a CONNECT is safe to repeat, so if no worker answers, try
+        // the next configured router.  API calls are not re-sent on
+        // timeout since they may not be safe to run twice.
+        let routers = self.client.singleton().borrow().router_count();
 
-        self.recv(trace, CONNECT_TIMEOUT)?;
+        for _ in 0..routers {
+            let router = self.client.singleton().borrow().router_addr().clone();
+            log::debug!("{self} sending CONNECT via router {router}");
 
-        if self.connected() {
-            log::trace!("{self} connected OK");
-            Ok(())
-        } else {
-            self.reset();
-            Err(format!("CONNECT timed out").into())
+            self.client_internal_mut().send_to_router(tm.clone())?;
+
+            self.recv(trace, CONNECT_TIMEOUT)?;
+
+            if self.connected() {
+                log::trace!("{self} connected OK");
+                return Ok(());
+            }
+
+            log::warn!("{self} CONNECT via router {router} timed out");
+
+            if !self.client_internal_mut().router_failed() {
+                break;
+            }
         }
+
+        self.reset();
+        Err(format!("CONNECT timed out").into())
     }
 
     /// Send a DISCONNECT to our remote worker.
@@ -605,7 +769,7 @@ impl ClientSession {
     /// Send a request and receive a ResponseIterator for iterating
     /// the responses to the method.
     ///
-    /// Uses the default request timeout DEFAULT_REQUEST_TIMEOUT.
+    /// Uses the default request timeout; see default_request_timeout().
     pub fn send_recv(
         &mut self,
         method: &str,
@@ -734,6 +898,49 @@ impl MultiSession {
     }
 }
 
+/// Destination for API responses.
+///
+/// Code which produces a series of values, e.g. rows from a search,
+/// can accept a Responder and hand each value off as it's found instead
+/// of collecting them first.  ServerSession sends each value to the
+/// caller immediately; Vec collects them, which is useful outside of
+/// a method handler.
+pub trait Responder {
+    fn respond(&mut self, value: EgValue) -> EgResult<()>;
+}
+
+impl Responder for Vec<EgValue> {
+    fn respond(&mut self, value: EgValue) -> EgResult<()> {
+        self.push(value);
+        Ok(())
+    }
+}
+
+impl Responder for ServerSession {
+    fn respond(&mut self, value: EgValue) -> EgResult<()> {
+        ServerSession::respond(self, value)
+    }
+}
+
+/// Server-side view of a client session, passed to method handlers
+/// so they can reply to the caller.
+///
+/// A method handler may respond any number of times.  Each call to
+/// respond() sends a Result message to the caller right away, so large
+/// result sets can be streamed without buffering them in the worker.
+///
+/// Once the handler returns Ok, the worker sends the Request Complete
+/// status message, unless the handler already sent one via
+/// respond_complete() or send_complete().  Responses sent after the
+/// Request Complete message are dropped.
+///
+/// If the handler returns Err, the caller receives any responses
+/// already sent, followed by an error status in place of the
+/// Request Complete message.
+///
+/// For ".atomic" method variants, responses are queued and delivered
+/// as a single array along with the Request Complete message.  Handlers
+/// do not need to treat atomic calls differently.
 pub struct ServerSession {
     /// Service name.
     service: String,
@@ -759,6 +966,10 @@ pub struct ServerSession {
 
     /// Responses collected to be packed into an "atomic" response array.
     atomic_resp_queue: Option<Vec<EgValue>>,
+
+    /// Copies of the responses sent for the current request, collected
+    /// so they can be replayed.  See osrf::dedup.
+    recorded: Option<Vec<EgValue>>,
 }
 
 impl fmt::Display for ServerSession {
@@ -783,6 +994,7 @@ impl ServerSession {
             responded_complete: false,
             thread: thread.to_string(),
             atomic_resp_queue: None,
+            recorded: None,
         }
     }
 
@@ -820,6 +1032,18 @@ impl ServerSession {
         self.client.singleton().borrow_mut()
     }
 
+    /// Keep a copy of each response sent until take_recorded() is
+    /// called.
+    pub fn start_recording(&mut self) {
+        self.recorded = Some(Vec::new());
+    }
+
+    /// Returns the responses sent since start_recording() and stops
+    /// recording.
+    pub fn take_recorded(&mut self) -> Option<Vec<EgValue>> {
+        self.recorded.take()
+    }
+
     pub fn responded_complete(&self) -> bool {
         self.responded_complete
     }
@@ -877,6 +1101,10 @@ impl ServerSession {
 
     /// Respond with a value and/or a complete message.
     fn respond_with_parts(&mut self, value: Option<EgValue>, complete: bool) -> EgResult<()> {
+        // Stop a handler which has run out of time.  The worker
+        // replies with a Timeout status in place of the response.
+        method::check_call_deadline()?;
+
         if self.responded_complete {
             log::warn!(
                 r#"Dropping trailing replies after already sending a
@@ -888,6 +1116,10 @@ impl ServerSession {
 
         let mut complete_msg = None;
 
+        if let (Some(recorded), Some(v)) = (self.recorded.as_mut(), value.as_ref()) {
+            recorded.push(v.clone());
+        }
+
         let mut result_msg = self.build_result_message(value, complete)?;
 
         if complete {
@@ -936,10 +1168,12 @@ impl ServerSession {
         self.respond_with_parts(None, true)
     }
 
+    /// Send a response to the caller without completing the request.
     pub fn respond(&mut self, value: impl Into<EgValue>) -> EgResult<()> {
         self.respond_with_parts(Some(value.into()), false)
     }
 
+    /// Send a final response along with the Request Complete message.
     pub fn respond_complete(&mut self, value: impl Into<EgValue>) -> EgResult<()> {
         self.respond_with_parts(Some(value.into()), true)
     }
@@ -1,7 +1,9 @@
 use crate::osrf::addr::BusAddress;
 use crate::osrf::app;
+use crate::osrf::buspool::BusPool;
 use crate::osrf::client::{Client, ClientSingleton};
 use crate::osrf::conf;
+use crate::osrf::dedup::{self, Prior};
 use crate::osrf::logging::Logger;
 use crate::osrf::message;
 use crate::osrf::message::Message;
@@ -10,7 +12,7 @@ use crate::osrf::message::MessageType;
 use crate::osrf::message::Payload;
 use crate::osrf::message::TransportMessage;
 use crate::osrf::method;
-use crate::osrf::method::ParamCount;
+use crate::osrf::metrics;
 use crate::osrf::sclient::HostSettings;
 use crate::osrf::session::ServerSession;
 use crate::util;
@@ -25,7 +27,13 @@ use std::thread;
 use std::time;
 
 // How often each worker wakes to check for shutdown signals, etc.
-const IDLE_WAKE_TIME: i32 = 5;
+pub const IDLE_WAKE_TIME: i32 = 5;
+
+/// Max time in seconds a worker spends finishing its current
+/// conversation after receiving a stop signal.
+///
+/// Configured via apps/<service>/unix_config/drain_timeout
+pub const DEFAULT_DRAIN_TIMEOUT: i32 = 30;
 
 /// Each worker thread is in one of these states.
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -59,6 +67,10 @@ pub struct Worker {
 
     client: Client,
 
+    /// Pool our bus connection was borrowed from, if pooling is
+    /// enabled.  The connection is returned when we are dropped.
+    bus_pool: Option<Arc<BusPool>>,
+
     /// True if the caller has requested a stateful conversation.
     connected: bool,
 
@@ -76,6 +88,13 @@ pub struct Worker {
 
     /// Channel for sending worker state info to our parent.
     to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
+
+    /// Message ID of the transport message being handled.
+    msg_id: Option<String>,
+
+    /// Seconds to remember requests for methods which opt in to
+    /// deduplication.  0 disables.  See osrf::dedup.
+    dedup_window: u32,
 }
 
 impl fmt::Display for Worker {
@@ -91,8 +110,12 @@ impl Worker {
         sig_tracker: SignalTracker,
         methods: Arc<HashMap<String, method::MethodDef>>,
         to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
+        bus_pool: Option<Arc<BusPool>>,
     ) -> EgResult<Worker> {
-        let client = Client::connect()?;
+        let client = match bus_pool.as_ref() {
+            Some(pool) => Client::from_bus(pool.acquire()?),
+            None => Client::connect()?,
+        };
 
         Ok(Worker {
             sig_tracker,
@@ -100,9 +123,12 @@ impl Worker {
             worker_id,
             methods,
             client,
+            bus_pool,
             to_parent_tx,
             session: None,
             connected: false,
+            msg_id: None,
+            dedup_window: dedup::DEFAULT_WINDOW,
         })
     }
 
@@ -150,8 +176,29 @@ impl Worker {
                 .as_usize()
                 .unwrap_or(5);
 
+        let drain_timeout: i32 =
+            HostSettings::get(&format!("apps/{}/unix_config/drain_timeout", self.service))
+                .expect("Host Settings Not Retrieved")
+                .as_i64()
+                .map(|t| t as i32)
+                .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+
+        self.dedup_window =
+            HostSettings::get(&format!("apps/{}/unix_config/dedup_window", self.service))
+                .expect("Host Settings Not Retrieved")
+                .as_usize()
+                .map(|w| w as u32)
+                .unwrap_or(dedup::DEFAULT_WINDOW);
+
         let mut requests: usize = 0;
 
+        // Started once we receive a stop signal in the middle of a
+        // stateful conversation.
+        let mut drain_timer: Option<util::Timer> = None;
+
+        // Requests left unhandled if the drain timeout is reached.
+        let mut pending_requests: i32 = 0;
+
         // We listen for API calls at an addressed scoped to our
         // username and domain.
         let username = self.client.address().username();
@@ -163,6 +210,30 @@ impl Worker {
         let my_addr = self.client.address().as_str().to_string();
 
         while requests < max_requests {
+            // Did we get a shutdown signal?  Stop accepting new
+            // requests, but let a stateful conversation finish,
+            // up to the drain timeout.
+            if self.sig_tracker.any_shutdown_requested() {
+                if !self.connected {
+                    log::info!("{selfstr} received a stop signal");
+                    break;
+                }
+
+                let timer = drain_timer.get_or_insert_with(|| {
+                    log::info!("{selfstr} received a stop signal; draining session");
+                    util::Timer::new(drain_timeout)
+                });
+
+                if timer.done() {
+                    pending_requests = self
+                        .client_internal_mut()
+                        .bus_mut()
+                        .llen(&my_addr)
+                        .unwrap_or(0);
+                    break;
+                }
+            }
+
             let timeout: i32;
             let sent_to: &str;
 
@@ -238,18 +309,17 @@ impl Worker {
                     break;
                 }
             }
-
-            // Did we get a shutdown signal?  Check this after
-            // "end_session()" so we don't interrupt a conversation to
-            // shutdown.
-            if self.sig_tracker.any_shutdown_requested() {
-                log::info!("{selfstr} received a stop signal");
-                break;
-            }
         }
 
         log::debug!("{self} exiting listen loop and cleaning up");
 
+        if drain_timer.map(|t| t.done()).unwrap_or(false) {
+            log::warn!(
+                "{selfstr} drain timeout of {drain_timeout} seconds reached \
+                with {pending_requests} request(s) still pending"
+            );
+        }
+
         if let Err(e) = appworker.worker_end() {
             log::error!("{selfstr} worker_end failed {e}");
         }
@@ -372,6 +442,8 @@ impl Worker {
             ));
         }
 
+        self.msg_id = tmsg.msg_id().map(|id| id.to_string());
+
         for msg in tmsg.body_mut().drain(..) {
             self.handle_message(msg, appworker)?;
         }
@@ -450,7 +522,6 @@ impl Worker {
             _ => return self.reply_bad_request("Request sent without a MethoCall payload"),
         };
 
-        let param_count = method_call.params().len();
         let api_name = method_call.method();
 
         let log_params =
@@ -496,45 +567,96 @@ impl Worker {
         }
 
         let method_def = method_def.unwrap();
-        let pcount = method_def.param_count();
-
-        // Make sure the number of params sent by the caller matches the
-        // parameter count for the method.
-        if !ParamCount::matches(&pcount, param_count as u8) {
-            return self.reply_bad_request(&format!(
-                "Invalid param count sent: method={} sent={} needed={}",
-                api_name, param_count, &pcount,
-            ));
+
+        // Verify the param count and, at least superficially, the
+        // param types.  Do this after deserialization.
+        if let Err(e) = method_def.validate_params(method_call.params()) {
+            return self.reply_bad_request(&e);
         }
 
-        // Verify paramter types are correct, at least superficially.
-        // Do this after deserialization.
-        if let Some(param_defs) = method_def.params() {
-            for (idx, param_def) in param_defs.iter().enumerate() {
-                // There may be more param defs than parameters if
-                // some param are optional.
-                if let Some(param_val) = method_call.params().get(idx) {
-                    if idx >= pcount.minimum().into() && param_val.is_null() {
-                        // NULL placeholders for non-required parameters are
-                        // allowed.
-                        continue;
+        // Replay or drop a redelivered request for a method which
+        // opts in to deduplication.
+        let dedup_key = match self.msg_id.as_deref() {
+            Some(id) if method_def.dedup() && self.dedup_window > 0 => {
+                let key = dedup::cache_key(id, self.session().last_thread_trace());
+
+                match dedup::claim(&key, self.dedup_window, method_def.timeout()) {
+                    Ok(Prior::New) => {
+                        self.session_mut().start_recording();
+                        Some(key)
                     }
-                    if !param_def.datatype.matches(param_val) {
-                        return self.reply_bad_request(&format!(
-                            "Invalid paramter type: wanted={} got={}",
-                            param_def.datatype,
-                            param_val.clone().dump()
-                        ));
+                    Ok(Prior::Running(_)) => {
+                        log::warn!(
+                            "{self} dropping redelivered request {key} which is still running"
+                        );
+                        return Ok(());
+                    }
+                    Ok(Prior::Complete(responses)) => {
+                        log::info!("{self} replaying responses to redelivered request {key}");
+                        for value in responses {
+                            self.session_mut().respond(value)?;
+                        }
+                        return self.session_mut().send_complete();
+                    }
+                    Err(e) => {
+                        log::warn!("{self} cannot check for redelivery of request {key}: {e}");
+                        None
                     }
-                } else {
-                    // More defs than actual params. Verification complete.
-                    break;
                 }
             }
-        }
+            _ => None,
+        };
 
         // Call the API
-        if let Err(err) = (method_def.handler())(appworker, self.session_mut(), &method_call) {
+        let started = time::Instant::now();
+        method::set_call_deadline(method_def.timeout());
+        let handler = method_def.handler();
+        let result = method::catch_panic(method_def.name(), || {
+            handler(appworker, self.session_mut(), method_call)
+        });
+        let timed_out = method::call_deadline_exceeded();
+        method::clear_call_deadline();
+
+        if let Some(key) = dedup_key {
+            // Only a successful call is replayed.  Otherwise, let a
+            // later delivery try again.  A call which ran past its
+            // timeout but succeeded made its changes, so it counts.
+            let outcome = match self.session_mut().take_recorded() {
+                Some(responses) if result.is_ok() => {
+                    dedup::complete(&key, responses, self.dedup_window)
+                }
+                _ => dedup::release(&key),
+            };
+
+            if let Err(e) = outcome {
+                log::warn!("{self} cannot update redelivery record {key}: {e}");
+            }
+        }
+        metrics::record_call(
+            method_def.name(),
+            started.elapsed(),
+            result.is_err() || timed_out,
+        );
+
+        if timed_out {
+            let msg = format!(
+                "{self} method {} exceeded its timeout of {:?}",
+                method_call.method(),
+                method_def.timeout().unwrap_or_default(),
+            );
+            log::error!("{msg}");
+
+            let err = match result {
+                Err(e) => e,
+                Ok(()) => msg.as_str().into(),
+            };
+            appworker.api_call_error(&method_call, err);
+
+            self.reply_with_status(MessageStatus::Timeout, "Method Call Timeout")?;
+            return Err(msg.into());
+        }
+
+        if let Err(err) = result {
             let msg = format!("{self} method {} failed with {err}", method_call.method());
             log::error!("{msg}");
             appworker.api_call_error(&method_call, err);
@@ -611,3 +733,24 @@ impl Worker {
             .map_err(|e| format!("mpsc::SendError: {e}").into())
     }
 }
+
+impl Drop for Worker {
+    /// Return a pooled bus connection, including when the worker
+    /// thread panics.
+    fn drop(&mut self) {
+        let pool = match self.bus_pool.take() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let bus = match self.client.singleton().try_borrow_mut() {
+            Ok(mut singleton) => singleton.try_take_bus(),
+            Err(_) => None,
+        };
+
+        match bus {
+            Some(bus) => pool.release(bus),
+            None => pool.forget(),
+        }
+    }
+}
@@ -50,6 +50,11 @@ use std::fmt;
 /// ```
 pub type EgResult<T> = std::result::Result<T, EgError>;
 
+/// Error type for most Evergreen functions.
+///
+/// Variants other than Event carry a plain message, which is also the
+/// Display value.  API handlers convert all of them into events via
+/// event_or_default().
 #[derive(Debug, Clone)]
 pub enum EgError {
     /// General error/failure messages that is not linked to an EgEvent.
@@ -58,6 +63,14 @@ pub enum EgError {
     /// fatal error strings.
     Debug(String),
     Event(EgEvent),
+    /// A requested object or record does not exist.
+    NotFound(String),
+    /// The requestor lacks the permission needed to proceed.
+    Permission(String),
+    /// Communication with another service failed.
+    Transport(String),
+    /// Caller-provided data is malformed or out of range.
+    Validation(String),
 }
 
 impl std::error::Error for EgError {
@@ -78,21 +91,37 @@ impl EgError {
     pub fn event_or_default(&self) -> EgEvent {
         match self {
             EgError::Event(e) => e.clone(),
-            EgError::Debug(s) => {
+            _ => {
                 let mut evt = EgEvent::new("INTERNAL_SERVER_ERROR");
                 // This is for debug purposes only -- i18n not needed.
-                evt.set_desc(&format!("Server Error: {s}"));
+                evt.set_desc(&format!("Server Error: {self}"));
                 evt
             }
         }
     }
+
+    /// Returns the error message for non-Event variants.
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Self::Event(_) => None,
+            Self::Debug(m)
+            | Self::NotFound(m)
+            | Self::Permission(m)
+            | Self::Transport(m)
+            | Self::Validation(m) => Some(m.as_str()),
+        }
+    }
 }
 
 impl fmt::Display for EgError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            Self::Debug(ref m) => write!(f, "{m}"),
-            Self::Event(ref e) => write!(f, "{e}"),
+        match self {
+            Self::Event(e) => write!(f, "{e}"),
+            Self::Debug(m)
+            | Self::NotFound(m)
+            | Self::Permission(m)
+            | Self::Transport(m)
+            | Self::Validation(m) => write!(f, "{m}"),
         }
     }
 }
@@ -115,10 +144,7 @@ impl From<&str> for EgError {
 /// OpenSRF published APIs
 impl From<EgError> for String {
     fn from(err: EgError) -> Self {
-        match err {
-            EgError::Debug(m) => m.to_string(),
-            EgError::Event(e) => e.to_string(),
-        }
+        err.to_string()
     }
 }
 
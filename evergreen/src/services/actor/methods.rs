@@ -453,10 +453,7 @@ pub fn user_opac_vital_stats(
         }
     };
 
-    let mut unread_count = 0;
-    if let Some(unread) = editor.json_query(unread_query)?.get(0) {
-        unread_count = unread["count"].int()?;
-    }
+    let unread_count = editor.query_scalar_i64(unread_query)?;
 
     let resp = eg::hash! {
         fines: fines,
@@ -1,5 +1,7 @@
+use eg::common::settings;
 use eg::osrf::app::{Application, ApplicationWorker, ApplicationWorkerFactory};
 use eg::osrf::cache::Cache;
+use eg::osrf::events;
 use eg::osrf::message;
 use eg::osrf::method::MethodDef;
 use eg::Client;
@@ -9,12 +11,25 @@ use evergreen as eg;
 use std::any::Any;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 // Import our local methods module.
 use crate::methods;
 
 const APPNAME: &str = "open-ils.rs-auth-internal";
 
+/// IDL classes we work with, loaded in place of the full IDL when
+/// partial IDL loading is enabled.  See eg::init::load_idl_classes().
+///
+/// Every class queried by our methods, including via the shared auth
+/// code, must be listed here.
+const IDL_CLASSES: &[&str] = &["au", "aou", "ac", "aws", "cgf"];
+
+/// Max run time of each of our methods.  Auth calls do a handful of
+/// small lookups and sit in front of every login, so one which runs
+/// long is stuck and should fail fast instead of tying up a worker.
+const METHOD_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Our main application class.
 pub struct RsAuthInternalApplication {}
 
@@ -31,7 +46,23 @@ impl Application for RsAuthInternalApplication {
 
     /// Load the IDL and perform any other needed global startup work.
     fn init(&mut self, _client: Client) -> EgResult<()> {
-        eg::init::load_idl()?;
+        eg::init::load_idl_classes(IDL_CLASSES)?;
+
+        // Drop cached auth settings when they change.  All workers
+        // share one subscriber.
+        let mut subscriber = events::Subscriber::new();
+
+        subscriber.on(events::SETTINGS_CHANGED, |event| {
+            let name = event.payload()["name"].as_str().unwrap_or("");
+            if event.missed() || name.starts_with("auth.") {
+                methods::forget_settings();
+            }
+        });
+
+        subscriber.on(events::CACHE_BUST, |_| methods::forget_settings());
+
+        subscriber.start();
+
         Ok(())
     }
 
@@ -42,7 +73,9 @@ impl Application for RsAuthInternalApplication {
         // Create Method objects from our static method definitions.
         for def in methods::METHODS.iter() {
             log::info!("Registering method: {}", def.name());
-            methods.push(def.into_method(APPNAME));
+            let mut method = def.into_method(APPNAME);
+            method.set_timeout(METHOD_TIMEOUT);
+            methods.push(method);
         }
 
         Ok(methods)
@@ -104,6 +137,7 @@ impl ApplicationWorker for RsAuthInternalWorker {
         methods: Arc<HashMap<String, MethodDef>>,
     ) -> EgResult<()> {
         Cache::init_cache("global")?;
+        settings::configure_shared_cache(APPNAME)?;
         self.client = Some(client);
         self.methods = Some(methods);
         Ok(())
@@ -134,3 +168,43 @@ impl ApplicationWorker for RsAuthInternalWorker {
 
     fn api_call_error(&mut self, _request: &message::MethodCall, _error: EgError) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::IDL_CLASSES;
+
+    /// Classes queried on our behalf by eg::common code.
+    const SHARED_CODE_CLASSES: &[&str] = &["aws"]; // auth::Session::internal_session
+
+    /// Classes named in editor.search() and editor.retrieve() calls.
+    fn queried_classes(source: &str) -> Vec<&str> {
+        let mut classes = Vec::new();
+
+        for call in [".search(\"", ".retrieve(\""] {
+            for (pos, _) in source.match_indices(call) {
+                let rest = &source[pos + call.len()..];
+                if let Some(end) = rest.find('"') {
+                    classes.push(&rest[..end]);
+                }
+            }
+        }
+
+        classes
+    }
+
+    #[test]
+    fn idl_classes_cover_queries() {
+        let mut classes = queried_classes(include_str!("methods.rs"));
+
+        assert!(classes.contains(&"cgf"));
+
+        classes.extend(SHARED_CODE_CLASSES);
+
+        for class in classes {
+            assert!(
+                IDL_CLASSES.contains(&class),
+                "IDL class '{class}' is queried but not in IDL_CLASSES"
+            );
+        }
+    }
+}
@@ -9,11 +9,34 @@ use eg::EgEvent;
 use eg::EgResult;
 use eg::EgValue;
 use evergreen as eg;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 // Import our local app module
 use crate::app;
 
+const BLOCK_EXPIRED_STAFF_FLAG: &str = "auth.block_expired_staff_login";
+
+/// How long workers reuse a looked-up auth setting.  Changes made via
+/// eg::common::settings are announced sooner.  See forget_settings().
+const SETTINGS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Whether BLOCK_EXPIRED_STAFF_FLAG is enabled, and when we checked,
+/// shared by all workers.
+static BLOCK_EXPIRED_STAFF: Mutex<Option<(bool, Instant)>> = Mutex::new(None);
+
+/// Drop our cached auth settings so they are looked up again.
+pub fn forget_settings() {
+    log::debug!("Dropping cached auth settings");
+    if let Ok(mut cached) = BLOCK_EXPIRED_STAFF.lock() {
+        *cached = None;
+    }
+}
+
 /// List of method definitions we know at compile time.
+///
+/// Incoming params are validated against these definitions before
+/// the handler is called.  See MethodDef::validate_params().
 pub static METHODS: &[StaticMethodDef] = &[
     StaticMethodDef {
         name: "session.create",
@@ -26,6 +49,28 @@ pub static METHODS: &[StaticMethodDef] = &[
             desc: "Hash of Login Options and Values",
         }],
     },
+    StaticMethodDef {
+        name: "session.delete",
+        desc: "Delete an Authentication Session",
+        param_count: ParamCount::Exactly(1),
+        handler: delete_auth_session,
+        params: &[StaticParam {
+            name: "Authtoken",
+            datatype: ParamDataType::String,
+            desc: "Authentication Session Token",
+        }],
+    },
+    StaticMethodDef {
+        name: "session.refresh",
+        desc: "Extend an Authentication Session's Inactivity Timeout",
+        param_count: ParamCount::Exactly(1),
+        handler: refresh_auth_session,
+        params: &[StaticParam {
+            name: "Authtoken",
+            datatype: ParamDataType::String,
+            desc: "Authentication Session Token",
+        }],
+    },
     StaticMethodDef {
         name: "user.validate",
         desc: "Validate a User for Login",
@@ -37,6 +82,29 @@ pub static METHODS: &[StaticMethodDef] = &[
             desc: "Hash of Login Options and Values",
         }],
     },
+    StaticMethodDef {
+        name: "user.password.verify",
+        desc: "Verify a User's Main Password",
+        param_count: ParamCount::Exactly(3),
+        handler: verify_user_password,
+        params: &[
+            StaticParam {
+                name: "Authtoken",
+                datatype: ParamDataType::String,
+                desc: "Authentication Session Token",
+            },
+            StaticParam {
+                name: "User ID",
+                datatype: ParamDataType::Number,
+                desc: "actor.usr ID",
+            },
+            StaticParam {
+                name: "Password",
+                datatype: ParamDataType::String,
+                desc: "Plain text password",
+            },
+        ],
+    },
 ];
 
 pub fn create_auth_session(
@@ -45,23 +113,52 @@ pub fn create_auth_session(
     method: &message::MethodCall,
 ) -> EgResult<()> {
     let worker = app::RsAuthInternalWorker::downcast(worker)?;
-    let options = method.param(0);
-
-    let user_id = options["user_id"].int()?;
-    let login_type = auth::LoginType::try_from(options["login_type"].str()?)?;
+    let args = auth::InternalLoginArgs::try_from(method.param(0))?;
 
     let mut editor = Editor::new(worker.client());
 
-    let args = auth::InternalLoginArgs {
-        user_id,
-        login_type,
-        org_unit: options["org_id"].as_int(),
-        workstation: options["workstation"].as_str().map(|v| v.to_string()),
+    let auth_ses = auth::Session::internal_session(&mut editor, &args)?;
+
+    session.respond(EgValue::from(&auth_ses.auth_session()))
+}
+
+/// Responds with a SUCCESS event whether or not the session still
+/// existed, so repeated logouts are harmless.
+pub fn delete_auth_session(
+    _worker: &mut Box<dyn ApplicationWorker>,
+    session: &mut ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    let token = method.param(0).str()?;
+
+    let mut evt = EgEvent::success();
+
+    if !auth::Session::delete(token)? {
+        log::info!("Auth session {token} already removed");
+        evt.set_note("Session already removed");
+    }
+
+    session.respond(evt.to_value())
+}
+
+/// Responds with a SUCCESS event whose payload is the number of
+/// seconds until the session expires, or NO_SESSION.
+pub fn refresh_auth_session(
+    _worker: &mut Box<dyn ApplicationWorker>,
+    session: &mut ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    let token = method.param(0).str()?;
+
+    let timeout = match auth::Session::refresh(token)? {
+        Some(t) => t,
+        None => return session.respond(EgEvent::value("NO_SESSION")),
     };
 
-    let auth_ses = auth::Session::internal_session(&mut editor, &args)?;
+    let mut evt = EgEvent::success();
+    evt.set_payload(EgValue::from(timeout));
 
-    session.respond(eg::hash! {"authtime": auth_ses.authtime(), "authtoken": auth_ses.token()})
+    session.respond(evt.to_value())
 }
 
 pub fn validate_user(
@@ -70,14 +167,11 @@ pub fn validate_user(
     method: &message::MethodCall,
 ) -> EgResult<()> {
     let worker = app::RsAuthInternalWorker::downcast(worker)?;
-    let options = method.param(0);
-
-    let user_id = options["user_id"].int()?;
-    let login_type = auth::LoginType::try_from(options["login_type"].str()?)?;
+    let args = auth::ValidateUserArgs::try_from(method.param(0))?;
 
     let mut editor = Editor::new(worker.client());
 
-    let user = match editor.retrieve("au", user_id)? {
+    let user = match editor.retrieve("au", args.user_id)? {
         Some(u) => u,
         None => return session.respond(EgEvent::value("LOGIN_FAILED")),
     };
@@ -104,7 +198,7 @@ pub fn validate_user(
         return session.respond(EgEvent::value("LOGIN_FAILED"));
     }
 
-    if let Some(barcode) = options["barcode"].as_str() {
+    if let Some(barcode) = args.barcode.as_deref() {
         let card_op = editor.search("ac", eg::hash! {"barcode": barcode})?.pop();
         if let Some(card) = card_op {
             if !card["active"].boolish() {
@@ -113,7 +207,7 @@ pub fn validate_user(
         }
     }
 
-    let permission = match login_type {
+    let permission = match args.login_type {
         auth::LoginType::Opac => "OPAC_LOGIN",
         auth::LoginType::Staff | auth::LoginType::Temp => "STAFF_LOGIN",
         auth::LoginType::Persist => "PERSISTENT_LOGIN",
@@ -127,6 +221,45 @@ pub fn validate_user(
     session.respond(EgEvent::success_value())
 }
 
+/// Responds with true if the password matches the user's main
+/// password.
+///
+/// Legacy password hashes may be upgraded along the way, so the
+/// requestor needs UPDATE_USER at the user's home org unit.  Checks
+/// count toward the user's failed logins.  See auth::verify_login_password().
+pub fn verify_user_password(
+    worker: &mut Box<dyn ApplicationWorker>,
+    session: &mut ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    let worker = app::RsAuthInternalWorker::downcast(worker)?;
+    let authtoken = method.param(0).str()?;
+    let user_id = method.param(1).int()?;
+    let password = method.param(2).str()?;
+
+    let mut editor = Editor::with_auth(worker.client(), authtoken);
+
+    if !editor.checkauth()? {
+        return session.respond(editor.event());
+    }
+
+    let user = editor
+        .retrieve("au", user_id)?
+        .ok_or_else(|| editor.die_event())?;
+
+    if !editor.allowed_at("UPDATE_USER", user["home_ou"].int()?)? {
+        return session.respond(editor.event());
+    }
+
+    editor.xact_begin()?;
+
+    let matched = auth::verify_login_password(&mut editor, user_id, password)?;
+
+    editor.commit()?;
+
+    session.respond(matched)
+}
+
 /// Returns true if we block expired STAFF_LOGIN accounts and the
 /// user in question -- the editor's requestor -- has STAFF_LOGIN
 /// permissions.
@@ -143,10 +276,28 @@ fn block_expired_staff(editor: &mut Editor) -> EgResult<bool> {
     // catalog... but then, staff members really ought to be using a
     // separate account when acting as a library patron anyway.
 
-    let query = eg::hash! {"enabled": "t", "name": "auth.block_expired_staff_login"};
+    let cached = match BLOCK_EXPIRED_STAFF.lock() {
+        Ok(c) => *c,
+        Err(_) => None,
+    };
+
+    let enabled = match cached {
+        Some((enabled, at)) if at.elapsed() < SETTINGS_CACHE_TTL => enabled,
+        _ => {
+            let query = eg::hash! {"enabled": "t", "name": BLOCK_EXPIRED_STAFF_FLAG};
+            let enabled = editor.search("cgf", query)?.first().is_some();
+
+            if let Ok(mut c) = BLOCK_EXPIRED_STAFF.lock() {
+                *c = Some((enabled, Instant::now()));
+            }
+
+            enabled
+        }
+    };
 
-    match editor.search("cgf", query)?.first() {
-        Some(_) => editor.allowed("STAFF_LOGIN"),
-        None => Ok(false),
+    if enabled {
+        editor.allowed("STAFF_LOGIN")
+    } else {
+        Ok(false)
     }
 }
@@ -13,6 +13,17 @@ use crate::methods;
 
 const APPNAME: &str = "open-ils.rs-circ";
 
+/// Methods which change circulation data and so must not run twice
+/// when the bus redelivers a request.  See eg::osrf::dedup.
+const DEDUP_METHODS: &[&str] = &[
+    "checkin",
+    "checkin.override",
+    "checkout",
+    "checkout.override",
+    "renew",
+    "renew.override",
+];
+
 /// Our main application class.
 pub struct RsCircApplication {}
 
@@ -40,7 +51,9 @@ impl Application for RsCircApplication {
         // Create Method objects from our static method definitions.
         for def in methods::METHODS.iter() {
             log::info!("Registering method: {}", def.name());
-            methods.push(def.into_method(APPNAME));
+            let mut method = def.into_method(APPNAME);
+            method.set_dedup(DEDUP_METHODS.contains(&def.name()));
+            methods.push(method);
         }
 
         Ok(methods)
@@ -1,6 +1,18 @@
+use crate as eg;
+use crate::common::auth;
+use crate::common::billing::{self, XactFinishAction};
+use crate::date;
+use crate::editor::OrderBy;
+use crate::idl;
+use crate::osrf::conf::ConfigBuilder;
+use crate::osrf::jsonrpc;
 use crate::osrf::message::Message;
 use crate::osrf::message::Payload;
 use crate::osrf::message::TransportMessage;
+use crate::osrf::method;
+use crate::osrf::metrics;
+use crate::osrf::session::Responder;
+use crate::result::EgError;
 use json;
 
 const TRANSPORT_MSG_JSON: &str = r#"{
@@ -45,6 +57,96 @@ fn parse_transport_message() {
     }
 }
 
+#[test]
+fn transport_message_dedup_id() {
+    use crate::osrf::dedup::{self, Prior};
+
+    let json_value = json::parse(TRANSPORT_MSG_JSON).unwrap();
+    let mut tm = TransportMessage::from_json_value(json_value, true).unwrap();
+    assert_eq!(tm.msg_id(), None);
+    assert!(tm.clone().into_json_value()["osrf_msg_id"].is_null());
+
+    tm.set_msg_id("my-thread.1");
+    let tm = TransportMessage::from_json_value(tm.into_json_value(), true).unwrap();
+    assert_eq!(tm.msg_id(), Some("my-thread.1"));
+
+    assert_eq!(dedup::prior_from_cache(None), Prior::New);
+
+    let mut running = eg::EgValue::new_object();
+    running["running"] = true.into();
+    assert_eq!(
+        dedup::prior_from_cache(Some(running.clone())),
+        Prior::Running(0.0)
+    );
+
+    running["claimed_at"] = 1700000000.5.into();
+    assert_eq!(
+        dedup::prior_from_cache(Some(running)),
+        Prior::Running(1700000000.5)
+    );
+
+    let mut complete = eg::EgValue::new_object();
+    complete["responses"] = vec![eg::EgValue::from("Hello"), eg::EgValue::from(5)].into();
+
+    match dedup::prior_from_cache(Some(complete)) {
+        Prior::Complete(responses) => {
+            assert_eq!(responses.len(), 2);
+            assert_eq!(responses[0].as_str(), Some("Hello"));
+        }
+        p => panic!("Unexpected prior: {p:?}"),
+    }
+}
+
+#[test]
+fn parse_malformed_bus_message() {
+    use crate::osrf::bus;
+
+    let recipient = "opensrf:service:open-ils.rs-circ";
+
+    // Truncated mid-message.
+    let truncated = &TRANSPORT_MSG_JSON.as_bytes()[..TRANSPORT_MSG_JSON.len() / 2];
+    let err = bus::parse_message(truncated, recipient).unwrap_err();
+    assert_eq!(err.recipient(), recipient);
+    assert_eq!(err.sender(), Some("my-from"));
+    assert_eq!(err.thread(), Some("my-thread"));
+    assert_eq!(err.size(), truncated.len());
+    assert!(err.reason().starts_with("Invalid JSON"));
+
+    let text = err.to_string();
+    assert!(text.contains("from=my-from"));
+    assert!(text.contains("thread=my-thread"));
+
+    // Not UTF-8.
+    let mut bytes = br#"{"from" : "my-from", "thread":"#.to_vec();
+    bytes.extend_from_slice(&[0xff, 0xfe, 0x00]);
+    let err = bus::parse_message(&bytes, recipient).unwrap_err();
+    assert_eq!(err.sender(), Some("my-from"));
+    assert_eq!(err.thread(), None);
+    assert!(err.reason().starts_with("Invalid UTF-8"));
+
+    // Valid JSON, but not a message.
+    let err = bus::parse_message(b"[1, 2, 3]", recipient).unwrap_err();
+    assert_eq!(err.sender(), None);
+    assert!(err.to_string().contains("from=<unknown>"));
+
+    // A corrupt compressed payload.
+    let bad_gzip = br#"{"from":"my-from","osrf_compression":"gzip","osrf_payload":"AAAA"}"#;
+    let err = bus::parse_message(bad_gzip, recipient).unwrap_err();
+    assert!(err.reason().contains("decompress"));
+
+    // Well-formed JSON with an invalid body is rejected by the
+    // TransportMessage parser without panicking.
+    let value = bus::parse_message(
+        br#"{"from":"my-from","thread":"t","body":[{"__c":"osrfMessage","__p":7}]}"#,
+        recipient,
+    )
+    .unwrap();
+    assert!(TransportMessage::from_json_value(value, true).is_err());
+
+    let value = bus::parse_message(TRANSPORT_MSG_JSON.as_bytes(), recipient).unwrap();
+    assert!(TransportMessage::from_json_value(value, true).is_ok());
+}
+
 #[test]
 fn parse_opensrf_message() {
     let mut json_value = json::parse(TRANSPORT_MSG_JSON).unwrap();
@@ -54,3 +156,998 @@ fn parse_opensrf_message() {
     let msg = msg_op.unwrap();
     assert_eq!(msg.ingress(), "opensrf");
 }
+
+#[test]
+fn xact_finish_action_zero_owed() {
+    // (zero_owed, xact_open, no_circ_or_complete)
+    assert_eq!(
+        billing::xact_finish_action(true, true, true),
+        XactFinishAction::Close
+    );
+    assert_eq!(
+        billing::xact_finish_action(true, true, false),
+        XactFinishAction::Keep
+    );
+    assert_eq!(
+        billing::xact_finish_action(true, false, true),
+        XactFinishAction::Keep
+    );
+    assert_eq!(
+        billing::xact_finish_action(true, false, false),
+        XactFinishAction::Reopen
+    );
+}
+
+#[test]
+fn xact_finish_action_non_zero_owed() {
+    assert_eq!(
+        billing::xact_finish_action(false, true, true),
+        XactFinishAction::Keep
+    );
+    assert_eq!(
+        billing::xact_finish_action(false, true, false),
+        XactFinishAction::Keep
+    );
+    assert_eq!(
+        billing::xact_finish_action(false, false, true),
+        XactFinishAction::Reopen
+    );
+    assert_eq!(
+        billing::xact_finish_action(false, false, false),
+        XactFinishAction::Reopen
+    );
+}
+
+#[test]
+fn bill_payment_map_net_balance() {
+    let map = billing::BillPaymentMap {
+        bill: eg::EgValue::Null,
+        adjustments: Vec::new(),
+        payments: Vec::new(),
+        bill_amount: 1.10,
+        adjustment_amount: 0.20,
+        paid_amount: 0.70,
+    };
+
+    assert_eq!(map.net_balance(), 0.20);
+}
+
+#[test]
+fn internal_login_args_round_trip() {
+    let mut args = auth::InternalLoginArgs::new(42, auth::LoginType::Staff);
+    args.set_org_unit(4);
+
+    let value = eg::EgValue::from(&args);
+    let parsed = auth::InternalLoginArgs::try_from(&value).expect("Valid args");
+
+    assert_eq!(parsed.user_id, 42);
+    assert_eq!(parsed.login_type, auth::LoginType::Staff);
+    assert_eq!(parsed.org_unit, Some(4));
+    assert_eq!(parsed.workstation, None);
+
+    // Missing required fields are an error.
+    let value = eg::hash! {"login_type": "staff"};
+    assert!(auth::InternalLoginArgs::try_from(&value).is_err());
+}
+
+#[test]
+fn auth_session_round_trip() {
+    let ses = auth::AuthSession {
+        authtoken: "abc123".to_string(),
+        authtime: 420,
+    };
+
+    let value = eg::EgValue::from(&ses);
+    assert_eq!(auth::AuthSession::try_from(&value).expect("Valid"), ses);
+
+    let value = eg::hash! {"authtime": 420};
+    assert!(auth::AuthSession::try_from(&value).is_err());
+}
+
+fn config_with_services(services: &str) -> String {
+    format!(
+        r#"<?xml version="1.0"?>
+        <config>
+          <opensrf>
+            <domain>localhost</domain>
+            <username>opensrf</username>
+            <passwd>password</passwd>
+          </opensrf>
+          <services>{services}</services>
+        </config>"#
+    )
+}
+
+#[test]
+fn config_service_worker_counts() {
+    let xml = config_with_services(
+        r#"<service name="open-ils.rs-actor">
+          <min_children>2</min_children>
+          <max_children>10</max_children>
+        </service>"#,
+    );
+
+    let config = ConfigBuilder::from_xml_string(&xml)
+        .expect("Valid XML")
+        .build()
+        .expect("Valid config");
+
+    let service = config.service("open-ils.rs-actor").expect("Has service");
+    assert_eq!(service.min_workers(), Some(2));
+    assert_eq!(service.max_workers(), Some(10));
+    assert_eq!(service.min_idle_workers(), None);
+    assert!(config.service("open-ils.rs-circ").is_none());
+}
+
+#[test]
+fn config_service_worker_counts_invalid() {
+    let xml = config_with_services(
+        r#"<service name="open-ils.rs-actor">
+          <min_children>10</min_children>
+          <max_children>2</max_children>
+        </service>"#,
+    );
+
+    let result = ConfigBuilder::from_xml_string(&xml)
+        .expect("Valid XML")
+        .build();
+    assert!(result.unwrap_err().contains("max_children"));
+
+    let xml = config_with_services(
+        r#"<service name="open-ils.rs-actor">
+          <max_children>lots</max_children>
+        </service>"#,
+    );
+
+    assert!(ConfigBuilder::from_xml_string(&xml).is_err());
+}
+
+#[test]
+fn config_validate_reports_all_issues() {
+    let xml = r#"<?xml version="1.0"?>
+        <config>
+          <opensrf>
+            <domain>localhost</domain>
+            <port>big</port>
+            <loglevel>loud</loglevel>
+          </opensrf>
+          <services>
+            <service name="open-ils.rs-actor">
+              <min_children>10</min_children>
+              <max_children>2</max_children>
+            </service>
+          </services>
+        </config>"#;
+
+    let builder = ConfigBuilder::from_xml_string(xml).expect("Valid XML");
+    let issues = builder.validate().unwrap_err();
+
+    for problem in ["port", "loglevel", "username", "password", "max_children"] {
+        assert!(
+            issues.iter().any(|i| i.contains(problem)),
+            "{problem} reported in {issues:?}"
+        );
+    }
+
+    let xml = config_with_services("");
+    let builder = ConfigBuilder::from_xml_string(&xml).expect("Valid XML");
+    assert!(builder.validate().is_ok());
+}
+
+#[test]
+fn config_private_and_public_domains() {
+    use crate::osrf::conf::DomainKind;
+
+    let xml = r#"<?xml version="1.0"?>
+        <config>
+          <opensrf>
+            <domain>private.localhost</domain>
+            <username>opensrf</username>
+            <passwd>private-password</passwd>
+            <routers>
+              <router>
+                <name>router</name>
+                <domain>private.localhost</domain>
+              </router>
+              <router>
+                <name>router</name>
+                <domain>public.localhost</domain>
+                <services>
+                  <service>open-ils.rs-actor</service>
+                </services>
+              </router>
+            </routers>
+          </opensrf>
+          <gateway>
+            <domain>public.localhost</domain>
+            <port>6380</port>
+            <username>gateway</username>
+            <passwd>public-password</passwd>
+          </gateway>
+          <routers>
+            <router>
+              <transport>
+                <server>private.localhost</server>
+                <username>router</username>
+                <password>router-password</password>
+              </transport>
+            </router>
+            <router>
+              <transport>
+                <server>public.localhost</server>
+                <username>router</username>
+                <password>router-password</password>
+              </transport>
+            </router>
+          </routers>
+        </config>"#;
+
+    let builder = ConfigBuilder::from_xml_string(xml).expect("Valid XML");
+    assert!(builder.validate().is_ok());
+    let config = builder.build().expect("Valid config");
+
+    let private = config.domain_client(DomainKind::Private).unwrap();
+    assert_eq!(private.domain().name(), "private.localhost");
+    assert_eq!(private.username(), "opensrf");
+    assert_eq!(private.routers().len(), 2);
+
+    let public = config.domain_client(DomainKind::Public).unwrap();
+    assert_eq!(public.domain().to_string(), "public.localhost:6380");
+    assert_eq!(public.username(), "gateway");
+    assert_eq!(public.password(), "public-password");
+
+    let found = config.client_for_domain("public.localhost").unwrap();
+    assert_eq!(found.username(), "gateway");
+    let found = config.client_for_domain("private.localhost").unwrap();
+    assert_eq!(found.username(), "opensrf");
+    assert!(config.client_for_domain("other.localhost").is_none());
+
+    for domain in ["private.localhost", "public.localhost"] {
+        let router = config.get_router_conf(domain).unwrap();
+        assert_eq!(router.client().username(), "router");
+    }
+
+    // Without a gateway there is no public domain.
+    let config = ConfigBuilder::from_xml_string(&config_with_services(""))
+        .unwrap()
+        .build()
+        .unwrap();
+
+    assert!(config.domain_client(DomainKind::Public).is_none());
+    assert!(config.client_for_domain("localhost").is_some());
+}
+
+#[test]
+fn config_from_string_and_reader() {
+    let xml = r#"<config>
+          <opensrf>
+            <domain>${EG_TEST_CONFIG_DOMAIN:-private.localhost}</domain>
+            <username>opensrf</username>
+            <passwd>password</passwd>
+          </opensrf>
+        </config>"#;
+
+    let config = ConfigBuilder::from_string(xml)
+        .expect("Valid XML")
+        .build()
+        .expect("Valid config");
+
+    assert_eq!(config.client().domain().name(), "private.localhost");
+    assert_eq!(config.client().username(), "opensrf");
+
+    let config = ConfigBuilder::from_reader(xml.as_bytes())
+        .expect("Valid XML")
+        .build()
+        .expect("Valid config");
+
+    assert_eq!(config.client().domain().name(), "private.localhost");
+
+    assert!(ConfigBuilder::from_string("<config>").is_err());
+}
+
+#[test]
+fn bus_compression_config() {
+    use crate::osrf::compression::{self, Algorithm};
+
+    let xml = |compression: &str| {
+        format!(
+            r#"<config><opensrf>
+              <domain>private.localhost</domain>
+              <username>opensrf</username>
+              <passwd>password</passwd>
+              {compression}
+            </opensrf></config>"#
+        )
+    };
+
+    let config = ConfigBuilder::from_string(&xml(""))
+        .unwrap()
+        .build()
+        .unwrap();
+    assert!(config.client().compression().is_none());
+
+    let config =
+        ConfigBuilder::from_string(&xml(r#"<compression threshold="1024">gzip</compression>"#))
+            .unwrap()
+            .build()
+            .unwrap();
+
+    let options = config.client().compression().unwrap();
+    assert_eq!(options.algorithm(), Algorithm::Gzip);
+    assert_eq!(options.threshold(), 1024);
+
+    // Small messages are sent as-is.
+    let small = json::object! {"to": "a", "from": "b", "thread": "t", "body": []};
+    let sent = compression::pack(&small, small.dump(), options).unwrap();
+    assert_eq!(sent, small.dump());
+    assert_eq!(compression::unpack(small.clone()).unwrap(), small);
+
+    // Envelopes keep the routing fields readable.
+    let large = json::object! {"to": "a", "from": "b", "thread": "t", "body": "x".repeat(4096)};
+    let envelope = json::parse(&compression::pack(&large, large.dump(), options).unwrap()).unwrap();
+    assert_eq!(envelope["to"], large["to"]);
+    assert_eq!(envelope[compression::COMPRESSION_KEY], "gzip");
+    assert!(envelope["body"].is_null());
+    assert_eq!(compression::unpack(envelope).unwrap(), large);
+
+    assert!(ConfigBuilder::from_string(&xml("<compression>lzma</compression>")).is_err());
+}
+
+#[test]
+fn circuit_breaker_states() {
+    use crate::osrf::breaker::{BreakerOptions, BreakerState, CircuitBreakers};
+    use std::time::Duration;
+
+    let config = ConfigBuilder::from_string(
+        r#"<config><opensrf>
+          <domain>private.localhost</domain>
+          <circuit_breaker failures="2" cooldown="10"/>
+        </opensrf></config>"#,
+    )
+    .unwrap()
+    .build()
+    .unwrap();
+
+    let options = config.client().circuit_breaker().unwrap();
+    assert_eq!(options.failure_threshold(), 2);
+    assert_eq!(options.cooldown(), Duration::from_secs(10));
+
+    let service = "open-ils.cstore";
+    let breakers = CircuitBreakers::new(options.clone());
+
+    breakers.record_failure(service);
+    assert_eq!(breakers.state(service), BreakerState::Closed);
+    assert!(breakers.check(service).is_ok());
+
+    // A success resets the failure count.
+    breakers.record_success(service);
+    breakers.record_failure(service);
+    assert_eq!(breakers.state(service), BreakerState::Closed);
+
+    breakers.record_failure(service);
+    assert_eq!(breakers.state(service), BreakerState::Open);
+    assert!(breakers.check(service).is_err());
+    assert!(breakers.check("open-ils.pcrud").is_ok());
+
+    let value = breakers.to_eg_value();
+    assert_eq!(value[0]["service"].as_str(), Some(service));
+    assert_eq!(value[0]["state"].as_str(), Some("open"));
+    assert_eq!(value[0]["failures"].int().unwrap(), 2);
+
+    // With no cooldown, an open breaker lets the next call through
+    // as a probe.
+    let breakers = CircuitBreakers::new(BreakerOptions::new(1, Duration::ZERO));
+    breakers.record_failure(service);
+    assert_eq!(breakers.state(service), BreakerState::HalfOpen);
+    assert!(breakers.check(service).is_ok());
+
+    // A failed probe reopens the breaker.
+    breakers.record_failure(service);
+    assert_eq!(breakers.state(service), BreakerState::HalfOpen);
+    assert!(breakers.check(service).is_ok());
+
+    breakers.record_success(service);
+    assert_eq!(breakers.state(service), BreakerState::Closed);
+    assert!(breakers.to_eg_value().is_empty());
+
+    // A threshold of 0 disables the breakers.
+    let breakers = CircuitBreakers::new(BreakerOptions::new(0, Duration::from_secs(10)));
+    breakers.record_failure(service);
+    assert_eq!(breakers.state(service), BreakerState::Closed);
+}
+
+#[test]
+fn bus_pool_exhausted() {
+    use crate::osrf::buspool::{BusPool, ExhaustedPolicy};
+    use std::time::{Duration, Instant};
+
+    assert_eq!(ExhaustedPolicy::from_wait_secs(0), ExhaustedPolicy::Fail);
+    assert_eq!(
+        ExhaustedPolicy::from_wait_secs(5),
+        ExhaustedPolicy::Wait(Duration::from_secs(5))
+    );
+
+    // A pool with no room fails without trying to connect.
+    let pool = BusPool::new(0, ExhaustedPolicy::Fail);
+    assert!(pool.acquire().is_err());
+    assert_eq!(pool.in_use(), 0);
+
+    let wait = Duration::from_millis(50);
+    let pool = BusPool::new(0, ExhaustedPolicy::Wait(wait));
+
+    let started = Instant::now();
+    assert!(pool.acquire().is_err());
+    assert!(started.elapsed() >= wait);
+
+    // Forgetting a connection never drops the count below zero.
+    pool.forget();
+    assert_eq!(pool.in_use(), 0);
+}
+
+#[test]
+fn metrics_render() {
+    let method = "eg.test.metrics.render";
+
+    metrics::record_call(method, std::time::Duration::from_millis(20), false);
+    metrics::record_call(method, std::time::Duration::from_secs(60), true);
+
+    let stats = metrics::method_stats(method).unwrap();
+    assert_eq!(stats.requests, 2);
+    assert_eq!(stats.errors, 1);
+
+    let text = metrics::render();
+    let labels = format!("method=\"{method}\"");
+
+    let line = |prefix: &str| {
+        text.lines()
+            .find(|l| l.starts_with(prefix) && l.contains(&labels))
+            .map(|l| l.rsplit(' ').next().unwrap().to_string())
+    };
+
+    assert_eq!(line("osrf_requests_total").as_deref(), Some("2"));
+    assert_eq!(line("osrf_request_errors_total").as_deref(), Some("1"));
+    assert_eq!(
+        line("osrf_request_duration_seconds_count").as_deref(),
+        Some("2")
+    );
+
+    // 20ms lands in the 25ms bucket; 60s only counts toward +Inf.
+    let bucket = |le: &str| {
+        text.lines()
+            .find(|l| l.contains(&labels) && l.contains(&format!("le=\"{le}\"")))
+            .map(|l| l.rsplit(' ').next().unwrap().to_string())
+    };
+
+    assert_eq!(bucket("0.01").as_deref(), Some("0"));
+    assert_eq!(bucket("0.025").as_deref(), Some("1"));
+    assert_eq!(bucket("30").as_deref(), Some("1"));
+    assert_eq!(bucket("+Inf").as_deref(), Some("2"));
+
+    assert!(text.contains("# TYPE osrf_workers gauge"));
+}
+
+#[test]
+fn jsonrpc_request_parse() {
+    let value = json::object! {
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": "opensrf.system.echo",
+        "params": ["hello", 3],
+    };
+
+    let req = jsonrpc::JsonRpcRequest::from_json_value(value).unwrap();
+
+    assert_eq!(req.id, json::from(7));
+    assert_eq!(req.method, "opensrf.system.echo");
+    assert_eq!(req.params.len(), 2);
+    assert_eq!(req.params[0].as_str(), Some("hello"));
+
+    let value = json::object! {"jsonrpc": "2.0", "id": "abc", "params": []};
+    let (id, err) = jsonrpc::JsonRpcRequest::from_json_value(value).unwrap_err();
+
+    assert_eq!(id, json::from("abc"));
+    assert_eq!(err.code, jsonrpc::INVALID_REQUEST);
+
+    let value = json::object! {
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "opensrf.system.echo",
+        "params": {"a": 1},
+    };
+    let (_, err) = jsonrpc::JsonRpcRequest::from_json_value(value).unwrap_err();
+
+    assert_eq!(err.code, jsonrpc::INVALID_PARAMS);
+
+    let resp = jsonrpc::error_response(json::from(1), &err);
+
+    assert_eq!(resp["jsonrpc"], "2.0");
+    assert_eq!(resp["error"]["code"], jsonrpc::INVALID_PARAMS);
+}
+
+#[test]
+fn responder_collects_values() {
+    fn find_rows(responder: &mut impl Responder) -> eg::EgResult<()> {
+        for id in 1..=3 {
+            responder.respond(eg::hash! {"id": id})?;
+        }
+        Ok(())
+    }
+
+    let mut rows: Vec<eg::EgValue> = Vec::new();
+    find_rows(&mut rows).unwrap();
+
+    let ids: Vec<i64> = rows.iter().map(|r| r.id().unwrap()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+}
+
+#[test]
+fn editor_scalar_from_rows() {
+    use crate::editor::scalar_from_rows;
+
+    let value = scalar_from_rows(vec![eg::hash! {"count": 12}]).unwrap();
+    assert_eq!(value.int().unwrap(), 12);
+
+    // Numeric strings from the database are accepted.
+    let value = scalar_from_rows(vec![eg::hash! {"total": "4.50"}]).unwrap();
+    assert_eq!(value.float().unwrap(), 4.5);
+
+    // e.g. a sum over no rows.
+    let value = scalar_from_rows(vec![eg::hash! {"total": eg::EgValue::Null}]).unwrap();
+    assert!(value.float().is_err());
+
+    let err = scalar_from_rows(vec![]).unwrap_err();
+    assert!(err.to_string().contains("returned 0 rows"));
+
+    let rows = vec![eg::hash! {"count": 1}, eg::hash! {"count": 2}];
+    let err = scalar_from_rows(rows).unwrap_err();
+    assert!(err.to_string().contains("returned 2 rows"));
+
+    let err = scalar_from_rows(vec![eg::hash! {"id": 1, "count": 2}]).unwrap_err();
+    assert!(err.to_string().contains("2 columns"));
+
+    assert!(scalar_from_rows(vec![eg::EgValue::from(5)]).is_err());
+}
+
+#[test]
+fn method_call_deadline() {
+    fn handler(
+        _: &mut Box<dyn crate::osrf::app::ApplicationWorker>,
+        _: &mut crate::osrf::session::ServerSession,
+        _: &crate::osrf::message::MethodCall,
+    ) -> eg::EgResult<()> {
+        Ok(())
+    }
+
+    let mut def = method::MethodDef::new("eg.test.timeout", method::ParamCount::Zero, handler);
+    assert_eq!(def.timeout(), None);
+
+    // No timeout means no deadline.
+    method::set_call_deadline(def.timeout());
+    assert_eq!(method::call_time_remaining(), None);
+    assert!(!method::call_deadline_exceeded());
+
+    def.set_timeout(std::time::Duration::from_secs(30));
+    method::set_call_deadline(def.timeout());
+
+    let remaining = method::call_time_remaining().unwrap();
+    assert!(remaining > std::time::Duration::from_secs(25));
+    assert!(remaining <= std::time::Duration::from_secs(30));
+    assert!(!method::call_deadline_exceeded());
+
+    method::set_call_deadline(Some(std::time::Duration::ZERO));
+    assert!(method::call_deadline_exceeded());
+    assert!(method::check_call_deadline().is_err());
+
+    method::clear_call_deadline();
+    assert!(method::check_call_deadline().is_ok());
+}
+
+#[test]
+fn method_catch_panic() {
+    fn panicking_handler(value: &eg::EgValue) -> eg::EgResult<()> {
+        let num = value.as_int().unwrap();
+        assert!(num > 0);
+        todo!()
+    }
+
+    let result = method::catch_panic("eg.test.todo", || panicking_handler(&5.into()));
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("eg.test.todo"));
+    assert!(err.contains("not yet implemented"));
+
+    // String payload, e.g. from a failed unwrap().
+    let result = method::catch_panic("eg.test.unwrap", || panicking_handler(&"x".into()));
+    assert!(result.unwrap_err().to_string().contains("eg.test.unwrap"));
+
+    // The thread lives on to handle the next call.
+    assert!(method::catch_panic("eg.test.ok", || Ok(())).is_ok());
+
+    let result = method::catch_panic("eg.test.err", || Err("Failed".into()));
+    assert_eq!(result.unwrap_err().to_string(), "Failed");
+}
+
+#[test]
+fn method_def_validate_params() {
+    fn handler(
+        _: &mut Box<dyn crate::osrf::app::ApplicationWorker>,
+        _: &mut crate::osrf::session::ServerSession,
+        _: &crate::osrf::message::MethodCall,
+    ) -> eg::EgResult<()> {
+        Ok(())
+    }
+
+    let mut def =
+        method::MethodDef::new("eg.test.validate", method::ParamCount::Range(1, 2), handler);
+
+    def.add_param(method::Param {
+        name: String::from("Authtoken"),
+        datatype: method::ParamDataType::String,
+        desc: None,
+    });
+
+    def.add_param(method::Param {
+        name: String::from("Options"),
+        datatype: method::ParamDataType::Object,
+        desc: None,
+    });
+
+    assert!(def.param_required(0));
+    assert!(!def.param_required(1));
+
+    assert!(def.validate_params(&["abc".into()]).is_ok());
+    assert!(def
+        .validate_params(&["abc".into(), eg::EgValue::Null])
+        .is_ok());
+
+    let err = def.validate_params(&[]).unwrap_err();
+    assert!(err.contains("Invalid param count"));
+
+    let err = def.validate_params(&[eg::EgValue::Null]).unwrap_err();
+    assert!(err.contains("Missing required param 1 'Authtoken'"));
+
+    let err = def.validate_params(&[5.into()]).unwrap_err();
+    assert!(err.contains("wanted=String got=Number"));
+
+    let err = def
+        .validate_params(&["abc".into(), "xyz".into()])
+        .unwrap_err();
+    assert!(err.contains("param 2 'Options'"));
+
+    assert_eq!(
+        def.to_summary_string(),
+        "eg.test.validate (*'Authtoken','Options')"
+    );
+
+    let value = def.to_eg_value();
+    assert_eq!(value["api_name"].as_str(), Some("eg.test.validate"));
+    assert_eq!(value["argc_min"].as_int(), Some(1));
+    assert_eq!(value["argc_max"].as_int(), Some(2));
+    assert_eq!(value["params"][0]["name"].as_str(), Some("Authtoken"));
+    assert_eq!(value["params"][0]["datatype"].as_str(), Some("String"));
+    assert!(value["params"][0]["required"].boolish());
+    assert!(!value["params"][1]["required"].boolish());
+}
+
+#[test]
+fn idl_partial_load() {
+    let xml = r#"<IDL>
+      <class id="au">
+        <fields><field name="id"/><field name="home_ou"/></fields>
+        <links><link field="home_ou" reltype="has_a" key="id" map="" class="aou"/></links>
+      </class>
+      <class id="aou">
+        <fields><field name="id"/><field name="parent_ou"/></fields>
+        <links><link field="parent_ou" reltype="has_a" key="id" map="" class="aout"/></links>
+      </class>
+      <class id="aout"><fields><field name="id"/></fields></class>
+      <class id="mb"><fields><field name="id"/></fields></class>
+    </IDL>"#;
+
+    let full = idl::Parser::parse_string(xml, None).unwrap();
+    assert_eq!(full.classes().len(), 4);
+
+    // Directly linked classes are kept; links of links are not.
+    let partial = idl::Parser::parse_string(xml, Some(&["au"])).unwrap();
+    let mut names: Vec<&str> = partial.classes().keys().map(|k| k.as_str()).collect();
+    names.sort();
+
+    assert_eq!(names, ["aou", "au"]);
+    assert!(partial.field_exists("au", "home_ou"));
+
+    assert!(idl::Parser::parse_string(xml, Some(&["nope"])).is_err());
+}
+
+#[test]
+fn idl_compiled_round_trip() {
+    let xml = r#"<IDL xmlns:oils_persist="http://open-ils.org/spec/opensrf/IDL/persistence/v1"
+        xmlns:reporter="http://open-ils.org/spec/opensrf/IDL/reporter/v1">
+      <class id="au" oils_persist:tablename="actor.usr" reporter:label="User">
+        <fields oils_persist:primary="id">
+          <field name="id" reporter:datatype="id"/>
+          <field name="home_ou" reporter:datatype="org_unit" reporter:label="Home Library"/>
+          <field name="usrname" reporter:selector="usrname"/>
+          <field name="cards" oils_persist:virtual="true" reporter:datatype="link"/>
+        </fields>
+        <links><link field="home_ou" reltype="has_a" key="id" map="" class="aou"/></links>
+      </class>
+      <class id="aou"><fields><field name="id"/></fields></class>
+    </IDL>"#;
+
+    let parsed = idl::Parser::parse_string(xml, None).unwrap();
+    let compiled = idl::Parser::compile(xml).unwrap();
+    let loaded = idl::Parser::parse_compiled(&compiled, xml).unwrap();
+
+    assert_eq!(loaded.classes().len(), parsed.classes().len());
+
+    for (name, class) in parsed.classes() {
+        assert_eq!(loaded.classes()[name], *class);
+    }
+
+    assert_eq!(loaded.classes()["au"].tablename(), Some("actor.usr"));
+    assert_eq!(loaded.classes()["au"].selector(), Some("usrname"));
+    assert_eq!(loaded.field_names("au"), parsed.field_names("au"));
+
+    // Compiled from a different version of the XML.
+    let newer = xml.replace("usrname", "username");
+    assert!(idl::Parser::parse_compiled(&compiled, &newer).is_err());
+}
+
+#[test]
+fn idl_link_field_queries() {
+    let xml = r#"<IDL>
+      <class id="au">
+        <fields><field name="id"/><field name="home_ou"/><field name="card"/></fields>
+        <links>
+          <link field="home_ou" reltype="has_a" key="id" map="" class="aou"/>
+          <link field="card" reltype="has_a" key="id" map="" class="ac"/>
+        </links>
+      </class>
+      <class id="aou"><fields><field name="id"/></fields></class>
+    </IDL>"#;
+
+    let idl = idl::Parser::parse_string(xml, None).unwrap();
+
+    assert!(idl.class_exists("au"));
+    assert!(!idl.class_exists("mbt"));
+
+    // Includes the auto fields.
+    let fields = idl.field_names("au").unwrap();
+    assert_eq!(&fields[..3], ["id", "home_ou", "card"]);
+    assert!(fields.contains(&"isnew"));
+
+    assert_eq!(idl.link_fields("au").unwrap(), ["card", "home_ou"]);
+    assert!(idl.link_fields("mbt").is_none());
+
+    let ops = eg::hash! {"flesh": 1, "flesh_fields": {"au": ["home_ou", "card"]}};
+    assert!(idl.validate_flesh_fields(&ops).is_ok());
+    assert!(idl.validate_flesh_fields(&eg::EgValue::Null).is_ok());
+
+    let ops = eg::hash! {"flesh": 1, "flesh_fields": {"au": ["home_uo"]}};
+    assert!(idl.validate_flesh_fields(&ops).is_err());
+
+    let ops = eg::hash! {"flesh": 1, "flesh_fields": {"mbt": ["grocery"]}};
+    assert!(idl.validate_flesh_fields(&ops).is_err());
+}
+
+#[test]
+fn date_errors_are_validation() {
+    let err = date::parse_datetime("2023-02").unwrap_err();
+    assert!(matches!(err, EgError::Validation(_)));
+    assert_eq!(err.to_string(), "Invalid date string: 2023-02");
+    assert_eq!(err.message(), Some("Invalid date string: 2023-02"));
+
+    let err = date::validate_interval("1 fortnite").unwrap_err();
+    assert!(matches!(err, EgError::Validation(_)));
+
+    // Non-event errors still map to a server error event.
+    let evt = err.event_or_default();
+    assert_eq!(evt.textcode(), "INTERNAL_SERVER_ERROR");
+    assert!(evt.desc().unwrap_or("").contains("fortnite"));
+
+    let err: EgError = eg::event::EgEvent::new("PROBLEM").into();
+    assert_eq!(err.message(), None);
+}
+
+#[test]
+fn payment_window_across_timezones() {
+    // A payment made 90 minutes ago, stored with a non-local offset.
+    let offset = chrono::FixedOffset::west_opt(9 * 3600).unwrap();
+    let paid = (chrono::Utc::now() - chrono::Duration::minutes(90)).with_timezone(&offset);
+    let payment_ts = paid.format("%Y-%m-%dT%H:%M:%S%z").to_string();
+
+    let payment_dt = date::parse_datetime(&payment_ts).unwrap();
+
+    assert!(date::is_within_interval_of_now(&payment_dt, "2 hours").unwrap());
+    assert!(!date::is_within_interval_of_now(&payment_dt, "1 hour").unwrap());
+    assert!(date::is_within_interval_of_now(&payment_dt, "").is_err());
+}
+
+#[test]
+fn distribute_payment_amounts() {
+    let balances = [(1, 3.00), (2, 0.10), (3, 4.25)];
+
+    // Exact
+    let (splits, leftover) = billing::distribute_payment(7.35, &balances);
+    assert_eq!(splits, [(1, 3.00), (2, 0.10), (3, 4.25)]);
+    assert_eq!(leftover, 0.0);
+
+    // Under
+    let (splits, leftover) = billing::distribute_payment(3.05, &balances);
+    assert_eq!(splits, [(1, 3.00), (2, 0.05)]);
+    assert_eq!(leftover, 0.0);
+
+    // Over
+    let (splits, leftover) = billing::distribute_payment(10.00, &balances);
+    assert_eq!(splits, [(1, 3.00), (2, 0.10), (3, 4.25)]);
+    assert_eq!(leftover, 2.65);
+
+    // Nothing owed
+    let (splits, leftover) = billing::distribute_payment(1.00, &[(1, 0.0)]);
+    assert!(splits.is_empty());
+    assert_eq!(leftover, 1.00);
+}
+
+fn payment_mapping_summary(
+    bills: Vec<eg::EgValue>,
+    payments: Vec<eg::EgValue>,
+    mapping: billing::PaymentMapping,
+) -> Vec<(i64, f64, Vec<i64>)> {
+    billing::map_payments_to_bills(bills, payments, mapping)
+        .unwrap()
+        .iter()
+        .map(|m| {
+            let ids = m.payments.iter().map(|p| p.id().unwrap()).collect();
+            (m.bill.id().unwrap(), m.paid_amount, ids)
+        })
+        .collect()
+}
+
+#[test]
+fn deterministic_payment_mapping() {
+    let bills = vec![
+        eg::hash! {"id": 1, "billing_ts": "2024-01-01T10:00:00-0500", "amount": 2.0},
+        eg::hash! {"id": 2, "billing_ts": "2024-01-01T10:00:00-0500", "amount": 3.0},
+    ];
+
+    let payments = vec![
+        eg::hash! {"id": 10, "payment_ts": "2024-01-02T10:00:00-0500",
+        "amount": 3.0, "payment_type": "cash_payment"},
+        eg::hash! {"id": 11, "payment_ts": "2024-01-03T10:00:00-0500",
+        "amount": 1.0, "payment_type": "cash_payment"},
+    ];
+
+    let mut reversed_bills = bills.clone();
+    reversed_bills.reverse();
+    let mut reversed_payments = payments.clone();
+    reversed_payments.reverse();
+
+    let mapping = billing::PaymentMapping::Deterministic;
+    let expected = vec![(1, 2.0, vec![10]), (2, 2.0, vec![10, 11])];
+
+    // Same results regardless of input order.
+    assert_eq!(
+        payment_mapping_summary(bills.clone(), payments.clone(), mapping),
+        expected
+    );
+    assert_eq!(
+        payment_mapping_summary(reversed_bills, reversed_payments, mapping),
+        expected
+    );
+
+    // Matching amounts are paired first.
+    let mapping = billing::PaymentMapping::AmountMatch;
+    assert_eq!(
+        payment_mapping_summary(bills, payments, mapping),
+        vec![(1, 1.0, vec![11]), (2, 3.0, vec![10])]
+    );
+}
+
+#[test]
+fn order_by_validates_fields() {
+    let xml = r#"<IDL>
+      <class id="mp">
+        <fields><field name="id"/><field name="payment_ts"/></fields>
+      </class>
+    </IDL>"#;
+
+    let idl = idl::Parser::parse_string(xml, None).unwrap();
+
+    let order = OrderBy::new("mp", "payment_ts").desc().then("id");
+    assert!(order.validate(&idl).is_ok());
+
+    let value = order.to_value_unchecked();
+    assert_eq!(value[0]["direction"].as_str(), Some("desc"));
+    assert_eq!(value[1]["field"].as_str(), Some("id"));
+    assert_eq!(value[1]["direction"].as_str(), Some("asc"));
+
+    assert!(OrderBy::new("mp", "payment_tz").validate(&idl).is_err());
+    assert!(OrderBy::new("mb", "id").validate(&idl).is_err());
+}
+
+#[test]
+fn amount_match_payment_sort() {
+    let mapping = billing::PaymentMapping::AmountMatch;
+
+    let bills = vec![
+        eg::hash! {"id": 1, "billing_ts": "2024-01-01T10:00:00-0500", "amount": 6.0},
+        eg::hash! {"id": 2, "billing_ts": "2024-01-01T11:00:00-0500", "amount": 5.0},
+    ];
+
+    let payments = vec![
+        eg::hash! {"id": 10, "amount": "5.01", "payment_type": "cash_payment"},
+        eg::hash! {"id": 11, "amount": "5.99", "payment_type": "cash_payment"},
+    ];
+
+    // The $5.99 payment is applied first.
+    assert_eq!(
+        payment_mapping_summary(bills, payments, mapping),
+        vec![(1, 6.0, vec![11, 10]), (2, 5.0, vec![10])]
+    );
+
+    let bills = vec![eg::hash! {"id": 1, "amount": 4.0}];
+
+    let payments = vec![
+        eg::hash! {"id": 21, "amount": 3.0, "payment_type": "cash_payment"},
+        eg::hash! {"id": 20, "amount": 3.0, "payment_type": "cash_payment"},
+    ];
+
+    // Equal amounts are applied in ID order.
+    assert_eq!(
+        payment_mapping_summary(bills, payments, mapping),
+        vec![(1, 4.0, vec![20, 21])]
+    );
+}
+
+#[test]
+fn billing_type_round_trip() {
+    use crate::constants::{self as C, BillingType};
+
+    for id in C::BTYPE_OVERDUE_MATERIALS..=C::BTYPE_LONG_OVERDUE_MATERIALS_PROCESSING_FEE {
+        let btype = BillingType::try_from(id).expect("Stock billing type");
+
+        assert_eq!(i64::from(btype), id);
+        assert_eq!(BillingType::try_from(btype.label()).ok(), Some(btype));
+    }
+
+    assert!(matches!(
+        BillingType::try_from(0),
+        Err(EgError::NotFound(_))
+    ));
+}
+
+#[test]
+fn fixed_clock_freezes_now() {
+    let frozen = date::parse_datetime("2024-03-01T12:00:00-0500").unwrap();
+
+    date::set_thread_clock(date::FixedClock(frozen));
+
+    assert_eq!(date::now(), frozen);
+    assert_eq!(date::now_local(), frozen);
+
+    let paid = date::parse_datetime("2024-03-01T16:30:00+0000").unwrap();
+
+    assert!(date::is_within_interval_of_now(&paid, "1 hour").unwrap());
+    assert!(!date::is_within_interval_of_now(&paid, "15 minutes").unwrap());
+
+    // Other threads still see the system clock.
+    let other = std::thread::spawn(date::now).join().unwrap();
+    assert!(other > frozen);
+
+    date::reset_thread_clock();
+    assert!(date::now() > frozen);
+}
+
+#[test]
+fn idl_read_only_class() {
+    let xml = r#"<IDL xmlns:oils_persist="http://open-ils.org/spec/opensrf/IDL/persistence/v1">
+      <class id="mbts" oils_persist:readonly="true">
+        <fields><field name="id"/></fields>
+      </class>
+      <class id="mb"><fields><field name="id"/></fields></class>
+    </IDL>"#;
+
+    let parser = idl::Parser::parse_string(xml, None).unwrap();
+
+    assert!(parser.classes()["mbts"].is_read_only());
+    assert!(!parser.classes()["mb"].is_read_only());
+}
@@ -233,6 +233,50 @@ pub fn fpsum(a: f64, b: f64) -> f64 {
     ((a * 100.00) + (b * 100.00)) / 100.00
 }
 
+/// Round a money amount to the nearest cent.
+///
+/// ```
+/// use evergreen::util;
+/// assert_eq!(util::round_to_cents(1.234), 1.23);
+/// assert_eq!(util::round_to_cents(0.1 + 0.2), 0.3);
+/// assert_eq!(util::round_to_cents(-2.675), -2.68);
+/// ```
+pub fn round_to_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+/// Calculate percent percent of amount, rounded to the nearest cent.
+///
+/// ```
+/// use evergreen::util;
+/// assert_eq!(util::percent_of(10.00, 33.0), 3.3);
+/// assert_eq!(util::percent_of(19.99, 10.0), 2.0);
+/// assert_eq!(util::percent_of(25.00, 150.0), 37.5);
+/// assert_eq!(util::percent_of(10.00, 0.0), 0.0);
+/// ```
+pub fn percent_of(amount: f64, percent: f64) -> f64 {
+    round_to_cents(amount * percent / 100.0)
+}
+
+/// Calculate what percentage part is of whole.
+///
+/// Returns 0.0 when whole is zero instead of NaN or infinity.
+///
+/// ```
+/// use evergreen::util;
+/// assert_eq!(util::what_percent(2.5, 10.0), 25.0);
+/// assert_eq!(util::what_percent(15.0, 10.0), 150.0);
+/// assert_eq!(util::what_percent(5.0, 0.0), 0.0);
+/// assert_eq!(util::what_percent(0.0, 0.0), 0.0);
+/// ```
+pub fn what_percent(part: f64, whole: f64) -> f64 {
+    if whole == 0.0 {
+        return 0.0;
+    }
+
+    part / whole * 100.0
+}
+
 /// "check", "create", "delete" a lockfile
 pub fn lockfile(path: &str, action: &str) -> EgResult<bool> {
     match action {
@@ -334,6 +378,69 @@ pub fn tcp_listener(address: &str, port: u16, read_timeout: u64) -> EgResult<Tcp
     Ok(socket.into())
 }
 
+/// Returns the value as an i64 if it's a number or numeric string,
+/// None otherwise.
+///
+/// Useful when iterating large result sets where a bad row should
+/// be skipped instead of aborting the whole operation.
+///
+/// ```
+/// use evergreen::util;
+/// use evergreen::EgValue;
+/// assert_eq!(util::try_int(&EgValue::from(12)), Some(12));
+/// assert_eq!(util::try_int(&EgValue::from("12")), Some(12));
+/// assert_eq!(util::try_int(&EgValue::from("twelve")), None);
+/// assert_eq!(util::try_int(&EgValue::Null), None);
+/// ```
+pub fn try_int(v: &EgValue) -> Option<i64> {
+    v.as_int()
+}
+
+/// Returns the value as an f64 if it's a number or numeric string,
+/// None otherwise.
+///
+/// ```
+/// use evergreen::util;
+/// use evergreen::EgValue;
+/// assert_eq!(util::try_float(&EgValue::from(1.5)), Some(1.5));
+/// assert_eq!(util::try_float(&EgValue::from("1.50")), Some(1.5));
+/// assert_eq!(util::try_float(&EgValue::Null), None);
+/// ```
+pub fn try_float(v: &EgValue) -> Option<f64> {
+    v.as_float()
+}
+
+/// Returns the value as a &str if it's a string, None otherwise.
+///
+/// ```
+/// use evergreen::util;
+/// use evergreen::EgValue;
+/// assert_eq!(util::try_str(&EgValue::from("hello")), Some("hello"));
+/// assert_eq!(util::try_str(&EgValue::from(12)), None);
+/// assert_eq!(util::try_str(&EgValue::Null), None);
+/// ```
+pub fn try_str(v: &EgValue) -> Option<&str> {
+    v.as_str()
+}
+
+/// Returns the value as an owned String, stringifying numbers.
+///
+/// Unlike `value.as_str().unwrap()`, this returns an Err containing
+/// the offending value instead of panicking when the value is null
+/// or otherwise not stringifiable.
+///
+/// ```
+/// use evergreen::util;
+/// use evergreen::EgValue;
+/// assert_eq!(util::json_string(&EgValue::from("2023-01-01")).unwrap(), "2023-01-01");
+/// assert_eq!(util::json_string(&EgValue::from(42)).unwrap(), "42");
+/// assert!(util::json_string(&EgValue::Null).is_err());
+/// ```
+pub fn json_string(v: &EgValue) -> EgResult<String> {
+    v.to_string()
+        .ok_or_else(|| format!("Expected a string value, found: {}", v.dump()).into())
+}
+
 #[deprecated(note = "See EgValue::as_int()")]
 pub fn json_int(v: &EgValue) -> EgResult<i64> {
     Ok(v.as_int()
@@ -344,3 +451,216 @@ pub fn json_int(v: &EgValue) -> EgResult<i64> {
 pub fn json_bool(v: &EgValue) -> bool {
     v.boolish()
 }
+
+/// Returns the (grouping separator, decimal separator, symbol-follows-amount)
+/// conventions for a locale string like "en-US" or "fr_CA".
+fn money_conventions(locale: &str) -> (&'static str, &'static str, bool) {
+    let lang = locale
+        .split(&['-', '_', '.'])
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+
+    match lang.as_str() {
+        "fr" => ("\u{a0}", ",", true),
+        "de" | "es" | "it" | "nl" | "pt" => (".", ",", true),
+        _ => (",", ".", false),
+    }
+}
+
+/// Currency symbol for a 3-letter ISO currency code.
+///
+/// Unknown codes are returned as-is.
+fn currency_symbol(currency: &str) -> &str {
+    match currency.to_uppercase().as_str() {
+        "USD" | "CAD" | "AUD" | "NZD" | "MXN" => "$",
+        "EUR" => "€",
+        "GBP" => "£",
+        "JPY" => "¥",
+        _ => currency,
+    }
+}
+
+/// Format a money amount for display using the symbol, digit grouping,
+/// and decimal separator appropriate for the currency and locale.
+///
+/// Amounts are rounded to 2 decimal places.
+///
+/// ```
+/// use evergreen::util;
+/// assert_eq!(util::format_money(1234.5, "USD", "en-US"), "$1,234.50");
+/// assert_eq!(util::format_money(-0.25, "USD", "en-US"), "-$0.25");
+/// assert_eq!(util::format_money(1234567.891, "EUR", "de-DE"), "1.234.567,89 €");
+/// assert_eq!(util::format_money(12.0, "CAD", "fr-CA"), "12,00 $");
+/// ```
+pub fn format_money(amount: f64, currency: &str, locale: &str) -> String {
+    let (group_sep, dec_sep, symbol_after) = money_conventions(locale);
+    let symbol = currency_symbol(currency);
+
+    let cents = (amount.abs() * 100.0).round() as u64;
+    let whole = (cents / 100).to_string();
+    let frac = cents % 100;
+
+    let mut grouped = String::new();
+    for (idx, c) in whole.chars().enumerate() {
+        if idx > 0 && (whole.len() - idx).is_multiple_of(3) {
+            grouped += group_sep;
+        }
+        grouped.push(c);
+    }
+
+    let sign = if amount < 0.0 && cents > 0 { "-" } else { "" };
+
+    if symbol_after {
+        format!("{sign}{grouped}{dec_sep}{frac:02} {symbol}")
+    } else {
+        format!("{sign}{symbol}{grouped}{dec_sep}{frac:02}")
+    }
+}
+
+/// Parse a money string, as produced by format_money() or entered by
+/// staff, into an f64, ignoring currency symbols and digit grouping.
+///
+/// When both "," and "." are present, whichever appears last is
+/// treated as the decimal separator.  A lone "," is treated as the
+/// decimal separator when it's followed by 1 or 2 digits.
+///
+/// ```
+/// use evergreen::util;
+/// assert_eq!(util::parse_money("$1,234.50").unwrap(), 1234.5);
+/// assert_eq!(util::parse_money("1.234.567,89 €").unwrap(), 1234567.89);
+/// assert_eq!(util::parse_money("12,00 $").unwrap(), 12.0);
+/// assert_eq!(util::parse_money("-$0.25").unwrap(), -0.25);
+/// assert_eq!(util::parse_money("1,000").unwrap(), 1000.0);
+/// assert!(util::parse_money("twelve").is_err());
+/// ```
+pub fn parse_money(value: &str) -> EgResult<f64> {
+    let cleaned: String = value
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '-' || *c == '.' || *c == ',')
+        .collect();
+
+    let last_comma = cleaned.rfind(',');
+    let last_dot = cleaned.rfind('.');
+
+    let decimal = match (last_comma, last_dot) {
+        (Some(c), Some(d)) => {
+            if c > d {
+                Some(',')
+            } else {
+                Some('.')
+            }
+        }
+        (Some(c), None) => {
+            let trailing = cleaned.len() - c - 1;
+            if cleaned.matches(',').count() == 1 && (trailing == 1 || trailing == 2) {
+                Some(',')
+            } else {
+                None
+            }
+        }
+        (None, Some(_)) => {
+            if cleaned.matches('.').count() == 1 {
+                Some('.')
+            } else {
+                // e.g. 1.234.567
+                None
+            }
+        }
+        (None, None) => None,
+    };
+
+    let normalized: String = cleaned
+        .chars()
+        .filter_map(|c| match c {
+            ',' | '.' if Some(c) == decimal => Some('.'),
+            ',' | '.' => None,
+            _ => Some(c),
+        })
+        .collect();
+
+    normalized
+        .parse::<f64>()
+        .map_err(|e| format!("Invalid money value '{value}': {e}").into())
+}
+
+/// Parse a number written with the decimal separator and digit
+/// grouping of a locale, e.g. "1.234,5" for "de-DE".
+///
+/// EgValue::float() is strict and only accepts "." decimals with no
+/// grouping, which is what the database and API clients send.  Use
+/// this instead for data entered or exported in a known locale, e.g.
+/// records migrated from an Evergreen instance which stored "1,50".
+///
+/// Grouping separators are only accepted every 3 digits before the
+/// decimal separator, so a value written for a different locale is
+/// rejected instead of misread.  Numbers are returned as-is.
+///
+/// ```
+/// use evergreen::util;
+/// use evergreen::EgValue;
+///
+/// let v = |s: &str| EgValue::from(s);
+///
+/// assert_eq!(util::json_float_locale(&v("1,50"), "de-DE").unwrap(), 1.5);
+/// assert_eq!(util::json_float_locale(&v("-1.234,5"), "de-DE").unwrap(), -1234.5);
+/// assert_eq!(util::json_float_locale(&v("1 234,5"), "fr-CA").unwrap(), 1234.5);
+/// assert_eq!(util::json_float_locale(&v("1,234.5"), "en-US").unwrap(), 1234.5);
+/// assert_eq!(util::json_float_locale(&EgValue::from(2.5), "de-DE").unwrap(), 2.5);
+///
+/// assert!(util::json_float_locale(&v("1,234.5"), "de-DE").is_err());
+/// assert!(util::json_float_locale(&v("12,34,5"), "en-US").is_err());
+/// assert!(util::json_float_locale(&v("1,50"), "en-US").is_err());
+/// assert!(util::json_float_locale(&EgValue::Null, "de-DE").is_err());
+///
+/// // The default stays strict.
+/// assert!(v("1,50").float().is_err());
+/// ```
+pub fn json_float_locale(value: &EgValue, locale: &str) -> EgResult<f64> {
+    let text = match value {
+        EgValue::Number(_) => return value.float(),
+        EgValue::String(s) => s.trim(),
+        _ => return Err(format!("{value} is not a float").into()),
+    };
+
+    let invalid = || format!("Invalid number '{text}' for locale {locale}");
+
+    let (group_sep, dec_sep, _) = money_conventions(locale);
+
+    // Spaces of any width group digits where the convention is a
+    // non-breaking space.
+    let is_group =
+        |c: char| group_sep.contains(c) || (group_sep == "\u{a0}" && (c == ' ' || c == '\u{202f}'));
+
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(t) => ("-", t),
+        None => ("", text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let (whole, frac) = match unsigned.split_once(dec_sep) {
+        Some((w, f)) => (w, f),
+        None => (unsigned, ""),
+    };
+
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+
+    if !is_digits(frac) || (whole.is_empty() && frac.is_empty()) {
+        return Err(invalid().into());
+    }
+
+    let groups: Vec<&str> = whole.split(is_group).collect();
+
+    let grouped_ok = groups.len() == 1
+        || (!groups[0].is_empty()
+            && groups[0].len() <= 3
+            && groups[1..].iter().all(|g| g.len() == 3));
+
+    if !grouped_ok || !groups.iter().all(|g| is_digits(g)) {
+        return Err(invalid().into());
+    }
+
+    format!("{sign}{}.{frac}", groups.concat())
+        .trim_end_matches('.')
+        .parse::<f64>()
+        .map_err(|_| invalid().into())
+}
@@ -557,13 +557,58 @@ impl EgValue {
     pub fn insert(&mut self, key: &str, value: impl Into<EgValue>) -> EgResult<()> {
         match self {
             EgValue::Hash(ref mut o) => o.insert(key.to_string(), value.into()),
-            EgValue::Blessed(ref mut o) => o.values.insert(key.to_string(), value.into()),
+            EgValue::Blessed(ref mut o) => {
+                // Catch typos in field names during development
+                // instead of waiting for the backend to reject them.
+                #[cfg(debug_assertions)]
+                if !o.idl_class.has_field(key) {
+                    log::warn!(
+                        "insert() on IDL class {} with undeclared field {key}",
+                        o.idl_class.classname()
+                    );
+                }
+                o.values.insert(key.to_string(), value.into())
+            }
             _ => return Err(format!("{self} Cannot call insert() on a non-object type").into()),
         };
 
         Ok(())
     }
 
+    /// Returns a ref to the value stored in the named field.
+    ///
+    /// Unlike indexing, which panics, this returns an Err if the
+    /// value is Blessed and its IDL class has no such field.
+    pub fn get_field(&self, field: &str) -> EgResult<&EgValue> {
+        if let EgValue::Blessed(ref o) = self {
+            if !o.idl_class.has_field(field) {
+                return Err(
+                    format!("IDL class {} has no field {field}", o.idl_class.classname()).into(),
+                );
+            }
+        }
+
+        Ok(&self[field])
+    }
+
+    /// Set the value of the named field.
+    ///
+    /// Unlike indexing, which panics, this returns an Err if the
+    /// value is Blessed and its IDL class has no such field.
+    pub fn set_field(&mut self, field: &str, value: impl Into<EgValue>) -> EgResult<()> {
+        if let EgValue::Blessed(ref o) = self {
+            if !o.idl_class.has_field(field) {
+                return Err(
+                    format!("IDL class {} has no field {field}", o.idl_class.classname()).into(),
+                );
+            }
+        }
+
+        self[field] = value.into();
+
+        Ok(())
+    }
+
     /// True if this is a Hash or Blessed value which contains the
     /// provided key.
     /// ```
@@ -1,7 +1,10 @@
 use crate::util;
 use eg::common::auth;
+use eg::common::user::{self, PasswordMatch};
 use eg::osrf::cache::Cache;
+use eg::osrf::sclient::HostSettings;
 use eg::EgResult;
+use eg::EgValue;
 use evergreen as eg;
 
 /// Values from EG's opensrf.xml.example
@@ -10,6 +13,7 @@ const DEFAULT_STAFF_LOGIN_DURATION: u32 = 7200;
 const DEFAULT_TEMP_LOGIN_DURATION: u32 = 300;
 const DEFAULT_PERSIST_LOGIN_DURATION: u32 = 2 * 604800; // "2 weeks"
 const NO_ORG_UNIT: i64 = 0;
+const TEST_PASSWORD: &str = "_EG_TEST_PASSWORD_";
 
 pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
     tester.timer.start();
@@ -71,5 +75,238 @@ pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
     assert!(auth::Session::from_cache(ses2.token())?.is_none());
     tester.timer.log("Removed session from cache");
 
+    let ses = auth::Session::internal_session(&mut tester.editor, &args)?;
+    assert_eq!(auth::Session::refresh(ses.token())?, Some(staff));
+    assert_eq!(auth::Session::refresh(ses.token())?, Some(staff));
+    tester.timer.log("Refreshed session");
+
+    assert!(auth::Session::delete(ses.token())?);
+    assert!(auth::Session::from_cache(ses.token())?.is_none());
+
+    // Deleting a session that's already gone is harmless.
+    assert!(!auth::Session::delete(ses.token())?);
+    assert!(auth::Session::refresh(ses.token())?.is_none());
+    tester.timer.log("Deleted session");
+
+    password_upgrade(tester)?;
+    login_password_lockout(tester)?;
+    method_catalog(tester)?;
+
+    Ok(())
+}
+
+/// Store a password using Evergreen's migrated md5 scheme.
+///
+/// Returns the md5(salt + md5(password)) digest which was stored.
+fn set_legacy_password(tester: &mut util::Tester, user_id: i64) -> EgResult<String> {
+    let e = &mut tester.editor;
+
+    let query = eg::hash! {from: ["actor.create_salt", user::PW_TYPE_MAIN]};
+    let salt = e.json_query(query)?[0]["actor.create_salt"]
+        .str()?
+        .to_string();
+
+    let md5_pass = format!("{:x}", md5::compute(TEST_PASSWORD));
+    let legacy = format!("{:x}", md5::compute(format!("{salt}{md5_pass}")));
+
+    let query = eg::hash! {
+        from: ["actor.set_passwd", user_id, user::PW_TYPE_MAIN, legacy.as_str(), salt]
+    };
+
+    e.json_query(query)?;
+
+    Ok(legacy)
+}
+
+/// Create the password type described by user::PW_UPGRADE_SETTING
+/// if the database does not have it yet.
+///
+/// Returns the type if we created it, so it can be removed.
+fn create_upgraded_pw_type(tester: &mut util::Tester) -> EgResult<Option<EgValue>> {
+    let e = &mut tester.editor;
+
+    if e.retrieve("cpt", user::PW_TYPE_UPGRADED)?.is_some() {
+        return Ok(None);
+    }
+
+    let pw_type = eg::hash! {
+        "code": user::PW_TYPE_UPGRADED,
+        "name": "Upgraded Main Password",
+        "crypt_algo": "bf",
+        "iter_count": 12,
+    };
+
+    e.xact_begin()?;
+    let pw_type = e.create(EgValue::create("cpt", pw_type)?)?;
+    e.commit()?;
+
+    Ok(Some(pw_type))
+}
+
+fn password_upgrade(tester: &mut util::Tester) -> EgResult<()> {
+    let pw_type = create_upgraded_pw_type(tester)?;
+
+    tester.editor.xact_begin()?;
+    let user_id = tester.samples.create_default_au(&mut tester.editor)?.id()?;
+    let legacy = set_legacy_password(tester, user_id)?;
+    tester.editor.commit()?;
+
+    let e = &mut tester.editor;
+
+    assert_eq!(
+        user::check_password(e, user_id, TEST_PASSWORD)?,
+        PasswordMatch::Legacy
+    );
+    assert_eq!(
+        user::check_password(e, user_id, "wrong")?,
+        PasswordMatch::NoMatch
+    );
+
+    // The stored digest is no stand-in for the password.
+    assert_eq!(
+        user::check_password(e, user_id, &legacy)?,
+        PasswordMatch::NoMatch
+    );
+    tester.timer.log("Verified legacy password");
+
+    // Upgrades are opt-in.
+    e.xact_begin()?;
+    assert!(user::verify_password_with_upgrade(
+        e,
+        user_id,
+        TEST_PASSWORD
+    )?);
+    e.commit()?;
+
+    assert!(!user::has_password_type(
+        e,
+        user_id,
+        user::PW_TYPE_UPGRADED
+    )?);
+
+    let setting = eg::hash! {
+        "org_unit": tester.samples.aou_id,
+        "name": user::PW_UPGRADE_SETTING,
+        "value": "true",
+    };
+
+    e.xact_begin()?;
+    let setting = e.create(EgValue::create("aous", setting)?)?;
+    e.commit()?;
+
+    e.xact_begin()?;
+    assert!(user::verify_password_with_upgrade(
+        e,
+        user_id,
+        TEST_PASSWORD
+    )?);
+    e.commit()?;
+    tester.timer.log("Upgraded legacy password on login");
+
+    assert_eq!(
+        user::check_password(e, user_id, TEST_PASSWORD)?,
+        PasswordMatch::Upgraded
+    );
+    assert_eq!(
+        user::check_password(e, user_id, "wrong")?,
+        PasswordMatch::NoMatch
+    );
+    assert_eq!(
+        user::check_password(e, user_id, &legacy)?,
+        PasswordMatch::NoMatch
+    );
+
+    // The legacy credential still works for the Perl auth stack.
+    assert!(user::verify_migrated_password(
+        e,
+        user_id,
+        TEST_PASSWORD,
+        false
+    )?);
+
+    // Verifying an upgraded password leaves it as is.
+    e.xact_begin()?;
+    assert!(user::verify_password_with_upgrade(
+        e,
+        user_id,
+        TEST_PASSWORD
+    )?);
+    e.commit()?;
+
+    assert_eq!(
+        user::check_password(e, user_id, TEST_PASSWORD)?,
+        PasswordMatch::Upgraded
+    );
+    tester.timer.log("Verified upgraded password");
+
+    e.xact_begin()?;
+    e.delete(setting)?;
+    tester.samples.delete_default_au(e)?;
+    if let Some(t) = pw_type {
+        e.delete(t)?;
+    }
+    e.commit()?;
+
+    Ok(())
+}
+
+/// Repeated failed password checks block further checks for the
+/// user, even with the right password.
+fn login_password_lockout(tester: &mut util::Tester) -> EgResult<()> {
+    let block_count = HostSettings::get("apps/open-ils.auth/app_settings/auth_limits/block_count")?
+        .as_int()
+        .unwrap_or(10);
+
+    tester.editor.xact_begin()?;
+    let user_id = tester.samples.create_default_au(&mut tester.editor)?.id()?;
+    set_legacy_password(tester, user_id)?;
+    tester.editor.commit()?;
+
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    assert!(auth::verify_login_password(e, user_id, TEST_PASSWORD)?);
+
+    for _ in 0..block_count {
+        assert!(!auth::verify_login_password(e, user_id, "wrong")?);
+    }
+
+    assert!(!auth::verify_login_password(e, user_id, TEST_PASSWORD)?);
+
+    tester.samples.delete_default_au(e)?;
+    e.commit()?;
+
+    tester
+        .timer
+        .log("Blocked password checks after failed logins");
+
+    Ok(())
+}
+
+fn method_catalog(tester: &mut util::Tester) -> EgResult<()> {
+    let service = "open-ils.rs-auth-internal";
+
+    let methods = tester
+        .client
+        .send_recv_iter(service, "opensrf.system.method.all", None)?
+        .collect::<EgResult<Vec<EgValue>>>()?;
+
+    let create = methods
+        .iter()
+        .find(|m| m["api_name"].as_str() == Some("open-ils.rs-auth-internal.session.create"))
+        .expect("session.create should be published");
+
+    assert_eq!(create["argc_min"].as_int(), Some(1));
+    assert_eq!(create["params"][0]["datatype"].as_str(), Some("Object"));
+    assert!(create["params"][0]["required"].boolish());
+
+    // The introspection methods describe themselves too.
+    assert!(methods
+        .iter()
+        .any(|m| m["api_name"].as_str() == Some("opensrf.system.method.all")));
+
+    tester.timer.log("Fetched Method Catalog");
+
     Ok(())
 }
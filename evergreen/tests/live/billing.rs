@@ -0,0 +1,1331 @@
+use crate::util;
+use chrono::{Datelike, Weekday};
+use eg::common::billing;
+use eg::common::circulator::Circulator;
+use eg::common::hours;
+use eg::common::org;
+use eg::common::penalty;
+use eg::common::settings::Settings;
+use eg::constants as C;
+use eg::date;
+use eg::EgResult;
+use eg::EgValue;
+use evergreen as eg;
+use std::collections::HashMap;
+
+const CLOSED_REASON: &str = "_EG_TEST_";
+const DAY_OF_SECONDS: i64 = 86400;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    delete_test_assets(tester)?;
+
+    extend_grace_into_closed_dates(tester)?;
+    tester
+        .timer
+        .log("extend_grace_period() across closed dates");
+
+    explain_grace_into_closed_dates(tester)?;
+    tester.timer.log("explain_grace_extension()");
+
+    extend_grace_never_open(tester)?;
+    tester
+        .timer
+        .log("extend_grace_period() for an always-closed branch");
+
+    extend_grace_partial_closures(tester)?;
+    tester
+        .timer
+        .log("extend_grace_period() with partial-day closures");
+
+    extend_grace_midnight_closed_end(tester)?;
+    tester
+        .timer
+        .log("extend_grace_period() with a closed date ending at midnight");
+
+    util::login(tester)?;
+    delete_circ_assets(tester)?;
+    create_circ_assets(tester)?;
+
+    let circ_id = fines_skip_closed_weekends(tester)?;
+    tester
+        .timer
+        .log("generate_fines_for_circ() skips closed weekends");
+
+    fines_for_circs_batch(tester, circ_id)?;
+    tester
+        .timer
+        .log("generate_fines_for_circs() continues past failures");
+
+    fines_for_circs_batch_repeat(tester, circ_id)?;
+    tester
+        .timer
+        .log("generate_fines_for_circs() run twice on open days");
+
+    next_fine_time_predicted(tester, circ_id)?;
+    tester.timer.log("next_fine_time() for an overdue circ");
+
+    fetch_xact_summary_matches(tester, circ_id)?;
+    tester.timer.log("Editor::fetch_xact_summary()");
+
+    max_fine_already_met(tester, circ_id)?;
+    tester
+        .timer
+        .log("generate_fines_for_circ() with max fine met");
+
+    void_skips_adjusted_bills(tester, circ_id)?;
+    tester
+        .timer
+        .log("void_bills() skips adjusted-to-zero bills");
+
+    void_with_reason_code(tester, circ_id)?;
+    tester.timer.log("void_bills() with a reason code");
+
+    void_summary_counts(tester, circ_id)?;
+    tester.timer.log("void_bills_with_summary()");
+
+    void_without_penalty_recalc(tester, circ_id)?;
+    tester
+        .timer
+        .log("void_bills_with_recalc() defers penalties");
+
+    void_bills_of_type_for_user(tester, circ_id)?;
+    tester.timer.log("void_or_zero_bills_of_type_for_user()");
+
+    void_as_system_user(tester, circ_id)?;
+    tester.timer.log("void_bills() with only a system user");
+
+    void_overdues_in_range(tester, circ_id)?;
+    tester.timer.log("void_overdue_bills_in_range()");
+
+    find_bills_filters(tester, circ_id)?;
+    tester.timer.log("find_bills()");
+
+    xact_ledger_balances(tester, circ_id)?;
+    tester.timer.log("xact_ledger()");
+
+    audit_balance_matches(tester, circ_id)?;
+    tester.timer.log("audit_xact_balance()");
+
+    recompute_balance_matches(tester, circ_id)?;
+    tester.timer.log("recompute_balance()");
+
+    age_closed_xact(tester, circ_id)?;
+    tester.timer.log("age_transaction()");
+
+    sub_day_grace_delays_fines(tester, circ_id)?;
+    tester
+        .timer
+        .log("generate_fines_for_xact() with a 4 hour grace period");
+
+    fines_in_branch_timezone(tester, circ_id)?;
+    tester
+        .timer
+        .log("generate_fines_for_xact() in the branch timezone");
+
+    delete_circ_assets(tester)?;
+
+    delete_test_assets(tester)?;
+
+    Ok(())
+}
+
+/// A Sunday at 23:59:59 at least 30 days in the future.
+fn future_sunday() -> EgResult<date::EgDate> {
+    let mut due = date::set_hms(&date::add_interval(date::now(), "30 days")?, 23, 59, 59)?;
+    while due.weekday() != Weekday::Sun {
+        due = date::add_interval(due, "1 day")?;
+    }
+    Ok(due)
+}
+
+fn create_org_setting(tester: &mut util::Tester, name: &str, value: &str) -> EgResult<()> {
+    let setting = eg::hash! {
+        "org_unit": tester.samples.aou_id,
+        "name": name,
+        "value": value,
+    };
+
+    tester.editor.create(EgValue::create("aous", setting)?)?;
+
+    Ok(())
+}
+
+/// Replace an org setting at our org unit with a committed value, so
+/// it's visible to Settings lookups, which run outside of our
+/// transaction.
+fn commit_org_setting(tester: &mut util::Tester, name: &str, value: &str) -> EgResult<()> {
+    delete_org_setting(tester, name)?;
+    tester.editor.xact_begin()?;
+    create_org_setting(tester, name, value)?;
+    tester.editor.commit()
+}
+
+fn delete_org_setting(tester: &mut util::Tester, name: &str) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    for setting in e.search("aous", eg::hash! {"org_unit": org_id, "name": name})? {
+        e.delete(setting)?;
+    }
+
+    e.commit()
+}
+
+fn create_closed_date(
+    tester: &mut util::Tester,
+    start: &date::EgDate,
+    end: &date::EgDate,
+) -> EgResult<()> {
+    let closed = eg::hash! {
+        "org_unit": tester.samples.aou_id,
+        "reason": CLOSED_REASON,
+        "close_start": date::to_iso(start),
+        "close_end": date::to_iso(end),
+    };
+
+    tester.editor.create(EgValue::create("aoucd", closed)?)?;
+
+    Ok(())
+}
+
+fn delete_test_assets(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    for closed in e.search("aoucd", eg::hash! {"reason": CLOSED_REASON})? {
+        e.delete(closed)?;
+    }
+
+    let query = eg::hash! {"org_unit": org_id, "name": {"like": "circ.grace.extend%"}};
+    for setting in e.search("aous", query)? {
+        e.delete(setting)?;
+    }
+
+    e.commit()
+}
+
+/// Grace period of 1 day on an item due Sunday night, with the branch
+/// closed Monday through Wednesday, extends to Thursday.
+fn extend_grace_into_closed_dates(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let due_date = future_sunday()?;
+
+    let close_start = date::set_hms(&date::add_interval(due_date, "1 day")?, 0, 0, 0)?;
+    let close_end = date::add_interval(due_date, "3 days")?;
+
+    tester.editor.xact_begin()?;
+    create_org_setting(tester, "circ.grace.extend", "true")?;
+    create_closed_date(tester, &close_start, &close_end)?;
+    tester.editor.commit()?;
+
+    let e = &mut tester.editor;
+
+    assert!(!hours::is_org_open(
+        e,
+        org_id,
+        &date::add_interval(due_date, "2 days")?
+    )?);
+    assert!(hours::is_org_open(
+        e,
+        org_id,
+        &date::add_interval(due_date, "4 days")?
+    )?);
+
+    let mut settings = Settings::new(e);
+    let (value, source) = settings.get_value_and_source_org("circ.grace.extend", org_id)?;
+    assert!(value.boolish());
+    assert_eq!(source, Some(org_id));
+
+    let grace = billing::extend_grace_period(e, org_id, DAY_OF_SECONDS, due_date, None)?;
+
+    assert_eq!(grace, DAY_OF_SECONDS * 4);
+
+    // A scan limit shorter than the closure leaves the grace period
+    // unextended.
+    let grace = billing::extend_grace_period_within(e, org_id, DAY_OF_SECONDS, due_date, None, 1)?;
+
+    assert_eq!(grace, DAY_OF_SECONDS);
+
+    Ok(())
+}
+
+/// The closures from extend_grace_into_closed_dates() each appear as
+/// a step in the explanation.
+fn explain_grace_into_closed_dates(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let due_date = future_sunday()?;
+    let e = &mut tester.editor;
+
+    let explained = billing::explain_grace_extension(e, org_id, DAY_OF_SECONDS, due_date)?;
+
+    assert_eq!(explained.original_seconds, DAY_OF_SECONDS);
+    assert_eq!(
+        explained.extended_seconds,
+        billing::extend_grace_period(e, org_id, DAY_OF_SECONDS, due_date, None)?
+    );
+
+    let closed_days: Vec<&billing::GraceStep> = explained
+        .steps
+        .iter()
+        .filter(|s| s.added_seconds > 0)
+        .collect();
+
+    assert_eq!(closed_days.len(), 3);
+    assert!(closed_days
+        .iter()
+        .all(|s| s.date.is_some() && s.description.contains(CLOSED_REASON)));
+
+    let added: i64 = explained.steps.iter().map(|s| s.added_seconds).sum();
+    assert_eq!(
+        added,
+        explained.extended_seconds - explained.original_seconds
+    );
+
+    Ok(())
+}
+
+/// Replace the hours of operation for our org unit so the provided
+/// days of the week (0 = Sunday) are closed and all others are open.
+///
+/// Returns the original hours so they may be restored.
+fn set_closed_weekdays(tester: &mut util::Tester, closed: &[i64]) -> EgResult<Option<EgValue>> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    let orig_hours = e.retrieve("aouhoo", org_id)?;
+
+    let mut hours = match orig_hours.as_ref() {
+        Some(h) => h.clone(),
+        None => EgValue::create("aouhoo", eg::hash! {"id": org_id})?,
+    };
+
+    for day in 0..7 {
+        let (open, close) = if closed.contains(&day) {
+            ("00:00:00", "00:00:00")
+        } else {
+            ("09:00:00", "17:00:00")
+        };
+        hours[&format!("dow_{day}_open")] = EgValue::from(open);
+        hours[&format!("dow_{day}_close")] = EgValue::from(close);
+    }
+
+    e.xact_begin()?;
+
+    if orig_hours.is_some() {
+        e.update(hours)?;
+    } else {
+        e.create(hours)?;
+    }
+
+    e.commit()?;
+
+    Ok(orig_hours)
+}
+
+/// Put back the hours of operation replaced by set_closed_weekdays().
+fn restore_hours(tester: &mut util::Tester, orig_hours: Option<EgValue>) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    if let Some(h) = orig_hours {
+        e.update(h)?;
+    } else if let Some(h) = e.retrieve("aouhoo", org_id)? {
+        e.delete(h)?;
+    }
+
+    e.commit()
+}
+
+/// With every day of the week closed, the branch is never open and
+/// the grace period is returned as-is.
+fn extend_grace_never_open(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let due_date = future_sunday()?;
+
+    let orig_hours = set_closed_weekdays(tester, &[0, 1, 2, 3, 4, 5, 6])?;
+
+    let e = &mut tester.editor;
+
+    assert_eq!(hours::closed_weekdays(e, org_id)?.len(), 7);
+
+    let state = org::next_open_date(e, org_id, &due_date)?;
+    assert!(state == org::OrgOpenState::Never);
+
+    // circ.grace.extend was enabled by the previous test.
+    let grace = billing::extend_grace_period(e, org_id, DAY_OF_SECONDS * 2, due_date, None)?;
+    assert_eq!(grace, DAY_OF_SECONDS * 2);
+
+    restore_hours(tester, orig_hours)
+}
+
+/// With hours of 09:00 - 17:00 every day, a closed afternoon does not
+/// extend the grace period, but a day fully covered by multiple
+/// partial closings does.
+fn extend_grace_partial_closures(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+
+    // One week past the closings created by extend_grace_into_closed_dates().
+    let due_date = date::add_interval(future_sunday()?, "7 days")?;
+    let monday = date::add_interval(due_date, "1 day")?;
+    let tuesday = date::add_interval(due_date, "2 days")?;
+
+    let orig_hours = set_closed_weekdays(tester, &[])?;
+
+    tester.editor.xact_begin()?;
+    create_closed_date(
+        tester,
+        &date::set_hms(&monday, 13, 0, 0)?,
+        &date::set_hms(&monday, 23, 59, 59)?,
+    )?;
+    create_closed_date(
+        tester,
+        &date::set_hms(&tuesday, 0, 0, 0)?,
+        &date::set_hms(&tuesday, 12, 0, 0)?,
+    )?;
+    create_closed_date(
+        tester,
+        &date::set_hms(&tuesday, 12, 0, 0)?,
+        &date::set_hms(&tuesday, 23, 59, 59)?,
+    )?;
+    tester.editor.commit()?;
+
+    let e = &mut tester.editor;
+
+    assert!(hours::is_org_open_on_day(e, org_id, &monday)?);
+    assert!(!hours::is_org_open_on_day(e, org_id, &tuesday)?);
+
+    // Grace ends Monday, which is partly open.
+    let grace = billing::extend_grace_period(e, org_id, DAY_OF_SECONDS, due_date, None)?;
+    assert_eq!(grace, DAY_OF_SECONDS);
+
+    // Grace ends Tuesday, which is closed for all open hours.
+    let grace = billing::extend_grace_period(e, org_id, DAY_OF_SECONDS * 2, due_date, None)?;
+    assert_eq!(grace, DAY_OF_SECONDS * 3);
+
+    restore_hours(tester, orig_hours)
+}
+
+/// A closed date from Monday 00:00 to Tuesday 00:00 covers all of
+/// Tuesday, as whole-day closures are often stored.
+fn extend_grace_midnight_closed_end(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+
+    // Two weeks past the closings created by extend_grace_into_closed_dates().
+    let due_date = date::add_interval(future_sunday()?, "14 days")?;
+    let monday = date::add_interval(due_date, "1 day")?;
+    let tuesday = date::add_interval(due_date, "2 days")?;
+    let wednesday = date::add_interval(due_date, "3 days")?;
+
+    let orig_hours = set_closed_weekdays(tester, &[])?;
+
+    tester.editor.xact_begin()?;
+    create_closed_date(
+        tester,
+        &date::set_hms(&monday, 0, 0, 0)?,
+        &date::set_hms(&tuesday, 0, 0, 0)?,
+    )?;
+    tester.editor.commit()?;
+
+    let e = &mut tester.editor;
+
+    assert!(!hours::is_org_open_on_day(e, org_id, &monday)?);
+    assert!(!hours::is_org_open_on_day(e, org_id, &tuesday)?);
+    assert!(hours::is_org_open_on_day(e, org_id, &wednesday)?);
+
+    // Fine periods ending late Tuesday are closed too, as the fine
+    // generator sees them.
+    assert!(!hours::is_org_open(e, org_id, &tuesday)?);
+    assert!(hours::is_org_open(e, org_id, &wednesday)?);
+
+    // Grace ends Monday and extends through Tuesday.
+    let grace = billing::extend_grace_period(e, org_id, DAY_OF_SECONDS, due_date, None)?;
+    assert_eq!(grace, DAY_OF_SECONDS * 3);
+
+    restore_hours(tester, orig_hours)
+}
+
+/// Check out our sample copy to our sample patron and backdate the
+/// circulation so it's overdue by the requested number of days with
+/// a daily fine.
+///
+/// Returns the circulation ID.
+fn create_overdue_circ(tester: &mut util::Tester, days_overdue: i64) -> EgResult<i64> {
+    let mut options: HashMap<String, EgValue> = HashMap::new();
+    options.insert(
+        "copy_barcode".to_string(),
+        EgValue::from(tester.samples.acp_barcode.as_str()),
+    );
+    options.insert(
+        "patron_barcode".to_string(),
+        EgValue::from(tester.samples.au_barcode.as_str()),
+    );
+
+    tester.editor.xact_begin()?;
+
+    let mut circulator = Circulator::new(&mut tester.editor, options)?;
+    circulator.checkout()?;
+    circulator.commit()?;
+
+    let evt = circulator
+        .events()
+        .get(0)
+        .ok_or(format!("Checkout returned no result!"))?;
+
+    assert!(evt.is_success());
+
+    let circ_id = evt.payload()["circ"].id()?;
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let mut circ = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("Cannot find circ {circ_id}"))?;
+
+    let due_date = date::subtract_interval(date::now(), &format!("{days_overdue} days"))?;
+    let due_date = date::set_hms(&due_date, 23, 59, 59)?;
+
+    circ["xact_start"] =
+        EgValue::from(date::to_iso(&date::subtract_interval(due_date, "14 days")?));
+    circ["due_date"] = EgValue::from(date::to_iso(&due_date));
+    circ["fine_interval"] = EgValue::from("1 day");
+    circ["recurring_fine"] = EgValue::from(0.10);
+    circ["max_fine"] = EgValue::from(10.00);
+    circ["grace_period"] = EgValue::from("0 seconds");
+
+    e.update(circ)?;
+    e.commit()?;
+
+    Ok(circ_id)
+}
+
+fn create_circ_assets(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let acn = tester.samples.create_default_acn(e)?;
+    tester.samples.create_default_acp(e, acn.id()?)?;
+    tester.samples.create_default_au(e)?;
+
+    e.commit()
+}
+
+fn delete_circ_assets(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    tester.samples.delete_default_acp(e)?;
+    tester.samples.delete_default_acn(e)?;
+    tester.samples.delete_default_au(e)?;
+
+    e.commit()
+}
+
+/// A batch run records circs which fail and generates nothing new
+/// for a circ whose fines are already current.
+fn fines_for_circs_batch(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let bill_count = e.search("mb", eg::hash! {"xact": circ_id})?.len();
+
+    e.xact_begin()?;
+    let summary = billing::generate_fines_for_circs(e, &[-1, circ_id])?;
+    e.commit()?;
+
+    assert_eq!(summary.processed, 1);
+    assert_eq!(summary.bills_created, 0);
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].0, -1);
+
+    assert_eq!(
+        e.search("mb", eg::hash! {"xact": circ_id})?.len(),
+        bill_count
+    );
+
+    Ok(())
+}
+
+/// Repeating a batch run over days the branch is open creates no
+/// bills the first run did not already create.
+fn fines_for_circs_batch_repeat(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let orig_hours = set_closed_weekdays(tester, &[])?;
+
+    // Fine days the circ has not yet been billed for.
+    let as_of = date::add_interval(date::now(), "3 days")?;
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let first = billing::generate_fines_for_circs_as_of(e, &[circ_id], Some(as_of))?;
+    assert_eq!(first.processed, 1);
+    assert!(first.bills_created > 0);
+    assert!(first.errors.is_empty());
+
+    let bill_count = e.search("mb", eg::hash! {"xact": circ_id})?.len();
+
+    let second = billing::generate_fines_for_circs_as_of(e, &[circ_id], Some(as_of))?;
+    assert_eq!(second.processed, 1);
+    assert_eq!(second.bills_created, 0);
+    assert!(second.errors.is_empty());
+
+    assert_eq!(
+        e.search("mb", eg::hash! {"xact": circ_id})?.len(),
+        bill_count
+    );
+
+    // Leave nothing behind.
+    e.rollback()?;
+
+    restore_hours(tester, orig_hours)
+}
+
+/// A branch closed on weekends generates no fines for Saturday or
+/// Sunday when circ.fines.charge_when_closed is not set.
+///
+/// Returns the ID of the fined circulation.
+fn fines_skip_closed_weekends(tester: &mut util::Tester) -> EgResult<i64> {
+    let orig_hours = set_closed_weekdays(tester, &[0, 6])?;
+
+    // 10 days overdue covers at least one full weekend.
+    let circ_id = create_overdue_circ(tester, 10)?;
+
+    let e = &mut tester.editor;
+
+    // Catch up on fines as of 5 days ago first.
+    let as_of = date::subtract_interval(date::now(), "5 days")?;
+
+    e.xact_begin()?;
+    billing::generate_fines_for_circ(e, circ_id, Some(as_of), None)?;
+    e.commit()?;
+
+    let backfilled = e.search("mb", eg::hash! {"xact": circ_id})?;
+    assert!(backfilled.len() > 0);
+
+    for bill in backfilled.iter() {
+        let billing_ts = date::parse_datetime(bill["billing_ts"].str()?)?;
+        let period_end = date::parse_datetime(bill["period_end"].str()?)?;
+        assert_eq!(billing_ts.timestamp(), as_of.timestamp());
+        assert!(period_end <= as_of);
+    }
+
+    // The regular run continues from the as_of run without billing
+    // any period twice.
+    e.xact_begin()?;
+    billing::generate_fines_for_circ(e, circ_id, None, None)?;
+    e.commit()?;
+
+    let bills = e.search("mb", eg::hash! {"xact": circ_id})?;
+
+    let mut period_ends: Vec<&str> = bills
+        .iter()
+        .map(|b| b["period_end"].as_str().unwrap())
+        .collect();
+    period_ends.sort();
+    period_ends.dedup();
+    assert_eq!(period_ends.len(), bills.len());
+
+    assert!(bills.len() > 0);
+    assert!(bills.len() < 10);
+
+    for bill in bills.iter() {
+        let period_end = date::parse_datetime(bill["period_end"].str()?)?;
+        let weekday = period_end.weekday();
+        assert!(weekday != Weekday::Sat && weekday != Weekday::Sun);
+    }
+
+    restore_hours(tester, orig_hours)?;
+
+    Ok(circ_id)
+}
+
+/// The next fine posts one fine interval after the most recent fine.
+fn next_fine_time_predicted(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let ops = eg::hash! {"order_by": {"mb": "billing_ts DESC"}, "limit": 1};
+    let last_fine = e
+        .search_with_ops("mb", eg::hash! {"xact": circ_id}, ops)?
+        .pop()
+        .ok_or_else(|| format!("No fines for circ {circ_id}"))?;
+
+    let last_fine_dt = date::parse_datetime(last_fine["billing_ts"].str()?)?;
+
+    let next_fine = billing::next_fine_time(e, circ_id)?
+        .ok_or_else(|| format!("No next fine time for circ {circ_id}"))?;
+
+    assert!(next_fine.timestamp() - last_fine_dt.timestamp() >= DAY_OF_SECONDS);
+
+    Ok(())
+}
+
+/// The fetched transaction summary agrees with the summary and
+/// circulation it was built from.
+fn fetch_xact_summary_matches(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let summary = e.fetch_xact_summary(circ_id)?;
+
+    let mbts = e
+        .retrieve("mbts", circ_id)?
+        .ok_or_else(|| format!("No summary for circ {circ_id}"))?;
+
+    let circ = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("Cannot find circ {circ_id}"))?;
+
+    assert_eq!(summary.xact_id, circ_id);
+    assert_eq!(summary.usr, circ["usr"].int()?);
+    assert_eq!(summary.billing_location, circ["circ_lib"].int()?);
+    assert_eq!(summary.balance_owed, mbts["balance_owed"].float()?);
+    assert!(summary.xact_open);
+
+    Ok(())
+}
+
+/// When the fines on a circulation already equal its max fine, no new
+/// bills are created and the circ is marked as having reached max fines.
+fn max_fine_already_met(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let bill_count = e.search("mb", eg::hash! {"xact": circ_id})?.len();
+
+    let summary = e
+        .retrieve("mbts", circ_id)?
+        .ok_or_else(|| format!("No summary for circ {circ_id}"))?;
+
+    e.xact_begin()?;
+
+    let mut circ = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("Cannot find circ {circ_id}"))?;
+
+    circ["max_fine"] = summary["total_owed"].clone();
+    e.update(circ)?;
+
+    billing::generate_fines_for_circ(e, circ_id, None, None)?;
+
+    e.commit()?;
+
+    assert_eq!(
+        e.search("mb", eg::hash! {"xact": circ_id})?.len(),
+        bill_count
+    );
+
+    let circ = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("Cannot find circ {circ_id}"))?;
+
+    assert_eq!(
+        circ["stop_fines"].as_str(),
+        Some(C::CIRC_STOP_FINES_MAXFINES)
+    );
+
+    assert!(billing::next_fine_time(e, circ_id)?.is_none());
+
+    Ok(())
+}
+
+/// A bill which has been adjusted to zero is not voided.
+fn void_skips_adjusted_bills(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let bill = billing::create_bill(
+        e,
+        1.00,
+        C::BTYPE_DAMAGED_ITEM,
+        "Damaged Item",
+        circ_id,
+        None,
+        None,
+        None,
+    )?;
+
+    let bill_id = bill.id()?;
+
+    billing::adjust_bills_to_zero(e, &[bill_id], "Adjusted by test")?;
+    billing::void_bills(e, &[bill_id], Some("Voided by test"), None)?;
+
+    e.commit()?;
+
+    let bill = e
+        .retrieve("mb", bill_id)?
+        .ok_or_else(|| format!("Cannot find bill {bill_id}"))?;
+
+    assert!(!bill["voided"].boolish());
+
+    Ok(())
+}
+
+/// Voids made by an editor with no requestor, e.g. the nightly fine
+/// run, are credited to the system user.
+fn void_as_system_user(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let system_user = tester.editor.requestor_id()?;
+
+    let mut e = eg::Editor::new(&tester.client);
+    e.set_system_user(Some(system_user));
+
+    e.xact_begin()?;
+
+    let bill = billing::create_bill(
+        &mut e,
+        1.00,
+        C::BTYPE_DAMAGED_ITEM,
+        "Damaged Item",
+        circ_id,
+        None,
+        None,
+        None,
+    )?;
+
+    let bill_id = bill.id()?;
+
+    billing::void_bills(&mut e, &[bill_id], None, None)?;
+
+    let bill = e
+        .retrieve("mb", bill_id)?
+        .ok_or_else(|| format!("Cannot find bill {bill_id}"))?;
+
+    e.rollback()?;
+
+    assert!(bill["voided"].boolish());
+    assert_eq!(bill["voider"].int()?, system_user);
+
+    Ok(())
+}
+
+/// Amnesty-style voiding of all overdue fines at our org unit.
+/// Reason codes must be allowed by org setting and are stored in
+/// the bill note.
+fn void_with_reason_code(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    commit_org_setting(tester, "bill.void_reason_codes", r#"["amnesty"]"#)?;
+
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let bill = billing::create_bill(
+        e,
+        1.00,
+        C::BTYPE_DAMAGED_ITEM,
+        "Damaged Item",
+        circ_id,
+        None,
+        None,
+        None,
+    )?;
+
+    let bill_id = bill.id()?;
+
+    assert!(billing::void_bills(e, &[bill_id], None, Some("policy_change")).is_err());
+
+    billing::void_bills(e, &[bill_id], Some("Snow day"), Some("amnesty"))?;
+
+    let bill = e
+        .retrieve("mb", bill_id)?
+        .ok_or_else(|| format!("Cannot find bill {bill_id}"))?;
+
+    assert!(bill["voided"].boolish());
+    assert_eq!(
+        billing::void_reason_code(bill["note"].str()?),
+        Some("amnesty")
+    );
+
+    // Leave nothing behind.
+    e.rollback()?;
+
+    delete_org_setting(tester, "bill.void_reason_codes")
+}
+
+fn void_bills_of_type_for_user(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let bill = billing::create_bill(
+        e,
+        1.00,
+        C::BTYPE_DAMAGED_ITEM_PROCESSING_FEE,
+        "Damaged Item Processing Fee",
+        circ_id,
+        None,
+        None,
+        None,
+    )?;
+
+    let bill_id = bill.id()?;
+
+    let usr = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("No such circ {circ_id}"))?["usr"]
+        .int()?;
+
+    // Penalties are recalculated as the system user.
+    e.set_system_user(Some(e.requestor_id()?));
+
+    let xact_ids = billing::void_or_zero_bills_of_type_for_user(
+        e,
+        usr,
+        C::BTYPE_DAMAGED_ITEM_PROCESSING_FEE,
+        "Processing fee forgiven by test",
+    )?;
+
+    e.set_system_user(None);
+
+    assert!(xact_ids.contains(&circ_id));
+
+    let bill = e
+        .retrieve("mb", bill_id)?
+        .ok_or_else(|| format!("Cannot find bill {bill_id}"))?;
+
+    // Voided or adjusted to zero, depending on settings.
+    let maps = billing::bill_payment_map_for_xact(e, circ_id)?;
+    let zeroed = maps
+        .iter()
+        .any(|m| m.bill["id"] == bill["id"] && m.net_balance() == 0.0);
+
+    assert!(bill["voided"].boolish() || zeroed);
+
+    // Leave nothing behind.
+    e.rollback()
+}
+
+fn void_summary_counts(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let mut bill_ids = Vec::new();
+
+    for amount in [1.25, 2.50] {
+        let bill = billing::create_bill(
+            e,
+            amount,
+            C::BTYPE_DAMAGED_ITEM,
+            "Damaged Item",
+            circ_id,
+            None,
+            None,
+            None,
+        )?;
+
+        bill_ids.push(bill.id()?);
+    }
+
+    billing::void_bills(e, &bill_ids[..1], None, None)?;
+
+    // One new void, one already voided, and one which does not exist.
+    let summary = billing::void_bills_with_summary(e, &[bill_ids[0], bill_ids[1], -1], None, None)?;
+
+    assert_eq!(summary.voided, 1);
+    assert_eq!(summary.already_voided, 1);
+    assert_eq!(summary.adjusted_to_zero, 0);
+    assert_eq!(summary.not_found, 1);
+    assert_eq!(summary.amount_voided, 2.50);
+
+    // Leave nothing behind.
+    e.rollback()
+}
+
+fn void_without_penalty_recalc(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let bill = billing::create_bill(
+        e,
+        1.00,
+        C::BTYPE_DAMAGED_ITEM,
+        "Damaged Item",
+        circ_id,
+        None,
+        None,
+        None,
+    )?;
+
+    let bill_id = bill.id()?;
+
+    let usr = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("No such circ {circ_id}"))?["usr"]
+        .int()?;
+
+    let org_id = billing::xact_org(e, circ_id)?;
+
+    let pairs = billing::void_bills_with_recalc(e, &[bill_id], None, None, false)?;
+
+    // The caller is now responsible for a penalty update on each pair.
+    assert_eq!(pairs, vec![(usr, org_id)]);
+
+    // Batch recalculation runs as the system user.
+    assert!(penalty::calculate_penalties_batch(e, &pairs).is_err());
+
+    e.set_system_user(Some(e.requestor_id()?));
+    penalty::calculate_penalties_batch(e, &pairs)?;
+    e.set_system_user(None);
+
+    // Leave nothing behind.
+    e.rollback()
+}
+
+fn void_overdues_in_range(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    let query = eg::hash! {
+        "xact": circ_id,
+        "btype": C::BTYPE_OVERDUE_MATERIALS,
+        "voided": "f",
+    };
+
+    let unvoided = e.search("mb", query.clone())?.len();
+    assert!(unvoided > 0);
+
+    let start = date::subtract_interval(date::now(), "30 days")?;
+    let end = date::now();
+
+    e.xact_begin()?;
+    let count = billing::void_overdue_bills_in_range(e, org_id, &start, &end, "Amnesty")?;
+    e.commit()?;
+
+    // Other transactions at the org may have been voided as well.
+    assert!(count >= unvoided);
+    assert_eq!(e.search("mb", query)?.len(), 0);
+
+    Ok(())
+}
+
+/// The ledger includes the voided bills and ends at the same balance
+/// as the transaction summary.
+/// Voided bills are only returned on request, and bills are
+/// returned oldest first.
+fn find_bills_filters(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let bill = billing::create_bill(
+        e,
+        1.00,
+        C::BTYPE_DAMAGED_ITEM,
+        "Damaged Item",
+        circ_id,
+        None,
+        None,
+        None,
+    )?;
+
+    let bill_id = bill.id()?;
+    let btype = Some(C::BTYPE_DAMAGED_ITEM);
+
+    let bills = billing::find_bills(e, circ_id, btype, false)?;
+    assert!(bills.iter().any(|b| b.id().ok() == Some(bill_id)));
+    assert!(bills.iter().all(|b| b["btype"].int().ok() == btype));
+
+    billing::void_bills(e, &[bill_id], None, None)?;
+
+    let bills = billing::find_bills(e, circ_id, btype, false)?;
+    assert!(!bills.iter().any(|b| b.id().ok() == Some(bill_id)));
+
+    let bills = billing::find_bills(e, circ_id, None, true)?;
+    assert!(bills.iter().any(|b| b.id().ok() == Some(bill_id)));
+
+    let times: Vec<&str> = bills
+        .iter()
+        .map(|b| b["billing_ts"].str())
+        .collect::<EgResult<_>>()?;
+    let mut sorted = times.clone();
+    sorted.sort_by_key(|t| date::parse_datetime(t).map(|d| d.timestamp()).unwrap_or(0));
+    assert_eq!(times, sorted);
+
+    // Leave nothing behind.
+    e.rollback()
+}
+
+fn xact_ledger_balances(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let ledger = billing::xact_ledger(e, circ_id)?;
+
+    assert!(!ledger.is_empty());
+    assert!(ledger.iter().any(|entry| entry.voided));
+    assert!(ledger
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    let mbts = e
+        .retrieve("mbts", circ_id)?
+        .ok_or_else(|| format!("No summary for circ {circ_id}"))?;
+
+    let last = ledger.last().unwrap();
+    assert_eq!(last.running_balance, mbts["balance_owed"].float()?);
+
+    Ok(())
+}
+
+fn audit_balance_matches(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let audit = billing::audit_xact_balance(&mut tester.editor, circ_id)?;
+
+    assert_eq!(audit.xact_id, circ_id);
+    assert!(audit.stored_balance >= 0.0);
+    assert!(!audit.discrepancy);
+    assert!(!audit.unexpected_negative);
+
+    Ok(())
+}
+
+/// The balance recomputed from the source rows matches the summary,
+/// so a repair changes nothing.
+fn recompute_balance_matches(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let stored = e.fetch_xact_summary(circ_id)?.balance_owed;
+    assert_eq!(billing::recompute_balance(e, circ_id)?, stored);
+
+    e.xact_begin()?;
+    let repaired = billing::recompute_balance_with_repair(e, circ_id, true)?;
+    assert!(!e.has_pending_changes());
+    e.rollback()?;
+
+    assert_eq!(repaired, stored);
+
+    Ok(())
+}
+
+fn create_grocery(
+    tester: &mut util::Tester,
+    usr: i64,
+    xact_finish: &date::EgDate,
+) -> EgResult<i64> {
+    let finish = date::to_iso(xact_finish);
+
+    let grocery = eg::hash! {
+        "usr": usr,
+        "billing_location": tester.samples.aou_id,
+        "xact_start": finish.as_str(),
+        "xact_finish": finish.as_str(),
+    };
+
+    tester.editor.create(EgValue::create("mg", grocery)?)?.id()
+}
+
+fn age_closed_xact(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    commit_org_setting(tester, "history.money.retention_age", "\"1 day\"")?;
+
+    tester.editor.xact_begin()?;
+
+    let usr = tester
+        .editor
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("No such circ {circ_id}"))?["usr"]
+        .int()?;
+
+    let staff_id = tester.editor.requestor_id()?;
+
+    // Aging runs as the system user, so one is required.
+    assert!(billing::age_transaction(&mut tester.editor, circ_id).is_err());
+    assert_eq!(tester.editor.requestor_id()?, staff_id);
+
+    // Any user will do as the system user, as long as it's not the
+    // requestor, so we can see the requestor put back.
+    tester.editor.set_system_user(Some(usr));
+
+    // The overdue circ is still open.  The requestor is restored
+    // though the elevated aging call failed.
+    assert!(billing::age_transaction(&mut tester.editor, circ_id).is_err());
+    assert_eq!(
+        tester.editor.requestor().map(|r| r.id()).transpose()?,
+        Some(staff_id)
+    );
+
+    let recent_id = create_grocery(tester, usr, &date::now())?;
+    let old_id = create_grocery(
+        tester,
+        usr,
+        &date::subtract_interval(date::now(), "3 days")?,
+    )?;
+
+    let e = &mut tester.editor;
+
+    // Closed within the retention age.
+    assert!(!billing::age_transaction(e, recent_id)?);
+
+    let bill = billing::create_bill(
+        e,
+        1.0,
+        C::BTYPE_DAMAGED_ITEM,
+        "Damaged Item",
+        old_id,
+        None,
+        None,
+        None,
+    )?;
+
+    // Non-zero balance.
+    assert!(billing::age_transaction(e, old_id).is_err());
+
+    billing::void_bills(e, &[bill.id()?], None, None)?;
+
+    assert!(billing::age_transaction(e, old_id)?);
+    assert!(e.search("mb", eg::hash! {"xact": old_id})?.is_empty());
+    assert_eq!(e.requestor_id()?, staff_id);
+
+    // Leave nothing behind.
+    e.rollback()?;
+    e.set_system_user(None);
+
+    delete_org_setting(tester, "history.money.retention_age")
+}
+
+/// A grace period shorter than a day is never extended, but it still
+/// holds off the first fine.
+fn sub_day_grace_delays_fines(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    let usr = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("No such circ {circ_id}"))?["usr"]
+        .int()?;
+
+    e.xact_begin()?;
+
+    let grocery = eg::hash! {
+        "usr": usr,
+        "billing_location": org_id,
+        "xact_start": "now",
+    };
+
+    let xact_id = e.create(EgValue::create("mg", grocery)?)?.id()?;
+
+    let due_date = date::to_iso(&date::subtract_interval(date::now(), "1 hour")?);
+
+    let generate = |e: &mut eg::Editor, as_of: Option<date::EgDate>| {
+        let params = billing::FineParams {
+            due_date: due_date.clone(),
+            circ_lib: org_id,
+            recurring_fine: 0.25,
+            fine_interval: "1 day".to_string(),
+            max_fine: 5.00,
+            grace_period: Some("4 hours".to_string()),
+            xact_type: billing::BillableTransactionType::Circ,
+            as_of,
+            timezone: None,
+        };
+
+        billing::generate_fines_for_xact(e, xact_id, &params)
+    };
+
+    let due_date_dt = date::parse_datetime(&due_date)?;
+    assert_eq!(
+        billing::extend_grace_period(e, org_id, 4 * 3600, due_date_dt, None)?,
+        4 * 3600
+    );
+
+    // One hour overdue is still within the grace period.
+    generate(e, None)?;
+    assert!(e.search("mb", eg::hash! {"xact": xact_id})?.is_empty());
+
+    // Fines post once the grace period has passed.
+    generate(e, Some(date::add_interval(date::now(), "5 days")?))?;
+    assert!(!e.search("mb", eg::hash! {"xact": xact_id})?.is_empty());
+
+    // Leave nothing behind.
+    e.rollback()
+}
+
+/// A branch in New York closed on Sundays with an item due Saturday
+/// at 22:00 local time, which is already Sunday in UTC.
+///
+/// In the branch's timezone, the 1 day grace period ends on Sunday
+/// and extends through Monday, and the Sunday fine period is skipped.
+/// Evaluated in UTC, the grace period ends on an open Monday and
+/// fines post for Monday and Tuesday.
+fn fines_in_branch_timezone(tester: &mut util::Tester, circ_id: i64) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let tz = "America/New_York";
+
+    let orig_hours = set_closed_weekdays(tester, &[0])?;
+    commit_org_setting(tester, "circ.grace.extend", "true")?;
+
+    // A Saturday at 22:00 New York time at least two weeks ago.
+    let mut due = date::set_timezone(date::subtract_interval(date::now(), "14 days")?, tz)?;
+    while due.weekday() != Weekday::Sat {
+        due = date::subtract_interval(due, "1 day")?;
+    }
+    let due = date::set_hms(&due, 22, 0, 0)?;
+
+    // Stored in UTC, as a server in UTC would report it.
+    let utc_due = date::set_timezone(due, "UTC")?;
+    assert_eq!(utc_due.weekday(), Weekday::Sun);
+    let due_date = date::to_iso(&utc_due);
+
+    // Monday and Tuesday around midday in New York.
+    let monday = date::add_interval(due, "38 hours")?;
+    let tuesday = date::add_interval(due, "62 hours")?;
+
+    let e = &mut tester.editor;
+
+    let usr = e
+        .retrieve("circ", circ_id)?
+        .ok_or_else(|| format!("No such circ {circ_id}"))?["usr"]
+        .int()?;
+
+    e.xact_begin()?;
+
+    let generate = |e: &mut eg::Editor, as_of: date::EgDate, timezone: &str| {
+        let grocery = eg::hash! {
+            "usr": usr,
+            "billing_location": org_id,
+            "xact_start": date::to_iso(&due),
+        };
+
+        let xact_id = e.create(EgValue::create("mg", grocery)?)?.id()?;
+
+        let params = billing::FineParams {
+            due_date: due_date.clone(),
+            circ_lib: org_id,
+            recurring_fine: 0.25,
+            fine_interval: "1 day".to_string(),
+            max_fine: 5.00,
+            grace_period: Some("1 day".to_string()),
+            xact_type: billing::BillableTransactionType::Circ,
+            as_of: Some(as_of),
+            timezone: Some(timezone.to_string()),
+        };
+
+        billing::generate_fines_for_xact(e, xact_id, &params)?;
+
+        e.search("mb", eg::hash! {"xact": xact_id})
+    };
+
+    // Still within the extended grace period on Monday.
+    assert!(generate(e, monday, tz)?.is_empty());
+
+    // By Tuesday, fines post for Monday and Tuesday, but not Sunday.
+    let bills = generate(e, tuesday, tz)?;
+    assert_eq!(bills.len(), 2);
+
+    for bill in bills.iter() {
+        let period_end = date::set_timezone(date::parse_datetime(bill["period_end"].str()?)?, tz)?;
+        assert!(period_end.weekday() != Weekday::Sun);
+    }
+
+    // In UTC, the grace period ends on Monday, which is open.
+    assert_eq!(generate(e, monday, "UTC")?.len(), 2);
+
+    // Leave nothing behind.
+    e.rollback()?;
+
+    delete_org_setting(tester, "circ.grace.extend")?;
+    restore_hours(tester, orig_hours)
+}
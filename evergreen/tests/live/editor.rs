@@ -0,0 +1,193 @@
+use crate::util;
+use eg::EgResult;
+use evergreen as eg;
+
+const BTYPE_NAME: &str = "_EG_TEST_";
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    delete_test_btypes(tester)?;
+
+    upsert(tester)?;
+    tester.timer.log("upsert()");
+
+    delete_test_btypes(tester)?;
+
+    search_page(tester)?;
+    tester.timer.log("search_page()");
+
+    max_flesh_depth(tester)?;
+    tester.timer.log("max flesh depth");
+
+    savepoint_rollback(tester)?;
+    tester.timer.log("savepoint_rollback()");
+
+    check_perm(tester)?;
+    tester.timer.log("check_perm()");
+
+    system_user(tester)?;
+    tester.timer.log("system user");
+
+    delete_test_btypes(tester)?;
+
+    Ok(())
+}
+
+fn delete_test_btypes(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    for btype in e.search(
+        "cbt",
+        eg::hash! {"name": {"like": format!("{BTYPE_NAME}%")}},
+    )? {
+        e.delete(btype)?;
+    }
+
+    e.commit()
+}
+
+fn upsert(tester: &mut util::Tester) -> EgResult<()> {
+    let owner = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    // No matching row exists, so one is created.
+    let btype = eg::hash! {"name": BTYPE_NAME, "owner": owner, "default_price": 1.25};
+    let created = e.upsert("cbt", btype, &["name", "owner"])?;
+    let btype_id = created.id()?;
+
+    // Matching row exists, so it is updated in place.
+    let btype = eg::hash! {"name": BTYPE_NAME, "owner": owner, "default_price": 2.50};
+    let updated = e.upsert("cbt", btype, &["name", "owner"])?;
+    assert_eq!(updated.id()?, btype_id);
+
+    let fetched = e
+        .retrieve("cbt", btype_id)?
+        .ok_or_else(|| format!("Cannot find billing type {btype_id}"))?;
+
+    assert_eq!(fetched["default_price"].float()?, 2.50);
+
+    let matches = e.search("cbt", eg::hash! {"name": BTYPE_NAME, "owner": owner})?;
+    assert_eq!(matches.len(), 1);
+
+    e.commit()
+}
+
+fn search_page(tester: &mut util::Tester) -> EgResult<()> {
+    let owner = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    for idx in 0..25 {
+        let btype = eg::hash! {"name": format!("{BTYPE_NAME}{idx:02}"), "owner": owner};
+        e.create(eg::EgValue::create("cbt", btype)?)?;
+    }
+
+    let query = eg::hash! {"name": {"like": format!("{BTYPE_NAME}%")}, "owner": owner};
+
+    let mut seen = Vec::new();
+    for (offset, expected) in [(0, 10), (10, 10), (20, 5)] {
+        let (page, total) = e.search_page("cbt", query.clone(), 10, offset)?;
+        assert_eq!(total, 25);
+        assert_eq!(page.len(), expected);
+        for btype in page {
+            seen.push(btype.id()?);
+        }
+    }
+
+    // Every row appears on exactly one page.
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 25);
+
+    e.commit()
+}
+
+fn max_flesh_depth(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let deep = eg::hash! {"flesh": 6, "flesh_fields": {"aou": ["parent_ou"]}};
+
+    assert!(e.retrieve_with_ops("aou", 1, deep.clone()).is_err());
+    assert!(e
+        .retrieve_with_ops("aou", 1, eg::hash! {"flesh": -1})
+        .is_err());
+
+    let org = e.with_max_flesh_depth(6, |e| e.retrieve_with_ops("aou", 1, deep))?;
+    assert!(org.is_some());
+
+    Ok(())
+}
+
+fn savepoint_rollback(tester: &mut util::Tester) -> EgResult<()> {
+    let owner = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let btype = eg::hash! {"name": BTYPE_NAME, "owner": owner};
+    let btype_id = e.create(eg::EgValue::create("cbt", btype)?)?.id()?;
+
+    e.savepoint_set("bad_row")?;
+
+    // Name is a required field.
+    let bad = eg::hash! {"name": eg::NULL, "owner": owner};
+    assert!(e.create(eg::EgValue::create("cbt", bad)?).is_err());
+
+    e.savepoint_rollback("bad_row")?;
+    e.savepoint_release("bad_row")?;
+
+    // Work done before the savepoint survives and the transaction
+    // remains usable.
+    assert!(e.retrieve("cbt", btype_id)?.is_some());
+
+    e.commit()?;
+
+    assert!(tester.editor.retrieve("cbt", btype_id)?.is_some());
+
+    Ok(())
+}
+
+fn check_perm(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+
+    tester.editor.check_perm("VOID_BILLING", org_id)?;
+
+    // No requestor means no permissions.
+    let mut e = eg::Editor::new(&tester.client);
+
+    let err = e.check_perm("VOID_BILLING", org_id).unwrap_err();
+
+    match err {
+        eg::EgError::Event(evt) => {
+            assert_eq!(evt.textcode(), "PERM_FAILURE");
+            assert_eq!(evt.ilsperm(), Some("VOID_BILLING"));
+        }
+        _ => panic!("Expected a PERM_FAILURE event: {err}"),
+    }
+
+    Ok(())
+}
+
+fn system_user(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let user_id = tester.editor.requestor_id()?;
+
+    let mut e = eg::Editor::new(&tester.client);
+
+    assert!(!e.has_requestor());
+
+    e.set_system_user(Some(user_id));
+
+    assert!(e.has_requestor());
+    assert_eq!(e.requestor_id()?, user_id);
+
+    // Permissions are checked against the system user.
+    e.check_perm("VOID_BILLING", org_id)?;
+
+    Ok(())
+}
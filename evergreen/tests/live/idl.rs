@@ -0,0 +1,107 @@
+use crate::util;
+use eg::EgResult;
+use eg::EgValue;
+use evergreen as eg;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    let idl = tester.editor.idl();
+
+    assert!(idl.field_exists("mb", "voided"));
+    assert!(!idl.field_exists("mb", "voidd"));
+    assert!(!idl.field_exists("no-such-class", "id"));
+
+    tester.timer.log("Verified IDL field checks");
+
+    let mut bill = EgValue::stub("mb")?;
+
+    assert!(bill.set_field("voided", "t").is_ok());
+    assert_eq!(bill.get_field("voided")?.as_str(), Some("t"));
+
+    assert!(bill.set_field("voidd", "t").is_err());
+    assert!(bill.get_field("voidd").is_err());
+
+    tester.timer.log("Verified checked field get/set");
+
+    let mut bill = idl.new_object("mb")?;
+    assert_eq!(bill.class(), "mb");
+
+    bill.set("amount", 1.25)?;
+    bill.set("note", "test")?;
+    bill.set("xact", 5)?;
+
+    assert_eq!(bill.get_float("amount")?, 1.25);
+    assert_eq!(bill.get_str("note")?, "test");
+    assert_eq!(bill.get_int("xact")?, 5);
+    assert!(bill.get_int("note").is_err());
+    assert!(bill.set("amunt", 1).is_err());
+
+    // Round-trip through the wire format.
+    let copy = eg::idl::IdlObject::from_json_value(bill.clone().into_json_value())?;
+    assert_eq!(copy, bill);
+
+    assert!(eg::idl::IdlObject::try_from(eg::hash! {"amount": 1}).is_err());
+
+    tester.timer.log("Verified IdlObject typed access");
+
+    fieldmapper_round_trip(tester)?;
+
+    let idl = tester.editor.idl();
+
+    assert!(idl.class_exists("mbt"));
+    assert!(idl.link_fields("mbt").unwrap().contains(&"grocery"));
+
+    // Flesh spec used by billing::adjust_bills_to_zero().
+    let flesh = eg::hash! {
+        "flesh": 2,
+        "flesh_fields": {
+            "mbt": ["grocery", "circulation"],
+            "circ": ["target_copy"]
+        }
+    };
+    idl.validate_flesh_fields(&flesh)?;
+
+    tester.timer.log("Verified flesh fields");
+
+    Ok(())
+}
+
+fn fieldmapper_round_trip(tester: &mut util::Tester) -> EgResult<()> {
+    let idl = tester.editor.idl();
+    let mb = idl.classes().get("mb").expect("mb class exists");
+
+    let hash = json::object! {
+        "_classname": "mb",
+        "id": 10,
+        "xact": 5,
+        "amount": 1.25,
+        "billing_type": "Overdue materials",
+        "btype": 1,
+        "note": "test",
+        "voided": "f",
+    };
+
+    let fm = eg::idl::hash_to_fieldmapper(hash)?;
+
+    assert_eq!(fm["__c"], "mb");
+
+    // Values are positioned by IDL field order.
+    let amount_pos = mb.get_field("amount").unwrap().array_pos();
+    assert_eq!(fm["__p"][amount_pos].as_f64(), Some(1.25));
+    assert_eq!(fm["__p"].len(), mb.fields().len());
+
+    let hash = eg::idl::fieldmapper_to_hash(fm.clone())?;
+
+    assert_eq!(hash["_classname"], "mb");
+    assert_eq!(hash["amount"].as_f64(), Some(1.25));
+    assert_eq!(hash["note"], "test");
+    assert!(hash["void_time"].is_null());
+    assert!(hash.has_key("void_time"));
+
+    assert_eq!(eg::idl::hash_to_fieldmapper(hash)?, fm);
+
+    tester.timer.log("Verified fieldmapper round trip");
+
+    Ok(())
+}
@@ -1,8 +1,12 @@
 use evergreen as eg;
 mod auth;
+mod billing;
 mod cache;
 mod circ;
+mod editor;
+mod idl;
 mod json_query;
+mod org;
 mod store;
 mod util;
 
@@ -32,10 +36,18 @@ fn main() -> eg::EgResult<()> {
 
     cache::run_live_tests(&mut tester)?;
 
+    idl::run_live_tests(&mut tester)?;
+
+    editor::run_live_tests(&mut tester)?;
+
+    org::run_live_tests(&mut tester)?;
+
     auth::run_live_tests(&mut tester)?;
 
     circ::run_live_tests(&mut tester)?;
 
+    billing::run_live_tests(&mut tester)?;
+
     // open-ils.rs-store tester
     //store::run_live_tests(&mut tester)?;
 
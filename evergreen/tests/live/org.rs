@@ -0,0 +1,119 @@
+use crate::util;
+use eg::common::org;
+use eg::common::settings::{SettingImport, Settings};
+use eg::EgResult;
+use evergreen as eg;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    let ancestors = org::ancestors(e, org_id)?;
+    assert!(ancestors.contains(&org_id));
+
+    let root_id = e
+        .search("aou", eg::hash! {"parent_ou": eg::NULL})?
+        .pop()
+        .ok_or("No root org unit")?
+        .id()?;
+
+    assert!(ancestors.contains(&root_id));
+
+    let descendants = org::descendants(e, root_id)?;
+    assert!(descendants.contains(&org_id));
+    assert!(descendants.contains(&root_id));
+
+    tester.timer.log("Verified org ancestors and descendants");
+
+    // Cached and uncached lookups agree.
+    assert_eq!(org::descendants(e, root_id)?, descendants);
+    org::clear_relations_cache(e);
+    assert_eq!(org::descendants(e, root_id)?, descendants);
+
+    tester.timer.log("Verified org relations cache");
+
+    settings_import_export(tester)?;
+    tester.timer.log("Settings::import_org() and export_org()");
+
+    Ok(())
+}
+
+fn settings_import_export(tester: &mut util::Tester) -> EgResult<()> {
+    let org_id = tester.samples.aou_id;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    // Start from a clean slate within our transaction.
+    let query =
+        eg::hash! {"org_unit": org_id, "name": ["circ.grace.extend", "circ.hold_expire_interval"]};
+    for setting in e.search("aous", query)? {
+        e.delete(setting)?;
+    }
+
+    let data = eg::hash! {
+        "circ.grace.extend": true,
+        "circ.hold_expire_interval": "1 fortnite",
+        "_eg_test.no_such_setting": 1,
+    };
+
+    let report = Settings::import_org(e, org_id, &data, false)?;
+    let outcome = |report: &[(String, SettingImport)], name: &str| {
+        report
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, o)| o.clone())
+    };
+
+    assert_eq!(
+        outcome(&report, "circ.grace.extend"),
+        Some(SettingImport::Created)
+    );
+    assert!(matches!(
+        outcome(&report, "circ.hold_expire_interval"),
+        Some(SettingImport::Skipped(_))
+    ));
+    assert!(matches!(
+        outcome(&report, "_eg_test.no_such_setting"),
+        Some(SettingImport::Skipped(_))
+    ));
+
+    // Intervals which would not fully parse are rejected.
+    for interval in ["", "daily", "1 day extra"] {
+        let data = eg::hash! {"circ.hold_expire_interval": interval};
+
+        let report = Settings::import_org(e, org_id, &data, false)?;
+        assert!(matches!(
+            outcome(&report, "circ.hold_expire_interval"),
+            Some(SettingImport::Skipped(_))
+        ));
+    }
+
+    // Existing values are only replaced when asked.
+    let data = eg::hash! {"circ.grace.extend": false};
+
+    let report = Settings::import_org(e, org_id, &data, false)?;
+    assert!(matches!(
+        outcome(&report, "circ.grace.extend"),
+        Some(SettingImport::Skipped(_))
+    ));
+
+    let report = Settings::import_org(e, org_id, &data, true)?;
+    assert_eq!(
+        outcome(&report, "circ.grace.extend"),
+        Some(SettingImport::Updated)
+    );
+
+    let export = Settings::export_org(e, org_id)?;
+    assert_eq!(export["circ.grace.extend"].as_bool(), Some(false));
+    assert!(export["circ.hold_expire_interval"].is_null());
+
+    // Leave nothing behind, then drop any values cached for the
+    // settings we touched.
+    e.rollback()?;
+
+    let mut settings = Settings::new(e);
+    settings.invalidate_import(org_id, &report)
+}
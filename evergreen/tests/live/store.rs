@@ -97,5 +97,53 @@ pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
     ses.disconnect()?; // this would also cause a rollback
     tester.timer.log("Disconnected");
 
+    cancelled_request_replies_discarded(tester)?;
+    streamed_responses(tester)?;
+
+    Ok(())
+}
+
+fn cancelled_request_replies_discarded(tester: &mut util::Tester) -> EgResult<()> {
+    let mut ses = tester.client.session("open-ils.rs-store");
+
+    let query = eg::hash! {"id": {"!=": EgValue::Null}};
+    let mut req = ses.request("open-ils.rs-store.direct.config.billing_type.search", query)?;
+    req.cancel()?;
+
+    assert!(req.complete());
+    assert!(req.recv_with_timeout(0)?.is_none());
+
+    // Replies to the cancelled request must not leak into later
+    // requests on the same session.
+    let mut req = ses.request("open-ils.rs-store.direct.config.billing_type.retrieve", 1)?;
+    let cbt = req.first()?.expect("retrieve should return a value");
+    assert_eq!(cbt.id()?, 1);
+
+    tester.timer.log("Cancelled Request Replies Discarded");
+
+    Ok(())
+}
+
+fn streamed_responses(tester: &mut util::Tester) -> EgResult<()> {
+    let method = "opensrf.system.stream.example";
+
+    let values = tester
+        .client
+        .send_recv_iter("open-ils.rs-store", method, None)?
+        .collect::<EgResult<Vec<EgValue>>>()?;
+
+    let values: Vec<i64> = values.iter().map(|v| v.int_required()).collect();
+    assert_eq!(values, [1, 2, 3]);
+
+    // Atomic calls receive the same responses as a single array.
+    let list = tester
+        .client
+        .send_recv_one("open-ils.rs-store", &format!("{method}.atomic"), None)?
+        .expect("atomic call should return a value");
+
+    assert_eq!(list.len(), 3);
+
+    tester.timer.log("Streamed Responses");
+
     Ok(())
 }